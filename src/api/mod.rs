@@ -1,5 +1,11 @@
 pub mod schema;
 pub mod validation;
 
-pub use schema::{BindMount, ExecutionRequest, ExecutionStatus, IsolationConfig, ResourceLimits};
+pub use schema::{
+    BindMount, CacheMount, CloneLocation, DnsConfig, EnforcementPolicy, ExecutionRequest,
+    ExecutionStatus, GitSource, IsolationConfig, MacOSProfile, NetworkMode, NetworkPolicy,
+    ProxyConfig, ResourceLimits, SeccompMode, SeccompPreset, SocketMount, TimeOffset,
+};
+#[allow(unused_imports)] // Library API
 pub use validation::validate_execution_request;
+pub use validation::{validate_execution_request_with, Ceilings, PathPolicy};
@@ -1,6 +1,9 @@
+use base64::engine::general_purpose::STANDARD_PAD_INDIFFERENT;
+use base64::Engine;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -8,12 +11,134 @@ pub struct ExecutionRequest {
     pub command: Vec<String>,
     #[serde(default)]
     pub environment: HashMap<String, String>,
+    /// Only these variables are inherited from capsule-run's own environment;
+    /// unset inherits all of them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment_inherit: Option<Vec<String>>,
     #[serde(default = "default_timeout")]
     pub timeout_ms: u64,
     #[serde(default)]
     pub resources: ResourceLimits,
     #[serde(default)]
     pub isolation: IsolationConfig,
+    /// Opt-in: record a resource sample at this interval into `metrics.samples`
+    #[serde(default)]
+    pub sample_interval_ms: Option<u64>,
+    /// Glob patterns, relative to /workspace, of files to collect after exit
+    #[serde(default)]
+    pub outputs: Vec<String>,
+    /// Host directory collected files are copied to instead of being inlined
+    #[serde(default)]
+    pub output_dir: Option<String>,
+    /// Host tarball (.tar, .tar.gz or .tar.zst) unpacked into /workspace before the run
+    #[serde(default)]
+    pub workspace_in: Option<String>,
+    /// Host tarball the whole of /workspace is captured into after the run
+    #[serde(default)]
+    pub workspace_out: Option<String>,
+    /// Report the files the command created, modified or deleted in /workspace
+    #[serde(default)]
+    pub track_changes: bool,
+    /// Files written into /workspace before the command starts
+    #[serde(default)]
+    pub files: Vec<InputFile>,
+    /// Git checkout that populates /workspace before anything else
+    #[serde(default)]
+    pub source: Option<GitSource>,
+    /// Artifacts downloaded on the host and verified before the run
+    #[serde(default)]
+    pub inputs: Vec<RemoteInput>,
+    /// Config profile layered under this request; `--profile` takes precedence
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Runs waiting for a concurrency slot start in order of priority,
+    /// highest first
+    #[serde(default)]
+    pub priority: i32,
+    /// Fail with E4007 rather than wait longer than this for a slot
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_timeout_ms: Option<u64>,
+    /// The `[tenants]` entry whose quotas the run counts against
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+/// A file fetched over HTTP(S) into /workspace, accepted only if its
+/// contents match `sha256`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RemoteInput {
+    pub url: String,
+    /// Path relative to /workspace
+    pub dest: String,
+    /// Expected SHA-256 of the contents, in hex
+    pub sha256: String,
+}
+
+/// A git repository checked out into /workspace before the run.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GitSource {
+    /// Remote URL (https, http, ssh or git)
+    pub git: String,
+    /// Branch, tag or commit; the remote's HEAD when unset
+    #[serde(default, rename = "ref")]
+    pub git_ref: Option<String>,
+    /// Shallow fetch of this many commits; full history when unset
+    #[serde(default)]
+    pub depth: Option<u32>,
+    #[serde(default)]
+    pub clone: CloneLocation,
+}
+
+/// Where a git source is fetched from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloneLocation {
+    /// On the host before setup, with its network and credentials; only
+    /// for remotes in `security.allowed_host_git_sources`
+    Host,
+    /// From inside the sandbox after setup, which needs `isolation.network`
+    #[default]
+    Sandbox,
+}
+
+/// A file shipped with the request, such as a generated script or its data.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InputFile {
+    /// Path relative to /workspace
+    pub path: String,
+    /// Text contents; exactly one of `contents` and `base64` is set
+    #[serde(default)]
+    pub contents: Option<String>,
+    /// Binary contents in standard base64
+    #[serde(default)]
+    pub base64: Option<String>,
+    /// Permission bits; 0644 when unset
+    #[serde(default, with = "file_mode")]
+    pub mode: Option<u32>,
+}
+
+impl InputFile {
+    /// The file's bytes, from whichever of `contents` and `base64` is set.
+    pub fn data(&self) -> Result<Vec<u8>, String> {
+        match (&self.contents, &self.base64) {
+            (Some(contents), None) => Ok(contents.as_bytes().to_vec()),
+            (None, Some(encoded)) => {
+                // Padding is optional and whitespace is ignored, so wrapped
+                // output from tools like `base64` decodes as-is
+                let digits: Vec<u8> = encoded
+                    .bytes()
+                    .filter(|b| !b.is_ascii_whitespace())
+                    .collect();
+                STANDARD_PAD_INDIFFERENT
+                    .decode(digits)
+                    .map_err(|_| format!("{}: base64 contents are malformed", self.path))
+            }
+            _ => Err(format!(
+                "{}: exactly one of contents and base64 must be set",
+                self.path
+            )),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -26,20 +151,447 @@ pub struct ResourceLimits {
     pub max_output_bytes: usize,
     #[serde(default = "default_max_pids")]
     pub max_pids: u32,
+    #[serde(default = "default_max_open_files")]
+    pub max_open_files: u32,
+    /// Hard CPU cap in percent of one core (150 = 1.5 cores), via cpu.max
+    #[serde(default)]
+    pub cpu_quota_percent: Option<u32>,
+    /// CPUs the execution may run on, in cpuset list format ("0-3,6")
+    #[serde(default)]
+    pub cpuset: Option<String>,
+    /// NUMA memory nodes, in cpuset list format (cgroup only)
+    #[serde(default)]
+    pub cpuset_mems: Option<String>,
+    /// Size cap for /tmp and /workspace (tmpfs size or project quota)
+    #[serde(default)]
+    pub disk_bytes: Option<u64>,
+    /// Largest file the execution may write (RLIMIT_FSIZE)
+    #[serde(default)]
+    pub max_file_size_bytes: Option<u64>,
+    /// Bandwidth and IOPS caps for the workspace's block device (io.max)
+    #[serde(default)]
+    pub io_read_bps: Option<u64>,
+    #[serde(default)]
+    pub io_write_bps: Option<u64>,
+    #[serde(default)]
+    pub io_read_iops: Option<u64>,
+    #[serde(default)]
+    pub io_write_iops: Option<u64>,
+    /// Bytes per second in each direction over the bridge network (tc tbf)
+    #[serde(default)]
+    pub network_bps: Option<u64>,
 }
 
+/// Host directories the root is built from unless `base_mounts` is set
+pub const DEFAULT_BASE_MOUNTS: [&str; 6] = ["/bin", "/sbin", "/usr", "/lib", "/lib64", "/etc"];
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct IsolationConfig {
     #[serde(default = "default_network")]
     pub network: bool,
+    /// How network access is provided when `network` is enabled
+    #[serde(default)]
+    pub network_mode: NetworkMode,
+    /// Destinations a bridged execution may reach; without one, anything
+    #[serde(default)]
+    pub network_policy: Option<NetworkPolicy>,
+    /// Resolvers, search domains and a domain allowlist for the sandbox (Linux)
+    #[serde(default)]
+    pub dns: Option<DnsConfig>,
+    /// Hostnames added to the sandbox's /etc/hosts (Linux)
+    #[serde(default)]
+    pub extra_hosts: HashMap<String, IpAddr>,
+    /// Proxy that a bridged execution's egress is confined to
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+    /// Summarize traffic per destination from conntrack (`network_mode` bridge)
+    #[serde(default)]
+    pub track_destinations: bool,
     #[serde(default)]
     pub readonly_paths: Vec<String>,
     #[serde(default)]
     pub writable_paths: Vec<String>,
+    /// Host directories bound read-only at the same path to form the root
+    /// (Linux). Unset means /bin, /sbin, /usr, /lib, /lib64 and /etc; empty
+    /// means an empty root with only /dev, /proc, /sys, /tmp and /workspace
+    #[serde(default)]
+    pub base_mounts: Option<Vec<String>>,
+    /// Paths hidden from the command: files read as empty, directories as
+    /// empty and read-only
+    #[serde(default)]
+    pub masked_paths: Vec<String>,
+    /// Paths replaced by an empty, writable tmpfs (Linux)
+    #[serde(default)]
+    pub tmpfs_paths: Vec<String>,
     #[serde(default = "default_working_directory")]
     pub working_directory: String,
     #[serde(default)]
     pub bind_mounts: Vec<BindMount>,
+    /// Host Unix sockets exposed to the command, within the config's
+    /// `security.allowed_unix_sockets`
+    #[serde(default)]
+    pub unix_sockets: Vec<SocketMount>,
+    /// Host device nodes exposed at the same path, within the config's
+    /// `security.allowed_devices` (Linux)
+    #[serde(default)]
+    pub devices: Vec<String>,
+    /// Host cache directories shared between executions, mounted read-write
+    #[serde(default)]
+    pub caches: Vec<CacheMount>,
+    /// FUSE filesystems mounted for the command; /dev/fuse must be in the
+    /// config's `security.allowed_devices` (Linux)
+    #[serde(default)]
+    pub fuse_mounts: Vec<FuseMount>,
+    /// Keep the command from reaching abstract Unix sockets outside the
+    /// sandbox when it shares the host's network namespace (Linux)
+    #[serde(default = "default_block_abstract_sockets")]
+    pub block_abstract_sockets: bool,
+    /// Back /workspace with a per-execution tmpfs rather than a directory on
+    /// the host's /tmp (Linux)
+    #[serde(default = "default_workspace_tmpfs")]
+    pub workspace_tmpfs: bool,
+    #[serde(default)]
+    pub time_offset: Option<TimeOffset>,
+    #[serde(default)]
+    pub shm_size: Option<u64>,
+    #[serde(default)]
+    pub host_ipc: bool,
+    #[serde(default, with = "file_mode")]
+    pub umask: Option<u32>,
+    #[serde(default, with = "file_mode")]
+    pub workspace_mode: Option<u32>,
+    #[serde(default)]
+    pub workspace_uid: Option<u32>,
+    #[serde(default)]
+    pub workspace_gid: Option<u32>,
+    /// Built-in seccomp preset; ignored in favour of `seccomp_profile`
+    #[serde(default)]
+    pub seccomp: SeccompPreset,
+    /// Syscalls allowed on top of the preset, within the config's
+    /// `security.allowed_extra_syscalls`
+    #[serde(default)]
+    pub extra_syscalls: Vec<String>,
+    /// Path to an OCI-format seccomp profile replacing the built-in allowlist
+    #[serde(default)]
+    pub seccomp_profile: Option<String>,
+    #[serde(default)]
+    pub seccomp_mode: SeccompMode,
+    /// Syscalls decided at runtime by the executor's syscall policy
+    /// (seccomp user notification, Linux 5.5+)
+    #[serde(default)]
+    pub notify_syscalls: Vec<String>,
+    /// Base Seatbelt profile on macOS
+    #[serde(default)]
+    pub macos_profile: MacOSProfile,
+    /// SBPL rules appended to the generated macOS profile; later rules win
+    #[serde(default)]
+    pub macos_profile_extra: Vec<String>,
+    /// Whether a requested feature the host cannot provide fails the execution
+    #[serde(default)]
+    pub enforcement: EnforcementPolicy,
+}
+
+/// Built-in seccomp allowlists of increasing permissiveness.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeccompPreset {
+    /// Base allowlist without ioctl, kill, prctl or sockets
+    Strict,
+    /// Base allowlist for single-process commands
+    #[default]
+    Default,
+    /// Adds process creation, ptrace and perf_event_open for compilers and test runners
+    Build,
+    /// Adds sockets of any family, epoll_ctl and io_uring for servers and clients
+    Network,
+}
+
+impl std::str::FromStr for SeccompPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "default" => Ok(Self::Default),
+            "build" => Ok(Self::Build),
+            "network" => Ok(Self::Network),
+            _ => Err(format!(
+                "invalid seccomp preset '{}' (expected strict, default, build or network)",
+                s
+            )),
+        }
+    }
+}
+
+/// What happens when the sandboxed command makes a syscall the filter does not allow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeccompMode {
+    /// Kill the process with SIGSYS
+    #[default]
+    Kill,
+    /// Allow the call and record it (audit mode)
+    Log,
+    /// Fail the call with EPERM and record it (complain mode)
+    Errno,
+}
+
+impl std::str::FromStr for SeccompMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kill" => Ok(Self::Kill),
+            "log" => Ok(Self::Log),
+            "errno" => Ok(Self::Errno),
+            _ => Err(format!(
+                "invalid seccomp mode '{}' (expected kill, log or errno)",
+                s
+            )),
+        }
+    }
+}
+
+/// Base Seatbelt (SBPL) profiles for macOS, from most to least restrictive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MacOSProfile {
+    /// No child processes and no metadata reads outside the allowed paths, for untrusted code
+    Strict,
+    /// System libraries and binaries plus the configured paths
+    #[default]
+    Default,
+    /// Adds Xcode, Homebrew, Mach services and the per-user temp and cache dirs toolchains use
+    Developer,
+}
+
+impl std::str::FromStr for MacOSProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "default" => Ok(Self::Default),
+            "developer" => Ok(Self::Developer),
+            _ => Err(format!(
+                "invalid macOS profile '{}' (expected strict, default or developer)",
+                s
+            )),
+        }
+    }
+}
+
+/// How a network-enabled execution reaches the network.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkMode {
+    /// Share the host's network stack
+    #[default]
+    Host,
+    /// Private network namespace with a veth pair on the capsule0 bridge and
+    /// NAT to the outside (Linux, root)
+    Bridge,
+}
+
+impl std::str::FromStr for NetworkMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "host" => Ok(Self::Host),
+            "bridge" => Ok(Self::Bridge),
+            _ => Err(format!(
+                "invalid network mode '{}' (expected host or bridge)",
+                s
+            )),
+        }
+    }
+}
+
+/// Egress allowlist for `network_mode` bridge; everything else is rejected.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct NetworkPolicy {
+    /// `host[:port]`, `address[:port]` or `address/prefix[:port]` entries
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+/// An HTTP(S) proxy for bridged executions: the command is pointed at it
+/// through the environment, and other egress is rejected.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProxyConfig {
+    /// `http://[user:password@]host[:port]`
+    pub url: String,
+    /// Hosts reached without the proxy, passed as NO_PROXY; they must also
+    /// be allowed by `network_policy`
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+    /// Redirect TCP ports 80 and 443 to the proxy for clients that ignore the
+    /// environment; the proxy must accept intercepted connections
+    #[serde(default)]
+    pub intercept: bool,
+}
+
+impl ProxyConfig {
+    /// Where the proxy listens, as an egress rule.
+    pub fn endpoint(&self) -> Result<EgressRule, String> {
+        let invalid = || {
+            format!(
+                "invalid proxy url '{}' (expected http://host:port)",
+                self.url
+            )
+        };
+
+        let (rest, default_port) = if let Some(rest) = self.url.strip_prefix("http://") {
+            (rest, 80)
+        } else if let Some(rest) = self.url.strip_prefix("https://") {
+            (rest, 443)
+        } else {
+            return Err(invalid());
+        };
+        let authority = rest.split('/').next().unwrap_or_default();
+        let host_port = authority
+            .rsplit_once('@')
+            .map_or(authority, |(_, host)| host);
+        let endpoint = if host_port.contains(':') {
+            host_port.to_string()
+        } else {
+            format!("{}:{}", host_port, default_port)
+        };
+
+        let rule: EgressRule = endpoint.parse().map_err(|_| invalid())?;
+        match rule.destination {
+            EgressDestination::Network(_, 32) | EgressDestination::Host(_) => Ok(rule),
+            EgressDestination::Network(..) => Err(invalid()),
+        }
+    }
+
+    /// Proxy variables in both the upper- and lowercase spellings clients read.
+    pub fn environment(&self) -> Vec<(String, String)> {
+        let mut variables = Vec::new();
+        for name in ["HTTP_PROXY", "HTTPS_PROXY"] {
+            variables.push((name.to_string(), self.url.clone()));
+            variables.push((name.to_lowercase(), self.url.clone()));
+        }
+
+        let no_proxy = ["localhost", "127.0.0.1"]
+            .into_iter()
+            .map(str::to_string)
+            .chain(self.no_proxy.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(",");
+        variables.push(("NO_PROXY".to_string(), no_proxy.clone()));
+        variables.push(("no_proxy".to_string(), no_proxy));
+        variables
+    }
+}
+
+/// A parsed `network_policy.allow` entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EgressRule {
+    pub destination: EgressDestination,
+    /// TCP and UDP port; any port when unset
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EgressDestination {
+    /// IPv4 network, a single address being a /32
+    Network(Ipv4Addr, u8),
+    /// Hostname, resolved when the execution starts
+    Host(String),
+}
+
+impl std::str::FromStr for EgressRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (destination, port) = match s.rsplit_once(':') {
+            Some((destination, port)) => {
+                let port = port
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|port| *port != 0)
+                    .ok_or_else(|| format!("invalid port in egress rule '{}'", s))?;
+                (destination, Some(port))
+            }
+            None => (s, None),
+        };
+
+        let destination = if let Some((address, prefix)) = destination.split_once('/') {
+            let address = address
+                .parse::<Ipv4Addr>()
+                .map_err(|_| format!("invalid network in egress rule '{}'", s))?;
+            let prefix = prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= 32)
+                .ok_or_else(|| format!("invalid prefix length in egress rule '{}'", s))?;
+            EgressDestination::Network(address, prefix)
+        } else if let Ok(address) = destination.parse::<Ipv4Addr>() {
+            EgressDestination::Network(address, 32)
+        } else if is_hostname(destination) {
+            EgressDestination::Host(destination.to_ascii_lowercase())
+        } else {
+            return Err(format!(
+                "invalid egress rule '{}' (expected host, address or address/prefix, with an optional :port)",
+                s
+            ));
+        };
+
+        Ok(Self { destination, port })
+    }
+}
+
+pub(crate) fn is_hostname(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// The sandbox's /etc/resolv.conf; unset settings are copied from the host.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct DnsConfig {
+    #[serde(default)]
+    pub nameservers: Vec<IpAddr>,
+    #[serde(default)]
+    pub search: Vec<String>,
+    /// Only these domains and their subdomains resolve, through a filtering
+    /// stub on 127.0.0.1 (`network_mode` bridge)
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+}
+
+/// What happens when a requested isolation feature cannot be applied on this host.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnforcementPolicy {
+    /// Fail setup before the command runs
+    Strict,
+    /// Run anyway and list what was not applied in `degraded_features`
+    #[default]
+    BestEffort,
+}
+
+impl std::str::FromStr for EnforcementPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "best_effort" | "best-effort" => Ok(Self::BestEffort),
+            _ => Err(format!(
+                "invalid enforcement policy '{}' (expected strict or best_effort)",
+                s
+            )),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -49,6 +601,60 @@ pub struct BindMount {
     pub readonly: bool,
 }
 
+/// A host Unix socket bound into the sandbox.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SocketMount {
+    pub source: String,
+    /// Path inside the sandbox; the same as `source` when unset
+    #[serde(default)]
+    pub destination: Option<String>,
+}
+
+impl SocketMount {
+    pub fn destination(&self) -> &str {
+        self.destination.as_deref().unwrap_or(&self.source)
+    }
+}
+
+/// A named host cache directory mounted at `path`, shared by every
+/// execution that uses the same name.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CacheMount {
+    pub name: String,
+    pub path: String,
+    /// Least recently modified files are evicted above this size before the
+    /// cache is mounted
+    #[serde(default)]
+    pub size_bytes: Option<u64>,
+}
+
+/// A FUSE filesystem served by `command` at `path`.
+///
+/// capsule-run opens /dev/fuse and mounts it, then starts the daemon inside
+/// the sandbox with the connection as `/dev/fd/N`, which libfuse 3.3+ accepts
+/// as a mountpoint. Neither the daemon nor the command can mount anything.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FuseMount {
+    /// Daemon argv; a `{mountpoint}` argument is replaced by `/dev/fd/N`,
+    /// which is otherwise appended
+    pub command: Vec<String>,
+    pub path: String,
+    #[serde(default)]
+    pub readonly: bool,
+}
+
+/// Clock offsets applied inside a private time namespace (Linux 5.6+).
+///
+/// Only CLOCK_MONOTONIC and CLOCK_BOOTTIME can be shifted; the wall clock
+/// (CLOCK_REALTIME) is shared with the host.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TimeOffset {
+    #[serde(default)]
+    pub monotonic_secs: i64,
+    #[serde(default)]
+    pub boottime_secs: i64,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct ExecutionResponse {
     pub execution_id: Uuid,
@@ -64,6 +670,130 @@ pub struct ExecutionResponse {
     pub timestamps: ExecutionTimestamps,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<ErrorResponse>,
+    /// Syscalls the filter did not allow, when running with seccomp_mode log or errno
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub denied_syscalls: Option<Vec<DeniedSyscall>>,
+    /// Files opened and sockets connected by the process tree, on macOS
+    /// builds with the `endpoint-security` feature
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audit_trail: Option<Vec<AuditEvent>>,
+    /// Requested isolation features that were not applied, under best-effort enforcement
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub degraded_features: Vec<DegradedFeature>,
+    /// Settings validation allowed but flagged, such as a writable / mount
+    /// with network access
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// Isolation mechanisms in force for the run; absent if setup failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<SandboxReport>,
+    /// Workspace files matching the request's `outputs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifacts: Option<Vec<Artifact>>,
+    /// Files the command changed in /workspace, with `track_changes`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workspace_changes: Option<Vec<WorkspaceChange>>,
+}
+
+/// How a workspace file changed over the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+}
+
+/// A file the command created, modified or deleted in /workspace.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WorkspaceChange {
+    /// Path relative to /workspace
+    pub path: String,
+    pub change: ChangeKind,
+    /// Size and hex SHA-256 after the run, or before it for a deleted file
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// A file collected from the workspace after the command exited.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Artifact {
+    /// Path relative to /workspace
+    pub path: String,
+    pub size: u64,
+    /// Hex SHA-256 of the contents
+    pub sha256: String,
+    /// Contents, unless copied to `output_dir` or over the inline size cap
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base64: Option<String>,
+}
+
+/// Isolation mechanisms that were actually applied to an execution.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SandboxReport {
+    /// `linux` (namespaces, cgroups and seccomp), `seatbelt` (macOS) or `none`
+    pub backend: String,
+    /// Linux namespaces the command runs in
+    pub namespaces: Vec<String>,
+    /// cgroup v2 controllers enabled for the execution's cgroup
+    pub cgroup_controllers: Vec<String>,
+    pub seccomp: bool,
+    /// Action for disallowed syscalls, when a seccomp filter is installed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seccomp_mode: Option<SeccompMode>,
+    /// Base Seatbelt profile, when one was applied on macOS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub macos_profile: Option<MacOSProfile>,
+    /// Network denied through the pf anchor fallback on macOS
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub pf_network_deny: bool,
+    /// Address on the `capsule0` bridge, in `network_mode` bridge
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bridge_address: Option<Ipv4Addr>,
+    pub resource_enforcement: ResourceEnforcement,
+}
+
+/// An isolation feature that was requested but could not be applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DegradedFeature {
+    pub feature: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeniedSyscall {
+    pub name: String,
+    pub count: u64,
+}
+
+/// Connections from the execution to one address, port and protocol.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NetworkDestination {
+    pub protocol: String,
+    pub address: IpAddr,
+    /// Unset for protocols without ports, such as ICMP
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    pub connections: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditEvent {
+    pub pid: u32,
+    pub kind: AuditEventKind,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[allow(dead_code)] // Only produced by the macOS Endpoint Security audit
+pub enum AuditEventKind {
+    Exec,
+    Open,
+    /// Connection to a Unix domain socket, identified by its path
+    Connect,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -84,6 +814,356 @@ pub struct ExecutionMetrics {
     pub max_memory_bytes: u64,
     pub io_bytes_read: u64,
     pub io_bytes_written: u64,
+    pub cpu_throttled_ms: u64,
+    pub peak_disk_bytes: u64,
+    pub major_page_faults: u64,
+    pub minor_page_faults: u64,
+    pub voluntary_context_switches: u64,
+    pub involuntary_context_switches: u64,
+    /// Highest number of concurrent tasks (Linux 6.1+ with cgroups, otherwise 0)
+    pub pids_peak: u64,
+    /// Whether a fork was refused because pids.max was reached
+    pub pids_limit_reached: bool,
+    pub enforcement: ResourceEnforcement,
+    /// Bytes received over the bridge network, in `network_mode` bridge
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_rx_bytes: Option<u64>,
+    /// Bytes sent over the bridge network, in `network_mode` bridge
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_tx_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_rx_packets: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_tx_packets: Option<u64>,
+    /// Traffic per destination, with `isolation.track_destinations`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_destinations: Option<Vec<NetworkDestination>>,
+    /// Resource usage over time, present when `sample_interval_ms` was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub samples: Option<Vec<ResourceSample>>,
+    /// Where the time went, to tell the command's own time from overhead
+    pub phases: ExecutionPhases,
+}
+
+/// Durations of the stages of a run. `wall_time_ms` covers `spawn_ms`
+/// through `io_drain_ms`; setup and cleanup happen around it.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ExecutionPhases {
+    /// Waiting for a concurrency slot
+    pub queue_ms: u64,
+    /// Namespaces, cgroup, mounts and seccomp, plus populating the workspace
+    pub sandbox_setup_ms: u64,
+    /// From preparing the command until it is running
+    pub spawn_ms: u64,
+    /// From the command starting until it exited
+    pub execution_ms: u64,
+    /// Reading the output left in the pipes after exit
+    pub io_drain_ms: u64,
+    /// Removing the cgroup and sandbox root
+    pub cleanup_ms: u64,
+}
+
+/// A point-in-time reading taken by the resource monitor.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceSample {
+    pub timestamp: DateTime<Utc>,
+    pub memory_bytes: u64,
+    pub cpu_time_ms: u64,
+}
+
+/// Mechanism that enforced the resource limits of an execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[allow(dead_code)] // Not every variant is reachable on every platform
+pub enum ResourceEnforcement {
+    /// cgroup v2 controllers (Linux)
+    Cgroup,
+    /// Per-process setrlimit limits (macOS, or Linux without usable cgroups)
+    Rlimit,
+    /// No limits could be applied
+    None,
+}
+
+/// Isolation features available on the host, as reported by `capsule-run capabilities`.
+///
+/// Features that do not exist on the platform are reported as unavailable.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostCapabilities {
+    pub version: String,
+    pub os: String,
+    pub arch: String,
+    /// Unprivileged processes can create user namespaces (Linux)
+    pub user_namespaces: bool,
+    /// The kernel supports time namespaces (Linux 5.6+)
+    pub time_namespaces: bool,
+    /// A cgroup v2 hierarchy is mounted (Linux)
+    pub cgroup_v2: bool,
+    /// capsule-run can create cgroups without asking systemd for a delegated scope (Linux)
+    pub cgroup_delegated: bool,
+    /// Controllers available to capsule-run's cgroup
+    pub cgroup_controllers: Vec<String>,
+    /// Seccomp filters are compiled in and supported by the kernel (Linux)
+    pub seccomp: bool,
+    /// The kernel supports seccomp user notification (Linux 5.0+)
+    pub seccomp_notify: bool,
+    /// Landlock ABI version, if enabled in the kernel (Linux)
+    pub landlock_abi: Option<u32>,
+    /// sandbox-exec is installed (macOS)
+    pub sandbox_exec: bool,
+    /// capsule-run already runs inside a Seatbelt sandbox, so profiles cannot be applied (macOS)
+    pub nested_sandbox: bool,
+    /// Endpoint Security audit is compiled in and capsule-run runs as root (macOS)
+    pub endpoint_security: bool,
+    /// Mechanism expected to enforce resource limits
+    pub resource_enforcement: ResourceEnforcement,
+}
+
+/// One line of the audit log, written once an execution has finished or
+/// been refused.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    pub execution_id: Uuid,
+    /// Who asked for the run, as the policy engine saw them
+    pub caller: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    /// The uid capsule-run ran as
+    pub uid: u32,
+    pub command: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Host paths the command could reach
+    pub mounts: Vec<BindMount>,
+    pub network: bool,
+    pub resources: ResourceLimits,
+    pub timeout_ms: u64,
+    pub status: ExecutionStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Error code and message, for a failed or refused run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wall_time_ms: Option<u64>,
+    /// Of the captured output as returned in the response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout_sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr_sha256: Option<String>,
+    /// `hash` of the record before this one; empty for the first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev_hash: Option<String>,
+    /// With hash chaining, SHA-256 of this record, `prev_hash` included,
+    /// without `hash`, as compact JSON with sorted keys
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+/// One line written to `--event-fd` as an execution progresses.
+#[derive(Debug, Clone, Serialize)]
+pub struct LifecycleEventRecord {
+    /// Counts up from 0 in the order events are written
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub execution_id: Uuid,
+    #[serde(flatten)]
+    pub event: LifecycleEvent,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LifecycleEvent {
+    /// The sandbox is set up and the workspace populated
+    SetupDone {
+        setup_ms: u64,
+    },
+    Spawned {
+        pid: u32,
+    },
+    /// Output captured so far; sent when it has grown, checked four times
+    /// a second
+    OutputProgress {
+        stdout_bytes: u64,
+        stderr_bytes: u64,
+    },
+    /// A chunk of the command's output, with `--event-output`. It covers
+    /// bytes `offset` to `offset + length` of `stream`
+    Output {
+        stream: &'static str,
+        offset: u64,
+        length: u64,
+        base64: String,
+    },
+    /// Memory use has reached 90% of the limit
+    OomWarning {
+        memory_bytes: u64,
+        limit_bytes: u64,
+    },
+    /// Always the last event, whether the run succeeded, failed or was refused
+    Finished {
+        status: ExecutionStatus,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        exit_code: Option<i32>,
+        /// Error code, or the message of a run refused before it started
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+/// An execution still running, as listed by `capsule-run ps`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunningExecution {
+    pub execution_id: Uuid,
+    /// The capsule-run process supervising the execution
+    pub supervisor_pid: u32,
+    /// The command's process, once it has been started
+    #[serde(default)]
+    pub pid: Option<u32>,
+    pub command: Vec<String>,
+    pub started_at: DateTime<Utc>,
+    /// Filled in when listed, not stored
+    #[serde(default)]
+    pub uptime_ms: u64,
+    /// Memory charged to the execution's cgroup, else the command's
+    /// resident set (Linux)
+    #[serde(default)]
+    pub memory_bytes: Option<u64>,
+}
+
+/// Current usage of a running execution's cgroup, as printed by
+/// `capsule-run stats`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecutionStats {
+    pub execution_id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub memory_bytes: u64,
+    /// memory.max, when there is one
+    pub memory_limit_bytes: Option<u64>,
+    /// CPU time used since the execution started
+    pub cpu_time_us: u64,
+    /// CPU use since the previous sample; 100 is one core fully busy
+    pub cpu_percent: Option<f64>,
+    pub pids: u64,
+    pub io_bytes_read: u64,
+    pub io_bytes_written: u64,
+}
+
+/// A set of jobs run by `capsule-run batch`, each once the jobs it depends
+/// on have succeeded.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BatchFile {
+    pub jobs: Vec<BatchJob>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BatchJob {
+    /// Unique within the batch; letters, digits, `-` and `_`
+    pub id: String,
+    /// Jobs that must succeed before this one starts
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// A job whose final /workspace this one starts with; implies depending
+    /// on it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace_from: Option<String>,
+    /// The job's JSON request, as `--json` reads it
+    pub request: serde_json::Value,
+}
+
+/// What `capsule-run batch` prints once every job has run or been skipped.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchReport {
+    pub success: bool,
+    /// In the order of the batch file
+    pub jobs: Vec<BatchJobResult>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchJobResult {
+    pub id: String,
+    pub status: BatchJobStatus,
+    /// The job's execution response, when it ran
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<serde_json::Value>,
+    /// Why the job failed without a response, or was skipped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchJobStatus {
+    Success,
+    Failed,
+    /// Not run because a job it depends on did not succeed
+    Skipped,
+}
+
+/// One host check made by `capsule-run doctor`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DoctorCheck {
+    pub name: String,
+    pub status: CheckStatus,
+    /// What was found
+    pub detail: String,
+    /// How to fix it, when the status is not `pass`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remediation: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    /// Runs work, with less isolation or fewer limits than they could have
+    Warn,
+    /// Runs fail
+    Fail,
+}
+
+/// What setting up the sandbox for a request would do, as printed by
+/// `capsule-run validate` and `--dry-run`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SandboxPlan {
+    /// `linux`, `seatbelt` or `none`, as in `SandboxReport`
+    pub backend: String,
+    /// Mounts in the order they are made (Linux)
+    pub mounts: Vec<PlannedMount>,
+    /// cgroup v2 interface files and the values written to them (Linux)
+    pub cgroup: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seccomp: Option<SeccompPlan>,
+    /// Requested features this host cannot provide
+    pub unavailable: Vec<DegradedFeature>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedMount {
+    /// Path inside the sandbox
+    pub destination: String,
+    /// Host path of a bind mount, or the filesystem type
+    pub source: String,
+    pub readonly: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<String>,
+    /// Destination of a later mount that covers this one entirely
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hidden_by: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SeccompPlan {
+    pub preset: SeccompPreset,
+    pub mode: SeccompMode,
+    /// OCI profile that replaces the built-in allowlist
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Syscalls the built-in allowlist permits; empty when a profile is used
+    pub allowed: Vec<String>,
+    /// Syscalls handed to the supervisor for a decision
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notify: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -107,6 +1187,17 @@ impl Default for ResourceLimits {
             cpu_shares: default_cpu_shares(),
             max_output_bytes: default_max_output(),
             max_pids: default_max_pids(),
+            max_open_files: default_max_open_files(),
+            cpu_quota_percent: None,
+            cpuset: None,
+            cpuset_mems: None,
+            disk_bytes: None,
+            max_file_size_bytes: None,
+            io_read_bps: None,
+            io_write_bps: None,
+            io_read_iops: None,
+            io_write_iops: None,
+            network_bps: None,
         }
     }
 }
@@ -115,10 +1206,40 @@ impl Default for IsolationConfig {
     fn default() -> Self {
         Self {
             network: default_network(),
+            network_mode: NetworkMode::Host,
+            network_policy: None,
+            dns: None,
+            extra_hosts: HashMap::new(),
+            proxy: None,
+            track_destinations: false,
             readonly_paths: vec![],
             writable_paths: vec![],
+            base_mounts: None,
+            masked_paths: vec![],
+            tmpfs_paths: vec![],
             working_directory: default_working_directory(),
             bind_mounts: vec![],
+            unix_sockets: vec![],
+            devices: vec![],
+            caches: vec![],
+            fuse_mounts: vec![],
+            block_abstract_sockets: default_block_abstract_sockets(),
+            workspace_tmpfs: default_workspace_tmpfs(),
+            time_offset: None,
+            shm_size: None,
+            host_ipc: false,
+            umask: None,
+            workspace_mode: None,
+            workspace_uid: None,
+            workspace_gid: None,
+            seccomp: SeccompPreset::Default,
+            extra_syscalls: vec![],
+            seccomp_profile: None,
+            seccomp_mode: SeccompMode::Kill,
+            notify_syscalls: vec![],
+            macos_profile: MacOSProfile::Default,
+            macos_profile_extra: vec![],
+            enforcement: EnforcementPolicy::BestEffort,
         }
     }
 }
@@ -142,6 +1263,13 @@ impl ExecutionResponse {
             metrics: Some(metrics),
             timestamps: ExecutionTimestamps { started, completed },
             error: None,
+            denied_syscalls: None,
+            audit_trail: None,
+            degraded_features: Vec::new(),
+            warnings: Vec::new(),
+            sandbox: None,
+            artifacts: None,
+            workspace_changes: None,
         }
     }
 
@@ -160,6 +1288,13 @@ impl ExecutionResponse {
             metrics: None,
             timestamps: ExecutionTimestamps { started, completed },
             error: Some(error),
+            denied_syscalls: None,
+            audit_trail: None,
+            degraded_features: Vec::new(),
+            warnings: Vec::new(),
+            sandbox: None,
+            artifacts: None,
+            workspace_changes: None,
         }
     }
 
@@ -187,11 +1322,18 @@ impl ExecutionResponse {
             metrics: None,
             timestamps: ExecutionTimestamps { started, completed },
             error: Some(error),
+            denied_syscalls: None,
+            audit_trail: None,
+            degraded_features: Vec::new(),
+            warnings: Vec::new(),
+            sandbox: None,
+            artifacts: None,
+            workspace_changes: None,
         }
     }
 }
 
-fn default_timeout() -> u64 {
+pub fn default_timeout() -> u64 {
     30_000 // 30 seconds
 }
 
@@ -211,10 +1353,54 @@ fn default_max_pids() -> u32 {
     100 // Maximum number of processes
 }
 
+fn default_max_open_files() -> u32 {
+    1024 // Maximum open file descriptors
+}
+
 fn default_network() -> bool {
     false // Network disabled by default
 }
 
+fn default_block_abstract_sockets() -> bool {
+    true // Host services listening on abstract sockets are unreachable by default
+}
+
+fn default_workspace_tmpfs() -> bool {
+    true // The workspace vanishes with the execution's mount namespace
+}
+
 fn default_working_directory() -> String {
     "/workspace".to_string()
 }
+
+/// Permission bits are written as octal strings ("022", "0750") so they read
+/// naturally in TOML and JSON; plain integers are accepted as-is.
+mod file_mode {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ModeRepr {
+        Number(u32),
+        Octal(String),
+    }
+
+    pub fn serialize<S: Serializer>(mode: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error> {
+        match mode {
+            Some(mode) => serializer.serialize_str(&format!("{:04o}", mode)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u32>, D::Error> {
+        match Option::<ModeRepr>::deserialize(deserializer)? {
+            Some(ModeRepr::Number(mode)) => Ok(Some(mode)),
+            Some(ModeRepr::Octal(mode)) => u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+                .map(Some)
+                .map_err(|_| de::Error::custom(format!("invalid octal mode '{}'", mode))),
+            None => Ok(None),
+        }
+    }
+}
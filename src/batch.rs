@@ -0,0 +1,295 @@
+use crate::api::schema::{BatchFile, BatchJob, BatchJobResult, BatchJobStatus, BatchReport};
+use crate::error::{CapsuleError, CapsuleResult, ErrorCode};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::path::Path;
+use tokio::task::JoinSet;
+
+/// Check `batch` and chain its workspaces. A job that another takes its
+/// workspace from writes /workspace to an archive in `archive_dir`, or to
+/// its own `workspace_out`, and the other job reads that archive as its
+/// `workspace_in`.
+pub fn plan(batch: BatchFile, archive_dir: &Path) -> CapsuleResult<Vec<BatchJob>> {
+    let invalid = |reason: String| CapsuleError::Config(format!("Invalid batch: {}", reason));
+    let mut jobs = batch.jobs;
+    if jobs.is_empty() {
+        return Err(invalid("it has no jobs".to_string()));
+    }
+
+    let mut ids = HashSet::new();
+    for job in &jobs {
+        let valid = !job.id.is_empty()
+            && job
+                .id
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_');
+        if !valid {
+            return Err(invalid(format!(
+                "job id '{}' may only have letters, digits, '-' and '_'",
+                job.id
+            )));
+        }
+        if !ids.insert(job.id.clone()) {
+            return Err(invalid(format!("job id {} is used twice", job.id)));
+        }
+        if !job.request.is_object() {
+            return Err(invalid(format!("job {} has no request object", job.id)));
+        }
+    }
+    for job in &mut jobs {
+        if let Some(from) = &job.workspace_from {
+            if !job.depends_on.contains(from) {
+                job.depends_on.push(from.clone());
+            }
+        }
+        for dependency in &job.depends_on {
+            if *dependency == job.id || !ids.contains(dependency) {
+                return Err(invalid(format!(
+                    "job {} depends on {}, which is not another job",
+                    job.id, dependency
+                )));
+            }
+        }
+    }
+    if let Some(id) = in_cycle(&jobs) {
+        return Err(invalid(format!(
+            "job {} depends on itself through others",
+            id
+        )));
+    }
+
+    let index: HashMap<String, usize> = jobs
+        .iter()
+        .enumerate()
+        .map(|(i, job)| (job.id.clone(), i))
+        .collect();
+    for i in 0..jobs.len() {
+        let Some(from) = jobs[i].workspace_from.clone() else {
+            continue;
+        };
+        if jobs[i].request.get("workspace_in").is_some() {
+            return Err(invalid(format!(
+                "job {} sets both workspace_from and workspace_in",
+                jobs[i].id
+            )));
+        }
+        let source = &mut jobs[index[&from]].request;
+        let archive = match source.get("workspace_out").and_then(Value::as_str) {
+            Some(path) => path.to_string(),
+            None => {
+                let path = archive_dir.join(format!("{}.tar", from));
+                path.to_string_lossy().into_owned()
+            }
+        };
+        source["workspace_out"] = Value::from(archive.clone());
+        jobs[i].request["workspace_in"] = Value::from(archive);
+    }
+    Ok(jobs)
+}
+
+/// A job in a dependency cycle, if there is one
+fn in_cycle(jobs: &[BatchJob]) -> Option<&str> {
+    let mut done: HashSet<&str> = HashSet::new();
+    loop {
+        let ready: Vec<&str> = jobs
+            .iter()
+            .filter(|job| !done.contains(job.id.as_str()))
+            .filter(|job| job.depends_on.iter().all(|d| done.contains(d.as_str())))
+            .map(|job| job.id.as_str())
+            .collect();
+        if ready.is_empty() {
+            break;
+        }
+        done.extend(ready);
+    }
+    jobs.iter()
+        .map(|job| job.id.as_str())
+        .find(|id| !done.contains(id))
+}
+
+/// Run each of `jobs` with `launch` once the jobs it depends on have
+/// succeeded, with jobs that are ready at the same time running side by
+/// side. `launch` returns the job's execution response; a job whose
+/// dependency failed or was skipped is skipped.
+pub async fn run<F, Fut>(jobs: &[BatchJob], launch: F) -> BatchReport
+where
+    F: Fn(&BatchJob) -> Fut,
+    Fut: Future<Output = CapsuleResult<Value>> + Send + 'static,
+{
+    let mut results: HashMap<String, BatchJobResult> = HashMap::new();
+    let mut started: HashSet<&str> = HashSet::new();
+    let mut running = JoinSet::new();
+    loop {
+        let mut progress = false;
+        for job in jobs {
+            if started.contains(job.id.as_str()) {
+                continue;
+            }
+            let statuses: Vec<Option<BatchJobStatus>> = job
+                .depends_on
+                .iter()
+                .map(|dependency| results.get(dependency).map(|result| result.status))
+                .collect();
+            if let Some(i) = statuses
+                .iter()
+                .position(|status| status.is_some_and(|s| s != BatchJobStatus::Success))
+            {
+                results.insert(
+                    job.id.clone(),
+                    BatchJobResult {
+                        id: job.id.clone(),
+                        status: BatchJobStatus::Skipped,
+                        response: None,
+                        error: Some(format!("{} did not succeed", job.depends_on[i])),
+                    },
+                );
+            } else if statuses.iter().all(Option::is_some) {
+                tracing::info!(job = %job.id, "Starting batch job");
+                let id = job.id.clone();
+                let launched = launch(job);
+                running.spawn(async move { (id, launched.await) });
+            } else {
+                continue;
+            }
+            started.insert(&job.id);
+            progress = true;
+        }
+
+        match running.join_next().await {
+            Some(Ok((id, outcome))) => {
+                let result = job_result(id.clone(), outcome);
+                results.insert(id, result);
+            }
+            Some(Err(e)) => std::panic::resume_unwind(e.into_panic()),
+            // Skipping jobs can leave others to skip on the next pass
+            None if progress => {}
+            None => break,
+        }
+    }
+
+    let jobs: Vec<BatchJobResult> = jobs
+        .iter()
+        .filter_map(|job| results.remove(&job.id))
+        .collect();
+    BatchReport {
+        success: jobs.iter().all(|job| job.status == BatchJobStatus::Success),
+        jobs,
+    }
+}
+
+fn job_result(id: String, outcome: CapsuleResult<Value>) -> BatchJobResult {
+    match outcome {
+        Ok(response) => BatchJobResult {
+            id,
+            status: if response.get("status").and_then(Value::as_str) == Some("success") {
+                BatchJobStatus::Success
+            } else {
+                BatchJobStatus::Failed
+            },
+            response: Some(response),
+            error: None,
+        },
+        Err(e) => BatchJobResult {
+            id,
+            status: BatchJobStatus::Failed,
+            response: None,
+            error: Some(ErrorCode::from(e).message),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn batch(jobs: Value) -> BatchFile {
+        serde_json::from_value(json!({ "jobs": jobs })).unwrap()
+    }
+
+    #[test]
+    fn test_plan_chains_workspaces() {
+        let jobs = plan(
+            batch(json!([
+                {"id": "build", "request": {"command": ["make"]}},
+                {"id": "test", "workspace_from": "build", "request": {"command": ["make", "test"]}},
+                {"id": "package", "depends_on": ["test"], "workspace_from": "build",
+                 "request": {"command": ["make", "dist"]}},
+            ])),
+            Path::new("/run/batch"),
+        )
+        .unwrap();
+        assert_eq!(jobs[0].request["workspace_out"], "/run/batch/build.tar");
+        assert_eq!(jobs[1].request["workspace_in"], "/run/batch/build.tar");
+        assert_eq!(jobs[1].depends_on, vec!["build"]);
+        assert_eq!(jobs[2].depends_on, vec!["test", "build"]);
+
+        let refused = |jobs: Value| {
+            plan(batch(jobs), Path::new("/tmp"))
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(refused(json!([])).contains("no jobs"));
+        assert!(refused(json!([
+            {"id": "a", "depends_on": ["b"], "request": {}},
+            {"id": "b", "depends_on": ["a"], "request": {}},
+        ]))
+        .contains("depends on itself"));
+        assert!(
+            refused(json!([{"id": "a", "depends_on": ["c"], "request": {}}]))
+                .contains("not another job")
+        );
+        assert!(refused(json!([
+            {"id": "a", "request": {}},
+            {"id": "a", "request": {}},
+        ]))
+        .contains("used twice"));
+        assert!(refused(json!([{"id": "../a", "request": {}}])).contains("letters"));
+    }
+
+    #[tokio::test]
+    async fn test_run_skips_dependents_of_failures() {
+        let jobs = plan(
+            batch(json!([
+                {"id": "build", "request": {"command": ["make"]}},
+                {"id": "lint", "request": {"command": ["false"]}},
+                {"id": "test", "depends_on": ["build"], "request": {"command": ["make", "test"]}},
+                {"id": "package", "depends_on": ["test", "lint"], "request": {"command": ["make", "dist"]}},
+                {"id": "publish", "depends_on": ["package"], "request": {"command": ["upload"]}},
+            ])),
+            Path::new("/tmp"),
+        )
+        .unwrap();
+        let report = run(&jobs, |job| {
+            let status = if job.request["command"][0] == "false" {
+                "error"
+            } else {
+                "success"
+            };
+            async move { Ok(json!({ "status": status })) }
+        })
+        .await;
+
+        let statuses: Vec<(&str, BatchJobStatus)> = report
+            .jobs
+            .iter()
+            .map(|job| (job.id.as_str(), job.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("build", BatchJobStatus::Success),
+                ("lint", BatchJobStatus::Failed),
+                ("test", BatchJobStatus::Success),
+                ("package", BatchJobStatus::Skipped),
+                ("publish", BatchJobStatus::Skipped),
+            ]
+        );
+        assert!(!report.success);
+        assert_eq!(
+            report.jobs[3].error.as_deref(),
+            Some("lint did not succeed")
+        );
+    }
+}
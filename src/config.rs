@@ -1,8 +1,14 @@
-use crate::api::schema::{IsolationConfig, ResourceLimits};
+use crate::api::schema::{
+    CloneLocation, ExecutionRequest, GitSource, IsolationConfig, ResourceLimits, SeccompMode,
+    SocketMount,
+};
+use crate::api::{Ceilings, PathPolicy};
 use crate::error::CapsuleResult;
+use crate::sandbox::artifacts::glob_match;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
@@ -10,6 +16,15 @@ pub struct Config {
     pub profiles: HashMap<String, ExecutionProfile>,
     pub security: SecurityConfig,
     pub monitoring: MonitoringConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub templates: HashMap<String, RequestTemplate>,
+    #[serde(default)]
+    pub policy: PolicyConfig,
+    /// Quotas for the callers sharing this host, by tenant name
+    #[serde(default)]
+    pub tenants: HashMap<String, TenantConfig>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -26,14 +41,146 @@ pub struct ExecutionProfile {
     pub resources: Option<ResourceLimits>,
     pub isolation: Option<IsolationConfig>,
     pub environment: Option<HashMap<String, String>>,
+    /// Replaces the `[limits]` fields it sets for requests using this
+    /// profile, e.g. to give an ML profile more memory than the rest
+    pub limits: Option<LimitsConfig>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SecurityConfig {
     pub allowed_commands: Option<Vec<String>>,
     pub blocked_commands: Option<Vec<String>>,
+    /// How many executions may run at once across the capsule-run
+    /// processes sharing a state directory
     pub max_concurrent_executions: Option<u32>,
+    /// What a run does when `max_concurrent_executions` are already
+    /// running; queueing is the default
+    pub on_concurrency_limit: Option<ConcurrencyOverflow>,
+    /// How many runs may wait for a slot at once; unset is unbounded
+    pub max_queued_executions: Option<u32>,
     pub audit_log: Option<AuditConfig>,
+    /// Syscalls a request may add through `isolation.extra_syscalls`
+    pub allowed_extra_syscalls: Option<Vec<String>>,
+    /// Host Unix sockets a request may mount through `isolation.unix_sockets`
+    pub allowed_unix_sockets: Option<Vec<String>>,
+    /// Host device nodes a request may expose through `isolation.devices`
+    pub allowed_devices: Option<Vec<String>>,
+    /// Git remotes a request may fetch on the host with `source.clone =
+    /// "host"`: whole URLs, or prefixes ending in `/`
+    pub allowed_host_git_sources: Option<Vec<String>>,
+    /// Directories `isolation.seccomp_profile` must be in
+    pub allowed_seccomp_profile_dirs: Option<Vec<String>>,
+    /// Values a request may give `isolation.seccomp_mode`; unset allows only
+    /// `kill`, as `log` lets every syscall through
+    pub allowed_seccomp_modes: Option<Vec<SeccompMode>>,
+    /// Paths requests may not use, in place of the built-in list
+    pub dangerous_paths: Option<Vec<String>>,
+    /// Paths at or below these are allowed even when a dangerous path
+    /// covers them
+    pub allowed_path_prefixes: Option<Vec<String>>,
+    /// Allow paths under /home and /root
+    pub allow_home: Option<bool>,
+    /// The tenant a request naming none is counted against; without it such
+    /// requests are refused once `[tenants]` has any
+    pub default_tenant: Option<String>,
+}
+
+/// Patterns the built-in policy engine checks requests against. Patterns
+/// are regular expressions and match anywhere unless anchored.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PolicyConfig {
+    /// The command line, arguments joined by spaces, must match one of these
+    pub allowed_command_patterns: Option<Vec<String>>,
+    /// A command line matching any of these is refused
+    pub blocked_command_patterns: Option<Vec<String>>,
+    /// Environment variable names a request may set
+    pub allowed_env_keys: Option<Vec<String>>,
+    /// Environment variable names a request may not set
+    pub blocked_env_keys: Option<Vec<String>>,
+    /// Environment variables every request must set, by exact name
+    pub required_env_keys: Option<Vec<String>>,
+    /// A request may not set any variable to a value matching these
+    pub blocked_env_values: Option<Vec<String>>,
+    /// Ceiling on the request's environment, counted as `KEY=VALUE\0`
+    pub max_env_bytes: Option<usize>,
+    /// SHA-256 hashes of the only binaries `command[0]` may run, checked on
+    /// the file inside the sandbox
+    pub allowed_executable_sha256: Option<Vec<String>>,
+    /// Also ask an OPA server (`opa` feature)
+    pub opa: Option<OpaConfig>,
+    /// Also evaluate a Cedar policy set (`cedar` feature)
+    pub cedar: Option<CedarConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OpaConfig {
+    /// Data API URL of the rule that decides, e.g.
+    /// `http://127.0.0.1:8181/v1/data/capsule/allow`
+    pub url: String,
+    /// How long to wait for a decision before denying
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CedarConfig {
+    /// File holding the policy set
+    pub policies: String,
+    /// Cedar JSON file of extra entities, such as group hierarchies
+    pub entities: Option<String>,
+}
+
+/// A request defined in the config and run by name with `run-template`, so
+/// common invocations are written and reviewed once.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestTemplate {
+    pub description: Option<String>,
+    /// Values for placeholders the caller does not set
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+    /// Fields of a JSON request; any string in it may hold `${NAME}`
+    pub request: Value,
+}
+
+/// A caller sharing the host and the caps on what it may use. Runs are
+/// counted against a tenant when the request names it in `tenant` or
+/// presents its token in `CAPSULE_TENANT_TOKEN`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TenantConfig {
+    /// SHA-256, in hex, of the token identifying the tenant; when set, a
+    /// request naming the tenant must present the token
+    pub token_sha256: Option<String>,
+    /// How many of the tenant's runs may run at once
+    pub max_concurrent_executions: Option<u32>,
+    /// CPU time the tenant's runs may use over any hour
+    pub cpu_seconds_per_hour: Option<u64>,
+    /// The most memory one of the tenant's runs may ask for
+    pub max_memory_bytes: Option<u64>,
+}
+
+/// Ceilings on every request, whatever its environment or flags ask for.
+/// The size ceilings replace the built-in 2 GB memory, 10 minute timeout
+/// and 10 MB output maximums, so they can also raise them.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct LimitsConfig {
+    pub max_memory_bytes: Option<u64>,
+    pub max_timeout_ms: Option<u64>,
+    pub max_output_bytes: Option<usize>,
+    /// Host directories that `readonly_paths`, `writable_paths`,
+    /// `base_mounts` and bind mount sources must be within
+    pub allowed_bind_prefixes: Option<Vec<String>>,
+    /// Whether a request may enable network access at all
+    pub allow_network: Option<bool>,
+}
+
+/// What a run does when the concurrency limit is reached
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConcurrencyOverflow {
+    /// Wait for a running execution to finish
+    #[default]
+    Queue,
+    /// Fail at once with E4006
+    Reject,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -41,6 +188,10 @@ pub struct AuditConfig {
     pub enabled: bool,
     pub log_file: Option<String>,
     pub log_level: String,
+    /// Chain each record to the one before it by hash, so edits and
+    /// deletions show up in `capsule-run audit verify`
+    #[serde(default)]
+    pub hash_chain: bool,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -82,17 +233,39 @@ impl Default for Config {
                     "chown".to_string(),
                 ]),
                 max_concurrent_executions: Some(10),
+                on_concurrency_limit: None,
+                max_queued_executions: None,
                 audit_log: Some(AuditConfig {
                     enabled: false,
                     log_file: None,
                     log_level: "info".to_string(),
+                    hash_chain: false,
                 }),
+                allowed_extra_syscalls: Some(vec![
+                    "membarrier".to_string(),
+                    "io_uring_setup".to_string(),
+                    "io_uring_enter".to_string(),
+                    "io_uring_register".to_string(),
+                ]),
+                allowed_unix_sockets: None,
+                allowed_devices: None,
+                dangerous_paths: None,
+                allowed_path_prefixes: None,
+                allow_home: None,
+                default_tenant: None,
+                allowed_host_git_sources: None,
+                allowed_seccomp_profile_dirs: None,
+                allowed_seccomp_modes: None,
             },
             monitoring: MonitoringConfig {
                 enabled: true,
                 interval_ms: 100,
                 metrics_export: None,
             },
+            limits: LimitsConfig::default(),
+            templates: HashMap::new(),
+            tenants: HashMap::new(),
+            policy: PolicyConfig::default(),
         }
     }
 }
@@ -101,24 +274,240 @@ impl Config {
     pub fn load_from_file(path: &Path) -> CapsuleResult<Self> {
         let content = std::fs::read_to_string(path)?;
 
-        // Support multiple formats
-        if path.extension().and_then(|s| s.to_str()) == Some("json") {
-            Ok(serde_json::from_str(&content)?)
-        } else {
-            // Default to TOML
-            Ok(toml::from_str(&content).map_err(|e| {
-                crate::error::CapsuleError::Config(format!("Failed to parse TOML config: {}", e))
-            })?)
+        Ok(Self::load_layers(path, &content)?.0)
+    }
+
+    /// The config in `content`, read from `path`, with any files it names in
+    /// `include` layered under it; also returns the merged settings as read.
+    fn load_layers(path: &Path, content: &str) -> CapsuleResult<(Self, Value)> {
+        let raw: Value = ConfigFormat::of(path).parse(content)?;
+        if raw.get("include").is_none() {
+            // Parsed directly so type errors keep their line and column
+            return Ok((Self::parse(path, content)?, raw));
+        }
+
+        let layered = Self::with_includes(path, raw, &mut Vec::new())?;
+        let config = serde_json::from_value(layered.clone()).map_err(|e| {
+            crate::error::CapsuleError::Config(format!(
+                "Failed to load {} with its includes: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Ok((config, layered))
+    }
+
+    /// `raw`, read from `path`, over the files it includes, in order; each
+    /// included file may itself include others. `chain` holds the files
+    /// being included from, to catch cycles.
+    fn with_includes(
+        path: &Path,
+        mut raw: Value,
+        chain: &mut Vec<PathBuf>,
+    ) -> CapsuleResult<Value> {
+        let Some(include) = raw.as_object_mut().and_then(|raw| raw.remove("include")) else {
+            return Ok(raw);
+        };
+        let failed = |file: &Path, e: String| {
+            crate::error::CapsuleError::Config(format!(
+                "Failed to include {}: {}",
+                file.display(),
+                e
+            ))
+        };
+
+        chain.push(path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
+        let mut layered = Value::Object(Default::default());
+        for file in include_files(path, &include)? {
+            let canonical = file
+                .canonicalize()
+                .map_err(|e| failed(&file, e.to_string()))?;
+            if chain.contains(&canonical) {
+                return Err(failed(&file, "include cycle".to_string()));
+            }
+            let content =
+                std::fs::read_to_string(&file).map_err(|e| failed(&file, e.to_string()))?;
+            let included = ConfigFormat::of(&file)
+                .parse(&content)
+                .map_err(|e| failed(&file, e.to_string()))?;
+            merge_json(&mut layered, Self::with_includes(&file, included, chain)?);
+        }
+        chain.pop();
+
+        merge_json(&mut layered, raw);
+        Ok(layered)
+    }
+
+    /// Parse `content` in the format of `path`'s extension: JSON for
+    /// `.json`, YAML for `.yaml` and `.yml`, TOML otherwise. Errors carry
+    /// the line and column.
+    pub fn parse(path: &Path, content: &str) -> CapsuleResult<Self> {
+        ConfigFormat::of(path).parse(content)
+    }
+
+    /// Check a config file: syntax and types, keys capsule-run does not
+    /// know, and whether the defaults and every profile make valid requests.
+    pub fn check_file(path: &Path) -> CapsuleResult<ConfigReport> {
+        let content = std::fs::read_to_string(path)?;
+        let mut report = ConfigReport::default();
+        let (config, raw) = match Self::load_layers(path, &content) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                report.errors.push(e.to_string());
+                return Ok(report);
+            }
+        };
+
+        let mut unknown = Vec::new();
+        unknown_keys(&raw, &serde_json::to_value(&config)?, "", &mut unknown);
+        report.warnings.extend(
+            unknown
+                .into_iter()
+                .map(|key| format!("Unknown key {}", key)),
+        );
+
+        if let (Some(allowed), Some(blocked)) = (
+            &config.security.allowed_commands,
+            &config.security.blocked_commands,
+        ) {
+            for command in allowed.iter().filter(|command| blocked.contains(command)) {
+                report.warnings.push(format!(
+                    "Command {} is both allowed and blocked; blocking wins",
+                    command
+                ));
+            }
+        }
+
+        let layers = config.check_layers();
+        report.errors.extend(layers.errors);
+        report.warnings.extend(layers.warnings);
+        if let Err(e) = crate::policy::from_config(&config) {
+            report.errors.push(e.to_string());
+        }
+        if let Err(e) = crate::policy::ExecutableAllowlist::from_config(&config) {
+            report.errors.push(e.to_string());
+        }
+        let mut tenants: Vec<&String> = config.tenants.keys().collect();
+        tenants.sort();
+        for name in tenants {
+            if let Err(e) = crate::executor::tenants::check_name(name) {
+                report.errors.push(e.to_string());
+            }
+            let token = config.tenants[name].token_sha256.as_deref();
+            if token.is_some_and(|hash| {
+                hash.len() != 64 || !hash.bytes().all(|byte| byte.is_ascii_hexdigit())
+            }) {
+                report.errors.push(format!(
+                    "tenants.{}.token_sha256 is not a hex SHA-256",
+                    name
+                ));
+            }
+        }
+        if let Some(name) = &config.security.default_tenant {
+            if !config.tenants.contains_key(name) {
+                report.errors.push(format!(
+                    "security.default_tenant {} is not in [tenants]",
+                    name
+                ));
+            }
+        }
+        if config.security.max_concurrent_executions == Some(0) {
+            report
+                .errors
+                .push("security.max_concurrent_executions must be at least 1".to_string());
+        }
+        if let Some(audit) = &config.security.audit_log {
+            if audit.enabled && audit.log_file.is_none() {
+                report
+                    .errors
+                    .push("security.audit_log is enabled but has no log_file".to_string());
+            }
+        }
+        Ok(report)
+    }
+
+    /// Why `[defaults]` or a profile would not make a valid request, and
+    /// what it would be warned about
+    fn check_layers(&self) -> ConfigReport {
+        let mut profiles: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+        profiles.sort();
+        let mut report = ConfigReport::default();
+        for profile in std::iter::once(None).chain(profiles.into_iter().map(Some)) {
+            let layer = match profile {
+                Some(name) => format!("profile {}", name),
+                None => "defaults".to_string(),
+            };
+            match self.check_layer(profile) {
+                Ok(warnings) => report.warnings.extend(
+                    warnings
+                        .into_iter()
+                        .map(|warning| format!("{}: {}", layer, warning)),
+                ),
+                Err(e) => report.errors.push(format!("{}: {}", layer, e)),
+            }
+        }
+        report
+    }
+
+    /// Whether a request with nothing but a command is valid under
+    /// `profile`, and its warnings if so
+    fn check_layer(&self, profile: Option<&str>) -> CapsuleResult<Vec<String>> {
+        let mut request =
+            self.apply_defaults(profile, serde_json::json!({ "command": ["true"] }))?;
+        request.profile = profile.map(String::from);
+        let warnings = crate::api::validate_execution_request_with(
+            &request,
+            &self.path_policy(),
+            &self.ceilings(profile),
+        )?;
+        self.validate_host_access(&request)?;
+        Ok(warnings)
+    }
+
+    /// The paths requests may use under `[security]`
+    pub fn path_policy(&self) -> PathPolicy {
+        let security = &self.security;
+        let mut paths = PathPolicy::default();
+        if let Some(dangerous) = &security.dangerous_paths {
+            paths.dangerous = dangerous.clone();
+        }
+        paths.allowed_prefixes = security.allowed_path_prefixes.clone().unwrap_or_default();
+        if security.allow_home == Some(true) {
+            paths
+                .allowed_prefixes
+                .extend(["/home".to_string(), "/root".to_string()]);
         }
+        paths
+    }
+
+    /// Check what a request takes from the host against the `security`
+    /// allowlists, and its size against `[limits]`
+    pub fn validate_host_access(&self, request: &ExecutionRequest) -> CapsuleResult<()> {
+        let isolation = &request.isolation;
+        self.validate_extra_syscalls(&isolation.extra_syscalls)?;
+        self.validate_seccomp_mode(isolation.seccomp_mode)?;
+        self.validate_seccomp_profile(isolation)?;
+        self.validate_unix_sockets(&isolation.unix_sockets)?;
+        self.validate_devices(&isolation.devices)?;
+        if !isolation.fuse_mounts.is_empty() {
+            self.validate_devices(&["/dev/fuse".to_string()])?;
+        }
+        if let Some(source) = &request.source {
+            self.validate_git_source(source)?;
+        }
+        self.validate_limits(request)
     }
 
     pub fn save_to_file(&self, path: &Path) -> CapsuleResult<()> {
-        let content = if path.extension().and_then(|s| s.to_str()) == Some("json") {
-            serde_json::to_string_pretty(self)?
-        } else {
-            toml::to_string_pretty(self).map_err(|e| {
-                crate::error::CapsuleError::Config(format!("Failed to serialize config: {}", e))
-            })?
+        let failed = |e: String| {
+            crate::error::CapsuleError::Config(format!("Failed to serialize config: {}", e))
+        };
+        let content = match ConfigFormat::of(path) {
+            ConfigFormat::Json => serde_json::to_string_pretty(self)?,
+            ConfigFormat::Yaml => serde_yaml::to_string(self).map_err(|e| failed(e.to_string()))?,
+            ConfigFormat::Toml => {
+                toml::to_string_pretty(self).map_err(|e| failed(e.to_string()))?
+            }
         };
 
         std::fs::write(path, content)?;
@@ -129,80 +518,733 @@ impl Config {
         self.profiles.get(name)
     }
 
-    pub fn merge_with_profile(&self, profile_name: Option<&str>) -> Self {
-        let mut config = self.clone();
-
-        if let Some(profile_name) = profile_name {
-            if let Some(profile) = self.get_profile(profile_name) {
-                // Merge profile settings with defaults
-                if let Some(timeout) = profile.timeout_ms {
-                    config.defaults.timeout_ms = timeout;
-                }
+    /// The settings a request starts from: built-in defaults, then
+    /// `[defaults]`, then the named profile, then `CAPSULE_*` environment
+    /// variables. Config layers only override what they set to something
+    /// other than the built-in default; a variable that is set always does.
+    pub fn request_defaults(&self, profile: Option<&str>) -> CapsuleResult<Value> {
+        self.layered_defaults(profile, &std::env::vars().collect())
+    }
 
-                if let Some(resources) = &profile.resources {
-                    config.defaults.resources = resources.clone();
-                }
+    fn layered_defaults(
+        &self,
+        profile: Option<&str>,
+        vars: &HashMap<String, String>,
+    ) -> CapsuleResult<Value> {
+        let builtin = serde_json::to_value(DefaultConfig {
+            timeout_ms: 30_000,
+            resources: ResourceLimits::default(),
+            isolation: IsolationConfig::default(),
+        })?;
+        let mut layered = builtin.clone();
+        merge_json(
+            &mut layered,
+            changed_fields(serde_json::to_value(&self.defaults)?, &builtin),
+        );
 
-                if let Some(isolation) = &profile.isolation {
-                    config.defaults.isolation = isolation.clone();
-                }
-            }
+        if let Some(name) = profile {
+            let profile = self.get_profile(name).ok_or_else(|| {
+                crate::error::CapsuleError::Config(format!("Unknown profile: {}", name))
+            })?;
+            let layer = serde_json::json!({
+                "timeout_ms": profile.timeout_ms,
+                "resources": profile.resources,
+                "isolation": profile.isolation,
+                "environment": profile.environment.as_ref().map(|environment| {
+                    environment
+                        .iter()
+                        .map(|(name, value)| {
+                            (name.clone(), expand_vars(value, &mut |var| vars.get(var).cloned()))
+                        })
+                        .collect::<HashMap<_, _>>()
+                }),
+            });
+            merge_json(&mut layered, changed_fields(layer, &builtin));
         }
+        merge_json(&mut layered, env_overrides(vars)?);
+        Ok(layered)
+    }
 
-        config
+    /// `request` over `request_defaults`: whatever the request gives wins,
+    /// and environment variables are merged by name.
+    pub fn apply_defaults(
+        &self,
+        profile: Option<&str>,
+        request: Value,
+    ) -> CapsuleResult<ExecutionRequest> {
+        let mut merged = self.request_defaults(profile)?;
+        merge_json(&mut merged, request);
+        Ok(serde_json::from_value(merged)?)
     }
 
+    /// Check the program against `security.blocked_commands` and
+    /// `security.allowed_commands`, by file name, so `/usr/bin/rm` is `rm`
+    /// and blocking `at` leaves `cat` alone. An empty allowed list allows
+    /// everything not blocked.
     pub fn validate_command(&self, command: &[String]) -> bool {
         if command.is_empty() {
             return false;
         }
 
-        let command_name = &command[0];
+        let command_name = Path::new(&command[0])
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(&command[0]);
 
         // Check blocked commands first
         if let Some(blocked) = &self.security.blocked_commands {
             if blocked
                 .iter()
-                .any(|blocked_cmd| command_name.contains(blocked_cmd))
+                .any(|blocked_cmd| command_name == blocked_cmd)
             {
                 return false;
             }
         }
 
         // Check allowed commands if specified
-        if let Some(allowed) = &self.security.allowed_commands {
+        if let Some(allowed) = self
+            .security
+            .allowed_commands
+            .as_ref()
+            .filter(|allowed| !allowed.is_empty())
+        {
             return allowed
                 .iter()
-                .any(|allowed_cmd| command_name.contains(allowed_cmd));
+                .any(|allowed_cmd| command_name == allowed_cmd);
         }
 
         // If no allowed list is specified, allow by default (after blocked check)
         true
     }
+
+    /// Check a request's extra syscalls against `security.allowed_extra_syscalls`
+    pub fn validate_extra_syscalls(&self, syscalls: &[String]) -> CapsuleResult<()> {
+        let allowed = self
+            .security
+            .allowed_extra_syscalls
+            .as_deref()
+            .unwrap_or_default();
+
+        let denied: Vec<&str> = syscalls
+            .iter()
+            .filter(|name| !allowed.contains(name))
+            .map(String::as_str)
+            .collect();
+        if denied.is_empty() {
+            return Ok(());
+        }
+
+        Err(crate::error::CapsuleError::Security(format!(
+            "Extra syscalls not allowed by security policy: {}",
+            denied.join(", ")
+        )))
+    }
+
+    /// Check a request's seccomp mode against `security.allowed_seccomp_modes`
+    pub fn validate_seccomp_mode(&self, mode: SeccompMode) -> CapsuleResult<()> {
+        let allowed = match &self.security.allowed_seccomp_modes {
+            Some(modes) => modes.contains(&mode),
+            None => mode == SeccompMode::Kill,
+        };
+        if allowed {
+            return Ok(());
+        }
+
+        Err(crate::error::CapsuleError::Security(format!(
+            "Seccomp mode not allowed by security policy: {}",
+            serde_json::to_value(mode)?.as_str().unwrap_or_default()
+        )))
+    }
+
+    /// Check a request's seccomp profile against
+    /// `security.allowed_seccomp_profile_dirs`, and the syscalls it allows
+    /// beyond the built-in allowlist against `security.allowed_extra_syscalls`
+    pub fn validate_seccomp_profile(&self, isolation: &IsolationConfig) -> CapsuleResult<()> {
+        #[cfg(all(target_os = "linux", feature = "seccomp"))]
+        self.load_seccomp_profile(isolation)?;
+        #[cfg(not(all(target_os = "linux", feature = "seccomp")))]
+        if let Some(profile) = &isolation.seccomp_profile {
+            self.seccomp_profile_path(profile)?;
+        }
+        Ok(())
+    }
+
+    /// A request's seccomp profile, read once and checked as in
+    /// `validate_seccomp_profile`. The sandbox installs this copy, so the
+    /// file changing after the check has no effect.
+    #[cfg(all(target_os = "linux", feature = "seccomp"))]
+    pub fn load_seccomp_profile(
+        &self,
+        isolation: &IsolationConfig,
+    ) -> CapsuleResult<Option<crate::sandbox::seccomp_profile::OciSeccompProfile>> {
+        use crate::sandbox::seccomp::allowed_syscalls;
+        use crate::sandbox::seccomp_profile::OciSeccompProfile;
+
+        let Some(profile) = &isolation.seccomp_profile else {
+            return Ok(None);
+        };
+        let loaded = OciSeccompProfile::load(&self.seccomp_profile_path(profile)?)?;
+        let allowed = loaded.allowed_names().ok_or_else(|| {
+            crate::error::CapsuleError::Security(format!(
+                "Seccomp profile {} allows every syscall by default",
+                profile
+            ))
+        })?;
+        let builtin = allowed_syscalls(
+            crate::api::schema::SeccompPreset::Default,
+            &[],
+            isolation.network,
+            !isolation.notify_syscalls.is_empty(),
+        );
+        let extra: Vec<String> = allowed
+            .into_iter()
+            .filter(|name| !builtin.iter().any(|allowed| allowed == name))
+            .map(str::to_string)
+            .collect();
+        self.validate_extra_syscalls(&extra)?;
+        Ok(Some(loaded))
+    }
+
+    /// `profile` resolved, so neither `..` nor a symlink leads out of the
+    /// directory, if it is in one of `security.allowed_seccomp_profile_dirs`
+    fn seccomp_profile_path(&self, profile: &str) -> CapsuleResult<PathBuf> {
+        let denied = || {
+            crate::error::CapsuleError::Security(format!(
+                "Seccomp profile not allowed by security policy: {}",
+                profile
+            ))
+        };
+        let path = Path::new(profile).canonicalize().map_err(|_| denied())?;
+        let in_allowed_dir = self
+            .security
+            .allowed_seccomp_profile_dirs
+            .as_deref()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|dir| Path::new(dir).canonicalize().ok())
+            .any(|dir| path.starts_with(dir));
+        if !in_allowed_dir {
+            return Err(denied());
+        }
+        Ok(path)
+    }
+
+    /// Check a request's socket mounts against `security.allowed_unix_sockets`
+    pub fn validate_unix_sockets(&self, sockets: &[SocketMount]) -> CapsuleResult<()> {
+        let allowed = self
+            .security
+            .allowed_unix_sockets
+            .as_deref()
+            .unwrap_or_default();
+
+        let denied: Vec<&str> = sockets
+            .iter()
+            .map(|socket| socket.source.as_str())
+            .filter(|source| !allowed.iter().any(|path| path == source))
+            .collect();
+        if denied.is_empty() {
+            return Ok(());
+        }
+
+        Err(crate::error::CapsuleError::Security(format!(
+            "Unix sockets not allowed by security policy: {}",
+            denied.join(", ")
+        )))
+    }
+
+    /// Check a request's devices against `security.allowed_devices`
+    pub fn validate_devices(&self, devices: &[String]) -> CapsuleResult<()> {
+        let allowed = self.security.allowed_devices.as_deref().unwrap_or_default();
+
+        let denied: Vec<&str> = devices
+            .iter()
+            .map(String::as_str)
+            .filter(|device| !allowed.iter().any(|path| path == device))
+            .collect();
+        if denied.is_empty() {
+            return Ok(());
+        }
+
+        Err(crate::error::CapsuleError::Security(format!(
+            "Devices not allowed by security policy: {}",
+            denied.join(", ")
+        )))
+    }
+
+    /// Check a git source fetched on the host, with the host's network and
+    /// credentials, against `security.allowed_host_git_sources`. On macOS
+    /// every clone runs on the host.
+    pub fn validate_git_source(&self, source: &GitSource) -> CapsuleResult<()> {
+        if source.clone != CloneLocation::Host && !cfg!(target_os = "macos") {
+            return Ok(());
+        }
+        let allowed = self
+            .security
+            .allowed_host_git_sources
+            .as_deref()
+            .unwrap_or_default();
+        let covered = allowed.iter().any(|remote| {
+            *remote == source.git || (remote.ends_with('/') && source.git.starts_with(remote))
+        });
+        if covered {
+            return Ok(());
+        }
+
+        Err(crate::error::CapsuleError::Security(format!(
+            "Cloning {} on the host is not allowed by security policy",
+            source.git
+        )))
+    }
+
+    /// Template `name`'s request, with placeholders filled from `vars` or
+    /// else the template's own `vars`. A placeholder with neither, or a
+    /// variable the template has no placeholder for, is an error.
+    pub fn template_request(
+        &self,
+        name: &str,
+        vars: &HashMap<String, String>,
+    ) -> CapsuleResult<Value> {
+        let template = self.templates.get(name).ok_or_else(|| {
+            crate::error::CapsuleError::Config(format!("Unknown template: {}", name))
+        })?;
+
+        let mut used = std::collections::BTreeSet::new();
+        let mut missing = std::collections::BTreeSet::new();
+        let request = fill_placeholders(template.request.clone(), &mut |var| {
+            used.insert(var.to_string());
+            let value = vars.get(var).or_else(|| template.vars.get(var)).cloned();
+            if value.is_none() {
+                missing.insert(var.to_string());
+            }
+            value
+        });
+        if !missing.is_empty() {
+            return Err(crate::error::CapsuleError::Config(format!(
+                "Template {} needs --var for: {}",
+                name,
+                missing.into_iter().collect::<Vec<_>>().join(", ")
+            )));
+        }
+        let mut unused: Vec<&str> = vars
+            .keys()
+            .map(String::as_str)
+            .filter(|var| !used.contains(*var))
+            .collect();
+        if !unused.is_empty() {
+            unused.sort();
+            return Err(crate::error::CapsuleError::Config(format!(
+                "Template {} has no placeholder for: {}",
+                name,
+                unused.join(", ")
+            )));
+        }
+        Ok(request)
+    }
+
+    /// `[limits]`, with the fields `profile`'s own `limits` set in their
+    /// place
+    pub fn limits_for(&self, profile: Option<&str>) -> LimitsConfig {
+        let mut limits = self.limits.clone();
+        let Some(overrides) = profile
+            .and_then(|name| self.get_profile(name))
+            .and_then(|profile| profile.limits.clone())
+        else {
+            return limits;
+        };
+        limits.max_memory_bytes = overrides.max_memory_bytes.or(limits.max_memory_bytes);
+        limits.max_timeout_ms = overrides.max_timeout_ms.or(limits.max_timeout_ms);
+        limits.max_output_bytes = overrides.max_output_bytes.or(limits.max_output_bytes);
+        limits.allowed_bind_prefixes = overrides
+            .allowed_bind_prefixes
+            .or(limits.allowed_bind_prefixes);
+        limits.allow_network = overrides.allow_network.or(limits.allow_network);
+        limits
+    }
+
+    /// The largest sizes a request under `profile` passes validation with
+    pub fn ceilings(&self, profile: Option<&str>) -> Ceilings {
+        let limits = self.limits_for(profile);
+        let builtin = Ceilings::default();
+        Ceilings {
+            memory_bytes: limits.max_memory_bytes.unwrap_or(builtin.memory_bytes),
+            timeout_ms: limits.max_timeout_ms.unwrap_or(builtin.timeout_ms),
+            output_bytes: limits.max_output_bytes.unwrap_or(builtin.output_bytes),
+        }
+    }
+
+    /// Check a request against the `[limits]` ceilings of its profile
+    pub fn validate_limits(&self, request: &ExecutionRequest) -> CapsuleResult<()> {
+        let limits = &self.limits_for(request.profile.as_deref());
+        let mut exceeded = Vec::new();
+
+        if let Some(max) = limits.max_memory_bytes {
+            if request.resources.memory_bytes > max {
+                exceeded.push(format!(
+                    "memory {} bytes (max: {})",
+                    request.resources.memory_bytes, max
+                ));
+            }
+        }
+        if let Some(max) = limits.max_timeout_ms {
+            if request.timeout_ms > max {
+                exceeded.push(format!("timeout {}ms (max: {})", request.timeout_ms, max));
+            }
+        }
+        if let Some(max) = limits.max_output_bytes {
+            if request.resources.max_output_bytes > max {
+                exceeded.push(format!(
+                    "output {} bytes (max: {})",
+                    request.resources.max_output_bytes, max
+                ));
+            }
+        }
+        if limits.allow_network == Some(false) && request.isolation.network {
+            exceeded.push("network access".to_string());
+        }
+        if let Some(prefixes) = &limits.allowed_bind_prefixes {
+            // Resolved, so neither `..` nor a symlink leads out of a prefix;
+            // a path that cannot be resolved is in none
+            let prefixes: Vec<PathBuf> = prefixes
+                .iter()
+                .filter_map(|prefix| Path::new(prefix).canonicalize().ok())
+                .collect();
+            let isolation = &request.isolation;
+            let host_paths = isolation
+                .readonly_paths
+                .iter()
+                .chain(&isolation.writable_paths)
+                .chain(isolation.base_mounts.iter().flatten())
+                .chain(isolation.bind_mounts.iter().map(|bind| &bind.source));
+            for path in host_paths {
+                let within = Path::new(path)
+                    .canonicalize()
+                    .is_ok_and(|path| prefixes.iter().any(|prefix| path.starts_with(prefix)));
+                if !within {
+                    exceeded.push(format!("host path {}", path));
+                }
+            }
+        }
+
+        if exceeded.is_empty() {
+            return Ok(());
+        }
+        Err(crate::error::CapsuleError::Security(format!(
+            "Request exceeds configured limits: {}",
+            exceeded.join(", ")
+        )))
+    }
 }
 
-pub fn load_config() -> CapsuleResult<Config> {
-    // Try to load config from various locations
-    let config_paths = [
+/// `CAPSULE_*` variables that override request defaults, and the setting
+/// each one sets
+const ENV_OVERRIDES: [(&str, &str); 10] = [
+    ("CAPSULE_TIMEOUT_MS", "timeout_ms"),
+    ("CAPSULE_MEMORY", "resources.memory_bytes"),
+    ("CAPSULE_CPU_SHARES", "resources.cpu_shares"),
+    ("CAPSULE_CPU_QUOTA", "resources.cpu_quota_percent"),
+    ("CAPSULE_MAX_PIDS", "resources.max_pids"),
+    ("CAPSULE_MAX_OPEN_FILES", "resources.max_open_files"),
+    ("CAPSULE_MAX_OUTPUT", "resources.max_output_bytes"),
+    ("CAPSULE_DISK", "resources.disk_bytes"),
+    ("CAPSULE_NETWORK", "isolation.network"),
+    ("CAPSULE_WORKDIR", "isolation.working_directory"),
+];
+
+/// The request defaults set by `CAPSULE_*` variables in `vars`
+pub fn env_overrides(vars: &HashMap<String, String>) -> CapsuleResult<Value> {
+    let mut layer = Value::Object(Default::default());
+    for (name, setting) in ENV_OVERRIDES {
+        let Some(raw) = vars.get(name) else {
+            continue;
+        };
+        let invalid = || crate::error::CapsuleError::Config(format!("Invalid {}: {}", name, raw));
+        let value = match setting {
+            "resources.memory_bytes" | "resources.max_output_bytes" | "resources.disk_bytes" => {
+                Value::from(parse_size(raw).map_err(|_| invalid())?)
+            }
+            "isolation.network" => match raw.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => Value::Bool(true),
+                "0" | "false" | "no" | "off" => Value::Bool(false),
+                _ => return Err(invalid()),
+            },
+            "isolation.working_directory" => Value::from(raw.as_str()),
+            _ => Value::from(raw.trim().parse::<u64>().map_err(|_| invalid())?),
+        };
+
+        let mut nested = value;
+        for key in setting.rsplit('.') {
+            nested = serde_json::json!({ key: nested });
+        }
+        merge_json(&mut layer, nested);
+    }
+    Ok(layer)
+}
+
+/// Replace each `${NAME}` in `value` with `lookup(NAME)`; names it has no
+/// value for expand to nothing. Profiles use it to extend variables such as
+/// PATH from the environment the command inherits.
+fn expand_vars(value: &str, lookup: &mut impl FnMut(&str) -> Option<String>) -> String {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start + 2..].find('}') else {
+            break;
+        };
+        expanded.push_str(&rest[..start]);
+        let name = &rest[start + 2..start + 2 + len];
+        expanded.push_str(&lookup(name).unwrap_or_default());
+        rest = &rest[start + 3 + len..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// `value` with `${NAME}` filled in every string it holds
+fn fill_placeholders(value: Value, lookup: &mut impl FnMut(&str) -> Option<String>) -> Value {
+    match value {
+        Value::String(s) => Value::String(expand_vars(&s, lookup)),
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| fill_placeholders(item, lookup))
+                .collect(),
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, field)| (key, fill_placeholders(field, lookup)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+/// Problems found by `Config::check_file`; any error makes the config unusable.
+#[derive(Debug, Default)]
+pub struct ConfigReport {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// The files an `include` list in `path` names, relative to `path`'s
+/// directory. A file name with `*` or `?` matches every file in that
+/// directory it fits, in name order; one without must exist.
+fn include_files(path: &Path, include: &Value) -> CapsuleResult<Vec<PathBuf>> {
+    let invalid = || {
+        crate::error::CapsuleError::Config(format!(
+            "Invalid include in {}: expected a list of paths",
+            path.display()
+        ))
+    };
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let mut files = Vec::new();
+    for pattern in include.as_array().ok_or_else(invalid)? {
+        let pattern = base.join(pattern.as_str().ok_or_else(invalid)?);
+        let name = pattern
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if !name.contains(['*', '?']) {
+            files.push(pattern);
+            continue;
+        }
+
+        let dir = match pattern.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let entries = std::fs::read_dir(dir).map_err(|e| {
+            crate::error::CapsuleError::Config(format!(
+                "Failed to include {}: {}",
+                pattern.display(),
+                e
+            ))
+        })?;
+        let mut matched: Vec<PathBuf> = entries
+            .flatten()
+            .filter(|entry| glob_match(&name, &entry.file_name().to_string_lossy()))
+            .map(|entry| entry.path())
+            .filter(|file| file.is_file())
+            .collect();
+        matched.sort();
+        files.extend(matched);
+    }
+    Ok(files)
+}
+
+/// Config file formats, chosen by extension
+enum ConfigFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl ConfigFormat {
+    fn of(path: &Path) -> Self {
+        match path.extension().and_then(|s| s.to_str()) {
+            Some("json") => Self::Json,
+            Some("yaml" | "yml") => Self::Yaml,
+            _ => Self::Toml,
+        }
+    }
+
+    fn parse<T: serde::de::DeserializeOwned>(&self, content: &str) -> CapsuleResult<T> {
+        let (format, parsed) = match self {
+            Self::Json => (
+                "JSON",
+                serde_json::from_str(content).map_err(|e| e.to_string()),
+            ),
+            Self::Yaml => (
+                "YAML",
+                serde_yaml::from_str(content).map_err(|e| e.to_string()),
+            ),
+            Self::Toml => ("TOML", toml::from_str(content).map_err(|e| e.to_string())),
+        };
+        parsed.map_err(|e| {
+            crate::error::CapsuleError::Config(format!("Failed to parse {} config: {}", format, e))
+        })
+    }
+}
+
+/// Dotted paths of keys in `raw` that did not survive deserializing into
+/// `known`, which serde would otherwise drop without a word
+fn unknown_keys(raw: &Value, known: &Value, prefix: &str, unknown: &mut Vec<String>) {
+    let (Value::Object(raw), Value::Object(known)) = (raw, known) else {
+        return;
+    };
+    for (key, value) in raw {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match known.get(key) {
+            Some(known) => unknown_keys(value, known, &path, unknown),
+            None => unknown.push(path),
+        }
+    }
+}
+
+/// Layer `overlay` onto `base`: objects merge key by key, anything else in
+/// `overlay` replaces what is in `base`.
+pub fn merge_json(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_json(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// What a typed layer sets: the fields of `value` that differ from
+/// `default`, recursing into objects. Nulls never override anything.
+pub fn changed_fields(value: Value, default: &Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .filter_map(|(key, value)| match default.get(&key) {
+                    Some(default) if *default == value => None,
+                    Some(default) if value.is_object() => {
+                        Some((key, changed_fields(value, default)))
+                    }
+                    _ => Some((key, value)),
+                })
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// Parse a size such as `512`, `64K`, `256M` or `2G` (binary units)
+pub fn parse_size(size_str: &str) -> CapsuleResult<u64> {
+    let size_str = size_str.trim().to_uppercase();
+
+    if let Some(number_part) = size_str.strip_suffix('K') {
+        let number: u64 = number_part.parse().map_err(|_| {
+            crate::error::CapsuleError::Config(format!("Invalid size format: {}", size_str))
+        })?;
+        Ok(number * 1024)
+    } else if let Some(number_part) = size_str.strip_suffix('M') {
+        let number: u64 = number_part.parse().map_err(|_| {
+            crate::error::CapsuleError::Config(format!("Invalid size format: {}", size_str))
+        })?;
+        Ok(number * 1024 * 1024)
+    } else if let Some(number_part) = size_str.strip_suffix('G') {
+        let number: u64 = number_part.parse().map_err(|_| {
+            crate::error::CapsuleError::Config(format!("Invalid size format: {}", size_str))
+        })?;
+        Ok(number * 1024 * 1024 * 1024)
+    } else {
+        // Assume bytes
+        size_str.parse().map_err(|_| {
+            crate::error::CapsuleError::Config(format!("Invalid size format: {}", size_str))
+        })
+    }
+}
+
+/// Parse a duration such as `90`, `90s`, `30m`, `1h` or `7d` (seconds by
+/// default)
+pub fn parse_duration(duration_str: &str) -> CapsuleResult<std::time::Duration> {
+    let duration_str = duration_str.trim().to_lowercase();
+    let invalid =
+        || crate::error::CapsuleError::Config(format!("Invalid duration format: {}", duration_str));
+
+    let (number_part, unit_secs) = match duration_str.char_indices().last() {
+        Some((i, 's')) => (&duration_str[..i], 1),
+        Some((i, 'm')) => (&duration_str[..i], 60),
+        Some((i, 'h')) => (&duration_str[..i], 3600),
+        Some((i, 'd')) => (&duration_str[..i], 86_400),
+        _ => (duration_str.as_str(), 1),
+    };
+    let number: u64 = number_part.parse().map_err(|_| invalid())?;
+    number
+        .checked_mul(unit_secs)
+        .map(std::time::Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+/// The config file used when none is given: the first of the usual
+/// locations that exists
+pub fn find_config_file() -> Option<PathBuf> {
+    let home_config = std::env::var_os("HOME")
+        .map(|home| Path::new(&home).join(".config/capsule-run/config.toml"));
+    [
         "capsule-run.toml",
         "capsule-run.json",
+        "capsule-run.yaml",
+        "capsule-run.yml",
         "config/capsule-run.toml",
         "config/capsule-run.json",
-        "~/.config/capsule-run/config.toml",
-        "/etc/capsule-run/config.toml",
-    ];
+        "config/capsule-run.yaml",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .chain(home_config)
+    .chain([PathBuf::from("/etc/capsule-run/config.toml")])
+    .find(|path| path.exists())
+}
 
-    for path_str in &config_paths {
-        let path = Path::new(path_str);
-        if path.exists() {
-            println!("Loading config from: {}", path.display());
-            return Config::load_from_file(path);
+pub fn load_config() -> CapsuleResult<Config> {
+    match find_config_file() {
+        Some(path) => {
+            tracing::info!(path = %path.display(), "Loading config");
+            Config::load_from_file(&path)
+        }
+        None => {
+            tracing::info!("No config file found, using defaults");
+            Ok(Config::default())
         }
     }
-
-    // If no config file found, return default config
-    println!("No config file found, using defaults");
-    Ok(Config::default())
 }
 
 pub fn create_default_config_file(path: &Path) -> CapsuleResult<()> {
@@ -225,6 +1267,17 @@ mod tests {
         assert!(config.monitoring.enabled);
     }
 
+    #[test]
+    fn test_parse_duration() {
+        use std::time::Duration;
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(1800));
+        assert_eq!(parse_duration("1H").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse_duration("7d").unwrap(), Duration::from_secs(604_800));
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("1w").is_err());
+    }
+
     #[test]
     fn test_config_serialization() {
         let config = Config::default();
@@ -239,12 +1292,140 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_yaml_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("capsule-run.yaml");
+        let mut config = Config::default();
+        config.defaults.timeout_ms = 45_000;
+        config.save_to_file(&path).unwrap();
+        let loaded = Config::load_from_file(&path).unwrap();
+        assert_eq!(loaded.defaults.timeout_ms, 45_000);
+        assert!(Config::check_file(&path).unwrap().errors.is_empty());
+
+        let yml = dir.path().join("capsule-run.yml");
+        let content = std::fs::read_to_string(&path)
+            .unwrap()
+            .replace("defaults:\n", "defaults:\n  bogus: 1\n");
+        std::fs::write(&yml, content).unwrap();
+        let report = Config::check_file(&yml).unwrap();
+        assert_eq!(report.warnings, vec!["Unknown key defaults.bogus"]);
+
+        let e = Config::parse(&yml, "defaults: [").unwrap_err().to_string();
+        assert!(e.contains("YAML") && e.contains("line 1"), "{}", e);
+    }
+
+    #[test]
+    fn test_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("capsule-run.toml");
+        let mut config = Config::default();
+        config.defaults.timeout_ms = 45_000;
+        config.save_to_file(&base).unwrap();
+        let content = std::fs::read_to_string(&base).unwrap();
+        std::fs::write(
+            &base,
+            format!(
+                "include = [\"profiles/*.toml\", \"local.yaml\"]\n{}",
+                content
+            ),
+        )
+        .unwrap();
+
+        let profiles = dir.path().join("profiles");
+        std::fs::create_dir(&profiles).unwrap();
+        std::fs::write(
+            profiles.join("node.toml"),
+            "[profiles.node]\ntimeout_ms = 60000\n",
+        )
+        .unwrap();
+        std::fs::write(
+            profiles.join("python.toml"),
+            "[profiles.python]\ntimeout_ms = 90000\n[profiles.python.environment]\nPYTHONDONTWRITEBYTECODE = \"1\"\n",
+        )
+        .unwrap();
+        std::fs::write(profiles.join("README.md"), "not a config").unwrap();
+        // The including file wins over what it includes
+        std::fs::write(
+            dir.path().join("local.yaml"),
+            "defaults:\n  timeout_ms: 1000\nprofiles:\n  node:\n    timeout_ms: 120000\n",
+        )
+        .unwrap();
+
+        let loaded = Config::load_from_file(&base).unwrap();
+        assert_eq!(loaded.defaults.timeout_ms, 45_000);
+        assert_eq!(loaded.profiles["node"].timeout_ms, Some(120_000));
+        assert_eq!(loaded.profiles["python"].timeout_ms, Some(90_000));
+        assert!(Config::check_file(&base).unwrap().errors.is_empty());
+
+        std::fs::write(
+            profiles.join("loop.toml"),
+            "include = [\"../capsule-run.toml\"]\n",
+        )
+        .unwrap();
+        let e = Config::load_from_file(&base).unwrap_err().to_string();
+        assert!(e.contains("include cycle"), "{}", e);
+    }
+
+    #[test]
+    fn test_template_request() {
+        let config: Config = toml::from_str(&format!(
+            "{}\n{}",
+            toml::to_string(&Config::default()).unwrap(),
+            r#"
+[templates.pytest]
+description = "Run a pytest target"
+vars = { flags = "-q" }
+
+[templates.pytest.request]
+command = ["pytest", "${flags}", "${path}"]
+profile = "python"
+environment = { PYTEST_ADDOPTS = "--junitxml=/workspace/${path}.xml" }
+"#
+        ))
+        .unwrap();
+        let vars = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let request = config
+            .template_request("pytest", &vars(&[("path", "tests/unit")]))
+            .unwrap();
+        assert_eq!(
+            request["command"],
+            serde_json::json!(["pytest", "-q", "tests/unit"])
+        );
+        assert_eq!(request["profile"], "python");
+        assert_eq!(
+            request["environment"]["PYTEST_ADDOPTS"],
+            "--junitxml=/workspace/tests/unit.xml"
+        );
+
+        let request = config
+            .template_request("pytest", &vars(&[("path", "t"), ("flags", "-x")]))
+            .unwrap();
+        assert_eq!(request["command"][1], "-x");
+
+        let e = config.template_request("pytest", &vars(&[])).unwrap_err();
+        assert!(e.to_string().contains("needs --var for: path"), "{}", e);
+        let e = config
+            .template_request("pytest", &vars(&[("path", "t"), ("pth", "t")]))
+            .unwrap_err();
+        assert!(e.to_string().contains("no placeholder for: pth"), "{}", e);
+        assert!(config.template_request("lint", &vars(&[])).is_err());
+    }
+
     #[test]
     fn test_command_validation() {
         let config = Config::default();
 
         // Test blocked command
         assert!(!config.validate_command(&["rm".to_string(), "-rf".to_string()]));
+        assert!(!config.validate_command(&["/bin/rm".to_string()]));
+        assert!(config.validate_command(&["/usr/bin/sum".to_string()]));
 
         // Test allowed command
         assert!(config.validate_command(&["echo".to_string(), "hello".to_string()]));
@@ -253,6 +1434,274 @@ mod tests {
         assert!(!config.validate_command(&[]));
     }
 
+    #[test]
+    fn test_extra_syscall_ceiling() {
+        let mut config = Config::default();
+        assert!(config
+            .validate_extra_syscalls(&["io_uring_setup".to_string()])
+            .is_ok());
+        assert!(config
+            .validate_extra_syscalls(&["ptrace".to_string(), "membarrier".to_string()])
+            .is_err());
+
+        config.security.allowed_extra_syscalls = None;
+        assert!(config.validate_extra_syscalls(&[]).is_ok());
+        assert!(config
+            .validate_extra_syscalls(&["membarrier".to_string()])
+            .is_err());
+    }
+
+    #[test]
+    fn test_seccomp_mode_allowlist() {
+        let mut config = Config::default();
+        let mut request: ExecutionRequest =
+            serde_json::from_value(serde_json::json!({ "command": ["true"] })).unwrap();
+        assert!(config.validate_host_access(&request).is_ok());
+
+        request.isolation.seccomp_mode = SeccompMode::Log;
+        let error = config.validate_host_access(&request).unwrap_err();
+        assert!(error.to_string().contains("log"), "{}", error);
+        assert!(config.validate_seccomp_mode(SeccompMode::Errno).is_err());
+
+        config.security.allowed_seccomp_modes = Some(vec![SeccompMode::Kill, SeccompMode::Errno]);
+        assert!(config.validate_seccomp_mode(SeccompMode::Errno).is_ok());
+        assert!(config.validate_host_access(&request).is_err());
+    }
+
+    #[test]
+    fn test_seccomp_profile_allowlist() {
+        let dir = tempfile::tempdir().unwrap();
+        let profiles = dir.path().join("profiles");
+        std::fs::create_dir_all(&profiles).unwrap();
+        let write = |name: &str, syscalls: &[&str]| {
+            let path = profiles.join(name);
+            let profile = serde_json::json!({
+                "defaultAction": "SCMP_ACT_ERRNO",
+                "syscalls": [{"names": syscalls, "action": "SCMP_ACT_ALLOW"}],
+            });
+            std::fs::write(&path, profile.to_string()).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        let isolation = |profile: &str| IsolationConfig {
+            seccomp_profile: Some(profile.to_string()),
+            ..Default::default()
+        };
+        let plain = write("plain.json", &["read", "write", "exit_group"]);
+        let outside = dir.path().join("outside.json");
+        std::fs::write(&outside, "{}").unwrap();
+
+        let mut config = Config::default();
+        assert!(config.validate_seccomp_profile(&isolation(&plain)).is_err());
+
+        config.security.allowed_seccomp_profile_dirs =
+            Some(vec![profiles.to_string_lossy().into_owned()]);
+        assert!(config.validate_seccomp_profile(&isolation(&plain)).is_ok());
+        let escape = profiles.join("../outside.json");
+        assert!(config
+            .validate_seccomp_profile(&isolation(&escape.to_string_lossy()))
+            .is_err());
+
+        if cfg!(all(target_os = "linux", feature = "seccomp")) {
+            let bpf = write("bpf.json", &["read", "bpf"]);
+            let error = config
+                .validate_seccomp_profile(&isolation(&bpf))
+                .unwrap_err();
+            assert!(error.to_string().contains("bpf"), "{}", error);
+            config.security.allowed_extra_syscalls = Some(vec!["bpf".to_string()]);
+            assert!(config.validate_seccomp_profile(&isolation(&bpf)).is_ok());
+
+            // The checked copy is what runs, whatever the file says later
+            #[cfg(all(target_os = "linux", feature = "seccomp"))]
+            {
+                let loaded = config.load_seccomp_profile(&isolation(&bpf)).unwrap();
+                std::fs::write(&bpf, r#"{"defaultAction": "SCMP_ACT_ALLOW"}"#).unwrap();
+                assert_eq!(loaded.unwrap().syscalls[0].names, ["read", "bpf"]);
+                assert!(config.validate_seccomp_profile(&isolation(&bpf)).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_unix_socket_allowlist() {
+        let socket = |source: &str| SocketMount {
+            source: source.to_string(),
+            destination: None,
+        };
+        let mut config = Config::default();
+        assert!(config.validate_unix_sockets(&[]).is_ok());
+        assert!(config
+            .validate_unix_sockets(&[socket("/run/cache.sock")])
+            .is_err());
+
+        config.security.allowed_unix_sockets = Some(vec!["/run/cache.sock".to_string()]);
+        assert!(config
+            .validate_unix_sockets(&[socket("/run/cache.sock")])
+            .is_ok());
+        assert!(config
+            .validate_unix_sockets(&[socket("/var/run/docker.sock")])
+            .is_err());
+    }
+
+    #[test]
+    fn test_host_git_source_allowlist() {
+        let source = |git: &str, clone: CloneLocation| GitSource {
+            git: git.to_string(),
+            git_ref: None,
+            depth: None,
+            clone,
+        };
+        let mut config = Config::default();
+        let repo = "https://git.example.com/org/repo.git";
+        assert!(config
+            .validate_git_source(&source(repo, CloneLocation::Host))
+            .is_err());
+        if !cfg!(target_os = "macos") {
+            assert!(config
+                .validate_git_source(&source(repo, CloneLocation::Sandbox))
+                .is_ok());
+        }
+
+        config.security.allowed_host_git_sources =
+            Some(vec!["https://git.example.com/org/".to_string()]);
+        assert!(config
+            .validate_git_source(&source(repo, CloneLocation::Host))
+            .is_ok());
+        assert!(config
+            .validate_git_source(&source(
+                "https://git.example.com/organization/repo.git",
+                CloneLocation::Host
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn test_device_allowlist() {
+        let mut config = Config::default();
+        assert!(config.validate_devices(&[]).is_ok());
+        assert!(config.validate_devices(&["/dev/fuse".to_string()]).is_err());
+
+        config.security.allowed_devices = Some(vec!["/dev/fuse".to_string()]);
+        assert!(config.validate_devices(&["/dev/fuse".to_string()]).is_ok());
+        assert!(config.validate_devices(&["/dev/kvm".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let host = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        std::fs::create_dir_all(dir.path().join("data/models")).unwrap();
+        std::fs::create_dir_all(dir.path().join("database")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("database"), dir.path().join("data/link"))
+            .unwrap();
+
+        let mut config = Config::default();
+        let mut request: ExecutionRequest =
+            serde_json::from_value(serde_json::json!({ "command": ["make"] })).unwrap();
+        request.resources.memory_bytes = 1 << 30;
+        request.isolation.network = true;
+        request.isolation.readonly_paths = vec![host("data/models")];
+        assert!(config.validate_limits(&request).is_ok());
+
+        config.limits = LimitsConfig {
+            max_memory_bytes: Some(1 << 30),
+            max_timeout_ms: Some(60_000),
+            max_output_bytes: None,
+            allowed_bind_prefixes: Some(vec![host("data")]),
+            allow_network: Some(true),
+        };
+        assert!(config.validate_limits(&request).is_ok());
+
+        for path in ["database", "data/link", "data/missing"] {
+            request.isolation.writable_paths = vec![host(path)];
+            let e = config.validate_limits(&request).unwrap_err().to_string();
+            assert!(e.contains(&format!("host path {}", host(path))), "{}", e);
+        }
+
+        request.isolation.writable_paths.clear();
+        request.resources.memory_bytes = 2 << 30;
+        config.limits.allow_network = Some(false);
+        let e = config.validate_limits(&request).unwrap_err().to_string();
+        assert!(e.contains("memory") && e.contains("network"), "{}", e);
+    }
+
+    #[test]
+    fn test_ceilings() {
+        let mut config = Config::default();
+        config.limits.max_timeout_ms = Some(3_600_000);
+        config.limits.max_output_bytes = Some(1 << 20);
+        config.profiles.insert(
+            "ml".to_string(),
+            ExecutionProfile {
+                description: None,
+                timeout_ms: None,
+                resources: None,
+                isolation: None,
+                environment: None,
+                limits: Some(LimitsConfig {
+                    max_memory_bytes: Some(32 << 30),
+                    ..Default::default()
+                }),
+            },
+        );
+        let mut request: ExecutionRequest =
+            serde_json::from_value(serde_json::json!({ "command": ["python3", "train.py"] }))
+                .unwrap();
+        request.timeout_ms = 3_600_000;
+        request.resources.memory_bytes = 16 << 30;
+        let validate = |config: &Config, request: &ExecutionRequest| {
+            let ceilings = config.ceilings(request.profile.as_deref());
+            crate::api::validate_execution_request_with(request, &PathPolicy::default(), &ceilings)
+                .and_then(|_| config.validate_limits(request))
+        };
+
+        let e = validate(&config, &request).unwrap_err().to_string();
+        assert!(e.contains("Memory limit too high"), "{}", e);
+        request.profile = Some("ml".to_string());
+        assert!(validate(&config, &request).is_ok());
+
+        request.resources.max_output_bytes = 2 << 20;
+        let e = validate(&config, &request).unwrap_err().to_string();
+        assert!(e.contains("Output limit too high"), "{}", e);
+        assert_eq!(
+            config.ceilings(None),
+            Ceilings {
+                memory_bytes: Ceilings::default().memory_bytes,
+                timeout_ms: 3_600_000,
+                output_bytes: 1 << 20,
+            }
+        );
+    }
+
+    #[test]
+    fn test_path_policy() {
+        let mut config = Config::default();
+        let mut request: ExecutionRequest =
+            serde_json::from_value(serde_json::json!({ "command": ["make"] })).unwrap();
+        request.isolation.readonly_paths = vec!["/home/alice/project".to_string()];
+        let validate = |config: &Config, request: &ExecutionRequest| {
+            crate::api::validate_execution_request_with(
+                request,
+                &config.path_policy(),
+                &Ceilings::default(),
+            )
+        };
+        assert!(validate(&config, &request).is_err());
+
+        config.security.allowed_path_prefixes = Some(vec!["/home/alice/project".to_string()]);
+        assert!(validate(&config, &request).is_ok());
+        request.isolation.readonly_paths = vec!["/home/alice/.ssh".to_string()];
+        assert!(validate(&config, &request).is_err());
+
+        config.security.allow_home = Some(true);
+        assert!(validate(&config, &request).is_ok());
+
+        config.security.allow_home = None;
+        config.security.dangerous_paths = Some(vec!["/srv/secrets".to_string()]);
+        assert!(validate(&config, &request).is_ok());
+        request.isolation.readonly_paths = vec!["/srv/secrets/db".to_string()];
+        assert!(validate(&config, &request).is_err());
+    }
+
     #[test]
     fn test_profile_merging() {
         let mut config = Config::default();
@@ -264,15 +1713,184 @@ mod tests {
             resources: None,
             isolation: None,
             environment: None,
+            limits: None,
         };
         config.profiles.insert("test".to_string(), profile);
 
         // Test merging
-        let merged = config.merge_with_profile(Some("test"));
-        assert_eq!(merged.defaults.timeout_ms, 60_000);
+        let merged = config.request_defaults(Some("test")).unwrap();
+        assert_eq!(merged["timeout_ms"], 60_000);
+
+        // Test without a profile, and with a non-existent one
+        let merged = config.request_defaults(None).unwrap();
+        assert_eq!(merged["timeout_ms"], 30_000);
+        assert!(config.request_defaults(Some("nonexistent")).is_err());
+    }
+
+    #[test]
+    fn test_check_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("capsule-run.toml");
+        Config::default().save_to_file(&path).unwrap();
+        let report = Config::check_file(&path).unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(
+            &path,
+            content.replace("[defaults]\n", "[defaults]\ntimeout = 5\n")
+                + "\n[profiles.big.resources]\nmax_pids = 100000\n",
+        )
+        .unwrap();
+        let report = Config::check_file(&path).unwrap();
+        assert_eq!(report.warnings, vec!["Unknown key defaults.timeout"]);
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].starts_with("profile big: "));
+
+        std::fs::write(
+            &path,
+            content.replace(
+                "max_concurrent_executions = 10\n",
+                "max_concurrent_executions = 0\non_concurrency_limit = \"reject\"\n",
+            ),
+        )
+        .unwrap();
+        let report = Config::check_file(&path).unwrap();
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        assert_eq!(
+            report.errors,
+            vec!["security.max_concurrent_executions must be at least 1"]
+        );
+
+        std::fs::write(
+            &path,
+            content.replace(
+                "max_concurrent_executions = 10\n",
+                "max_concurrent_executions = 10\ndefault_tenant = \"eval\"\n",
+            ) + "\n[tenants.\"ci/eval\"]\ncpu_seconds_per_hour = 600\n"
+                + "\n[tenants.agent]\ntoken_sha256 = \"abc\"\n",
+        )
+        .unwrap();
+        let report = Config::check_file(&path).unwrap();
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        assert_eq!(report.errors.len(), 3, "{:?}", report.errors);
+        assert!(report.errors[0].contains("tenants.agent.token_sha256"));
+        assert!(report.errors[1].contains("Invalid tenant name 'ci/eval'"));
+        assert!(report.errors[2].contains("security.default_tenant eval"));
+
+        std::fs::write(&path, "[defaults]\ntimeout_ms = \"soon\"\n").unwrap();
+        let report = Config::check_file(&path).unwrap();
+        assert!(report.errors[0].contains("line 2"), "{}", report.errors[0]);
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let vars = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+        let mut config = Config::default();
+        config.defaults.timeout_ms = 10_000;
+        config.defaults.resources.max_pids = 50;
+
+        let env = vars(&[
+            ("CAPSULE_TIMEOUT_MS", "90000"),
+            ("CAPSULE_MEMORY", "1G"),
+            ("CAPSULE_NETWORK", "true"),
+            ("PATH", "/usr/bin"),
+        ]);
+        let layered = config.layered_defaults(None, &env).unwrap();
+        assert_eq!(layered["timeout_ms"], 90_000);
+        assert_eq!(layered["resources"]["memory_bytes"], 1u64 << 30);
+        assert_eq!(layered["resources"]["max_pids"], 50);
+        assert_eq!(layered["isolation"]["network"], true);
 
-        // Test with non-existent profile
-        let merged = config.merge_with_profile(Some("nonexistent"));
-        assert_eq!(merged.defaults.timeout_ms, 30_000);
+        assert!(env_overrides(&vars(&[("CAPSULE_MEMORY", "lots")])).is_err());
+        assert!(env_overrides(&vars(&[("CAPSULE_NETWORK", "maybe")])).is_err());
+    }
+
+    #[test]
+    fn test_profile_layering() {
+        let mut config = Config::default();
+        config.defaults.resources.max_pids = 50;
+        config.defaults.isolation.network = true;
+        config.profiles.insert(
+            "ci".to_string(),
+            ExecutionProfile {
+                description: None,
+                timeout_ms: Some(120_000),
+                resources: Some(ResourceLimits {
+                    memory_bytes: 1 << 30,
+                    ..Default::default()
+                }),
+                isolation: None,
+                environment: Some(HashMap::from([
+                    ("CI".to_string(), "1".to_string()),
+                    ("LANG".to_string(), "C".to_string()),
+                ])),
+                limits: None,
+            },
+        );
+
+        let request = config
+            .apply_defaults(
+                Some("ci"),
+                serde_json::json!({
+                    "command": ["make"],
+                    "timeout_ms": 5_000,
+                    "environment": {"LANG": "C.UTF-8"}
+                }),
+            )
+            .unwrap();
+        // The request wins, then the profile, then [defaults]
+        assert_eq!(request.timeout_ms, 5_000);
+        assert_eq!(request.resources.memory_bytes, 1 << 30);
+        assert_eq!(request.resources.max_pids, 50);
+        assert!(request.isolation.network);
+        assert_eq!(request.environment["CI"], "1");
+        assert_eq!(request.environment["LANG"], "C.UTF-8");
+    }
+
+    #[test]
+    fn test_profile_environment_expansion() {
+        let mut config = Config::default();
+        config.profiles.insert(
+            "tools".to_string(),
+            ExecutionProfile {
+                description: None,
+                timeout_ms: None,
+                resources: None,
+                isolation: None,
+                environment: Some(HashMap::from([
+                    ("PATH".to_string(), "/opt/tools/bin:${PATH}".to_string()),
+                    ("TOOLS_HOME".to_string(), "${UNSET}/tools".to_string()),
+                ])),
+                limits: None,
+            },
+        );
+        let vars = HashMap::from([("PATH".to_string(), "/usr/bin:/bin".to_string())]);
+
+        let layered = config.layered_defaults(Some("tools"), &vars).unwrap();
+        assert_eq!(
+            layered["environment"]["PATH"],
+            "/opt/tools/bin:/usr/bin:/bin"
+        );
+        assert_eq!(layered["environment"]["TOOLS_HOME"], "/tools");
+
+        // A request's own PATH still wins
+        let mut merged = layered;
+        merge_json(
+            &mut merged,
+            serde_json::json!({"command": ["make"], "environment": {"PATH": "/bin"}}),
+        );
+        let request: ExecutionRequest = serde_json::from_value(merged).unwrap();
+        assert_eq!(request.environment["PATH"], "/bin");
+        assert_eq!(
+            expand_vars("${PATH", &mut |var| vars.get(var).cloned()),
+            "${PATH"
+        );
     }
 }
@@ -15,6 +15,23 @@ pub enum CapsuleError {
     #[error("Resource limit exceeded: {0}")]
     ResourceLimit(String),
 
+    #[error("Concurrency limit reached: max_concurrent_executions is {limit}")]
+    ConcurrencyLimit { limit: u32 },
+
+    #[error("Execution queue is full: max_queued_executions is {limit}")]
+    QueueFull { limit: u32 },
+
+    #[error("Queued for longer than queue_timeout_ms of {timeout_ms}ms")]
+    QueueTimeout { timeout_ms: u64 },
+
+    #[error("Tenant {tenant} is over its {quota} quota: {used} of {limit}")]
+    TenantQuota {
+        tenant: String,
+        quota: String,
+        limit: u64,
+        used: u64,
+    },
+
     #[error("Security violation: {0}")]
     Security(String),
 
@@ -48,6 +65,15 @@ pub enum SandboxError {
 
     #[error("User namespace mapping failed: {0}")]
     UserMapping(String),
+
+    #[error("Failed to apply sandbox profile: {0}")]
+    SandboxProfile(String),
+
+    #[error("Failed to setup network: {0}")]
+    NetworkSetup(String),
+
+    #[error("{feature} is not available under strict enforcement: {reason}")]
+    IsolationUnavailable { feature: String, reason: String },
 }
 
 #[derive(Error, Debug)]
@@ -63,6 +89,9 @@ pub enum ExecutionError {
     #[error("Process spawning failed: {0}")]
     SpawnFailed(String),
 
+    #[error("Executable not found in sandbox: {0}")]
+    ExecutableNotFound(String),
+
     #[error("I/O capture failed: {0}")]
     IoCaptureError(String),
 
@@ -81,6 +110,8 @@ pub struct ErrorCode {
     pub code: &'static str,
     pub message: String,
     pub category: ErrorCategory,
+    /// Structured context for the response's `error.details`
+    pub details: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone)]
@@ -98,8 +129,14 @@ impl ErrorCode {
             code,
             message,
             category,
+            details: None,
         }
     }
+
+    fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
 }
 
 impl From<CapsuleError> for ErrorCode {
@@ -128,6 +165,15 @@ impl From<CapsuleError> for ErrorCode {
             CapsuleError::SandboxSetup(SandboxError::UserMapping(msg)) => {
                 ErrorCode::new("E2006", msg, ErrorCategory::Security)
             }
+            CapsuleError::SandboxSetup(SandboxError::SandboxProfile(msg)) => {
+                ErrorCode::new("E2007", msg, ErrorCategory::Security)
+            }
+            CapsuleError::SandboxSetup(SandboxError::NetworkSetup(msg)) => {
+                ErrorCode::new("E2009", msg, ErrorCategory::Security)
+            }
+            CapsuleError::SandboxSetup(error @ SandboxError::IsolationUnavailable { .. }) => {
+                ErrorCode::new("E2008", error.to_string(), ErrorCategory::Security)
+            }
             CapsuleError::Execution(ExecutionError::Timeout { timeout_ms }) => ErrorCode::new(
                 "E3001",
                 format!("Command exceeded timeout limit of {}ms", timeout_ms),
@@ -141,6 +187,9 @@ impl From<CapsuleError> for ErrorCode {
             CapsuleError::Execution(ExecutionError::SpawnFailed(msg)) => {
                 ErrorCode::new("E3003", msg, ErrorCategory::Execution)
             }
+            error @ CapsuleError::Execution(ExecutionError::ExecutableNotFound(_)) => {
+                ErrorCode::new("E3007", error.to_string(), ErrorCategory::Execution)
+            }
             CapsuleError::Execution(ExecutionError::IoCaptureError(msg)) => {
                 ErrorCode::new("E3004", msg, ErrorCategory::System)
             }
@@ -155,6 +204,25 @@ impl From<CapsuleError> for ErrorCode {
             CapsuleError::ResourceLimit(msg) => {
                 ErrorCode::new("E4001", msg, ErrorCategory::Resource)
             }
+            error @ (CapsuleError::ConcurrencyLimit { .. } | CapsuleError::QueueFull { .. }) => {
+                ErrorCode::new("E4006", error.to_string(), ErrorCategory::Resource)
+            }
+            error @ CapsuleError::QueueTimeout { .. } => {
+                ErrorCode::new("E4007", error.to_string(), ErrorCategory::Resource)
+            }
+            CapsuleError::TenantQuota {
+                ref tenant,
+                ref quota,
+                limit,
+                used,
+            } => ErrorCode::new("E4008", error.to_string(), ErrorCategory::Resource).with_details(
+                serde_json::json!({
+                    "tenant": tenant,
+                    "quota": quota,
+                    "limit": limit,
+                    "used": used,
+                }),
+            ),
             CapsuleError::Security(msg) => ErrorCode::new("E5001", msg, ErrorCategory::Security),
             CapsuleError::Io(err) => ErrorCode::new(
                 "E6001",
@@ -0,0 +1,282 @@
+use crate::api::schema::{
+    AuditRecord, BindMount, ExecutionRequest, ExecutionResponse, ExecutionStatus,
+};
+use crate::config::Config;
+use crate::error::{CapsuleError, CapsuleResult};
+use crate::policy::Caller;
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// How much of the end of the log is read at a time to find the last record
+const TAIL_CHUNK: u64 = 16 * 1024;
+
+/// Append-only JSONL log with a record per execution.
+///
+/// The file is opened before the sandbox is set up, since the supervisor
+/// cannot reach the host filesystem afterwards. Appends are serialized
+/// with an flock, so concurrent executions can share a log and, with hash
+/// chaining, each record covers the one before it: editing or removing a
+/// record breaks every hash after it.
+pub struct AuditLog {
+    file: File,
+    path: PathBuf,
+    hash_chain: bool,
+}
+
+impl AuditLog {
+    /// None unless `[security.audit_log]` is enabled, which requires a
+    /// `log_file`.
+    pub fn from_config(config: &Config) -> CapsuleResult<Option<Self>> {
+        let Some(audit) = config.security.audit_log.as_ref().filter(|a| a.enabled) else {
+            return Ok(None);
+        };
+        let path = audit.log_file.as_ref().ok_or_else(|| {
+            CapsuleError::Config("security.audit_log is enabled but has no log_file".to_string())
+        })?;
+        Self::open(Path::new(path), audit.hash_chain).map(Some)
+    }
+
+    pub fn open(path: &Path, hash_chain: bool) -> CapsuleResult<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .mode(0o600)
+            .open(path)
+            .map_err(|e| failed(path, "open", e))?;
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            hash_chain,
+        })
+    }
+
+    /// Append the record for a run of `request` that ended in `outcome`.
+    pub fn record(
+        &self,
+        execution_id: Uuid,
+        caller: &Caller,
+        request: &ExecutionRequest,
+        outcome: Result<&ExecutionResponse, &CapsuleError>,
+    ) -> CapsuleResult<()> {
+        let mut record = AuditRecord {
+            timestamp: Utc::now(),
+            execution_id,
+            caller: caller.id.clone(),
+            groups: caller.groups.clone(),
+            uid: unsafe { libc::getuid() },
+            command: request.command.clone(),
+            profile: request.profile.clone(),
+            mounts: host_mounts(request),
+            network: request.isolation.network,
+            resources: request.resources.clone(),
+            timeout_ms: request.timeout_ms,
+            status: ExecutionStatus::Error,
+            exit_code: None,
+            error: None,
+            wall_time_ms: None,
+            stdout_sha256: None,
+            stderr_sha256: None,
+            prev_hash: None,
+            hash: None,
+        };
+        match outcome {
+            Ok(response) => {
+                record.status = response.status.clone();
+                record.exit_code = response.exit_code;
+                record.error = response
+                    .error
+                    .as_ref()
+                    .map(|error| format!("{}: {}", error.code, error.message));
+                record.wall_time_ms = response.metrics.as_ref().map(|m| m.wall_time_ms);
+                record.stdout_sha256 = response.stdout.as_deref().map(sha256);
+                record.stderr_sha256 = response.stderr.as_deref().map(sha256);
+            }
+            Err(error) => record.error = Some(error.to_string()),
+        }
+
+        let mut file = &self.file;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(failed(&self.path, "lock", std::io::Error::last_os_error()));
+        }
+        let written = self.append(&mut file, record);
+        unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) };
+        written
+    }
+
+    /// Append `record` with the lock held, chaining it to the last one.
+    fn append(&self, file: &mut &File, mut record: AuditRecord) -> CapsuleResult<()> {
+        if self.hash_chain {
+            let prev_hash = last_hash(file).map_err(|e| failed(&self.path, "read", e))?;
+            record.prev_hash = Some(prev_hash);
+            record.hash = Some(chain_hash(&record)?);
+        }
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())
+            .map_err(|e| failed(&self.path, "write", e))
+    }
+}
+
+/// Check the hash chain of the log at `path`, returning how many records it
+/// holds. Records written without chaining are only checked to parse.
+pub fn verify(path: &Path) -> CapsuleResult<usize> {
+    let file = File::open(path).map_err(|e| failed(path, "open", e))?;
+    let mut prev_hash: Option<String> = None;
+    let mut count = 0;
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| failed(path, "read", e))?;
+        if line.is_empty() {
+            continue;
+        }
+        let broken = |reason: &str| {
+            CapsuleError::Security(format!(
+                "Audit log {} line {}: {}",
+                path.display(),
+                index + 1,
+                reason
+            ))
+        };
+        let record: AuditRecord =
+            serde_json::from_str(&line).map_err(|e| broken(&e.to_string()))?;
+        count += 1;
+        // A record written without chaining starts the chain over
+        let Some(hash) = &record.hash else {
+            prev_hash = None;
+            continue;
+        };
+        let expected_prev = prev_hash.clone().unwrap_or_default();
+        if record.prev_hash.as_deref() != Some(expected_prev.as_str()) {
+            return Err(broken("prev_hash does not match the record before it"));
+        }
+        if *hash != chain_hash(&record)? {
+            return Err(broken("hash does not match the record's contents"));
+        }
+        prev_hash = Some(hash.clone());
+    }
+    Ok(count)
+}
+
+fn chain_hash(record: &AuditRecord) -> CapsuleResult<String> {
+    // Through a Value, whose keys are sorted, so the hash does not depend on
+    // field order
+    let mut value = serde_json::to_value(record)?;
+    if let Some(fields) = value.as_object_mut() {
+        fields.remove("hash");
+    }
+    Ok(sha256(&serde_json::to_string(&value)?))
+}
+
+/// The `hash` of the log's last record; empty for an empty log or one whose
+/// last record is unchained.
+fn last_hash(file: &mut &File) -> std::io::Result<String> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut chunk = TAIL_CHUNK;
+    loop {
+        let start = len.saturating_sub(chunk);
+        file.seek(SeekFrom::Start(start))?;
+        let mut tail = Vec::new();
+        file.take(len - start).read_to_end(&mut tail)?;
+        let tail = String::from_utf8_lossy(&tail);
+        let mut lines = tail.trim_end_matches('\n').rsplit('\n');
+        let last = lines.next().unwrap_or_default();
+        // The last line is whole once a newline precedes it
+        if start == 0 || lines.next().is_some() {
+            let record: Option<AuditRecord> = serde_json::from_str(last).ok();
+            return Ok(record.and_then(|record| record.hash).unwrap_or_default());
+        }
+        chunk *= 2;
+    }
+}
+
+/// Host paths the request mounts into the sandbox
+fn host_mounts(request: &ExecutionRequest) -> Vec<BindMount> {
+    let isolation = &request.isolation;
+    let same = |path: &String, readonly| BindMount {
+        source: path.clone(),
+        destination: path.clone(),
+        readonly,
+    };
+    isolation
+        .readonly_paths
+        .iter()
+        .map(|path| same(path, true))
+        .chain(
+            isolation
+                .writable_paths
+                .iter()
+                .map(|path| same(path, false)),
+        )
+        .chain(isolation.bind_mounts.iter().cloned())
+        .chain(isolation.unix_sockets.iter().map(|socket| BindMount {
+            source: socket.source.clone(),
+            destination: socket.destination().to_string(),
+            readonly: false,
+        }))
+        .chain(isolation.devices.iter().map(|device| same(device, false)))
+        .collect()
+}
+
+fn sha256(data: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn failed(path: &Path, action: &str, e: std::io::Error) -> CapsuleError {
+    CapsuleError::Io(std::io::Error::new(
+        e.kind(),
+        format!("Failed to {} audit log {}: {}", action, path.display(), e),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> ExecutionRequest {
+        serde_json::from_value(serde_json::json!({
+            "command": ["make", "test"],
+            "isolation": {"bind_mounts": [{"source": "/srv/data", "destination": "/data", "readonly": true}]}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_audit_chain_detects_tampering() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let caller = Caller {
+            id: "ci".to_string(),
+            groups: Vec::new(),
+        };
+        let log = AuditLog::open(&path, true).unwrap();
+        let refused = CapsuleError::Security("Command 'rm' is not allowed".to_string());
+        for _ in 0..3 {
+            log.record(Uuid::new_v4(), &caller, &request(), Err(&refused))
+                .unwrap();
+        }
+        assert_eq!(verify(&path).unwrap(), 3);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let first: AuditRecord = serde_json::from_str(contents.lines().next().unwrap()).unwrap();
+        assert_eq!(first.prev_hash.as_deref(), Some(""));
+        assert_eq!(first.mounts[0].destination, "/data");
+        assert!(first.error.unwrap().contains("not allowed"));
+
+        // Rewriting a record breaks the chain at that record
+        std::fs::write(&path, contents.replacen("\"ci\"", "\"admin\"", 1)).unwrap();
+        assert!(verify(&path).unwrap_err().to_string().contains("line 1"));
+
+        // So does dropping one
+        let dropped: Vec<&str> = contents.lines().skip(1).collect();
+        std::fs::write(&path, dropped.join("\n")).unwrap();
+        assert!(verify(&path).is_err());
+    }
+}
@@ -0,0 +1,318 @@
+use crate::config::{ConcurrencyOverflow, Config};
+use crate::error::{CapsuleError, CapsuleResult};
+use std::cmp::Reverse;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// How often a queued run looks for a free slot
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Caps how many executions run at once across every capsule-run process
+/// sharing a state directory.
+///
+/// Each of the `max` slots is a lock file in `slots/` under the state
+/// directory, and a run holds the flock on one until it ends. The kernel
+/// drops the lock when the process exits, so a run that is killed frees its
+/// slot all the same. The file is opened before setup and kept open through
+/// the supervisor's pivot into the sandbox root.
+///
+/// Runs waiting for a slot hold a ticket in `queue/`, flocked the same way,
+/// with their priority and when they joined. A waiting run takes a slot
+/// only while fewer tickets rank ahead of it than slots are free: higher
+/// priorities first, then first come first served.
+pub struct ConcurrencyLimit {
+    dir: PathBuf,
+    max: u32,
+    overflow: ConcurrencyOverflow,
+    max_queued: Option<u32>,
+}
+
+/// A held slot, freed when dropped
+pub struct Slot {
+    _file: File,
+}
+
+/// Where a waiting run stands in the queue; lower ranks go first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Rank {
+    priority: Reverse<i32>,
+    queued_at_ns: u128,
+}
+
+/// A waiting run's place in the queue, given up when dropped
+struct Ticket {
+    _file: File,
+    path: PathBuf,
+    rank: Rank,
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl ConcurrencyLimit {
+    pub fn new(state_dir: &Path, max: u32, overflow: ConcurrencyOverflow) -> Self {
+        Self {
+            dir: state_dir.to_path_buf(),
+            max,
+            overflow,
+            max_queued: None,
+        }
+    }
+
+    /// Reject runs with E4006 once `max_queued` are already waiting.
+    pub fn with_max_queued(mut self, max_queued: Option<u32>) -> Self {
+        self.max_queued = max_queued;
+        self
+    }
+
+    /// None unless `[security] max_concurrent_executions` is set
+    pub fn from_config(config: &Config, state_dir: &Path) -> Option<Self> {
+        let security = &config.security;
+        security.max_concurrent_executions.map(|max| {
+            Self::new(
+                state_dir,
+                max,
+                security.on_concurrency_limit.unwrap_or_default(),
+            )
+            .with_max_queued(security.max_queued_executions)
+        })
+    }
+
+    /// Take a free slot, queueing at `priority` for up to `queue_timeout`
+    /// unless the limit rejects overflowing runs.
+    pub async fn acquire(
+        &self,
+        priority: i32,
+        queue_timeout: Option<Duration>,
+    ) -> CapsuleResult<Slot> {
+        let queue = self.dir.join("queue");
+        if self.overflow == ConcurrencyOverflow::Reject || waiting(&queue, None)?.is_empty() {
+            if let Some(slot) = self.try_acquire()? {
+                return Ok(slot);
+            }
+        }
+        if self.overflow == ConcurrencyOverflow::Reject {
+            return Err(CapsuleError::ConcurrencyLimit { limit: self.max });
+        }
+        if let Some(limit) = self.max_queued {
+            if waiting(&queue, None)?.len() >= limit as usize {
+                return Err(CapsuleError::QueueFull { limit });
+            }
+        }
+
+        let ticket = enqueue(&queue, priority)?;
+        let queued = Instant::now();
+        tracing::info!(
+            limit = self.max,
+            priority,
+            "Waiting for a running execution to end"
+        );
+        loop {
+            let ahead = waiting(&queue, Some(&ticket))?
+                .into_iter()
+                .filter(|rank| *rank < ticket.rank)
+                .count();
+            if ahead < self.free_slots() {
+                if let Some(slot) = self.try_acquire()? {
+                    return Ok(slot);
+                }
+            }
+            if let Some(timeout) = queue_timeout.filter(|timeout| queued.elapsed() >= *timeout) {
+                return Err(CapsuleError::QueueTimeout {
+                    timeout_ms: timeout.as_millis() as u64,
+                });
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    fn try_acquire(&self) -> CapsuleResult<Option<Slot>> {
+        let slots = self.dir.join("slots");
+        create_dir(&slots)?;
+        for index in 0..self.max {
+            let path = slots.join(format!("{}.lock", index));
+            let file = open_locked(&path, libc::LOCK_EX).map_err(|e| failed(&slots, e))?;
+            if let Some(file) = file {
+                return Ok(Some(Slot { _file: file }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// How many slots no run holds; a slot being probed here may look taken
+    /// to a run trying it at the same moment, which then tries again later
+    fn free_slots(&self) -> usize {
+        let slots = self.dir.join("slots");
+        (0..self.max)
+            .filter(|index| {
+                open_locked(&slots.join(format!("{}.lock", index)), libc::LOCK_SH)
+                    .is_ok_and(|file| file.is_some())
+            })
+            .count()
+    }
+}
+
+/// Join the queue in `dir` at `priority`.
+fn enqueue(dir: &Path, priority: i32) -> CapsuleResult<Ticket> {
+    create_dir(dir)?;
+    let rank = Rank {
+        priority: Reverse(priority),
+        queued_at_ns: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    };
+    // Locked and written under another name first, so no other run sees
+    // it unlocked and takes it for stale
+    let id = Uuid::new_v4();
+    let partial = dir.join(format!("{}.partial", id));
+    let path = dir.join(format!("{}.ticket", id));
+    let mut file = open_locked(&partial, libc::LOCK_EX)
+        .map_err(|e| failed(dir, e))?
+        .ok_or_else(|| failed(dir, std::io::Error::from(std::io::ErrorKind::WouldBlock)))?;
+    writeln!(file, "{} {}", priority, rank.queued_at_ns)
+        .and_then(|()| fs::rename(&partial, &path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&partial);
+            failed(dir, e)
+        })?;
+    Ok(Ticket {
+        _file: file,
+        path,
+        rank,
+    })
+}
+
+/// Ranks of the runs waiting in `dir`, besides `own`. Tickets whose lock is
+/// free were left by runs that ended without removing them, and are removed.
+fn waiting(dir: &Path, own: Option<&Ticket>) -> CapsuleResult<Vec<Rank>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(failed(dir, e)),
+    };
+    Ok(entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ticket"))
+        .filter(|path| own.is_none_or(|own| own.path != *path))
+        .filter_map(|path| {
+            let mut file = File::open(&path).ok()?;
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB) } == 0 {
+                let _ = fs::remove_file(&path);
+                return None;
+            }
+            let mut contents = String::new();
+            file.read_to_string(&mut contents).ok()?;
+            let (priority, queued_at_ns) = contents.trim().split_once(' ')?;
+            Some(Rank {
+                priority: Reverse(priority.parse().ok()?),
+                queued_at_ns: queued_at_ns.parse().ok()?,
+            })
+        })
+        .collect())
+}
+
+/// Open `path`, creating it, and take a `lock` on it without waiting; None
+/// when another run holds a conflicting lock
+fn open_locked(path: &Path, lock: libc::c_int) -> std::io::Result<Option<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .mode(0o600)
+        .open(path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), lock | libc::LOCK_NB) } == 0 {
+        Ok(Some(file))
+    } else {
+        Ok(None)
+    }
+}
+
+fn create_dir(dir: &Path) -> CapsuleResult<()> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .map_err(|e| failed(dir, e))
+}
+
+fn failed(dir: &Path, e: std::io::Error) -> CapsuleError {
+    CapsuleError::Io(std::io::Error::new(
+        e.kind(),
+        format!("Failed to wait for a slot in {}: {}", dir.display(), e),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_concurrency_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let reject = ConcurrencyLimit::new(dir.path(), 2, ConcurrencyOverflow::Reject);
+        let first = reject.acquire(0, None).await.unwrap();
+        let _second = reject.acquire(0, None).await.unwrap();
+        let error = reject.acquire(0, None).await.err().unwrap();
+        assert!(matches!(error, CapsuleError::ConcurrencyLimit { limit: 2 }));
+
+        // A queued run starts once a slot is freed
+        let queue = ConcurrencyLimit::new(dir.path(), 2, ConcurrencyOverflow::Queue);
+        let waiting = tokio::spawn(async move { queue.acquire(0, None).await.map(|_| ()) });
+        tokio::time::sleep(POLL_INTERVAL * 2).await;
+        assert!(!waiting.is_finished());
+        drop(first);
+        waiting.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_queue_priority_and_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let limit = || {
+            std::sync::Arc::new(
+                ConcurrencyLimit::new(dir.path(), 1, ConcurrencyOverflow::Queue)
+                    .with_max_queued(Some(2)),
+            )
+        };
+        let running = limit().acquire(0, None).await.unwrap();
+
+        let error = limit().acquire(0, Some(POLL_INTERVAL)).await.err().unwrap();
+        assert!(matches!(error, CapsuleError::QueueTimeout { .. }));
+
+        let (started, mut order) = tokio::sync::mpsc::unbounded_channel();
+        let queue = |priority: i32| {
+            let limit = limit();
+            let started = started.clone();
+            tokio::spawn(async move {
+                let slot = limit.acquire(priority, None).await.unwrap();
+                started.send(priority).unwrap();
+                tokio::time::sleep(POLL_INTERVAL).await;
+                drop(slot);
+            })
+        };
+        let bulk = queue(0);
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let interactive = queue(10);
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        // Two are waiting already
+        let error = limit().acquire(5, None).await.err().unwrap();
+        assert!(matches!(error, CapsuleError::QueueFull { limit: 2 }));
+
+        // The later, higher priority run goes first
+        drop(running);
+        bulk.await.unwrap();
+        interactive.await.unwrap();
+        assert_eq!(order.recv().await, Some(10));
+        assert_eq!(order.recv().await, Some(0));
+    }
+}
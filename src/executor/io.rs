@@ -1,39 +1,110 @@
 use crate::error::{CapsuleResult, ExecutionError};
 use std::io::Read;
 use std::process::{ChildStderr, ChildStdout};
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
 use std::thread;
 use std::time::Duration;
 
+/// Bytes read from each stream so far, updated as output arrives
+#[derive(Debug, Clone, Default)]
+pub struct OutputCounters {
+    pub stdout: Arc<AtomicU64>,
+    pub stderr: Arc<AtomicU64>,
+}
+
+impl OutputCounters {
+    pub fn get(&self) -> (u64, u64) {
+        (
+            self.stdout.load(Ordering::Relaxed),
+            self.stderr.load(Ordering::Relaxed),
+        )
+    }
+
+    fn tap(&self, stream: &'static str, observer: &Option<Arc<dyn OutputObserver>>) -> Tap {
+        let counter = match stream {
+            "stdout" => &self.stdout,
+            _ => &self.stderr,
+        };
+        Tap {
+            stream,
+            counter: Arc::clone(counter),
+            observer: observer.clone(),
+        }
+    }
+}
+
+/// Sees each chunk of output as it is read, before it is buffered
+pub trait OutputObserver: Send + Sync {
+    /// `data` starts `offset` bytes into `stream`
+    fn chunk(&self, stream: &'static str, offset: u64, data: &[u8]);
+}
+
+/// A capture thread's counter and observer
+struct Tap {
+    stream: &'static str,
+    counter: Arc<AtomicU64>,
+    observer: Option<Arc<dyn OutputObserver>>,
+}
+
+impl Tap {
+    fn record(&self, data: &[u8]) {
+        let offset = self.counter.fetch_add(data.len() as u64, Ordering::Relaxed);
+        if let Some(observer) = &self.observer {
+            observer.chunk(self.stream, offset, data);
+        }
+    }
+}
+
 pub struct IoCapture {
     stdout_handle: Option<thread::JoinHandle<CapsuleResult<Vec<u8>>>>,
     stderr_handle: Option<thread::JoinHandle<CapsuleResult<Vec<u8>>>>,
+    counters: OutputCounters,
     _max_output_size: usize,
 }
 
 impl IoCapture {
+    #[allow(dead_code)] // Library API; the executor always goes through `observed`
     pub fn new(
         stdout: Option<ChildStdout>,
         stderr: Option<ChildStderr>,
         max_output_size: usize,
     ) -> Self {
+        Self::observed(stdout, stderr, max_output_size, None)
+    }
+
+    /// Like `new`, also handing each chunk to `observer` as it is read
+    pub fn observed(
+        stdout: Option<ChildStdout>,
+        stderr: Option<ChildStderr>,
+        max_output_size: usize,
+        observer: Option<Arc<dyn OutputObserver>>,
+    ) -> Self {
+        let counters = OutputCounters::default();
         let stdout_handle = stdout.map(|stdout| {
             let max_size = max_output_size;
-            thread::spawn(move || Self::capture_stream(stdout, max_size, "stdout"))
+            let tap = counters.tap("stdout", &observer);
+            thread::spawn(move || Self::capture_stream(stdout, max_size, &tap))
         });
 
         let stderr_handle = stderr.map(|stderr| {
             let max_size = max_output_size;
-            thread::spawn(move || Self::capture_stream(stderr, max_size, "stderr"))
+            let tap = counters.tap("stderr", &observer);
+            thread::spawn(move || Self::capture_stream(stderr, max_size, &tap))
         });
 
         Self {
             stdout_handle,
             stderr_handle,
+            counters,
             _max_output_size: max_output_size,
         }
     }
 
+    pub fn counters(&self) -> OutputCounters {
+        self.counters.clone()
+    }
+
     pub fn wait_for_completion(self) -> CapsuleResult<(String, String)> {
         let stdout = if let Some(handle) = self.stdout_handle {
             handle.join().map_err(|_| {
@@ -60,7 +131,7 @@ impl IoCapture {
     fn capture_stream<R: Read>(
         mut stream: R,
         max_size: usize,
-        stream_name: &str,
+        tap: &Tap,
     ) -> CapsuleResult<Vec<u8>> {
         let mut buffer = Vec::new();
         let mut temp_buffer = [0u8; 4096];
@@ -73,12 +144,13 @@ impl IoCapture {
                         return Err(ExecutionError::OutputSizeLimit { limit: max_size }.into());
                     }
                     buffer.extend_from_slice(&temp_buffer[..n]);
+                    tap.record(&temp_buffer[..n]);
                 }
                 Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
                 Err(e) => {
                     return Err(ExecutionError::IoCaptureError(format!(
                         "Failed to read from {}: {}",
-                        stream_name, e
+                        tap.stream, e
                     ))
                     .into());
                 }
@@ -95,6 +167,7 @@ pub struct StreamingIoCapture {
     stderr_receiver: Option<mpsc::Receiver<IoEvent>>,
     stdout_handle: Option<thread::JoinHandle<()>>,
     stderr_handle: Option<thread::JoinHandle<()>>,
+    counters: OutputCounters,
 }
 
 #[derive(Debug, Clone)]
@@ -112,10 +185,22 @@ impl StreamingIoCapture {
         stderr: Option<ChildStderr>,
         max_output_size: usize,
     ) -> Self {
+        Self::observed(stdout, stderr, max_output_size, None)
+    }
+
+    /// Like `new`, also handing each chunk to `observer` as it is read
+    pub fn observed(
+        stdout: Option<ChildStdout>,
+        stderr: Option<ChildStderr>,
+        max_output_size: usize,
+        observer: Option<Arc<dyn OutputObserver>>,
+    ) -> Self {
+        let counters = OutputCounters::default();
         let (stdout_receiver, stdout_handle) = if let Some(stdout) = stdout {
             let (tx, rx) = mpsc::channel();
+            let tap = counters.tap("stdout", &observer);
             let handle = thread::spawn(move || {
-                Self::stream_capture(stdout, tx, max_output_size, "stdout");
+                Self::stream_capture(stdout, tx, max_output_size, &tap);
             });
             (Some(rx), Some(handle))
         } else {
@@ -124,8 +209,9 @@ impl StreamingIoCapture {
 
         let (stderr_receiver, stderr_handle) = if let Some(stderr) = stderr {
             let (tx, rx) = mpsc::channel();
+            let tap = counters.tap("stderr", &observer);
             let handle = thread::spawn(move || {
-                Self::stream_capture(stderr, tx, max_output_size, "stderr");
+                Self::stream_capture(stderr, tx, max_output_size, &tap);
             });
             (Some(rx), Some(handle))
         } else {
@@ -137,9 +223,14 @@ impl StreamingIoCapture {
             stderr_receiver,
             stdout_handle,
             stderr_handle,
+            counters,
         }
     }
 
+    pub fn counters(&self) -> OutputCounters {
+        self.counters.clone()
+    }
+
     pub fn read_available(&self, timeout: Duration) -> (Option<IoEvent>, Option<IoEvent>) {
         let stdout_event = self
             .stdout_receiver
@@ -210,7 +301,7 @@ impl StreamingIoCapture {
         mut stream: R,
         sender: mpsc::Sender<IoEvent>,
         max_size: usize,
-        stream_name: &str,
+        tap: &Tap,
     ) {
         let mut total_size = 0;
         let mut buffer = [0u8; 1024];
@@ -231,6 +322,7 @@ impl StreamingIoCapture {
                         break;
                     }
 
+                    tap.record(&buffer[..n]);
                     let data = buffer[..n].to_vec();
                     if sender.send(IoEvent::Data(data)).is_err() {
                         break; // Receiver dropped
@@ -240,7 +332,7 @@ impl StreamingIoCapture {
                 Err(e) => {
                     let _ = sender.send(IoEvent::Error(format!(
                         "Failed to read from {}: {}",
-                        stream_name, e
+                        tap.stream, e
                     )));
                     break;
                 }
@@ -267,12 +359,14 @@ mod tests {
         let stderr = child.stderr.take();
 
         let capture = IoCapture::new(stdout, stderr, 1024);
+        let counters = capture.counters();
         let (stdout_str, stderr_str) = capture.wait_for_completion().unwrap();
 
         child.wait().expect("Failed to wait for child");
 
         assert_eq!(stdout_str.trim(), "hello world");
         assert!(stderr_str.is_empty());
+        assert_eq!(counters.get(), (12, 0));
     }
 
     #[test]
@@ -88,8 +88,7 @@ mod tests {
 
         // This might fail on some systems due to permissions
         if let Ok(stats) = monitor.get_total_stats() {
-            assert!(stats.read_bytes >= 0);
-            assert!(stats.write_bytes >= 0);
+            println!("I/O stats: {:?}", stats);
         }
     }
 
@@ -101,7 +100,6 @@ mod tests {
         // This test might fail if /proc/self/io is not readable
         if let Ok(stats) = get_process_io_stats(pid) {
             println!("I/O stats: {:?}", stats);
-            assert!(stats.read_bytes >= 0);
         }
     }
 }
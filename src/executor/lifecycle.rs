@@ -0,0 +1,252 @@
+use super::io::{OutputCounters, OutputObserver};
+use super::monitor::ResourceProvider;
+use crate::api::schema::{
+    ExecutionResponse, ExecutionStatus, LifecycleEvent, LifecycleEventRecord,
+};
+use crate::error::{CapsuleError, CapsuleResult};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::Utc;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How often output and memory are checked while the command runs
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Share of the memory limit at which `oom_warning` is sent
+const OOM_WARNING_PERCENT: u64 = 90;
+
+/// Lifecycle events written as JSON lines to a file descriptor the caller
+/// passed in, for wrappers that track a run without parsing stderr.
+///
+/// Writes are best effort: once the reader goes away, events are dropped
+/// and the run carries on.
+pub struct EventStream {
+    writer: Mutex<Writer>,
+    execution_id: Uuid,
+    output: bool,
+}
+
+struct Writer {
+    /// None once a write has failed
+    file: Option<File>,
+    seq: u64,
+}
+
+impl EventStream {
+    /// Take over `fd`, which must be open and not stdin, stdout or stderr.
+    /// It is made close-on-exec so the sandboxed command does not inherit it.
+    pub fn from_fd(fd: RawFd, execution_id: Uuid) -> CapsuleResult<Self> {
+        if fd <= libc::STDERR_FILENO {
+            return Err(CapsuleError::Config(format!(
+                "Event fd {} must be 3 or above; stdin, stdout and stderr are taken",
+                fd
+            )));
+        }
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+        if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } < 0 {
+            return Err(CapsuleError::Config(format!(
+                "Event fd {} is not open: {}",
+                fd,
+                std::io::Error::last_os_error()
+            )));
+        }
+        Ok(Self {
+            writer: Mutex::new(Writer {
+                file: Some(unsafe { File::from_raw_fd(fd) }),
+                seq: 0,
+            }),
+            execution_id,
+            output: false,
+        })
+    }
+
+    /// Also send the command's output as `output` events
+    pub fn with_output(mut self) -> Self {
+        self.output = true;
+        self
+    }
+
+    /// Whether the command's output is sent as events
+    pub fn sends_output(&self) -> bool {
+        self.output
+    }
+
+    pub fn emit(&self, event: LifecycleEvent) {
+        // Numbered under the lock, so seq follows the order of the lines
+        let Ok(mut guard) = self.writer.lock() else {
+            return;
+        };
+        let writer = &mut *guard;
+        let Some(file) = writer.file.as_mut() else {
+            return;
+        };
+        let record = LifecycleEventRecord {
+            seq: writer.seq,
+            timestamp: Utc::now(),
+            execution_id: self.execution_id,
+            event,
+        };
+        let Ok(mut line) = serde_json::to_vec(&record) else {
+            return;
+        };
+        line.push(b'\n');
+        if file.write_all(&line).is_err() {
+            writer.file = None;
+        }
+        writer.seq += 1;
+    }
+
+    /// Send `finished` for a run that ended in `outcome`
+    pub fn finished(&self, outcome: Result<&ExecutionResponse, &CapsuleError>) {
+        self.emit(match outcome {
+            Ok(response) => LifecycleEvent::Finished {
+                status: response.status.clone(),
+                exit_code: response.exit_code,
+                error: response.error.as_ref().map(|error| error.code.clone()),
+            },
+            Err(error) => LifecycleEvent::Finished {
+                status: ExecutionStatus::Error,
+                exit_code: None,
+                error: Some(error.to_string()),
+            },
+        });
+    }
+}
+
+impl OutputObserver for EventStream {
+    fn chunk(&self, stream: &'static str, offset: u64, data: &[u8]) {
+        self.emit(LifecycleEvent::Output {
+            stream,
+            offset,
+            length: data.len() as u64,
+            base64: STANDARD.encode(data),
+        });
+    }
+}
+
+/// Sends `output_progress` and `oom_warning` from a background thread while
+/// the command runs; stops when dropped.
+pub struct ProgressReporter {
+    stop_flag: Arc<AtomicBool>,
+}
+
+impl ProgressReporter {
+    pub fn start<P: ResourceProvider + 'static>(
+        events: Arc<EventStream>,
+        counters: OutputCounters,
+        provider: Arc<P>,
+        memory_limit: u64,
+    ) -> Self {
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&stop_flag);
+        thread::spawn(move || {
+            let mut reported = (0, 0);
+            let mut warned = false;
+            while !stop.load(Ordering::Relaxed) {
+                let (stdout_bytes, stderr_bytes) = counters.get();
+                if (stdout_bytes, stderr_bytes) != reported {
+                    reported = (stdout_bytes, stderr_bytes);
+                    events.emit(LifecycleEvent::OutputProgress {
+                        stdout_bytes,
+                        stderr_bytes,
+                    });
+                }
+                if !warned && memory_limit > 0 {
+                    if let Ok(usage) = provider.get_usage() {
+                        if usage.memory_bytes * 100 >= memory_limit * OOM_WARNING_PERCENT {
+                            warned = true;
+                            events.emit(LifecycleEvent::OomWarning {
+                                memory_bytes: usage.memory_bytes,
+                                limit_bytes: memory_limit,
+                            });
+                        }
+                    }
+                }
+                thread::sleep(PROGRESS_INTERVAL);
+            }
+        });
+        Self { stop_flag }
+    }
+}
+
+impl Drop for ProgressReporter {
+    fn drop(&mut self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::ResourceUsage;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::io::IntoRawFd;
+    use std::os::unix::net::UnixStream;
+
+    struct Usage(u64);
+
+    impl ResourceProvider for Usage {
+        fn get_usage(&self) -> CapsuleResult<ResourceUsage> {
+            Ok(ResourceUsage {
+                memory_bytes: self.0,
+                ..Default::default()
+            })
+        }
+
+        fn check_oom_killed(&self) -> CapsuleResult<bool> {
+            Ok(false)
+        }
+    }
+
+    #[test]
+    fn test_event_stream() {
+        assert!(EventStream::from_fd(1, Uuid::new_v4()).is_err());
+        assert!(EventStream::from_fd(1023, Uuid::new_v4()).is_err());
+
+        let (writer, reader) = UnixStream::pair().unwrap();
+        let events = Arc::new(EventStream::from_fd(writer.into_raw_fd(), Uuid::new_v4()).unwrap());
+        events.emit(LifecycleEvent::Spawned { pid: 42 });
+        let counters = OutputCounters::default();
+        counters.stdout.store(5, Ordering::Relaxed);
+        let reporter =
+            ProgressReporter::start(Arc::clone(&events), counters, Arc::new(Usage(95)), 100);
+
+        let mut lines = BufReader::new(reader).lines();
+        let mut next = || -> serde_json::Value {
+            serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap()
+        };
+        let spawned = next();
+        assert_eq!(
+            (spawned["seq"].as_u64(), spawned["pid"].as_u64()),
+            (Some(0), Some(42))
+        );
+        let progress = next();
+        assert_eq!(progress["event"], "output_progress");
+        assert_eq!(progress["stdout_bytes"], 5);
+        assert_eq!(next()["event"], "oom_warning");
+        drop(reporter);
+
+        events.finished(Err(&CapsuleError::Config("refused".to_string())));
+        let finished = next();
+        assert_eq!(finished["event"], "finished");
+        assert_eq!(finished["status"], "error");
+        assert_eq!(finished["seq"], 3);
+
+        events.chunk("stderr", 7, b"warn\n");
+        let output = next();
+        assert_eq!(output["event"], "output");
+        assert_eq!(output["stream"], "stderr");
+        assert_eq!(
+            (output["offset"].as_u64(), output["length"].as_u64()),
+            (Some(7), Some(5))
+        );
+        assert_eq!(output["base64"], "d2Fybgo=");
+    }
+}
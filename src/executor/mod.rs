@@ -1,20 +1,95 @@
+pub mod audit;
+pub mod concurrency;
 pub mod io;
 pub mod io_stats;
+pub mod lifecycle;
 pub mod monitor;
-
-use crate::api::schema::{ExecutionMetrics, ExecutionRequest, ExecutionResponse};
-use crate::error::{CapsuleResult, ErrorCode, ExecutionError};
-use crate::sandbox::{ResourceUsage, Sandbox};
+pub mod tenants;
+pub mod wait;
+
+use crate::api::schema::{
+    ExecutionMetrics, ExecutionPhases, ExecutionRequest, ExecutionResponse, LifecycleEvent,
+};
+use crate::api::PathPolicy;
+use crate::error::{CapsuleError, CapsuleResult, ErrorCode, ExecutionError};
+use crate::policy::{Caller, EnvironmentRules, ExecutableAllowlist, PolicyEngine};
+use crate::sandbox::archive::WorkspaceArchive;
+use crate::sandbox::artifacts::{self, ArtifactSink};
+use crate::sandbox::cache::CacheScope;
+use crate::sandbox::changes::WorkspaceSnapshot;
+use crate::sandbox::registry::Registration;
+use crate::sandbox::source::SourceCheckout;
+use crate::sandbox::trace;
+use crate::sandbox::{inputs, plan, ResourceUsage, Sandbox};
+use audit::AuditLog;
 use chrono::{DateTime, Utc};
-use std::process::{Command, Stdio};
+use concurrency::ConcurrencyLimit;
+use lifecycle::{EventStream, ProgressReporter};
+use std::process::Stdio;
 use std::time::{Duration, Instant};
+use tenants::Tenant;
+use tracing::Instrument;
 use uuid::Uuid;
 
 pub use io::IoCapture;
 
+/// Host-side files a request reads or writes around the run. They are opened
+/// before setup: on Linux the host filesystem is out of reach once the
+/// supervisor has pivoted into the sandbox root.
+struct HostFiles {
+    source: Option<SourceCheckout>,
+    downloads: Vec<inputs::Download>,
+    artifact_sink: Option<ArtifactSink>,
+    workspace_in: Option<WorkspaceArchive>,
+    workspace_out: Option<WorkspaceArchive>,
+}
+
+impl HostFiles {
+    fn open(request: &ExecutionRequest) -> CapsuleResult<Self> {
+        Ok(Self {
+            source: request
+                .source
+                .as_ref()
+                .map(SourceCheckout::prepare)
+                .transpose()?,
+            downloads: inputs::download(&request.inputs)?,
+            artifact_sink: request
+                .output_dir
+                .as_deref()
+                .map(|dir| ArtifactSink::open(std::path::Path::new(dir)))
+                .transpose()?,
+            workspace_in: request
+                .workspace_in
+                .as_deref()
+                .map(WorkspaceArchive::open)
+                .transpose()?,
+            workspace_out: request
+                .workspace_out
+                .as_deref()
+                .map(WorkspaceArchive::create)
+                .transpose()?,
+        })
+    }
+}
+
 pub struct Executor {
     execution_id: Uuid,
     sandbox: std::sync::Arc<Sandbox>,
+    policy: Option<(std::sync::Arc<dyn PolicyEngine>, Caller)>,
+    executables: Option<ExecutableAllowlist>,
+    env_rules: EnvironmentRules,
+    state_dir: Option<std::path::PathBuf>,
+    inherit_stdio: bool,
+    audit: Option<AuditLog>,
+    events: Option<std::sync::Arc<EventStream>>,
+    concurrency: Option<ConcurrencyLimit>,
+    tenant: Option<Tenant>,
+    /// Handed to the sandbox at setup
+    #[cfg(all(target_os = "linux", feature = "seccomp"))]
+    syscall_policy: Option<std::sync::Arc<dyn crate::sandbox::SyscallPolicy>>,
+    /// Handed to the sandbox at setup
+    #[cfg(all(target_os = "linux", feature = "seccomp"))]
+    seccomp_profile: Option<crate::sandbox::seccomp_profile::OciSeccompProfile>,
 }
 
 // pub struct ExecutionResult {
@@ -29,20 +104,233 @@ impl Executor {
         Ok(Self {
             execution_id,
             sandbox,
+            policy: None,
+            executables: None,
+            env_rules: EnvironmentRules::default(),
+            state_dir: None,
+            inherit_stdio: false,
+            audit: None,
+            events: None,
+            concurrency: None,
+            tenant: None,
+            #[cfg(all(target_os = "linux", feature = "seccomp"))]
+            syscall_policy: None,
+            #[cfg(all(target_os = "linux", feature = "seccomp"))]
+            seccomp_profile: None,
         })
     }
 
+    /// Authorize each request with `policy` on behalf of `caller` before the
+    /// sandbox is set up; a denied request fails with a security error.
+    pub fn with_policy(mut self, policy: std::sync::Arc<dyn PolicyEngine>, caller: Caller) -> Self {
+        self.policy = Some((policy, caller));
+        self
+    }
+
+    /// Only run a command whose binary, as found inside the sandbox, is in
+    /// `allowlist`.
+    pub fn with_executable_allowlist(mut self, allowlist: Option<ExecutableAllowlist>) -> Self {
+        self.executables = allowlist;
+        self
+    }
+
+    /// Drop the variables `rules` do not allow from what the command
+    /// inherits from capsule-run's own environment.
+    pub fn with_env_rules(mut self, rules: EnvironmentRules) -> Self {
+        self.env_rules = rules;
+        self
+    }
+
+    /// Register the execution in `dir` while it runs, so `capsule-run ps`
+    /// lists it and `capsule-run kill` can stop it. Registration is best
+    /// effort: a run never fails because the directory is unusable.
+    pub fn with_state_dir(mut self, dir: std::path::PathBuf) -> Self {
+        self.state_dir = Some(dir);
+        self
+    }
+
+    /// Give the command capsule-run's own stdin, stdout and stderr instead of
+    /// capturing its output, which the response then leaves out.
+    pub fn with_inherited_stdio(mut self) -> Self {
+        self.inherit_stdio = true;
+        self
+    }
+
+    /// Append a record of each request and its outcome to `log`. A run whose
+    /// record cannot be written fails, even if the command succeeded.
+    pub fn with_audit_log(mut self, log: Option<AuditLog>) -> Self {
+        self.audit = log;
+        self
+    }
+
+    /// Hold one of `limit`'s slots while the run is set up, runs and is
+    /// cleaned up. A run that gets none fails with E4006 before setup.
+    pub fn with_concurrency_limit(mut self, limit: Option<ConcurrencyLimit>) -> Self {
+        self.concurrency = limit;
+        self
+    }
+
+    /// Count the run against `tenant`'s quotas. A run over one fails with
+    /// E4008 before setup.
+    pub fn with_tenant(mut self, tenant: Option<Tenant>) -> Self {
+        self.tenant = tenant;
+        self
+    }
+
+    /// Write lifecycle events to `events` as the run progresses.
+    pub fn with_event_stream(mut self, events: Option<EventStream>) -> Self {
+        self.events = events.map(std::sync::Arc::new);
+        self
+    }
+
+    /// Check bind sources that resolve through a symlink against `paths`
+    /// rather than the built-in dangerous paths. Requests should have been
+    /// validated with the same policy.
+    #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+    pub fn with_path_policy(mut self, paths: PathPolicy) -> Self {
+        #[cfg(target_os = "linux")]
+        if let Some(sandbox) = std::sync::Arc::get_mut(&mut self.sandbox) {
+            sandbox.filesystem_manager.set_path_policy(paths);
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = paths;
+        self
+    }
+
+    /// Decide the syscalls in `isolation.notify_syscalls` with `policy` while
+    /// the command runs, e.g. to allow `connect` only to certain addresses.
+    #[cfg(all(target_os = "linux", feature = "seccomp"))]
+    #[allow(dead_code)] // Library API; the CLI has no way to supply a policy
+    pub fn with_syscall_policy(
+        mut self,
+        policy: std::sync::Arc<dyn crate::sandbox::SyscallPolicy>,
+    ) -> Self {
+        self.syscall_policy = Some(policy);
+        self
+    }
+
+    /// Install `profile` for the request's `isolation.seccomp_profile`, as
+    /// loaded and checked by `Config::load_seccomp_profile`. A request naming
+    /// a profile fails setup without one.
+    #[cfg(all(target_os = "linux", feature = "seccomp"))]
+    pub fn with_seccomp_profile(
+        mut self,
+        profile: Option<crate::sandbox::seccomp_profile::OciSeccompProfile>,
+    ) -> Self {
+        self.seccomp_profile = profile;
+        self
+    }
+
     pub async fn execute(mut self, request: ExecutionRequest) -> CapsuleResult<ExecutionResponse> {
-        let started = Utc::now();
+        // Everything logged for the run carries its id
+        let span = tracing::info_span!("execution", execution_id = %self.execution_id);
+        let execution_id = self.execution_id;
+        let events = self.events.clone();
+        let audit = self.audit.take().map(|audit| {
+            let caller = match &self.policy {
+                Some((_, caller)) => caller.clone(),
+                None => Caller::current_user(),
+            };
+            (audit, caller, request.clone())
+        });
+        let mut result = self.execute_request(request).instrument(span).await;
+        if let Some((audit, caller, request)) = audit {
+            result = audit
+                .record(execution_id, &caller, &request, result.as_ref())
+                .and(result);
+        }
+        if let Some(events) = events {
+            events.finished(result.as_ref());
+        }
+        result
+    }
 
-        // Setup sandbox
-        match std::sync::Arc::get_mut(&mut self.sandbox)
-            .ok_or_else(|| {
-                crate::error::CapsuleError::Config("Sandbox reference error".to_string())
-            })?
-            .setup(&request.resources, &request.isolation)
-        {
-            Ok(_) => {}
+    async fn execute_request(
+        mut self,
+        request: ExecutionRequest,
+    ) -> CapsuleResult<ExecutionResponse> {
+        let started = Utc::now();
+        if let Some((policy, caller)) = &self.policy {
+            policy.evaluate(&request, caller).into_result()?;
+        }
+        let admission = match &self.tenant {
+            Some(tenant) => match tenant.admit(&request).await {
+                Ok(admission) => Some(admission),
+                Err(e) => return Ok(self.error_response(e, started)),
+            },
+            None => None,
+        };
+        let queue_started = Instant::now();
+        let _slot = match &self.concurrency {
+            Some(limit) => match limit
+                .acquire(
+                    request.priority,
+                    request.queue_timeout_ms.map(Duration::from_millis),
+                )
+                .await
+            {
+                Ok(slot) => Some(slot),
+                Err(e) => return Ok(self.error_response(e, started)),
+            },
+            None => None,
+        };
+        let queue_ms = queue_started.elapsed().as_millis() as u64;
+
+        // Setup sandbox, then populate the workspace from the git source, the
+        // archive, the downloads and the input files. Host files are opened and
+        // downloads fetched first, while the host is still reachable; a missing
+        // executable is caught before any of it
+        let registration = self
+            .state_dir
+            .as_deref()
+            .and_then(|dir| Registration::register(dir, self.execution_id, &request.command).ok());
+        let setup_started = Instant::now();
+        let sandbox = std::sync::Arc::get_mut(&mut self.sandbox).ok_or_else(|| {
+            crate::error::CapsuleError::Config("Sandbox reference error".to_string())
+        })?;
+        #[cfg(all(target_os = "linux", feature = "seccomp"))]
+        if let Some(policy) = self.syscall_policy.take() {
+            sandbox.set_syscall_policy(policy);
+        }
+        #[cfg(all(target_os = "linux", feature = "seccomp"))]
+        if let Some(profile) = self.seccomp_profile.take() {
+            sandbox.set_seccomp_profile(profile);
+        }
+        let setup = plan::check_executable(&request)
+            .and_then(|()| HostFiles::open(&request))
+            .and_then(|host_files| {
+                let cache_scope = CacheScope {
+                    tenant: self.tenant.as_ref().map(|tenant| tenant.name().to_string()),
+                    lock_timeout: request.queue_timeout_ms.map(Duration::from_millis),
+                };
+                sandbox.setup(
+                    &request.resources,
+                    &request.isolation,
+                    request.timeout_ms,
+                    &cache_scope,
+                )?;
+                let workspace = sandbox.workspace_path()?;
+                if let Some(source) = &host_files.source {
+                    source.populate(&workspace)?;
+                }
+                if let Some(archive) = &host_files.workspace_in {
+                    archive.extract(&workspace)?;
+                }
+                inputs::write_downloads(&workspace, &host_files.downloads)?;
+                inputs::write_input_files(&workspace, &request.files)?;
+                // The baseline includes the inputs, so only the command's edits show
+                let baseline = request
+                    .track_changes
+                    .then(|| WorkspaceSnapshot::take(&workspace))
+                    .transpose()?;
+                Ok((host_files, baseline))
+            });
+        let setup_ms = setup_started.elapsed().as_millis() as u64;
+        let (host_files, baseline) = match setup {
+            Ok(setup) => {
+                self.emit(LifecycleEvent::SetupDone { setup_ms });
+                setup
+            }
             Err(e) => {
                 let completed = Utc::now();
                 let error_code = ErrorCode::from(e);
@@ -51,46 +339,159 @@ impl Executor {
                     crate::api::schema::ErrorResponse {
                         code: error_code.code.to_string(),
                         message: error_code.message,
-                        details: None,
+                        details: error_code.details,
                     },
                     started,
                     completed,
                 ));
             }
-        }
+        };
 
         // Execute the command
-        match self.execute_command(&request, started).await {
-            Ok(response) => Ok(response),
+        let mut response = match self
+            .execute_command(&request, started, registration.as_ref())
+            .await
+        {
+            Ok(response) => response,
             Err(e) => {
                 let completed = Utc::now();
                 let error_code = ErrorCode::from(e);
-                Ok(ExecutionResponse::error(
+                ExecutionResponse::error(
                     self.execution_id,
                     crate::api::schema::ErrorResponse {
                         code: error_code.code.to_string(),
                         message: error_code.message,
-                        details: None,
+                        details: error_code.details,
                     },
                     started,
                     completed,
-                ))
+                )
             }
+        };
+
+        if let Err(e) = self.collect_outputs(&request, &host_files, baseline, &mut response) {
+            let error_code = ErrorCode::from(e);
+            response = ExecutionResponse::error(
+                self.execution_id,
+                crate::api::schema::ErrorResponse {
+                    code: error_code.code.to_string(),
+                    message: error_code.message,
+                    details: error_code.details,
+                },
+                started,
+                Utc::now(),
+            );
+        }
+
+        if self.inherit_stdio {
+            response.stdout = None;
+            response.stderr = None;
+        }
+        response.denied_syscalls = self.sandbox.denied_syscalls();
+        response.audit_trail = self.sandbox.audit_trail();
+        response.degraded_features = self.sandbox.degraded_features();
+        response.sandbox = Some(self.sandbox.report());
+        if let (Some(metrics), Some(usage)) =
+            (response.metrics.as_mut(), self.sandbox.network_usage())
+        {
+            metrics.network_rx_bytes = Some(usage.rx_bytes);
+            metrics.network_tx_bytes = Some(usage.tx_bytes);
+            metrics.network_rx_packets = Some(usage.rx_packets);
+            metrics.network_tx_packets = Some(usage.tx_packets);
+            metrics.network_destinations = usage.destinations;
+        }
+
+        // Done with the sandbox; dropping it would clean up all the same,
+        // but only after the response is out
+        let cleanup_started = Instant::now();
+        let _ = self.sandbox.cleanup();
+        if let Some(metrics) = response.metrics.as_mut() {
+            metrics.phases.queue_ms = queue_ms;
+            metrics.phases.sandbox_setup_ms = setup_ms;
+            metrics.phases.cleanup_ms = cleanup_started.elapsed().as_millis() as u64;
+        }
+        if let (Some(admission), Some(metrics)) = (&admission, &response.metrics) {
+            if let Err(e) = admission.charge(metrics.cpu_time_ms) {
+                tracing::warn!(error = %e, "Could not charge the run to its tenant");
+            }
+        }
+        Ok(response)
+    }
+
+    /// The response for a run refused before setup
+    fn error_response(&self, e: CapsuleError, started: DateTime<Utc>) -> ExecutionResponse {
+        let error_code = ErrorCode::from(e);
+        ExecutionResponse::error(
+            self.execution_id,
+            crate::api::schema::ErrorResponse {
+                code: error_code.code.to_string(),
+                message: error_code.message,
+                details: error_code.details,
+            },
+            started,
+            Utc::now(),
+        )
+    }
+
+    /// Diff the workspace against `baseline`, gather the requested artifacts
+    /// and capture the workspace archive.
+    fn collect_outputs(
+        &self,
+        request: &ExecutionRequest,
+        host_files: &HostFiles,
+        baseline: Option<WorkspaceSnapshot>,
+        response: &mut ExecutionResponse,
+    ) -> CapsuleResult<()> {
+        if request.outputs.is_empty() && host_files.workspace_out.is_none() && baseline.is_none() {
+            return Ok(());
+        }
+
+        let workspace = self.sandbox.workspace_path()?;
+        if let Some(baseline) = baseline {
+            let after = WorkspaceSnapshot::take(&workspace)?;
+            response.workspace_changes = Some(baseline.changes(&after));
+        }
+        if !request.outputs.is_empty() {
+            response.artifacts = Some(artifacts::collect(
+                &workspace,
+                &request.outputs,
+                host_files.artifact_sink.as_ref(),
+                request.resources.max_output_bytes,
+            )?);
         }
+        if let Some(archive) = &host_files.workspace_out {
+            archive.capture(&workspace)?;
+        }
+        Ok(())
     }
 
     async fn execute_command(
         &self,
         request: &ExecutionRequest,
         started: DateTime<Utc>,
+        registration: Option<&Registration>,
     ) -> CapsuleResult<ExecutionResponse> {
         let start_time = Instant::now();
         let timeout_duration = Duration::from_millis(request.timeout_ms);
 
-        // Prepare command
-        let mut cmd = Command::new(&request.command[0]);
-        if request.command.len() > 1 {
-            cmd.args(&request.command[1..]);
+        // Prepare command, pinned to the vetted binary under an allowlist
+        // The sandbox may wrap the command in a launcher (macOS)
+        let mut argv = request.command.clone();
+        if let Some(allowlist) = &self.executables {
+            argv[0] = allowlist.verify(request)?.to_string_lossy().into_owned();
+        }
+        let mut cmd = self.sandbox.command(&argv);
+        #[cfg(target_os = "linux")]
+        std::os::unix::process::CommandExt::arg0(&mut cmd, &request.command[0]);
+
+        // Narrow what the command inherits from capsule-run to the allowlist
+        // and the env rules
+        cmd.env_clear();
+        cmd.envs(self.env_rules.inherited(request));
+
+        // Point the command at the proxy; the request's own variables win
+        if let Some(proxy) = &request.isolation.proxy {
+            cmd.envs(proxy.environment());
         }
 
         // Set environment variables
@@ -99,19 +500,47 @@ impl Executor {
         }
 
         // Configure stdio
-        cmd.stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .stdin(Stdio::null());
+        if self.inherit_stdio {
+            cmd.stdout(Stdio::inherit())
+                .stderr(Stdio::inherit())
+                .stdin(Stdio::inherit());
+        } else {
+            cmd.stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .stdin(Stdio::null());
+        }
 
-        // Prepare command with sandbox restrictions (macOS-specific)
-        #[cfg(target_os = "macos")]
+        // Prepare command with per-process sandbox restrictions
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
         self.sandbox.prepare_command(&mut cmd)?;
 
         // Spawn the process
-        let mut child = cmd
-            .spawn()
+        let command = || format!("command={}", trace::quote(&request.command[0]));
+        let mut child = trace::step("spawn", command, || cmd.spawn())
             .map_err(|e| ExecutionError::SpawnFailed(format!("Failed to spawn command: {}", e)))?;
 
+        if let Some(registration) = registration {
+            let _ = registration.set_pid(child.id());
+        }
+        self.emit(LifecycleEvent::Spawned { pid: child.id() });
+
+        // Enforce limits the kernel cannot apply to the whole tree (macOS memory)
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        self.sandbox.track_process(child.id());
+
+        // Record syscalls denied in seccomp log/errno mode
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        self.sandbox.watch_denied_syscalls(child.id());
+
+        // The command is blocked on its first notified syscall until this runs
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        if let Err(e) = self.sandbox.start_syscall_policy() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+        let spawned_at = Instant::now();
+
         // Setup I/O capture
         let stdout = child.stdout.take();
         let stderr = child.stderr.take();
@@ -129,24 +558,41 @@ impl Executor {
                     started,
                     timeout_duration,
                     start_time,
+                    spawned_at,
                 )
                 .await;
         }
 
-        let io_capture = IoCapture::new(stdout, stderr, request.resources.max_output_bytes);
+        let io_capture = IoCapture::observed(
+            stdout,
+            stderr,
+            request.resources.max_output_bytes,
+            self.output_observer(),
+        );
+        let _progress = self.report_progress(io_capture.counters(), request);
 
         // Setup monitoring for the process
         let process_id = child.id();
         let sandbox_provider = std::sync::Arc::clone(&self.sandbox);
-        let resource_monitor = monitor::ResourceMonitor::new(
-            sandbox_provider,
-            std::time::Duration::from_millis(50), // Monitor every 50ms
-        );
+        let resource_monitor = match request.sample_interval_ms {
+            Some(interval_ms) => monitor::ResourceMonitor::with_samples(
+                sandbox_provider,
+                Duration::from_millis(interval_ms),
+            ),
+            None => monitor::ResourceMonitor::new(
+                sandbox_provider,
+                std::time::Duration::from_millis(50), // Monitor every 50ms
+            ),
+        };
 
         // Setup I/O monitoring
         let io_monitor = io_stats::IoMonitor::new(process_id);
 
-        // Enhanced execution loop with better monitoring
+        let mut waiter = wait::ProcessWaiter::new(&self.sandbox, process_id);
+        let deadline = tokio::time::Instant::from_std(start_time + timeout_duration);
+        let mut pids_limit_reached = false;
+
+        // Event-driven execution loop: wakes on exit, timeout or a cgroup limit hit
         loop {
             // Check timeout
             if start_time.elapsed() >= timeout_duration {
@@ -166,12 +612,23 @@ impl Executor {
                 Ok(Some(status)) => {
                     // Process has exited - determine how it exited
                     let exit_code = status.code().unwrap_or(-1);
+                    let exited_at = Instant::now();
 
                     // Check if process was killed by signal
                     #[cfg(unix)]
                     {
                         use std::os::unix::process::ExitStatusExt;
                         if let Some(signal) = status.signal() {
+                            // A SIGKILL from the OOM killer is reported as a memory limit error
+                            if signal == libc::SIGKILL && self.oom_killed() {
+                                let _ = resource_monitor.stop_and_get_result();
+                                return Ok(self.oom_response(request, started));
+                            }
+                            if signal == libc::SIGKILL && self.sandbox.check_cpu_limit_exceeded() {
+                                let _ = resource_monitor.stop_and_get_result();
+                                return Ok(self.cpu_limit_response(request, started));
+                            }
+
                             // Process was killed by signal - create error response
                             let completed = Utc::now();
                             let error = crate::api::schema::ErrorResponse {
@@ -193,6 +650,7 @@ impl Executor {
 
                     // Collect I/O
                     let (stdout, stderr) = io_capture.wait_for_completion()?;
+                    let io_drain = exited_at.elapsed();
 
                     // Stop monitoring and get comprehensive results
                     let monitoring_result = resource_monitor.stop_and_get_result()?;
@@ -200,14 +658,23 @@ impl Executor {
                     // Get final I/O statistics
                     let io_stats = io_monitor.get_total_stats().unwrap_or_default();
 
+                    // Counters the sampler does not track come straight from the sandbox
+                    let sandbox_usage = self.sandbox.get_resource_usage().unwrap_or_default();
+
                     // Get final resource usage from monitoring
                     let final_usage = ResourceUsage {
-                        memory_bytes: monitoring_result.peak_memory,
+                        // Sampling every 50ms misses short spikes; prefer the kernel's peak
+                        memory_bytes: self
+                            .sandbox
+                            .peak_memory()
+                            .unwrap_or(monitoring_result.peak_memory),
                         cpu_time_us: monitoring_result.total_cpu_time,
                         user_time_us: monitoring_result.total_cpu_time / 2, // Approximation
                         kernel_time_us: monitoring_result.total_cpu_time / 2, // Approximation
                         io_bytes_read: io_stats.read_bytes,
                         io_bytes_written: io_stats.write_bytes,
+                        disk_bytes: monitoring_result.peak_disk,
+                        ..sandbox_usage
                     };
 
                     let completed = Utc::now();
@@ -222,6 +689,24 @@ impl Executor {
                         max_memory_bytes: final_usage.memory_bytes,
                         io_bytes_read: final_usage.io_bytes_read,
                         io_bytes_written: final_usage.io_bytes_written,
+                        cpu_throttled_ms: final_usage.cpu_throttled_us / 1000,
+                        peak_disk_bytes: final_usage.disk_bytes,
+                        major_page_faults: final_usage.major_faults,
+                        minor_page_faults: final_usage.minor_faults,
+                        voluntary_context_switches: final_usage.voluntary_ctx_switches,
+                        involuntary_context_switches: final_usage.involuntary_ctx_switches,
+                        pids_peak: final_usage.pids_peak,
+                        network_rx_bytes: None,
+                        network_tx_bytes: None,
+                        network_rx_packets: None,
+                        network_tx_packets: None,
+                        network_destinations: None,
+                        pids_limit_reached,
+                        enforcement: self.sandbox.enforcement(),
+                        samples: request
+                            .sample_interval_ms
+                            .map(|_| monitoring_result.samples),
+                        phases: phases(start_time, spawned_at, exited_at, io_drain),
                     };
 
                     return Ok(ExecutionResponse::success(
@@ -247,27 +732,23 @@ impl Executor {
                 }
             }
 
-            // Check for OOM kill
-            if let Ok(true) = self.sandbox.check_oom_killed() {
-                let _ = child.kill();
-                let _ = resource_monitor.stop_and_get_result(); // Stop monitoring
-                let completed = Utc::now();
-                return Ok(ExecutionResponse::error(
-                    self.execution_id,
-                    crate::api::schema::ErrorResponse {
-                        code: "E4002".to_string(),
-                        message: "Process killed due to memory limit".to_string(),
-                        details: Some(serde_json::json!({
-                            "memory_limit": request.resources.memory_bytes
-                        })),
-                    },
-                    started,
-                    completed,
-                ));
+            match waiter.wait(deadline).await {
+                wait::Wakeup::OomKill => {
+                    let _ = child.kill();
+                    let _ = resource_monitor.stop_and_get_result(); // Stop monitoring
+                    return Ok(self.oom_response(request, started));
+                }
+                wait::Wakeup::PidsLimit => pids_limit_reached = true,
+                wait::Wakeup::Poll => {
+                    // No cgroup events to wait on; fall back to checking memory.events
+                    if let Ok(true) = self.sandbox.check_oom_killed() {
+                        let _ = child.kill();
+                        let _ = resource_monitor.stop_and_get_result(); // Stop monitoring
+                        return Ok(self.oom_response(request, started));
+                    }
+                }
+                wait::Wakeup::ChildExited | wait::Wakeup::Deadline => {}
             }
-
-            // Small sleep to avoid busy waiting
-            tokio::time::sleep(Duration::from_millis(10)).await;
         }
     }
 
@@ -281,15 +762,32 @@ impl Executor {
         started: DateTime<Utc>,
         timeout_duration: Duration,
         start_time: Instant,
+        spawned_at: Instant,
     ) -> CapsuleResult<ExecutionResponse> {
         use io::StreamingIoCapture;
 
         // Setup streaming I/O capture
-        let streaming_io =
-            StreamingIoCapture::new(stdout, stderr, request.resources.max_output_bytes);
+        let streaming_io = StreamingIoCapture::observed(
+            stdout,
+            stderr,
+            request.resources.max_output_bytes,
+            self.output_observer(),
+        );
+        let _progress = self.report_progress(streaming_io.counters(), request);
         let mut stdout_buffer = Vec::new();
         let mut stderr_buffer = Vec::new();
 
+        let mut waiter = wait::ProcessWaiter::new(&self.sandbox, child.id());
+        let mut pids_limit_reached = false;
+
+        // Streaming executions only run the sampler when samples were requested
+        let mut sample_monitor = request.sample_interval_ms.map(|interval_ms| {
+            monitor::ResourceMonitor::with_samples(
+                std::sync::Arc::clone(&self.sandbox),
+                Duration::from_millis(interval_ms),
+            )
+        });
+
         loop {
             // Check timeout
             if start_time.elapsed() >= timeout_duration {
@@ -307,9 +805,11 @@ impl Executor {
             match child.try_wait() {
                 Ok(Some(status)) => {
                     // Process has exited - collect final I/O
+                    let exited_at = Instant::now();
                     let (final_stdout, final_stderr) = streaming_io.collect_remaining()?;
                     stdout_buffer.extend(final_stdout.as_bytes());
                     stderr_buffer.extend(final_stderr.as_bytes());
+                    let io_drain = exited_at.elapsed();
 
                     let exit_code = status.code().unwrap_or(-1);
 
@@ -318,6 +818,13 @@ impl Executor {
                     {
                         use std::os::unix::process::ExitStatusExt;
                         if let Some(signal) = status.signal() {
+                            if signal == libc::SIGKILL && self.oom_killed() {
+                                return Ok(self.oom_response(request, started));
+                            }
+                            if signal == libc::SIGKILL && self.sandbox.check_cpu_limit_exceeded() {
+                                return Ok(self.cpu_limit_response(request, started));
+                            }
+
                             let completed = Utc::now();
                             let error = crate::api::schema::ErrorResponse {
                                 code: "E3003".to_string(),
@@ -337,14 +844,10 @@ impl Executor {
                     }
 
                     // Get resource usage from sandbox
-                    let final_usage = self.sandbox.get_resource_usage().unwrap_or(ResourceUsage {
-                        memory_bytes: 0,
-                        cpu_time_us: 0,
-                        user_time_us: 0,
-                        kernel_time_us: 0,
-                        io_bytes_read: 0,
-                        io_bytes_written: 0,
-                    });
+                    let mut final_usage = self.sandbox.get_resource_usage().unwrap_or_default();
+                    if let Some(peak) = self.sandbox.peak_memory() {
+                        final_usage.memory_bytes = peak;
+                    }
 
                     let completed = Utc::now();
                     let wall_time = start_time.elapsed();
@@ -357,6 +860,25 @@ impl Executor {
                         max_memory_bytes: final_usage.memory_bytes,
                         io_bytes_read: final_usage.io_bytes_read,
                         io_bytes_written: final_usage.io_bytes_written,
+                        cpu_throttled_ms: final_usage.cpu_throttled_us / 1000,
+                        peak_disk_bytes: final_usage.disk_bytes,
+                        major_page_faults: final_usage.major_faults,
+                        minor_page_faults: final_usage.minor_faults,
+                        voluntary_context_switches: final_usage.voluntary_ctx_switches,
+                        involuntary_context_switches: final_usage.involuntary_ctx_switches,
+                        pids_peak: final_usage.pids_peak,
+                        network_rx_bytes: None,
+                        network_tx_bytes: None,
+                        network_rx_packets: None,
+                        network_tx_packets: None,
+                        network_destinations: None,
+                        pids_limit_reached,
+                        enforcement: self.sandbox.enforcement(),
+                        samples: sample_monitor
+                            .take()
+                            .and_then(|monitor| monitor.stop_and_get_result().ok())
+                            .map(|result| result.samples),
+                        phases: phases(start_time, spawned_at, exited_at, io_drain),
                     };
 
                     return Ok(ExecutionResponse::success(
@@ -392,27 +914,118 @@ impl Executor {
                 }
             }
 
-            // Check for OOM kill
-            if let Ok(true) = self.sandbox.check_oom_killed() {
+            // Output is read with a short timeout above, so only consume limit
+            // events that are already pending rather than blocking on them
+            if waiter.watches_oom() {
+                let now = tokio::time::Instant::now();
+                match waiter.wait(now).await {
+                    wait::Wakeup::OomKill => {
+                        let _ = child.kill();
+                        return Ok(self.oom_response(request, started));
+                    }
+                    wait::Wakeup::PidsLimit => pids_limit_reached = true,
+                    _ => {}
+                }
+            } else if let Ok(true) = self.sandbox.check_oom_killed() {
                 let _ = child.kill();
-                let completed = Utc::now();
-                return Ok(ExecutionResponse::error(
-                    self.execution_id,
-                    crate::api::schema::ErrorResponse {
-                        code: "E4002".to_string(),
-                        message: "Process killed due to memory limit".to_string(),
-                        details: Some(serde_json::json!({
-                            "memory_limit": request.resources.memory_bytes
-                        })),
-                    },
-                    started,
-                    completed,
-                ));
+                return Ok(self.oom_response(request, started));
             }
 
             tokio::time::sleep(Duration::from_millis(10)).await;
         }
     }
+
+    fn emit(&self, event: LifecycleEvent) {
+        if let Some(events) = &self.events {
+            events.emit(event);
+        }
+    }
+
+    /// The event stream, when it carries the command's output
+    fn output_observer(&self) -> Option<std::sync::Arc<dyn io::OutputObserver>> {
+        let events = self
+            .events
+            .as_ref()
+            .filter(|events| events.sends_output())?;
+        Some(std::sync::Arc::clone(events) as std::sync::Arc<dyn io::OutputObserver>)
+    }
+
+    /// Send output and memory events until the returned reporter is dropped
+    fn report_progress(
+        &self,
+        counters: io::OutputCounters,
+        request: &ExecutionRequest,
+    ) -> Option<ProgressReporter> {
+        let events = self.events.as_ref()?;
+        Some(ProgressReporter::start(
+            std::sync::Arc::clone(events),
+            counters,
+            std::sync::Arc::clone(&self.sandbox),
+            request.resources.memory_bytes,
+        ))
+    }
+
+    /// Whether the execution's cgroup recorded an OOM kill
+    fn oom_killed(&self) -> bool {
+        self.sandbox.check_oom_killed().unwrap_or(false)
+    }
+
+    fn oom_response(
+        &self,
+        request: &ExecutionRequest,
+        started: DateTime<Utc>,
+    ) -> ExecutionResponse {
+        ExecutionResponse::error(
+            self.execution_id,
+            crate::api::schema::ErrorResponse {
+                code: "E4002".to_string(),
+                message: "Process killed due to memory limit".to_string(),
+                details: Some(serde_json::json!({
+                    "memory_limit": request.resources.memory_bytes,
+                    "peak_memory_bytes": self.sandbox.peak_memory()
+                })),
+            },
+            started,
+            Utc::now(),
+        )
+    }
+
+    /// Response for a process tree killed by the macOS watchdog for
+    /// exceeding its CPU quota
+    fn cpu_limit_response(
+        &self,
+        request: &ExecutionRequest,
+        started: DateTime<Utc>,
+    ) -> ExecutionResponse {
+        ExecutionResponse::error(
+            self.execution_id,
+            crate::api::schema::ErrorResponse {
+                code: "E4005".to_string(),
+                message: "Process killed due to CPU limit".to_string(),
+                details: Some(serde_json::json!({
+                    "cpu_quota_percent": request.resources.cpu_quota_percent
+                })),
+            },
+            started,
+            Utc::now(),
+        )
+    }
+}
+
+/// The phases of a command that started at `start_time`; setup and cleanup
+/// are filled in by the caller
+fn phases(
+    start_time: Instant,
+    spawned_at: Instant,
+    exited_at: Instant,
+    io_drain: Duration,
+) -> ExecutionPhases {
+    ExecutionPhases {
+        spawn_ms: (spawned_at - start_time).as_millis() as u64,
+        execution_ms: (exited_at - spawned_at).as_millis() as u64,
+        io_drain_ms: io_drain.as_millis() as u64,
+        ..Default::default()
+    }
 }
 
 // Implement ResourceProvider directly for Arc<Sandbox> to avoid lifetime issues
@@ -482,6 +1095,33 @@ mod tests {
     use crate::api::schema::{IsolationConfig, ResourceLimits};
     use std::collections::HashMap;
 
+    #[test]
+    fn test_phases() {
+        let start_time = Instant::now();
+        let spawned_at = start_time + Duration::from_millis(3);
+        let exited_at = spawned_at + Duration::from_millis(250);
+        let phases = phases(start_time, spawned_at, exited_at, Duration::from_millis(4));
+        assert_eq!(
+            (phases.spawn_ms, phases.execution_ms, phases.io_drain_ms),
+            (3, 250, 4)
+        );
+        assert_eq!(phases.sandbox_setup_ms + phases.cleanup_ms, 0);
+    }
+
+    #[cfg(all(target_os = "linux", feature = "seccomp"))]
+    #[test]
+    fn test_syscall_policy_kept_while_sandbox_shared() {
+        let Ok(executor) = Executor::new(Uuid::new_v4()) else {
+            return; // Skip test if the sandbox cannot be created
+        };
+        let shared = executor.sandbox.clone();
+        let executor = executor.with_syscall_policy(std::sync::Arc::new(
+            |_: &crate::sandbox::SyscallNotification| crate::sandbox::SyscallDecision::Allow,
+        ));
+        drop(shared);
+        assert!(executor.syscall_policy.is_some());
+    }
+
     #[tokio::test]
     async fn test_executor_simple_command() {
         let execution_id = Uuid::new_v4();
@@ -495,9 +1135,23 @@ mod tests {
         let request = ExecutionRequest {
             command: vec!["echo".to_string(), "hello".to_string()],
             environment: HashMap::new(),
+            environment_inherit: None,
             timeout_ms: 5000,
             resources: ResourceLimits::default(),
             isolation: IsolationConfig::default(),
+            sample_interval_ms: None,
+            outputs: vec![],
+            output_dir: None,
+            workspace_in: None,
+            workspace_out: None,
+            track_changes: false,
+            files: vec![],
+            source: None,
+            inputs: vec![],
+            profile: None,
+            priority: 0,
+            queue_timeout_ms: None,
+            tenant: None,
         };
 
         let result = executor.unwrap().execute(request).await;
@@ -527,9 +1181,23 @@ mod tests {
         let request = ExecutionRequest {
             command: vec!["sleep".to_string(), "10".to_string()],
             environment: HashMap::new(),
+            environment_inherit: None,
             timeout_ms: 100, // Very short timeout
             resources: ResourceLimits::default(),
             isolation: IsolationConfig::default(),
+            sample_interval_ms: None,
+            outputs: vec![],
+            output_dir: None,
+            workspace_in: None,
+            workspace_out: None,
+            track_changes: false,
+            files: vec![],
+            source: None,
+            inputs: vec![],
+            profile: None,
+            priority: 0,
+            queue_timeout_ms: None,
+            tenant: None,
         };
 
         let result = executor.unwrap().execute(request).await;
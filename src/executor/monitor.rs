@@ -1,3 +1,4 @@
+use crate::api::schema::ResourceSample;
 use crate::error::{CapsuleResult, ExecutionError};
 use crate::sandbox::ResourceUsage;
 use std::sync::mpsc;
@@ -8,6 +9,9 @@ use std::sync::{
 use std::thread;
 use std::time::{Duration, Instant};
 
+/// Upper bound on recorded samples, so long executions cannot bloat the response
+const MAX_SAMPLES: usize = 10_000;
+
 #[allow(dead_code)] // Part of API design but not yet used
 pub struct ResourceMonitor {
     stop_flag: Arc<AtomicBool>,
@@ -19,9 +23,12 @@ pub struct ResourceMonitor {
 #[allow(dead_code)] // Part of API design but not yet used
 pub struct MonitoringResult {
     pub peak_memory: u64,
+    pub peak_disk: u64,
     pub total_cpu_time: u64,
     pub wall_time: Duration,
     pub oom_killed: bool,
+    /// Empty unless the monitor was created with `with_samples`
+    pub samples: Vec<ResourceSample>,
 }
 
 pub trait ResourceProvider: Send + Sync {
@@ -34,16 +41,25 @@ impl ResourceMonitor {
     pub fn new<P: ResourceProvider + 'static>(
         provider: Arc<P>,
         monitoring_interval: Duration,
+    ) -> Self {
+        Self::start(provider, monitoring_interval, false)
+    }
+
+    /// Like `new`, but also keeps every reading as a `ResourceSample`.
+    pub fn with_samples<P: ResourceProvider + 'static>(
+        provider: Arc<P>,
+        monitoring_interval: Duration,
+    ) -> Self {
+        Self::start(provider, monitoring_interval, true)
+    }
+
+    fn start<P: ResourceProvider + 'static>(
+        provider: Arc<P>,
+        monitoring_interval: Duration,
+        record_samples: bool,
     ) -> Self {
         let stop_flag = Arc::new(AtomicBool::new(false));
-        let peak_usage = Arc::new(Mutex::new(ResourceUsage {
-            memory_bytes: 0,
-            cpu_time_us: 0,
-            user_time_us: 0,
-            kernel_time_us: 0,
-            io_bytes_read: 0,
-            io_bytes_written: 0,
-        }));
+        let peak_usage = Arc::new(Mutex::new(ResourceUsage::default()));
 
         let monitor_handle = {
             let stop_flag = Arc::clone(&stop_flag);
@@ -57,6 +73,7 @@ impl ResourceMonitor {
                     peak_usage,
                     monitoring_interval,
                     start_time,
+                    record_samples,
                 )
             }))
         };
@@ -93,8 +110,11 @@ impl ResourceMonitor {
         peak_usage: Arc<Mutex<ResourceUsage>>,
         monitoring_interval: Duration,
         start_time: Instant,
+        record_samples: bool,
     ) -> CapsuleResult<MonitoringResult> {
+        let mut samples = Vec::new();
         let mut max_memory = 0u64;
+        let mut max_disk = 0u64;
         let mut final_cpu_time = 0u64;
         let mut oom_killed = false;
 
@@ -104,8 +124,17 @@ impl ResourceMonitor {
                     if usage.memory_bytes > max_memory {
                         max_memory = usage.memory_bytes;
                     }
+                    max_disk = max_disk.max(usage.disk_bytes);
                     final_cpu_time = usage.cpu_time_us;
 
+                    if record_samples && samples.len() < MAX_SAMPLES {
+                        samples.push(ResourceSample {
+                            timestamp: chrono::Utc::now(),
+                            memory_bytes: usage.memory_bytes,
+                            cpu_time_ms: usage.cpu_time_us / 1000,
+                        });
+                    }
+
                     // Update peak usage
                     if let Ok(mut peak) = peak_usage.lock() {
                         if usage.memory_bytes > peak.memory_bytes {
@@ -120,6 +149,7 @@ impl ResourceMonitor {
                         if usage.io_bytes_written > peak.io_bytes_written {
                             peak.io_bytes_written = usage.io_bytes_written;
                         }
+                        peak.disk_bytes = peak.disk_bytes.max(usage.disk_bytes);
                     }
                 }
                 Err(_) => {
@@ -142,13 +172,22 @@ impl ResourceMonitor {
 
         Ok(MonitoringResult {
             peak_memory: max_memory,
+            peak_disk: max_disk,
             total_cpu_time: final_cpu_time,
             wall_time,
             oom_killed,
+            samples,
         })
     }
 }
 
+impl Drop for ResourceMonitor {
+    fn drop(&mut self) {
+        // Early returns drop the monitor without collecting a result
+        self.stop_flag.store(true, Ordering::Relaxed);
+    }
+}
+
 #[allow(dead_code)] // Part of API design but not yet used
 pub struct ProcessMonitor {
     pid: u32,
@@ -357,6 +396,9 @@ mod tests {
                 kernel_time_us: self.cpu_time / 2,
                 io_bytes_read: 1024,
                 io_bytes_written: 512,
+                cpu_throttled_us: 0,
+                disk_bytes: 4096,
+                ..Default::default()
             })
         }
 
@@ -378,9 +420,31 @@ mod tests {
 
         let result = monitor.stop_and_get_result().unwrap();
         assert!(result.peak_memory > 0);
+        assert_eq!(result.peak_disk, 4096);
         assert!(result.wall_time >= Duration::from_millis(50));
     }
 
+    #[test]
+    fn test_resource_monitor_samples() {
+        let provider = Arc::new(MockResourceProvider {
+            memory: 2048,
+            cpu_time: 3000,
+        });
+
+        let monitor = ResourceMonitor::new(Arc::clone(&provider), Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(30));
+        assert!(monitor.stop_and_get_result().unwrap().samples.is_empty());
+
+        let monitor = ResourceMonitor::with_samples(provider, Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(50));
+
+        let samples = monitor.stop_and_get_result().unwrap().samples;
+        assert!(!samples.is_empty());
+        assert_eq!(samples[0].memory_bytes, 2048);
+        assert_eq!(samples[0].cpu_time_ms, 3);
+        assert!(samples.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+    }
+
     #[test]
     fn test_timeout_monitor() {
         let (monitor, _sender) = TimeoutMonitor::new(Duration::from_millis(100));
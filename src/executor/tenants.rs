@@ -0,0 +1,392 @@
+use crate::api::schema::ExecutionRequest;
+use crate::config::{ConcurrencyOverflow, Config, TenantConfig};
+use crate::error::{CapsuleError, CapsuleResult};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::concurrency::{ConcurrencyLimit, Slot};
+
+/// The environment variable a caller presents its tenant token in, which
+/// keeps it out of the command line, the request and the audit log
+pub const TOKEN_VAR: &str = "CAPSULE_TENANT_TOKEN";
+
+/// The window `cpu_seconds_per_hour` is counted over
+const HOUR_SECS: u64 = 3600;
+
+/// A tenant a run is counted against, with its quotas.
+///
+/// Usage lives under `tenants/<name>/` in the state directory, shared by
+/// every capsule-run process there: a slot per concurrent run, as for
+/// `max_concurrent_executions`, and a ledger of the CPU time of the runs
+/// that ended in the last hour. The CPU quota is checked when a run is
+/// admitted, so runs admitted together may overshoot it between them.
+pub struct Tenant {
+    name: String,
+    quota: TenantConfig,
+    dir: PathBuf,
+}
+
+/// A run admitted under its tenant's quotas, holding its slot until dropped
+pub struct Admission {
+    _slot: Option<Slot>,
+    ledger: Option<Ledger>,
+}
+
+impl Tenant {
+    /// The tenant named by `name` or owning `token`, or else
+    /// `[security] default_tenant`. None only when no tenants are configured;
+    /// a request naming none without a default, a name that is unknown, or
+    /// one that needs a token it was not given, is refused.
+    pub fn resolve(
+        config: &Config,
+        state_dir: &Path,
+        name: Option<&str>,
+        token: Option<&str>,
+    ) -> CapsuleResult<Option<Self>> {
+        let by_token = token
+            .map(|token| {
+                let hash = sha256(token);
+                config
+                    .tenants
+                    .iter()
+                    .find(|(_, tenant)| {
+                        tenant
+                            .token_sha256
+                            .as_deref()
+                            .is_some_and(|expected| expected.eq_ignore_ascii_case(&hash))
+                    })
+                    .map(|(name, _)| name.as_str())
+                    .ok_or_else(|| {
+                        CapsuleError::Security(format!("{} matches no tenant", TOKEN_VAR))
+                    })
+            })
+            .transpose()?;
+        if config.tenants.is_empty() && name.is_none() && by_token.is_none() {
+            return Ok(None);
+        }
+        let name = match (name, by_token) {
+            (Some(name), Some(owner)) if name != owner => {
+                return Err(CapsuleError::Security(format!(
+                    "{} belongs to tenant {}, not {}",
+                    TOKEN_VAR, owner, name
+                )))
+            }
+            (_, Some(owner)) => owner,
+            (name, None) => {
+                let name = name
+                    .or(config.security.default_tenant.as_deref())
+                    .ok_or_else(|| {
+                        CapsuleError::Security(format!(
+                            "A tenant is required: name one or set {}",
+                            TOKEN_VAR
+                        ))
+                    })?;
+                let tenant = config
+                    .tenants
+                    .get(name)
+                    .ok_or_else(|| CapsuleError::Security(format!("Unknown tenant {}", name)))?;
+                if tenant.token_sha256.is_some() {
+                    return Err(CapsuleError::Security(format!(
+                        "Tenant {} requires its token in {}",
+                        name, TOKEN_VAR
+                    )));
+                }
+                name
+            }
+        };
+        check_name(name)?;
+        Ok(Some(Self {
+            name: name.to_string(),
+            quota: config.tenants[name].clone(),
+            dir: state_dir.join("tenants").join(name),
+        }))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Check `request` against the tenant's quotas and take one of its
+    /// slots. Files are opened here, before setup, for `Admission::charge`.
+    pub async fn admit(&self, request: &ExecutionRequest) -> CapsuleResult<Admission> {
+        if let Some(max) = self.quota.max_memory_bytes {
+            if request.resources.memory_bytes > max {
+                return Err(self.exceeded("max_memory_bytes", max, request.resources.memory_bytes));
+            }
+        }
+        let ledger = match self.quota.cpu_seconds_per_hour {
+            Some(max) => {
+                let ledger = Ledger::open(&self.dir)?;
+                let used_ms = ledger.used_ms()?;
+                if used_ms >= max * 1000 {
+                    return Err(self.exceeded("cpu_seconds_per_hour", max, used_ms / 1000));
+                }
+                Some(ledger)
+            }
+            None => None,
+        };
+        let slot = match self.quota.max_concurrent_executions {
+            Some(max) => Some(
+                ConcurrencyLimit::new(&self.dir, max, ConcurrencyOverflow::Reject)
+                    .acquire(request.priority, None)
+                    .await
+                    .map_err(|e| match e {
+                        CapsuleError::ConcurrencyLimit { limit } => {
+                            self.exceeded("max_concurrent_executions", limit.into(), limit.into())
+                        }
+                        e => e,
+                    })?,
+            ),
+            None => None,
+        };
+        Ok(Admission {
+            _slot: slot,
+            ledger,
+        })
+    }
+
+    fn exceeded(&self, quota: &str, limit: u64, used: u64) -> CapsuleError {
+        CapsuleError::TenantQuota {
+            tenant: self.name.clone(),
+            quota: quota.to_string(),
+            limit,
+            used,
+        }
+    }
+}
+
+impl Admission {
+    /// Count `cpu_time_ms` of a finished run against the hourly CPU quota.
+    pub fn charge(&self, cpu_time_ms: u64) -> CapsuleResult<()> {
+        match &self.ledger {
+            Some(ledger) => ledger.charge(cpu_time_ms),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Tenant names become directory names, so they are kept to letters,
+/// digits, `-` and `_`.
+pub fn check_name(name: &str) -> CapsuleResult<()> {
+    let valid = !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_');
+    if valid {
+        Ok(())
+    } else {
+        Err(CapsuleError::Config(format!(
+            "Invalid tenant name '{}': use letters, digits, '-' and '_'",
+            name
+        )))
+    }
+}
+
+/// CPU time of the tenant's finished runs, a `<unix seconds> <ms>` line
+/// each, read and rewritten under an flock
+struct Ledger {
+    file: File,
+    path: PathBuf,
+}
+
+impl Ledger {
+    fn open(dir: &Path) -> CapsuleResult<Self> {
+        let path = dir.join("cpu.ledger");
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .and_then(|()| {
+                OpenOptions::new()
+                    .create(true)
+                    .truncate(false)
+                    .read(true)
+                    .write(true)
+                    .mode(0o600)
+                    .open(&path)
+            })
+            .map(|file| Self {
+                file,
+                path: path.clone(),
+            })
+            .map_err(|e| failed(&path, e))
+    }
+
+    /// CPU time charged in the last hour
+    fn used_ms(&self) -> CapsuleResult<u64> {
+        self.locked(|entries| Ok((entries.iter().map(|(_, ms)| ms).sum(), false)))
+    }
+
+    /// Add a run's CPU time, dropping entries older than an hour
+    fn charge(&self, cpu_time_ms: u64) -> CapsuleResult<()> {
+        self.locked(|entries| {
+            entries.push((now_secs(), cpu_time_ms));
+            Ok(((), true))
+        })
+    }
+
+    /// Run `f` on the entries of the last hour with the lock held, writing
+    /// them back when it says so
+    fn locked<T>(
+        &self,
+        f: impl FnOnce(&mut Vec<(u64, u64)>) -> CapsuleResult<(T, bool)>,
+    ) -> CapsuleResult<T> {
+        let mut file = &self.file;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(failed(&self.path, std::io::Error::last_os_error()));
+        }
+        let result = (|| {
+            let mut contents = String::new();
+            file.seek(SeekFrom::Start(0))?;
+            file.read_to_string(&mut contents)?;
+            let since = now_secs().saturating_sub(HOUR_SECS);
+            let mut entries: Vec<(u64, u64)> = contents
+                .lines()
+                .filter_map(|line| {
+                    let (at, ms) = line.split_once(' ')?;
+                    Some((at.parse().ok()?, ms.parse().ok()?))
+                })
+                .filter(|(at, _)| *at >= since)
+                .collect();
+            let (value, write) = f(&mut entries)?;
+            if write {
+                let lines: String = entries
+                    .iter()
+                    .map(|(at, ms)| format!("{} {}\n", at, ms))
+                    .collect();
+                file.set_len(0)?;
+                file.seek(SeekFrom::Start(0))?;
+                file.write_all(lines.as_bytes())?;
+            }
+            Ok(value)
+        })()
+        .map_err(|e: CapsuleError| match e {
+            CapsuleError::Io(e) => failed(&self.path, e),
+            e => e,
+        });
+        unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) };
+        result
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn sha256(data: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn failed(path: &Path, e: std::io::Error) -> CapsuleError {
+    CapsuleError::Io(std::io::Error::new(
+        e.kind(),
+        format!("Failed to update tenant usage {}: {}", path.display(), e),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.tenants.insert(
+            "eval".to_string(),
+            TenantConfig {
+                max_concurrent_executions: Some(1),
+                cpu_seconds_per_hour: Some(10),
+                max_memory_bytes: Some(512 * 1024 * 1024),
+                ..Default::default()
+            },
+        );
+        config.tenants.insert(
+            "agent".to_string(),
+            TenantConfig {
+                token_sha256: Some(sha256("s3cret")),
+                ..Default::default()
+            },
+        );
+        config
+    }
+
+    #[test]
+    fn test_resolve_tenant() {
+        let config = config();
+        let dir = Path::new("/nonexistent");
+        let resolve = |name, token| Tenant::resolve(&config, dir, name, token);
+
+        assert!(resolve(None, None).is_err());
+        assert_eq!(resolve(Some("eval"), None).unwrap().unwrap().name, "eval");
+        assert_eq!(
+            resolve(None, Some("s3cret")).unwrap().unwrap().name,
+            "agent"
+        );
+        assert_eq!(
+            resolve(Some("agent"), Some("s3cret"))
+                .unwrap()
+                .unwrap()
+                .name,
+            "agent"
+        );
+        assert!(resolve(Some("agent"), None).is_err());
+        assert!(resolve(Some("eval"), Some("s3cret")).is_err());
+        assert!(resolve(None, Some("guess")).is_err());
+        assert!(resolve(Some("other"), None).is_err());
+
+        let mut config = self::config();
+        config.security.default_tenant = Some("eval".to_string());
+        let tenant = Tenant::resolve(&config, dir, None, None).unwrap().unwrap();
+        assert_eq!(tenant.name, "eval");
+        config.security.default_tenant = Some("agent".to_string());
+        assert!(Tenant::resolve(&config, dir, None, None).is_err());
+
+        let untenanted = Config::default();
+        assert!(Tenant::resolve(&untenanted, dir, None, None)
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tenant_quotas() {
+        let dir = tempfile::tempdir().unwrap();
+        let tenant = Tenant::resolve(&config(), dir.path(), Some("eval"), None)
+            .unwrap()
+            .unwrap();
+        let mut request: ExecutionRequest =
+            serde_json::from_value(serde_json::json!({"command": ["true"]})).unwrap();
+
+        request.resources.memory_bytes = 1024 * 1024 * 1024;
+        let error = tenant.admit(&request).await.err().unwrap();
+        assert!(matches!(
+            error,
+            CapsuleError::TenantQuota { ref quota, .. } if quota == "max_memory_bytes"
+        ));
+
+        request.resources.memory_bytes = 256 * 1024 * 1024;
+        let admission = tenant.admit(&request).await.unwrap();
+        let error = tenant.admit(&request).await.err().unwrap();
+        assert!(matches!(
+            error,
+            CapsuleError::TenantQuota { ref quota, .. } if quota == "max_concurrent_executions"
+        ));
+
+        admission.charge(12_000).unwrap();
+        drop(admission);
+        let error = tenant.admit(&request).await.err().unwrap();
+        assert!(matches!(
+            error,
+            CapsuleError::TenantQuota { limit: 10, used: 12, ref quota, .. }
+                if quota == "cpu_seconds_per_hour"
+        ));
+    }
+}
@@ -0,0 +1,188 @@
+use crate::sandbox::Sandbox;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[cfg(target_os = "linux")]
+use crate::sandbox::{events, CgroupEventWatcher, LimitEvent};
+#[cfg(target_os = "linux")]
+use std::collections::VecDeque;
+#[cfg(target_os = "linux")]
+use std::os::fd::OwnedFd;
+#[cfg(target_os = "linux")]
+use tokio::io::unix::AsyncFd;
+
+/// Interval used when no event source is available for the child.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Why the execution loop was woken up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)] // Event variants are only produced on Linux
+pub enum Wakeup {
+    /// The child has exited and can be reaped without blocking
+    ChildExited,
+    /// The kernel OOM killer fired in the execution cgroup
+    OomKill,
+    /// A fork failed because pids.max was reached
+    PidsLimit,
+    /// The timeout deadline passed
+    Deadline,
+    /// No event source is available; the caller should poll
+    Poll,
+}
+
+/// Waits for the child to exit, the deadline to pass, or the cgroup to report
+/// a limit hit, without busy polling.
+///
+/// On Linux the child is watched through a pidfd and the cgroup through
+/// inotify on memory.events and pids.events. Elsewhere, or when either is
+/// unavailable, `wait` degrades to a short sleep and returns `Poll`.
+pub struct ProcessWaiter {
+    #[cfg(target_os = "linux")]
+    pidfd: Option<AsyncFd<OwnedFd>>,
+    #[cfg(target_os = "linux")]
+    limit_events: Option<AsyncFd<CgroupEventWatcher>>,
+    #[cfg(target_os = "linux")]
+    pending: VecDeque<Wakeup>,
+}
+
+impl ProcessWaiter {
+    #[cfg(target_os = "linux")]
+    pub fn new(sandbox: &Sandbox, pid: u32) -> Self {
+        let pidfd = events::open_pidfd(pid).and_then(|fd| AsyncFd::new(fd).ok());
+        let limit_events = sandbox
+            .watch_limit_events()
+            .and_then(|watcher| AsyncFd::new(watcher).ok());
+
+        Self {
+            pidfd,
+            limit_events,
+            pending: VecDeque::new(),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn new(_sandbox: &Sandbox, _pid: u32) -> Self {
+        Self {}
+    }
+
+    #[cfg(target_os = "linux")]
+    pub async fn wait(&mut self, deadline: Instant) -> Wakeup {
+        if let Some(wakeup) = self.pending.pop_front() {
+            return wakeup;
+        }
+
+        // Without a pidfd there is nothing to wake us on exit
+        let Some(pidfd) = &self.pidfd else {
+            return Self::sleep(deadline).await;
+        };
+
+        loop {
+            // Limit events win over exit so an OOM kill is not reported as a plain SIGKILL
+            tokio::select! {
+                biased;
+                events = Self::next_limit_events(&mut self.limit_events) => {
+                    self.pending.extend(events.into_iter().map(|event| match event {
+                        LimitEvent::OomKill => Wakeup::OomKill,
+                        LimitEvent::PidsLimit => Wakeup::PidsLimit,
+                    }));
+                    if let Some(wakeup) = self.pending.pop_front() {
+                        return wakeup;
+                    }
+                }
+                _ = pidfd.readable() => return Wakeup::ChildExited,
+                _ = tokio::time::sleep_until(deadline) => return Wakeup::Deadline,
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub async fn wait(&mut self, deadline: Instant) -> Wakeup {
+        Self::sleep(deadline).await
+    }
+
+    /// Whether `Wakeup::OomKill` is reported, so callers can skip polling memory.events
+    pub fn watches_oom(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        {
+            self.limit_events.is_some()
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            false
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn next_limit_events(
+        watcher: &mut Option<AsyncFd<CgroupEventWatcher>>,
+    ) -> Vec<LimitEvent> {
+        let Some(watcher) = watcher else {
+            return std::future::pending().await;
+        };
+
+        match watcher.readable_mut().await {
+            Ok(mut guard) => {
+                let events = guard.get_inner_mut().drain();
+                guard.clear_ready();
+                events
+            }
+            Err(_) => std::future::pending().await,
+        }
+    }
+
+    async fn sleep(deadline: Instant) -> Wakeup {
+        let next_poll = Instant::now() + POLL_INTERVAL;
+        if next_poll >= deadline {
+            tokio::time::sleep_until(deadline).await;
+            Wakeup::Deadline
+        } else {
+            tokio::time::sleep_until(next_poll).await;
+            Wakeup::Poll
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_waiter_reports_deadline() {
+        let sandbox = Sandbox::new(Uuid::new_v4());
+        if let Ok(sandbox) = sandbox {
+            let mut child = std::process::Command::new("sleep")
+                .arg("5")
+                .spawn()
+                .unwrap();
+            let mut waiter = ProcessWaiter::new(&sandbox, child.id());
+
+            let deadline = Instant::now() + Duration::from_millis(50);
+            let mut wakeup = waiter.wait(deadline).await;
+            while wakeup == Wakeup::Poll {
+                wakeup = waiter.wait(deadline).await;
+            }
+            assert_eq!(wakeup, Wakeup::Deadline);
+
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_waiter_reports_child_exit() {
+        let sandbox = Sandbox::new(Uuid::new_v4());
+        if let Ok(sandbox) = sandbox {
+            let mut child = std::process::Command::new("true").spawn().unwrap();
+            let mut waiter = ProcessWaiter::new(&sandbox, child.id());
+
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let mut wakeup = waiter.wait(deadline).await;
+            while wakeup == Wakeup::Poll && child.try_wait().unwrap().is_none() {
+                wakeup = waiter.wait(deadline).await;
+            }
+            assert_ne!(wakeup, Wakeup::Deadline);
+            let _ = child.wait();
+        }
+    }
+}
@@ -1,7 +1,10 @@
 pub mod api;
+pub mod batch;
 pub mod config;
 pub mod error;
 pub mod executor;
+pub mod logging;
+pub mod policy;
 pub mod sandbox;
 
 pub use api::*;
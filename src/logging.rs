@@ -0,0 +1,236 @@
+use crate::error::{CapsuleError, CapsuleResult};
+use crate::sandbox::trace::quote;
+use std::fmt::Write as _;
+use std::os::unix::net::UnixDatagram;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+const IDENTIFIER: &str = "capsule-run";
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Where diagnostics go besides, or instead of, stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogSink {
+    Stderr,
+    Syslog,
+    Journald,
+}
+
+impl std::str::FromStr for LogSink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stderr" => Ok(Self::Stderr),
+            "syslog" => Ok(Self::Syslog),
+            "journald" => Ok(Self::Journald),
+            _ => Err(format!(
+                "invalid log sink '{}' (expected stderr, syslog or journald)",
+                s
+            )),
+        }
+    }
+}
+
+/// A layer forwarding events to the host's syslog or journald, with the
+/// fields of the spans they happen in, such as `execution_id`.
+///
+/// The socket is connected when the layer is made: once the sandbox is set
+/// up the supervisor can no longer reach `/dev/log` or the journal socket
+/// by path.
+pub struct HostLog {
+    target: Target,
+}
+
+enum Target {
+    Syslog,
+    Journald(UnixDatagram),
+}
+
+impl HostLog {
+    /// Log through syslog(3) to the `user` facility, one logfmt line per event
+    pub fn syslog() -> Self {
+        // LOG_NDELAY connects now rather than on the first message
+        unsafe {
+            libc::openlog(
+                c"capsule-run".as_ptr(),
+                libc::LOG_PID | libc::LOG_NDELAY,
+                libc::LOG_USER,
+            )
+        };
+        Self {
+            target: Target::Syslog,
+        }
+    }
+
+    /// Log to journald's native protocol, each field its own journal field
+    pub fn journald() -> CapsuleResult<Self> {
+        let socket = UnixDatagram::unbound()
+            .and_then(|socket| socket.connect(JOURNALD_SOCKET).map(|_| socket))
+            .map_err(|e| {
+                CapsuleError::Config(format!(
+                    "Cannot connect to journald at {}: {}",
+                    JOURNALD_SOCKET, e
+                ))
+            })?;
+        Ok(Self {
+            target: Target::Journald(socket),
+        })
+    }
+}
+
+/// Fields recorded on a span, kept in its extensions
+struct SpanFields(Fields);
+
+#[derive(Default)]
+struct Fields {
+    message: Option<String>,
+    fields: Vec<(&'static str, String)>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.push(field, format!("{:?}", value));
+    }
+}
+
+impl Fields {
+    fn push(&mut self, field: &Field, value: String) {
+        if field.name() == "message" {
+            self.message = Some(value);
+        } else {
+            self.fields.push((field.name(), value));
+        }
+    }
+}
+
+impl<S> Layer<S> for HostLog
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(SpanFields(fields)) = span.extensions_mut().get_mut::<SpanFields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        // Outermost span first, then the event's own fields
+        let mut all = Vec::new();
+        for span in ctx
+            .event_scope(event)
+            .into_iter()
+            .flat_map(|s| s.from_root())
+        {
+            if let Some(SpanFields(span_fields)) = span.extensions().get::<SpanFields>() {
+                all.extend(span_fields.fields.iter().cloned());
+            }
+        }
+        all.append(&mut fields.fields);
+        let message = fields.message.unwrap_or_default();
+        let level = *event.metadata().level();
+
+        match &self.target {
+            Target::Syslog => {
+                let mut line = message;
+                for (name, value) in &all {
+                    let _ = write!(line, " {}={}", name, quote(value));
+                }
+                if let Ok(line) = std::ffi::CString::new(line) {
+                    unsafe { libc::syslog(priority(level), c"%s".as_ptr(), line.as_ptr()) };
+                }
+            }
+            Target::Journald(socket) => {
+                let mut payload = Vec::new();
+                put_field(&mut payload, "PRIORITY", &priority(level).to_string());
+                put_field(&mut payload, "SYSLOG_IDENTIFIER", IDENTIFIER);
+                put_field(&mut payload, "TARGET", event.metadata().target());
+                put_field(&mut payload, "MESSAGE", &message);
+                for (name, value) in &all {
+                    put_field(&mut payload, &journal_name(name), value);
+                }
+                // Best effort: there is nowhere left to report a lost event
+                let _ = socket.send(&payload);
+            }
+        }
+    }
+}
+
+fn priority(level: Level) -> libc::c_int {
+    match level {
+        Level::ERROR => libc::LOG_ERR,
+        Level::WARN => libc::LOG_WARNING,
+        Level::INFO => libc::LOG_INFO,
+        _ => libc::LOG_DEBUG,
+    }
+}
+
+/// A tracing field name as a journal field name: upper case letters, digits
+/// and underscores, not starting with an underscore, which marks fields
+/// only journald itself may set
+fn journal_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    match name.trim_start_matches(|c: char| c == '_' || c.is_ascii_digit()) {
+        "" => "FIELD".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// Append `name=value` in journald's native format; a value with a newline
+/// is sent with its length instead
+fn put_field(payload: &mut Vec<u8>, name: &str, value: &str) {
+    payload.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        payload.push(b'\n');
+        payload.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        payload.push(b'=');
+    }
+    payload.extend_from_slice(value.as_bytes());
+    payload.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_fields() {
+        assert_eq!(journal_name("execution_id"), "EXECUTION_ID");
+        assert_eq!(journal_name("_took.ms"), "TOOK_MS");
+
+        let mut payload = Vec::new();
+        put_field(&mut payload, "MESSAGE", "one\ntwo");
+        put_field(&mut payload, "STEP", "mount");
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&7u64.to_le_bytes());
+        expected.extend_from_slice(b"one\ntwo\nSTEP=mount\n");
+        assert_eq!(payload, expected);
+    }
+}
@@ -1,18 +1,37 @@
 mod api;
+mod batch;
 mod config;
 mod error;
 mod executor;
+mod logging;
+mod policy;
 mod sandbox;
 
+use crate::api::schema::{default_timeout, ExecutionResponse};
+use crate::api::validation::check_execution_request;
 use crate::api::{
-    validate_execution_request, BindMount, ExecutionRequest, IsolationConfig, ResourceLimits,
+    validate_execution_request_with, BindMount, CacheMount, Ceilings, CloneLocation, DnsConfig,
+    EnforcementPolicy, ExecutionRequest, GitSource, IsolationConfig, MacOSProfile, NetworkMode,
+    NetworkPolicy, PathPolicy, ProxyConfig, ResourceLimits, SeccompMode, SeccompPreset,
+    SocketMount, TimeOffset,
+};
+use crate::config::{
+    changed_fields, create_default_config_file, load_config, parse_duration, parse_size,
 };
-use crate::config::{create_default_config_file, load_config};
 use crate::error::CapsuleResult;
+use crate::executor::audit::{self, AuditLog};
+use crate::executor::concurrency::ConcurrencyLimit;
+use crate::executor::lifecycle::EventStream;
+use crate::executor::tenants::{self, Tenant};
 use crate::executor::Executor;
-use clap::{ArgAction, Parser};
+use crate::logging::{HostLog, LogSink};
+use crate::policy::{Caller, EnvironmentRules, ExecutableAllowlist, PolicyDecision};
+use crate::sandbox::registry;
+use clap::{ArgAction, Parser, Subcommand};
 use std::collections::HashMap;
-use std::io::{self, Read};
+use std::io::{self, IsTerminal, Read, Write};
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
 use uuid::Uuid;
 
 #[derive(Parser)]
@@ -24,6 +43,10 @@ struct Cli {
     #[arg(long, action = ArgAction::SetTrue)]
     json: bool,
 
+    /// Read the JSON request from FILE instead of stdin (implies --json)
+    #[arg(long, value_name = "FILE")]
+    request_file: Option<String>,
+
     /// Command timeout in milliseconds
     #[arg(long, short = 't', value_name = "MS")]
     timeout: Option<u64>,
@@ -36,6 +59,46 @@ struct Cli {
     #[arg(long, value_name = "SHARES")]
     cpu: Option<u32>,
 
+    /// Hard CPU cap in percent of one core (e.g., 50, 200)
+    #[arg(long, value_name = "PERCENT")]
+    cpu_quota: Option<u32>,
+
+    /// Pin the execution to these CPUs (e.g., 0-3,6)
+    #[arg(long, value_name = "CPUS")]
+    cpuset: Option<String>,
+
+    /// Disk limit for /tmp and /workspace (e.g., 512M, 2G)
+    #[arg(long, value_name = "SIZE")]
+    disk: Option<String>,
+
+    /// Read bandwidth limit for the workspace device (e.g., 10M per second)
+    #[arg(long, value_name = "SIZE")]
+    io_read_bps: Option<String>,
+
+    /// Write bandwidth limit for the workspace device (e.g., 10M per second)
+    #[arg(long, value_name = "SIZE")]
+    io_write_bps: Option<String>,
+
+    /// Read operations per second limit for the workspace device
+    #[arg(long, value_name = "NUM")]
+    io_read_iops: Option<u64>,
+
+    /// Write operations per second limit for the workspace device
+    #[arg(long, value_name = "NUM")]
+    io_write_iops: Option<u64>,
+
+    /// Bandwidth limit in each direction over the bridge network (e.g., 1M per second); implies bridge
+    #[arg(long, value_name = "SIZE")]
+    network_bps: Option<String>,
+
+    /// Maximum number of open file descriptors
+    #[arg(long, value_name = "NUM")]
+    max_open_files: Option<u32>,
+
+    /// Largest file the command may write (e.g., 100M)
+    #[arg(long, value_name = "SIZE")]
+    max_file_size: Option<String>,
+
     /// Maximum output size (e.g., 1M, 10K)
     #[arg(long, value_name = "SIZE")]
     max_output: Option<String>,
@@ -48,6 +111,118 @@ struct Cli {
     #[arg(long, action = ArgAction::SetTrue)]
     network: bool,
 
+    /// Network access when enabled: host, or bridge for a veth pair with NAT (Linux, root)
+    #[arg(long, value_name = "MODE")]
+    network_mode: Option<NetworkMode>,
+
+    /// Only allow egress to DEST (host, address or address/prefix, optionally :port); implies bridge (repeatable)
+    #[arg(long = "allow-egress", value_name = "DEST")]
+    allow_egress: Vec<String>,
+
+    /// Nameserver for the sandbox's resolv.conf (repeatable, Linux)
+    #[arg(long = "dns", value_name = "ADDR")]
+    dns: Vec<std::net::IpAddr>,
+
+    /// Search domain for the sandbox's resolv.conf (repeatable, Linux)
+    #[arg(long = "dns-search", value_name = "DOMAIN")]
+    dns_search: Vec<String>,
+
+    /// Only resolve DOMAIN and its subdomains, through a filtering stub; implies bridge (repeatable)
+    #[arg(long = "dns-allow", value_name = "DOMAIN")]
+    dns_allow: Vec<String>,
+
+    /// Confine egress to the HTTP(S) proxy at URL and point the command at it; implies bridge
+    #[arg(long, value_name = "URL")]
+    proxy: Option<String>,
+
+    /// Redirect TCP ports 80 and 443 to the --proxy, for clients that ignore HTTP_PROXY
+    #[arg(long, action = ArgAction::SetTrue, requires = "proxy")]
+    proxy_intercept: bool,
+
+    /// Report traffic per destination from conntrack; implies bridge
+    #[arg(long, action = ArgAction::SetTrue)]
+    track_destinations: bool,
+
+    /// Add NAME:ADDR to the sandbox's /etc/hosts (repeatable, Linux)
+    #[arg(long = "add-host", value_name = "NAME:ADDR")]
+    add_host: Vec<String>,
+
+    /// Shift the sandbox's monotonic and boot clocks by SECS (Linux time namespace)
+    #[arg(long, value_name = "SECS", allow_hyphen_values = true)]
+    clock_offset: Option<i64>,
+
+    /// Size of the private /dev/shm tmpfs (e.g., 256M, 1G)
+    #[arg(long, value_name = "SIZE")]
+    shm_size: Option<String>,
+
+    /// Share the host's IPC namespace and /dev/shm instead of private ones
+    #[arg(long, action = ArgAction::SetTrue)]
+    host_ipc: bool,
+
+    /// Let a command on the host network reach abstract Unix sockets outside the sandbox
+    #[arg(long, action = ArgAction::SetTrue)]
+    allow_abstract_sockets: bool,
+
+    /// Keep /workspace in a directory on the host's /tmp instead of a tmpfs
+    #[arg(long, action = ArgAction::SetTrue)]
+    disk_workspace: bool,
+
+    /// File mode creation mask for the sandboxed process, in octal (e.g., 077)
+    #[arg(long, value_name = "MODE")]
+    umask: Option<String>,
+
+    /// Permissions for the /workspace directory, in octal (e.g., 0750)
+    #[arg(long, value_name = "MODE")]
+    workspace_mode: Option<String>,
+
+    /// Owner of the /workspace directory (UID or UID:GID)
+    #[arg(long, value_name = "UID[:GID]")]
+    workspace_owner: Option<String>,
+
+    /// Record resource samples every MS milliseconds into metrics.samples
+    #[arg(long, value_name = "MS")]
+    sample_interval: Option<u64>,
+
+    /// Start before lower priority runs waiting for a concurrency slot
+    #[arg(long, value_name = "N", allow_hyphen_values = true)]
+    priority: Option<i32>,
+
+    /// Fail rather than wait longer than MS milliseconds for a concurrency slot
+    #[arg(long, value_name = "MS")]
+    queue_timeout: Option<u64>,
+
+    /// Count the run against the quotas of this configured tenant
+    #[arg(long, value_name = "NAME")]
+    tenant: Option<String>,
+
+    /// Built-in seccomp preset: strict, default, build or network
+    #[arg(long, value_name = "NAME")]
+    seccomp: Option<SeccompPreset>,
+
+    /// Allow an extra syscall on top of the preset (can be used multiple times)
+    #[arg(long, value_name = "NAME", action = ArgAction::Append)]
+    allow_syscall: Vec<String>,
+
+    /// OCI-format seccomp profile (JSON) to use instead of the built-in allowlist
+    #[arg(long, value_name = "FILE")]
+    seccomp_profile: Option<String>,
+
+    /// Action for syscalls the filter does not allow: kill, log or errno
+    #[arg(long, value_name = "MODE")]
+    seccomp_mode: Option<SeccompMode>,
+
+    /// Base sandbox profile on macOS: strict, default or developer
+    #[arg(long, value_name = "NAME")]
+    macos_profile: Option<MacOSProfile>,
+
+    /// SBPL rule appended to the macOS sandbox profile (can be used multiple times)
+    #[arg(long, value_name = "SBPL", action = ArgAction::Append)]
+    macos_profile_extra: Vec<String>,
+
+    /// Fail instead of running when requested isolation is unavailable: strict or best_effort
+    #[arg(long, value_name = "POLICY")]
+    enforcement: Option<EnforcementPolicy>,
+
     /// Working directory inside the sandbox
     #[arg(long, short = 'w', value_name = "DIR", default_value = "/workspace")]
     workdir: String,
@@ -56,6 +231,10 @@ struct Cli {
     #[arg(long, short = 'e', value_name = "KEY=VALUE", action = ArgAction::Append)]
     env: Vec<String>,
 
+    /// Only inherit these variables from capsule-run's environment (comma-separated)
+    #[arg(long, value_name = "NAMES", value_delimiter = ',')]
+    inherit_env: Option<Vec<String>>,
+
     /// Read-only bind mount (can be used multiple times)
     #[arg(long, value_name = "PATH", action = ArgAction::Append)]
     readonly: Vec<String>,
@@ -64,10 +243,74 @@ struct Cli {
     #[arg(long, value_name = "PATH", action = ArgAction::Append)]
     writable: Vec<String>,
 
+    /// Build the root from these host directories instead of /bin, /usr, /lib and /etc (Linux, repeatable)
+    #[arg(long = "base-mount", value_name = "PATH", action = ArgAction::Append, conflicts_with = "empty_root")]
+    base_mount: Vec<String>,
+
+    /// Start from an empty root with only /dev, /proc, /sys, /tmp and /workspace (Linux)
+    #[arg(long = "empty-root", action = ArgAction::SetTrue)]
+    empty_root: bool,
+
+    /// Hide a path: files read as empty, directories as empty and read-only (repeatable)
+    #[arg(long, value_name = "PATH", action = ArgAction::Append)]
+    mask: Vec<String>,
+
+    /// Mount an empty, writable tmpfs at PATH (Linux, repeatable)
+    #[arg(long, value_name = "PATH", action = ArgAction::Append)]
+    tmpfs: Vec<String>,
+
     /// Bind mount source:dest[:ro|rw] (can be used multiple times)
     #[arg(long, value_name = "SRC:DEST[:MODE]", action = ArgAction::Append)]
     bind: Vec<String>,
 
+    /// Expose a host Unix socket at SOCKET or DEST; must be in security.allowed_unix_sockets (repeatable)
+    #[arg(long = "unix-socket", value_name = "SOCKET[:DEST]", action = ArgAction::Append)]
+    unix_socket: Vec<String>,
+
+    /// Expose a host device node such as /dev/fuse; must be in security.allowed_devices (Linux, repeatable)
+    #[arg(long, value_name = "PATH", action = ArgAction::Append)]
+    device: Vec<String>,
+
+    /// Mount the shared host cache NAME at PATH, capped at SIZE (repeatable)
+    #[arg(long, value_name = "NAME:PATH[:SIZE]", action = ArgAction::Append)]
+    cache: Vec<String>,
+
+    /// Check out this git repository into /workspace before the run
+    #[arg(long, value_name = "URL")]
+    git: Option<String>,
+
+    /// Branch, tag or commit to check out with --git
+    #[arg(long = "git-ref", value_name = "REF", requires = "git")]
+    git_ref: Option<String>,
+
+    /// Fetch only the last N commits with --git
+    #[arg(long = "git-depth", value_name = "N", requires = "git")]
+    git_depth: Option<u32>,
+
+    /// Fetch the --git repository on the host instead of inside the sandbox
+    #[arg(long = "git-on-host", action = ArgAction::SetTrue, requires = "git")]
+    git_on_host: bool,
+
+    /// Collect workspace files matching GLOB after exit, e.g. "dist/**" (repeatable)
+    #[arg(long = "collect", value_name = "GLOB", action = ArgAction::Append)]
+    collect: Vec<String>,
+
+    /// Copy collected files into DIR instead of inlining them in the response
+    #[arg(long, value_name = "DIR", requires = "collect")]
+    collect_dir: Option<String>,
+
+    /// Populate /workspace from a .tar, .tar.gz or .tar.zst archive before the run
+    #[arg(long, value_name = "ARCHIVE")]
+    workspace_in: Option<String>,
+
+    /// Capture /workspace into a .tar, .tar.gz or .tar.zst archive after the run
+    #[arg(long, value_name = "ARCHIVE")]
+    workspace_out: Option<String>,
+
+    /// Report files the command created, modified or deleted in /workspace
+    #[arg(long, action = ArgAction::SetTrue)]
+    track_changes: bool,
+
     /// Execution ID for tracking (auto-generated if not provided)
     #[arg(long, value_name = "UUID")]
     execution_id: Option<String>,
@@ -76,18 +319,79 @@ struct Cli {
     #[arg(long, action = ArgAction::SetTrue)]
     pretty: bool,
 
-    /// Verbose output (show debugging information)
-    #[arg(long, short = 'v', action = ArgAction::SetTrue)]
-    verbose: bool,
+    /// Output format: json, jsonl (one compact line), text (the command's own
+    /// stdout and stderr) or quiet (exit status only)
+    #[arg(long, value_name = "FORMAT", default_value = "json")]
+    output: OutputFormat,
+
+    /// Run as a transparent wrapper: the command uses capsule-run's stdin,
+    /// stdout and stderr, and its exit code is capsule-run's
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "output")]
+    passthrough: bool,
+
+    /// Also write the JSON response to FILE
+    #[arg(long, value_name = "FILE")]
+    report: Option<String>,
+
+    /// Write the JSON response to FILE instead of stdout
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["output", "report"])]
+    response_file: Option<String>,
+
+    /// Write lifecycle events (setup_done, spawned, output_progress,
+    /// oom_warning, finished) as JSON lines to this inherited file descriptor
+    #[arg(long, value_name = "FD")]
+    event_fd: Option<i32>,
+
+    /// Also write the command's output to --event-fd as it arrives
+    #[arg(long, action = ArgAction::SetTrue, requires = "event_fd")]
+    event_output: bool,
+
+    /// Verbose output (show debugging information); -vv also times each sandbox setup step
+    #[arg(long, short = 'v', action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Log format on stderr: text or json; RUST_LOG overrides the level
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    log_format: LogFormat,
+
+    /// Where to log: stderr, syslog or journald (comma-separated or repeated)
+    #[arg(
+        long,
+        value_name = "SINK",
+        value_delimiter = ',',
+        default_value = "stderr",
+        action = ArgAction::Append
+    )]
+    log_sink: Vec<LogSink>,
 
     /// Configuration file path
-    #[arg(long, short = 'c', value_name = "PATH")]
+    #[arg(
+        long,
+        short = 'c',
+        value_name = "PATH",
+        env = "CAPSULE_CONFIG",
+        global = true
+    )]
     config: Option<String>,
 
     /// Execution profile to use from config
-    #[arg(long, short = 'p', value_name = "NAME")]
+    #[arg(
+        long,
+        short = 'p',
+        value_name = "NAME",
+        env = "CAPSULE_PROFILE",
+        global = true
+    )]
     profile: Option<String>,
 
+    /// Print the request after merging config defaults and the profile, without running it
+    #[arg(long, action = ArgAction::SetTrue)]
+    print_effective_config: bool,
+
+    /// Validate the request and print the sandbox it would get, without running it
+    #[arg(long, action = ArgAction::SetTrue)]
+    dry_run: bool,
+
     /// Create default configuration file
     #[arg(long, value_name = "PATH")]
     create_config: Option<String>,
@@ -95,6 +399,261 @@ struct Cli {
     /// Command and arguments to execute
     #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
     command: Vec<String>,
+
+    #[command(subcommand)]
+    subcommand: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Trace a command and write a seccomp profile allowing only the syscalls it used
+    ProfileRecord {
+        /// Write the profile to FILE instead of stdout
+        #[arg(long, short = 'o', value_name = "FILE")]
+        output: Option<String>,
+
+        /// Environment variable (can be used multiple times)
+        #[arg(long, short = 'e', value_name = "KEY=VALUE", action = ArgAction::Append)]
+        env: Vec<String>,
+
+        /// Command and arguments to trace
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        command: Vec<String>,
+    },
+
+    /// Run a request template from the config's [templates]
+    RunTemplate {
+        /// Template name
+        name: String,
+
+        /// Value for a ${NAME} placeholder in the template (can be used multiple times)
+        #[arg(long = "var", value_name = "NAME=VALUE", action = ArgAction::Append)]
+        vars: Vec<String>,
+    },
+
+    /// Run the jobs of a batch file in dependency order, chaining workspaces
+    Batch {
+        /// JSON file with a `jobs` array
+        file: String,
+    },
+
+    /// Create, check or inspect configuration files
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+
+    /// Check the audit log written for [security.audit_log]
+    Audit {
+        #[command(subcommand)]
+        action: AuditCommand,
+    },
+
+    /// Unmount and remove sandbox roots and cgroups left behind by crashed executions
+    Gc {
+        /// Print the report as JSON
+        #[arg(long, action = ArgAction::SetTrue)]
+        json: bool,
+
+        /// Only remove residue last changed at least this long ago (e.g., 90s, 30m, 1h)
+        #[arg(long, value_name = "DURATION")]
+        older_than: Option<String>,
+    },
+
+    /// List running executions with their uptime and memory
+    Ps {
+        /// Print the executions as JSON
+        #[arg(long, action = ArgAction::SetTrue)]
+        json: bool,
+    },
+
+    /// Show a running execution's memory, CPU, pids and I/O from its cgroup
+    Stats {
+        /// Execution ID, or a unique prefix of one
+        execution_id: String,
+
+        /// Print a new sample every second until the execution ends
+        #[arg(long, action = ArgAction::SetTrue)]
+        watch: bool,
+
+        /// Print samples as JSON, one per line
+        #[arg(long, action = ArgAction::SetTrue)]
+        json: bool,
+    },
+
+    /// Send a signal to a running execution's command
+    Kill {
+        /// Execution ID, or a unique prefix of one
+        execution_id: String,
+
+        /// Signal name or number
+        #[arg(long, short = 's', default_value = "KILL")]
+        signal: String,
+    },
+
+    /// Validate a request and print the sandbox it would get, like --dry-run
+    Validate {
+        /// Read the request from stdin as JSON
+        #[arg(long, action = ArgAction::SetTrue)]
+        json: bool,
+
+        /// Command and arguments to check
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+
+    /// Open an interactive shell in a fresh sandbox, set up as a run would be
+    Shell {
+        /// Shell to start, as found inside the sandbox
+        #[arg(long, value_name = "PATH", default_value = "/bin/sh")]
+        shell: String,
+    },
+
+    /// Probe which isolation features this host supports
+    Capabilities {
+        /// Print the feature matrix as JSON
+        #[arg(long, action = ArgAction::SetTrue)]
+        json: bool,
+    },
+
+    /// Check the host for what sandboxes need and say how to fix what is missing
+    Doctor {
+        /// Print the checks as JSON
+        #[arg(long, action = ArgAction::SetTrue)]
+        json: bool,
+    },
+
+    /// Exercise the sandbox primitives and exit 1 if any is broken, for health checks
+    Ping {
+        /// Print the checks as JSON
+        #[arg(long, action = ArgAction::SetTrue)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuditCommand {
+    /// Check the log's hash chain and print how many records it holds
+    Verify {
+        /// Log to check; defaults to the config's log_file
+        #[arg(value_name = "PATH")]
+        path: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Write a config file with every default spelled out
+    Init {
+        /// Where to write it; a .json or .yaml extension picks that format
+        #[arg(value_name = "PATH", default_value = "capsule-run.toml")]
+        path: String,
+
+        /// Overwrite an existing file
+        #[arg(long, action = ArgAction::SetTrue)]
+        force: bool,
+    },
+
+    /// Check a config file's syntax, keys, defaults and profiles
+    Validate {
+        /// File to check; defaults to --config or the usual locations
+        #[arg(value_name = "PATH")]
+        path: Option<String>,
+    },
+
+    /// Print the request defaults after merging the config and --profile
+    ShowEffective {
+        /// Print JSON instead of TOML
+        #[arg(long, action = ArgAction::SetTrue)]
+        json: bool,
+    },
+}
+
+/// How the execution response is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Json,
+    Jsonl,
+    Text,
+    Quiet,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "jsonl" => Ok(Self::Jsonl),
+            "text" => Ok(Self::Text),
+            "quiet" => Ok(Self::Quiet),
+            _ => Err(format!(
+                "invalid output format '{}' (expected json, jsonl, text or quiet)",
+                s
+            )),
+        }
+    }
+}
+
+/// How diagnostics are written to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "invalid log format '{}' (expected text or json)",
+                s
+            )),
+        }
+    }
+}
+
+/// Send diagnostics to `sinks`, keeping stdout for the response. RUST_LOG
+/// wins over the level -v and -vv select.
+fn init_logging(verbose: u8, format: LogFormat, sinks: &[LogSink]) -> CapsuleResult<()> {
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+    use tracing_subscriber::Layer;
+
+    let level = match verbose {
+        0 => "warn",
+        1 => "info",
+        _ => "debug",
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    let stderr = sinks.contains(&LogSink::Stderr).then(|| {
+        let layer = tracing_subscriber::fmt::layer().with_writer(io::stderr);
+        match format {
+            LogFormat::Text => layer
+                .with_ansi(io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none())
+                .with_target(false)
+                .with_timer(tracing_subscriber::fmt::time::uptime())
+                .boxed(),
+            LogFormat::Json => layer.json().boxed(),
+        }
+    });
+    let syslog = sinks.contains(&LogSink::Syslog).then(HostLog::syslog);
+    let journald = sinks
+        .contains(&LogSink::Journald)
+        .then(HostLog::journald)
+        .transpose()?;
+    // Best effort: an embedding test harness may have set one already
+    let _ = tracing_subscriber::registry()
+        .with(filter)
+        .with(stderr)
+        .with(syslog)
+        .with(journald)
+        .try_init();
+    Ok(())
 }
 
 #[tokio::main]
@@ -113,7 +672,8 @@ async fn main() {
 }
 
 async fn run() -> CapsuleResult<i32> {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    init_logging(cli.verbose, cli.log_format, &cli.log_sink)?;
 
     // Handle config creation
     if let Some(config_path) = &cli.create_config {
@@ -121,8 +681,101 @@ async fn run() -> CapsuleResult<i32> {
         return Ok(0);
     }
 
+    if let Some(Commands::ProfileRecord {
+        output,
+        env,
+        command,
+    }) = &cli.subcommand
+    {
+        return record_profile(command, env, output.as_deref());
+    }
+
+    if let Some(Commands::Capabilities { json }) = &cli.subcommand {
+        return print_capabilities(*json, cli.pretty);
+    }
+
+    if let Some(Commands::Ps { json }) = &cli.subcommand {
+        return list_executions(*json, cli.pretty);
+    }
+
+    if let Some(Commands::Stats {
+        execution_id,
+        watch,
+        json,
+    }) = &cli.subcommand
+    {
+        return print_stats(execution_id, *watch, *json, cli.pretty);
+    }
+
+    if let Some(Commands::Kill {
+        execution_id,
+        signal,
+    }) = &cli.subcommand
+    {
+        let signal = registry::parse_signal(signal)?;
+        let record = registry::kill(&registry::state_dir(), execution_id, signal)?;
+        eprintln!("Signalled {}", record.execution_id);
+        return Ok(0);
+    }
+
+    if let Some(Commands::Doctor { json }) = &cli.subcommand {
+        return run_doctor(*json, cli.pretty);
+    }
+
+    if let Some(Commands::Ping { json }) = &cli.subcommand {
+        let config = cli
+            .config
+            .as_ref()
+            .map(std::path::PathBuf::from)
+            .or_else(crate::config::find_config_file);
+        let capabilities = crate::sandbox::capabilities::probe();
+        let checks = crate::sandbox::doctor::ping(&capabilities, config.as_deref());
+        return print_checks(&checks, *json, cli.pretty);
+    }
+
+    if let Some(Commands::Config { action }) = &cli.subcommand {
+        return run_config_command(action, &cli);
+    }
+
+    if let Some(Commands::Batch { file }) = &cli.subcommand {
+        return run_batch(file, &cli).await;
+    }
+
+    if let Some(Commands::Audit { action }) = &cli.subcommand {
+        return run_audit_command(action, &cli);
+    }
+
+    if let Some(Commands::Gc { json, older_than }) = &cli.subcommand {
+        let min_age = older_than
+            .as_deref()
+            .map(parse_duration)
+            .transpose()?
+            .unwrap_or_default();
+        return collect_garbage(*json, min_age, cli.pretty);
+    }
+
+    // Takes its request the same ways a run does
+    if let Some(Commands::Validate { json, command }) = &cli.subcommand {
+        cli.json |= *json;
+        cli.command = command.clone();
+        cli.dry_run = true;
+    }
+
+    // A shell session is a passthrough run of the shell itself
+    let interactive = matches!(cli.subcommand, Some(Commands::Shell { .. }));
+    if let Some(Commands::Shell { shell }) = &cli.subcommand {
+        cli.command = vec![shell.clone(), "-i".to_string()];
+        cli.passthrough = true;
+    }
+
+    cli.json |= cli.request_file.is_some();
+
     // Show help if no command provided and not in JSON mode
-    if !cli.json && cli.command.is_empty() {
+    let template = match &cli.subcommand {
+        Some(Commands::RunTemplate { name, vars }) => Some((name, vars)),
+        _ => None,
+    };
+    if !cli.json && cli.command.is_empty() && template.is_none() {
         eprintln!("Error: No command specified.");
         eprintln!();
         eprintln!("Use --help for usage information or --json to read from stdin.");
@@ -142,18 +795,9 @@ async fn run() -> CapsuleResult<i32> {
         load_config()?
     };
 
-    // Merge with profile if specified
-    let config = config.merge_with_profile(cli.profile.as_deref());
-
-    if cli.verbose {
-        eprintln!("capsule-run v{}", env!("CARGO_PKG_VERSION"));
-        eprintln!(
-            "Execution ID: {}",
-            cli.execution_id.as_deref().unwrap_or("auto-generated")
-        );
-        if let Some(profile) = &cli.profile {
-            eprintln!("Using profile: {}", profile);
-        }
+    info!(version = env!("CARGO_PKG_VERSION"), "capsule-run starting");
+    if let Some(profile) = &cli.profile {
+        info!(profile, "Using profile");
     }
 
     // Parse execution ID or generate one
@@ -165,61 +809,210 @@ async fn run() -> CapsuleResult<i32> {
         Uuid::new_v4()
     };
 
-    if cli.verbose {
-        eprintln!("Using execution ID: {}", execution_id);
-    }
+    info!(%execution_id, "Using execution ID");
 
-    // Create execution request
-    let request = if cli.json {
-        read_json_request()?
+    // Create execution request, layered over the config's defaults and
+    // profile; --profile wins over a request's "profile"
+    let requested_profile = |request: &serde_json::Value| {
+        cli.profile.clone().or_else(|| {
+            request
+                .get("profile")
+                .and_then(serde_json::Value::as_str)
+                .map(String::from)
+        })
+    };
+    let (profile, overrides) = if let Some((name, vars)) = template {
+        let request = config.template_request(name, &parse_template_vars(vars)?)?;
+        (requested_profile(&request), request)
+    } else if cli.json {
+        let request = read_json_request(cli.request_file.as_deref())?;
+        (requested_profile(&request), request)
     } else {
-        create_request_from_cli(&cli, &config)?
+        let request = create_request_from_cli(&cli)?;
+        (cli.profile.clone(), cli_overrides(&request)?)
     };
+    let mut request = config.apply_defaults(profile.as_deref(), overrides)?;
+    request.profile = profile;
+    if let (false, None, Some(timeout)) = (cli.json, template, cli.timeout) {
+        // Explicit, even when it matches the built-in default
+        request.timeout_ms = timeout;
+    }
 
-    if cli.verbose {
-        eprintln!("Command: {:?}", request.command);
-        eprintln!("Timeout: {}ms", request.timeout_ms);
-        eprintln!("Memory limit: {} bytes", request.resources.memory_bytes);
-        eprintln!("Network enabled: {}", request.isolation.network);
+    if cli.print_effective_config {
+        println!("{}", serde_json::to_string_pretty(&request)?);
+        return Ok(0);
     }
 
-    // Validate request
-    validate_execution_request(&request)?;
+    info!(
+        command = ?request.command,
+        timeout_ms = request.timeout_ms,
+        memory_bytes = request.resources.memory_bytes,
+        network = request.isolation.network,
+        "Request ready"
+    );
 
-    // Create executor and run
-    let executor = Executor::new(execution_id)?;
-    let response = executor.execute(request).await?;
+    // Validate request; the executor then checks it against the config's
+    // policy before setting anything up
+    let paths = config.path_policy();
+    let ceilings = config.ceilings(request.profile.as_deref());
+    if interactive && cli.timeout.is_none() {
+        // The session lasts until the user exits, up to the longest run allowed
+        request.timeout_ms = ceilings.timeout_ms;
+    }
+    if cli.dry_run {
+        return print_plan(&config, &request, &paths, &ceilings, cli.pretty);
+    }
+    let warnings = validate_execution_request_with(&request, &paths, &ceilings)?;
+    for warning in &warnings {
+        warn!("{}", warning);
+    }
+    let policy = crate::policy::from_config(&config)?;
 
-    // Output response
-    let json_output = if cli.pretty {
-        serde_json::to_string_pretty(&response)?
+    // Best effort: a run never fails because old residue could not be removed
+    #[cfg(target_os = "linux")]
+    let _ = crate::sandbox::gc::collect_all(crate::sandbox::gc::STARTUP_MIN_AGE);
+
+    // Create executor and run
+    let executor = Executor::new(execution_id)?
+        .with_path_policy(paths)
+        .with_policy(policy, Caller::current_user())
+        .with_executable_allowlist(ExecutableAllowlist::from_config(&config)?)
+        .with_env_rules(EnvironmentRules::from_config(&config)?)
+        .with_state_dir(registry::state_dir())
+        .with_concurrency_limit(ConcurrencyLimit::from_config(
+            &config,
+            &registry::state_dir(),
+        ))
+        .with_tenant(Tenant::resolve(
+            &config,
+            &registry::state_dir(),
+            request.tenant.as_deref(),
+            std::env::var(tenants::TOKEN_VAR).ok().as_deref(),
+        )?)
+        .with_audit_log(AuditLog::from_config(&config)?)
+        .with_event_stream(
+            cli.event_fd
+                .map(|fd| EventStream::from_fd(fd, execution_id))
+                .transpose()?
+                .map(|events| {
+                    if cli.event_output {
+                        events.with_output()
+                    } else {
+                        events
+                    }
+                }),
+        );
+    // Read once here; the sandbox installs this copy rather than the file
+    #[cfg(all(target_os = "linux", feature = "seccomp"))]
+    let executor = executor.with_seccomp_profile(config.load_seccomp_profile(&request.isolation)?);
+    let executor = if cli.passthrough {
+        executor.with_inherited_stdio()
     } else {
-        serde_json::to_string(&response)?
+        executor
     };
+    let mut response = executor.execute(request).await?;
+    response.warnings = warnings;
 
-    println!("{}", json_output);
+    if let Some(file) = cli.report.as_ref().or(cli.response_file.as_ref()) {
+        let json = if cli.pretty {
+            serde_json::to_string_pretty(&response)?
+        } else {
+            serde_json::to_string(&response)?
+        };
+        std::fs::write(file, json + "\n")?;
+    }
+    // Passthrough output is already on the terminal; only errors remain
+    let format = if cli.passthrough {
+        OutputFormat::Text
+    } else if cli.response_file.is_some() {
+        OutputFormat::Quiet
+    } else {
+        cli.output
+    };
+    write_response(&response, format, cli.pretty)?;
 
     // Return appropriate exit code
     match response.status {
         crate::api::ExecutionStatus::Success => Ok(response.exit_code.unwrap_or(0)),
+        // A wrapper dies the way its shell would report it
+        crate::api::ExecutionStatus::Error if cli.passthrough => {
+            Ok(killed_by(&response).map(|signal| 128 + signal).unwrap_or(1))
+        }
         crate::api::ExecutionStatus::Error => Ok(1),
         crate::api::ExecutionStatus::Timeout => Ok(124), // Standard timeout exit code
         crate::api::ExecutionStatus::Killed => Ok(128 + 9), // SIGKILL
     }
 }
 
-fn read_json_request() -> CapsuleResult<ExecutionRequest> {
-    let mut buffer = String::new();
-    io::stdin().read_to_string(&mut buffer)?;
+fn write_response(
+    response: &ExecutionResponse,
+    format: OutputFormat,
+    pretty: bool,
+) -> CapsuleResult<()> {
+    match format {
+        OutputFormat::Json if pretty => {
+            println!("{}", serde_json::to_string_pretty(response)?)
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            println!("{}", serde_json::to_string(response)?)
+        }
+        OutputFormat::Text => {
+            // The command's output as it wrote it, with no trailing newline added
+            if let Some(stdout) = &response.stdout {
+                let mut out = io::stdout().lock();
+                out.write_all(stdout.as_bytes())?;
+                out.flush()?;
+            }
+            if let Some(stderr) = &response.stderr {
+                io::stderr().write_all(stderr.as_bytes())?;
+            }
+            if let Some(error) = &response.error {
+                eprintln!("capsule-run: {}: {}", error.code, error.message);
+            }
+        }
+        OutputFormat::Quiet => {}
+    }
+    Ok(())
+}
+
+/// The signal that killed the command, from an E3003 error's details
+fn killed_by(response: &ExecutionResponse) -> Option<i32> {
+    let error = response.error.as_ref().filter(|e| e.code == "E3003")?;
+    let signal = error.details.as_ref()?.get("signal")?.as_i64()?;
+    i32::try_from(signal).ok()
+}
+
+/// Read a JSON request from `file`, or from stdin without one
+fn read_json_request(file: Option<&str>) -> CapsuleResult<serde_json::Value> {
+    let buffer = match file {
+        Some(file) => std::fs::read_to_string(file).map_err(|e| {
+            crate::error::CapsuleError::Config(format!(
+                "Failed to read request file {}: {}",
+                file, e
+            ))
+        })?,
+        None => {
+            let mut buffer = String::new();
+            io::stdin().read_to_string(&mut buffer)?;
+            buffer
+        }
+    };
 
-    let request: ExecutionRequest = serde_json::from_str(&buffer)?;
+    let request: serde_json::Value = serde_json::from_str(&buffer)?;
     Ok(request)
 }
 
-fn create_request_from_cli(
-    cli: &Cli,
-    config: &crate::config::Config,
-) -> CapsuleResult<ExecutionRequest> {
+/// The settings the CLI actually gives: fields of `request` that differ
+/// from those of a request with nothing set
+fn cli_overrides(request: &ExecutionRequest) -> CapsuleResult<serde_json::Value> {
+    let unset: ExecutionRequest = serde_json::from_value(serde_json::json!({ "command": [] }))?;
+    Ok(changed_fields(
+        serde_json::to_value(request)?,
+        &serde_json::to_value(unset)?,
+    ))
+}
+
+fn create_request_from_cli(cli: &Cli) -> CapsuleResult<ExecutionRequest> {
     if cli.command.is_empty() {
         return Err(crate::error::CapsuleError::Config(
             "No command specified. Use --json for JSON input or provide command arguments."
@@ -227,26 +1020,7 @@ fn create_request_from_cli(
         ));
     }
 
-    // Validate command against security policy
-    if !config.validate_command(&cli.command) {
-        return Err(crate::error::CapsuleError::Security(format!(
-            "Command '{}' is not allowed by security policy",
-            cli.command[0]
-        )));
-    }
-
-    // Parse environment variables
-    let mut environment = HashMap::new();
-    for env_var in &cli.env {
-        if let Some((key, value)) = env_var.split_once('=') {
-            environment.insert(key.to_string(), value.to_string());
-        } else {
-            return Err(crate::error::CapsuleError::Config(format!(
-                "Invalid environment variable format: {}. Use KEY=VALUE.",
-                env_var
-            )));
-        }
-    }
+    let environment = parse_environment(&cli.env)?;
 
     // Parse bind mounts
     let mut bind_mounts = Vec::new();
@@ -272,41 +1046,723 @@ fn create_request_from_cli(
             .map(|s| s as usize)
             .unwrap_or(1_048_576), // 1MB default
         max_pids: cli.max_pids.unwrap_or(100),
+        max_open_files: cli.max_open_files.unwrap_or(1024),
+        cpu_quota_percent: cli.cpu_quota,
+        cpuset: cli.cpuset.clone(),
+        cpuset_mems: None,
+        disk_bytes: cli.disk.as_ref().map(|s| parse_size(s)).transpose()?,
+        io_read_bps: cli
+            .io_read_bps
+            .as_ref()
+            .map(|s| parse_size(s))
+            .transpose()?,
+        io_write_bps: cli
+            .io_write_bps
+            .as_ref()
+            .map(|s| parse_size(s))
+            .transpose()?,
+        io_read_iops: cli.io_read_iops,
+        io_write_iops: cli.io_write_iops,
+        network_bps: cli
+            .network_bps
+            .as_ref()
+            .map(|s| parse_size(s))
+            .transpose()?,
+        max_file_size_bytes: cli
+            .max_file_size
+            .as_ref()
+            .map(|s| parse_size(s))
+            .transpose()?,
+    };
+
+    let extra_hosts = cli
+        .add_host
+        .iter()
+        .map(|entry| parse_host_entry(entry))
+        .collect::<CapsuleResult<_>>()?;
+
+    let (workspace_uid, workspace_gid) = match &cli.workspace_owner {
+        Some(owner) => parse_owner(owner)?,
+        None => (None, None),
+    };
+
+    // Egress rules, the DNS stub, destination tracking and shaping live in
+    // the bridge's network namespace
+    let needs_bridge = !cli.allow_egress.is_empty()
+        || !cli.dns_allow.is_empty()
+        || cli.proxy.is_some()
+        || cli.track_destinations
+        || cli.network_bps.is_some();
+    let network_mode = match cli.network_mode {
+        Some(mode) => mode,
+        None if needs_bridge => NetworkMode::Bridge,
+        None => NetworkMode::Host,
     };
 
     // Create isolation config
     let isolation = IsolationConfig {
-        network: cli.network,
+        // Asking for a bridge or an allowlist implies asking for the network
+        network: cli.network || network_mode == NetworkMode::Bridge,
+        network_mode,
+        network_policy: (!cli.allow_egress.is_empty()).then(|| NetworkPolicy {
+            allow: cli.allow_egress.clone(),
+        }),
+        dns: (!cli.dns.is_empty() || !cli.dns_search.is_empty() || !cli.dns_allow.is_empty()).then(
+            || DnsConfig {
+                nameservers: cli.dns.clone(),
+                search: cli.dns_search.clone(),
+                allowed_domains: cli.dns_allow.clone(),
+            },
+        ),
+        extra_hosts,
+        proxy: cli.proxy.clone().map(|url| ProxyConfig {
+            url,
+            no_proxy: vec![],
+            intercept: cli.proxy_intercept,
+        }),
+        track_destinations: cli.track_destinations,
         readonly_paths: cli.readonly.clone(),
         writable_paths: cli.writable.clone(),
+        base_mounts: (cli.empty_root || !cli.base_mount.is_empty()).then(|| cli.base_mount.clone()),
+        masked_paths: cli.mask.clone(),
+        tmpfs_paths: cli.tmpfs.clone(),
         working_directory: cli.workdir.clone(),
         bind_mounts,
+        block_abstract_sockets: !cli.allow_abstract_sockets,
+        workspace_tmpfs: !cli.disk_workspace,
+        unix_sockets: cli
+            .unix_socket
+            .iter()
+            .map(|spec| match spec.split_once(':') {
+                Some((source, destination)) => SocketMount {
+                    source: source.to_string(),
+                    destination: Some(destination.to_string()),
+                },
+                None => SocketMount {
+                    source: spec.clone(),
+                    destination: None,
+                },
+            })
+            .collect(),
+        devices: cli.device.clone(),
+        fuse_mounts: Vec::new(),
+        caches: cli
+            .cache
+            .iter()
+            .map(|spec| parse_cache(spec))
+            .collect::<CapsuleResult<_>>()?,
+        time_offset: cli.clock_offset.map(|secs| TimeOffset {
+            monotonic_secs: secs,
+            boottime_secs: secs,
+        }),
+        shm_size: cli.shm_size.as_ref().map(|s| parse_size(s)).transpose()?,
+        host_ipc: cli.host_ipc,
+        umask: cli.umask.as_deref().map(parse_mode).transpose()?,
+        workspace_mode: cli.workspace_mode.as_deref().map(parse_mode).transpose()?,
+        workspace_uid,
+        workspace_gid,
+        seccomp: cli.seccomp.unwrap_or_default(),
+        extra_syscalls: cli.allow_syscall.clone(),
+        seccomp_profile: cli.seccomp_profile.clone(),
+        seccomp_mode: cli.seccomp_mode.unwrap_or_default(),
+        notify_syscalls: vec![],
+        macos_profile: cli.macos_profile.unwrap_or_default(),
+        macos_profile_extra: cli.macos_profile_extra.clone(),
+        enforcement: cli.enforcement.unwrap_or_default(),
     };
 
-    // Use config defaults with CLI overrides
-    let timeout_ms = cli.timeout.unwrap_or(config.defaults.timeout_ms);
-
-    // Merge environment variables from profile if available
-    let mut final_environment = environment;
-    if let Some(profile_name) = &cli.profile {
-        if let Some(profile) = config.get_profile(profile_name) {
-            if let Some(profile_env) = &profile.environment {
-                for (key, value) in profile_env {
-                    final_environment
-                        .entry(key.clone())
-                        .or_insert_with(|| value.clone());
-                }
-            }
-        }
-    }
-
+    // Config defaults and the profile are layered under this in run()
     Ok(ExecutionRequest {
         command: cli.command.clone(),
-        environment: final_environment,
-        timeout_ms,
+        environment,
+        // `--inherit-env ""` inherits nothing
+        environment_inherit: cli.inherit_env.as_ref().map(|names| {
+            names
+                .iter()
+                .filter(|name| !name.is_empty())
+                .cloned()
+                .collect()
+        }),
+        timeout_ms: cli.timeout.unwrap_or_else(default_timeout),
         resources,
         isolation,
+        sample_interval_ms: cli.sample_interval,
+        outputs: cli.collect.clone(),
+        output_dir: absolute_path(cli.collect_dir.as_deref())?,
+        workspace_in: absolute_path(cli.workspace_in.as_deref())?,
+        workspace_out: absolute_path(cli.workspace_out.as_deref())?,
+        track_changes: cli.track_changes,
+        files: Vec::new(),
+        inputs: Vec::new(),
+        profile: None,
+        priority: cli.priority.unwrap_or_default(),
+        queue_timeout_ms: cli.queue_timeout,
+        tenant: cli.tenant.clone(),
+        source: cli.git.clone().map(|git| GitSource {
+            git,
+            git_ref: cli.git_ref.clone(),
+            depth: cli.git_depth,
+            clone: if cli.git_on_host {
+                CloneLocation::Host
+            } else {
+                CloneLocation::Sandbox
+            },
+        }),
+    })
+}
+
+/// Host paths in requests are absolute; resolve CLI arguments against the cwd
+fn absolute_path(path: Option<&str>) -> CapsuleResult<Option<String>> {
+    Ok(path
+        .map(std::path::absolute)
+        .transpose()?
+        .map(|path| path.to_string_lossy().into_owned()))
+}
+
+#[cfg(all(target_os = "linux", feature = "seccomp"))]
+fn record_profile(command: &[String], env: &[String], output: Option<&str>) -> CapsuleResult<i32> {
+    use crate::sandbox::seccomp_profile::OciSeccompProfile;
+    use crate::sandbox::syscall_recorder::record_syscalls;
+
+    let recording = record_syscalls(command, &parse_environment(env)?)?;
+    let profile = OciSeccompProfile::allowing(recording.syscalls);
+    let json = serde_json::to_string_pretty(&profile)?;
+
+    match output {
+        Some(path) => std::fs::write(path, json + "\n")?,
+        None => println!("{}", json),
+    }
+
+    eprintln!(
+        "Recorded {} syscalls; command exited with code {}",
+        profile.syscalls[0].names.len(),
+        recording.exit_code
+    );
+    Ok(recording.exit_code)
+}
+
+#[cfg(not(all(target_os = "linux", feature = "seccomp")))]
+fn record_profile(
+    _command: &[String],
+    _env: &[String],
+    _output: Option<&str>,
+) -> CapsuleResult<i32> {
+    Err(crate::error::CapsuleError::Config(
+        "profile-record requires Linux with the seccomp feature enabled".to_string(),
+    ))
+}
+
+/// Run a batch file's jobs, each in a capsule-run process of its own, and
+/// print the report. Exits 1 unless every job succeeded.
+async fn run_batch(file: &str, cli: &Cli) -> CapsuleResult<i32> {
+    use crate::api::schema::BatchFile;
+    use crate::error::CapsuleError;
+
+    let contents = std::fs::read_to_string(file)
+        .map_err(|e| CapsuleError::Config(format!("Failed to read batch file {}: {}", file, e)))?;
+    let batch: BatchFile = serde_json::from_str(&contents)
+        .map_err(|e| CapsuleError::Config(format!("Invalid batch file {}: {}", file, e)))?;
+    // Holds the archives passed between jobs until the batch is done
+    let archives = tempfile::tempdir()?;
+    let jobs = batch::plan(batch, archives.path())?;
+
+    let exe = std::env::current_exe()?;
+    let mut options = Vec::new();
+    if let Some(config) = &cli.config {
+        options.extend(["--config".to_string(), config.clone()]);
+    }
+    if let Some(profile) = &cli.profile {
+        options.extend(["--profile".to_string(), profile.clone()]);
+    }
+    let report = batch::run(&jobs, |job| {
+        let mut command = tokio::process::Command::new(&exe);
+        command
+            .args(&options)
+            .args(["--json", "--output", "json"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        let request = job.request.to_string();
+        async move {
+            use tokio::io::AsyncWriteExt;
+
+            let mut child = command.spawn()?;
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(request.as_bytes()).await?;
+            }
+            let output = child.wait_with_output().await?;
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            eprint!("{}", stderr);
+            serde_json::from_slice(&output.stdout).map_err(|_| {
+                // Refused before it ran, such as by validation
+                let reason = stderr.lines().rev().find(|line| !line.trim().is_empty());
+                CapsuleError::Config(
+                    reason
+                        .map(|line| line.trim_start_matches("Error: ").to_string())
+                        .unwrap_or_else(|| format!("capsule-run exited with {}", output.status)),
+                )
+            })
+        }
     })
+    .await;
+
+    let output = if cli.pretty {
+        serde_json::to_string_pretty(&report)?
+    } else {
+        serde_json::to_string(&report)?
+    };
+    println!("{}", output);
+    Ok(if report.success { 0 } else { 1 })
+}
+
+fn run_audit_command(action: &AuditCommand, cli: &Cli) -> CapsuleResult<i32> {
+    match action {
+        AuditCommand::Verify { path } => {
+            let path = match path {
+                Some(path) => path.clone(),
+                None => {
+                    let config = match &cli.config {
+                        Some(path) => {
+                            crate::config::Config::load_from_file(std::path::Path::new(path))?
+                        }
+                        None => load_config()?,
+                    };
+                    config
+                        .security
+                        .audit_log
+                        .and_then(|audit| audit.log_file)
+                        .ok_or_else(|| {
+                            crate::error::CapsuleError::Config(
+                                "No audit log given and security.audit_log has no log_file"
+                                    .to_string(),
+                            )
+                        })?
+                }
+            };
+            match audit::verify(std::path::Path::new(&path)) {
+                Ok(records) => {
+                    println!("{}: OK, {} records", path, records);
+                    Ok(0)
+                }
+                Err(e @ crate::error::CapsuleError::Security(_)) => {
+                    eprintln!("error: {}", e);
+                    Ok(1)
+                }
+                Err(e) => Err(e),
+            }
+        }
+    }
+}
+
+fn run_config_command(action: &ConfigCommand, cli: &Cli) -> CapsuleResult<i32> {
+    use crate::config::{find_config_file, Config};
+    use std::path::{Path, PathBuf};
+
+    let config_path = || {
+        cli.config
+            .as_ref()
+            .map(PathBuf::from)
+            .or_else(find_config_file)
+    };
+
+    match action {
+        ConfigCommand::Init { path, force } => {
+            let path = Path::new(path);
+            if path.exists() && !force {
+                return Err(crate::error::CapsuleError::Config(format!(
+                    "{} already exists; pass --force to overwrite it",
+                    path.display()
+                )));
+            }
+            create_default_config_file(path)?;
+            Ok(0)
+        }
+        ConfigCommand::Validate { path } => {
+            let path = path
+                .as_ref()
+                .map(PathBuf::from)
+                .or_else(config_path)
+                .ok_or_else(|| {
+                    crate::error::CapsuleError::Config("No config file found".to_string())
+                })?;
+            let report = Config::check_file(&path)?;
+            for warning in &report.warnings {
+                eprintln!("warning: {}", warning);
+            }
+            for error in &report.errors {
+                eprintln!("error: {}", error);
+            }
+            if !report.errors.is_empty() {
+                return Ok(1);
+            }
+            println!("{}: OK", path.display());
+            Ok(0)
+        }
+        ConfigCommand::ShowEffective { json } => {
+            let (config, source) = match config_path() {
+                Some(path) => (Config::load_from_file(&path)?, path.display().to_string()),
+                None => (Config::default(), "built-in defaults".to_string()),
+            };
+            let effective = config.request_defaults(cli.profile.as_deref())?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&effective)?);
+            } else {
+                println!("# Config: {}", source);
+                if let Some(profile) = &cli.profile {
+                    println!("# Profile: {}", profile);
+                }
+                let toml = toml::to_string_pretty(&without_nulls(effective)).map_err(|e| {
+                    crate::error::CapsuleError::Config(format!("Failed to serialize config: {}", e))
+                })?;
+                print!("{}", toml);
+            }
+            Ok(0)
+        }
+    }
+}
+
+/// TOML has no null; an unset setting is simply left out
+fn without_nulls(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(fields) => fields
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| (key, without_nulls(value)))
+            .collect(),
+        serde_json::Value::Array(items) => items.into_iter().map(without_nulls).collect(),
+        value => value,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn collect_garbage(json: bool, min_age: std::time::Duration, pretty: bool) -> CapsuleResult<i32> {
+    let report = crate::sandbox::gc::collect_all(min_age)?;
+
+    if json {
+        let output = if pretty {
+            serde_json::to_string_pretty(&report)?
+        } else {
+            serde_json::to_string(&report)?
+        };
+        println!("{}", output);
+    } else {
+        for path in report.removed.iter().chain(&report.cgroups_removed) {
+            println!("removed {}", path.display());
+        }
+        for (path, reason) in &report.failed {
+            eprintln!("failed {}: {}", path.display(), reason);
+        }
+        println!(
+            "{} roots and {} cgroups removed, {} mounts detached, {} still running, \
+             {} too recent, {} failed",
+            report.removed.len(),
+            report.cgroups_removed.len(),
+            report.unmounted,
+            report.live,
+            report.recent,
+            report.failed.len()
+        );
+    }
+    Ok(if report.failed.is_empty() { 0 } else { 1 })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn collect_garbage(
+    _json: bool,
+    _min_age: std::time::Duration,
+    _pretty: bool,
+) -> CapsuleResult<i32> {
+    Err(crate::error::CapsuleError::Config(
+        "gc requires Linux".to_string(),
+    ))
+}
+
+/// Make every check a run makes before setting anything up, then print the
+/// request with the sandbox plan. Exits 1 when the request would be refused.
+fn print_plan(
+    config: &crate::config::Config,
+    request: &ExecutionRequest,
+    paths: &PathPolicy,
+    ceilings: &Ceilings,
+    pretty: bool,
+) -> CapsuleResult<i32> {
+    let mut report = check_execution_request(request, paths, ceilings);
+    let policy = crate::policy::from_config(config)?;
+    ExecutableAllowlist::from_config(config)?;
+    if let PolicyDecision::Deny(reason) = policy.evaluate(request, &Caller::current_user()) {
+        if !report.errors.contains(&reason) {
+            report.errors.push(reason);
+        }
+    }
+    if let Err(e) = crate::sandbox::plan::check_executable(request) {
+        report.errors.push(e.to_string());
+    }
+    let plan = crate::sandbox::plan::plan(request, &crate::sandbox::capabilities::probe());
+    if request.isolation.enforcement == EnforcementPolicy::Strict {
+        report.errors.extend(plan.unavailable.iter().map(|feature| {
+            crate::error::SandboxError::IsolationUnavailable {
+                feature: feature.feature.clone(),
+                reason: feature.reason.clone(),
+            }
+            .to_string()
+        }));
+    }
+
+    #[derive(serde::Serialize)]
+    struct DryRun<'a> {
+        valid: bool,
+        errors: Vec<String>,
+        warnings: Vec<String>,
+        request: &'a ExecutionRequest,
+        plan: crate::api::schema::SandboxPlan,
+    }
+    let valid = report.errors.is_empty();
+    let output = DryRun {
+        valid,
+        errors: report.errors,
+        warnings: report.warnings,
+        request,
+        plan,
+    };
+    let output = if pretty {
+        serde_json::to_string_pretty(&output)?
+    } else {
+        serde_json::to_string(&output)?
+    };
+    println!("{}", output);
+    Ok(if valid { 0 } else { 1 })
+}
+
+fn print_capabilities(json: bool, pretty: bool) -> CapsuleResult<i32> {
+    let capabilities = crate::sandbox::capabilities::probe();
+
+    if json {
+        let output = if pretty {
+            serde_json::to_string_pretty(&capabilities)?
+        } else {
+            serde_json::to_string(&capabilities)?
+        };
+        println!("{}", output);
+        return Ok(0);
+    }
+
+    let yes_no = |available: bool| if available { "yes" } else { "no" };
+    println!(
+        "capsule-run v{} on {}/{}",
+        capabilities.version, capabilities.os, capabilities.arch
+    );
+    println!(
+        "  user namespaces:      {}",
+        yes_no(capabilities.user_namespaces)
+    );
+    println!(
+        "  time namespaces:      {}",
+        yes_no(capabilities.time_namespaces)
+    );
+    println!("  cgroup v2:            {}", yes_no(capabilities.cgroup_v2));
+    println!(
+        "  cgroup delegated:     {}",
+        yes_no(capabilities.cgroup_delegated)
+    );
+    println!(
+        "  cgroup controllers:   {}",
+        capabilities.cgroup_controllers.join(" ")
+    );
+    println!("  seccomp:              {}", yes_no(capabilities.seccomp));
+    println!(
+        "  seccomp notify:       {}",
+        yes_no(capabilities.seccomp_notify)
+    );
+    println!(
+        "  landlock:             {}",
+        capabilities
+            .landlock_abi
+            .map_or("no".to_string(), |abi| format!("ABI {}", abi))
+    );
+    println!(
+        "  sandbox-exec:         {}",
+        yes_no(capabilities.sandbox_exec)
+    );
+    println!(
+        "  nested sandbox:       {}",
+        yes_no(capabilities.nested_sandbox)
+    );
+    println!(
+        "  endpoint security:    {}",
+        yes_no(capabilities.endpoint_security)
+    );
+    println!(
+        "  resource enforcement: {}",
+        serde_json::to_value(capabilities.resource_enforcement)?
+            .as_str()
+            .unwrap_or_default()
+    );
+    Ok(0)
+}
+
+fn list_executions(json: bool, pretty: bool) -> CapsuleResult<i32> {
+    let executions = registry::running(&registry::state_dir());
+
+    if json {
+        let output = if pretty {
+            serde_json::to_string_pretty(&executions)?
+        } else {
+            serde_json::to_string(&executions)?
+        };
+        println!("{}", output);
+        return Ok(0);
+    }
+
+    println!(
+        "{:<36}  {:>7}  {:>9}  {:>9}  COMMAND",
+        "EXECUTION ID", "PID", "UPTIME", "MEMORY"
+    );
+    for execution in &executions {
+        let secs = execution.uptime_ms / 1000;
+        let uptime = match secs {
+            0..=59 => format!("{}s", secs),
+            60..=3599 => format!("{}m{}s", secs / 60, secs % 60),
+            _ => format!("{}h{}m", secs / 3600, secs % 3600 / 60),
+        };
+        println!(
+            "{:<36}  {:>7}  {:>9}  {:>9}  {}",
+            execution.execution_id,
+            execution.pid.map_or("-".to_string(), |pid| pid.to_string()),
+            uptime,
+            execution
+                .memory_bytes
+                .map_or("-".to_string(), |bytes| format!("{}M", bytes / 1_048_576)),
+            execution.command.join(" ")
+        );
+    }
+    Ok(0)
+}
+
+/// Print samples a second apart, so each has a CPU percentage; one sample
+/// unless `watch`, which stops when the execution ends.
+fn print_stats(execution_id: &str, watch: bool, json: bool, pretty: bool) -> CapsuleResult<i32> {
+    let execution = registry::find(&registry::state_dir(), execution_id)?;
+    let mut previous = registry::stats(&execution)?;
+    if !json {
+        println!(
+            "{:<36}  {:>7}  {:>17}  {:>5}  {:>17}",
+            "EXECUTION ID", "CPU %", "MEMORY / LIMIT", "PIDS", "READ / WRITE"
+        );
+    }
+    loop {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        let Ok(mut current) = registry::stats(&execution) else {
+            eprintln!("Execution {} ended", execution.execution_id);
+            return Ok(0);
+        };
+        current.cpu_percent = registry::cpu_percent(&previous, &current);
+
+        if json {
+            let output = if pretty {
+                serde_json::to_string_pretty(&current)?
+            } else {
+                serde_json::to_string(&current)?
+            };
+            println!("{}", output);
+        } else {
+            let mib = |bytes: u64| format!("{}M", bytes / 1_048_576);
+            println!(
+                "{:<36}  {:>7}  {:>17}  {:>5}  {:>17}",
+                current.execution_id,
+                current
+                    .cpu_percent
+                    .map_or("-".to_string(), |percent| format!("{:.1}", percent)),
+                format!(
+                    "{} / {}",
+                    mib(current.memory_bytes),
+                    current.memory_limit_bytes.map_or("max".to_string(), mib)
+                ),
+                current.pids,
+                format!(
+                    "{} / {}",
+                    mib(current.io_bytes_read),
+                    mib(current.io_bytes_written)
+                )
+            );
+        }
+        if !watch {
+            return Ok(0);
+        }
+        previous = current;
+    }
+}
+
+/// Print each host check with its remediation. Exits 1 when a check
+/// failed, since runs would fail the same way.
+fn run_doctor(json: bool, pretty: bool) -> CapsuleResult<i32> {
+    let capabilities = crate::sandbox::capabilities::probe();
+    let checks = crate::sandbox::doctor::diagnose(&capabilities);
+    if !json {
+        println!(
+            "capsule-run v{} on {}/{}",
+            capabilities.version, capabilities.os, capabilities.arch
+        );
+    }
+    print_checks(&checks, json, pretty)
+}
+
+/// Print doctor or ping checks, returning exit code 1 when any failed
+fn print_checks(
+    checks: &[crate::api::schema::DoctorCheck],
+    json: bool,
+    pretty: bool,
+) -> CapsuleResult<i32> {
+    use crate::api::schema::CheckStatus;
+
+    let failed = checks.iter().any(|check| check.status == CheckStatus::Fail);
+    if json {
+        let output = if pretty {
+            serde_json::to_string_pretty(checks)?
+        } else {
+            serde_json::to_string(checks)?
+        };
+        println!("{}", output);
+    } else {
+        for check in checks {
+            let status = match check.status {
+                CheckStatus::Pass => "ok",
+                CheckStatus::Warn => "warn",
+                CheckStatus::Fail => "FAIL",
+            };
+            println!("  {:<5}{:<19}{}", status, check.name, check.detail);
+            if let Some(remediation) = &check.remediation {
+                println!("  {:<24}fix: {}", "", remediation);
+            }
+        }
+    }
+    Ok(if failed { 1 } else { 0 })
+}
+
+fn parse_template_vars(vars: &[String]) -> CapsuleResult<HashMap<String, String>> {
+    vars.iter()
+        .map(|var| match var.split_once('=') {
+            Some((name, value)) => Ok((name.to_string(), value.to_string())),
+            None => Err(crate::error::CapsuleError::Config(format!(
+                "Invalid template variable: {}. Use NAME=VALUE.",
+                var
+            ))),
+        })
+        .collect()
+}
+
+fn parse_environment(vars: &[String]) -> CapsuleResult<HashMap<String, String>> {
+    let mut environment = HashMap::new();
+    for env_var in vars {
+        if let Some((key, value)) = env_var.split_once('=') {
+            environment.insert(key.to_string(), value.to_string());
+        } else {
+            return Err(crate::error::CapsuleError::Config(format!(
+                "Invalid environment variable format: {}. Use KEY=VALUE.",
+                env_var
+            )));
+        }
+    }
+    Ok(environment)
 }
 
 fn parse_bind_mount(spec: &str) -> CapsuleResult<BindMount> {
@@ -345,32 +1801,60 @@ fn parse_bind_mount(spec: &str) -> CapsuleResult<BindMount> {
     }
 }
 
-fn parse_size(size_str: &str) -> CapsuleResult<u64> {
-    let size_str = size_str.trim().to_uppercase();
-
-    if let Some(number_part) = size_str.strip_suffix('K') {
-        let number: u64 = number_part.parse().map_err(|_| {
-            crate::error::CapsuleError::Config(format!("Invalid size format: {}", size_str))
-        })?;
-        Ok(number * 1024)
-    } else if let Some(number_part) = size_str.strip_suffix('M') {
-        let number: u64 = number_part.parse().map_err(|_| {
-            crate::error::CapsuleError::Config(format!("Invalid size format: {}", size_str))
-        })?;
-        Ok(number * 1024 * 1024)
-    } else if let Some(number_part) = size_str.strip_suffix('G') {
-        let number: u64 = number_part.parse().map_err(|_| {
-            crate::error::CapsuleError::Config(format!("Invalid size format: {}", size_str))
-        })?;
-        Ok(number * 1024 * 1024 * 1024)
-    } else {
-        // Assume bytes
-        size_str.parse().map_err(|_| {
-            crate::error::CapsuleError::Config(format!("Invalid size format: {}", size_str))
-        })
+fn parse_cache(spec: &str) -> CapsuleResult<CacheMount> {
+    let parts: Vec<&str> = spec.splitn(3, ':').collect();
+    match parts[..] {
+        [name, path] | [name, path, _] => Ok(CacheMount {
+            name: name.to_string(),
+            path: path.to_string(),
+            size_bytes: parts.get(2).map(|size| parse_size(size)).transpose()?,
+        }),
+        _ => Err(crate::error::CapsuleError::Config(format!(
+            "Invalid cache format '{}'. Use 'name:path' or 'name:path:size'.",
+            spec
+        ))),
     }
 }
 
+fn parse_mode(mode_str: &str) -> CapsuleResult<u32> {
+    u32::from_str_radix(mode_str.trim().trim_start_matches("0o"), 8).map_err(|_| {
+        crate::error::CapsuleError::Config(format!(
+            "Invalid octal mode '{}'. Use e.g. '022' or '0750'.",
+            mode_str
+        ))
+    })
+}
+
+fn parse_owner(owner: &str) -> CapsuleResult<(Option<u32>, Option<u32>)> {
+    let invalid = || {
+        crate::error::CapsuleError::Config(format!(
+            "Invalid owner format '{}'. Use 'UID' or 'UID:GID'.",
+            owner
+        ))
+    };
+
+    match owner.split_once(':') {
+        Some((uid, gid)) => Ok((
+            Some(uid.parse().map_err(|_| invalid())?),
+            Some(gid.parse().map_err(|_| invalid())?),
+        )),
+        None => Ok((Some(owner.parse().map_err(|_| invalid())?), None)),
+    }
+}
+
+fn parse_host_entry(entry: &str) -> CapsuleResult<(String, std::net::IpAddr)> {
+    // Names cannot contain ':', IPv6 addresses can
+    entry
+        .split_once(':')
+        .and_then(|(name, address)| Some((name.to_string(), address.parse().ok()?)))
+        .ok_or_else(|| {
+            crate::error::CapsuleError::Config(format!(
+                "Invalid host entry '{}'. Use 'NAME:ADDR'.",
+                entry
+            ))
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -432,4 +1916,241 @@ mod tests {
         assert_eq!(cli.readonly, vec!["/usr"]);
         assert_eq!(cli.command, vec!["echo", "hello"]);
     }
+
+    #[test]
+    fn test_parse_mode_and_owner() {
+        assert_eq!(parse_mode("022").unwrap(), 0o022);
+        assert_eq!(parse_mode("0750").unwrap(), 0o750);
+        assert!(parse_mode("999").is_err());
+
+        assert_eq!(parse_owner("1000").unwrap(), (Some(1000), None));
+        assert_eq!(parse_owner("1000:100").unwrap(), (Some(1000), Some(100)));
+        assert!(parse_owner("user:group").is_err());
+    }
+
+    #[test]
+    fn test_parse_host_entry() {
+        assert_eq!(
+            parse_host_entry("api.internal:10.1.2.3").unwrap(),
+            ("api.internal".to_string(), "10.1.2.3".parse().unwrap())
+        );
+        assert_eq!(
+            parse_host_entry("db:fd00::5").unwrap(),
+            ("db".to_string(), "fd00::5".parse().unwrap())
+        );
+        assert!(parse_host_entry("api.internal").is_err());
+        assert!(parse_host_entry("api.internal:nowhere").is_err());
+    }
+
+    #[test]
+    fn test_cli_clock_offset() {
+        use clap::Parser;
+
+        let cli =
+            Cli::try_parse_from(["capsule-run", "--clock-offset", "-3600", "--", "date"]).unwrap();
+        assert_eq!(cli.clock_offset, Some(-3600));
+        assert_eq!(cli.command, vec!["date"]);
+    }
+
+    #[test]
+    fn test_cli_queue_priority() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from([
+            "capsule-run",
+            "--priority",
+            "-5",
+            "--queue-timeout",
+            "2000",
+            "--",
+            "make",
+        ])
+        .unwrap();
+        let request = create_request_from_cli(&cli).unwrap();
+        assert_eq!(request.priority, -5);
+        assert_eq!(request.queue_timeout_ms, Some(2000));
+    }
+
+    #[test]
+    fn test_cli_profile_record() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from([
+            "capsule-run",
+            "profile-record",
+            "-o",
+            "app.json",
+            "--",
+            "python3",
+            "-c",
+            "print(1)",
+        ])
+        .unwrap();
+        match cli.subcommand {
+            Some(Commands::ProfileRecord {
+                output, command, ..
+            }) => {
+                assert_eq!(output.as_deref(), Some("app.json"));
+                assert_eq!(command, vec!["python3", "-c", "print(1)"]);
+            }
+            _ => panic!("expected profile-record subcommand"),
+        }
+
+        // Plain commands still go to the top-level positional
+        let cli = Cli::try_parse_from(["capsule-run", "echo", "hi"]).unwrap();
+        assert!(cli.subcommand.is_none());
+        assert_eq!(cli.command, vec!["echo", "hi"]);
+    }
+
+    #[test]
+    fn test_cli_capabilities() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from(["capsule-run", "capabilities", "--json"]).unwrap();
+        assert!(matches!(
+            cli.subcommand,
+            Some(Commands::Capabilities { json: true })
+        ));
+        assert!(!cli.json);
+    }
+
+    #[test]
+    fn test_cli_inherit_env() {
+        use clap::Parser;
+
+        let request = |args: &[&str]| {
+            let cli =
+                Cli::try_parse_from([&["capsule-run"], args, &["--", "env"]].concat()).unwrap();
+            create_request_from_cli(&cli).unwrap().environment_inherit
+        };
+        assert_eq!(request(&[]), None);
+        assert_eq!(
+            request(&["--inherit-env", "PATH,HOME", "--inherit-env", "LANG"]),
+            Some(vec![
+                "PATH".to_string(),
+                "HOME".to_string(),
+                "LANG".to_string()
+            ])
+        );
+        assert_eq!(request(&["--inherit-env", ""]), Some(vec![]));
+    }
+
+    #[test]
+    fn test_cli_request_and_response_files() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from([
+            "capsule-run",
+            "--request-file",
+            "req.json",
+            "--response-file",
+            "out.json",
+        ])
+        .unwrap();
+        assert_eq!(cli.request_file.as_deref(), Some("req.json"));
+        assert_eq!(cli.response_file.as_deref(), Some("out.json"));
+        let cli = Cli::try_parse_from(["capsule-run", "--event-fd", "3", "--", "make"]).unwrap();
+        assert_eq!(cli.event_fd, Some(3));
+        assert!(Cli::try_parse_from(["capsule-run", "--event-output", "--", "make"]).is_err());
+        assert!(Cli::try_parse_from([
+            "capsule-run",
+            "--response-file",
+            "out.json",
+            "--output",
+            "text",
+            "--",
+            "ls"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_cli_shell() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from(["capsule-run", "shell", "--profile", "dev"]).unwrap();
+        assert_eq!(cli.profile.as_deref(), Some("dev"));
+        assert!(matches!(
+            cli.subcommand,
+            Some(Commands::Shell { ref shell }) if shell == "/bin/sh"
+        ));
+    }
+
+    #[test]
+    fn test_cli_batch() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from(["capsule-run", "--pretty", "batch", "plan.json"]).unwrap();
+        assert!(cli.pretty);
+        assert!(matches!(
+            cli.subcommand,
+            Some(Commands::Batch { ref file }) if file == "plan.json"
+        ));
+    }
+
+    #[test]
+    fn test_cli_ping() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from(["capsule-run", "ping", "--json"]).unwrap();
+        assert!(matches!(
+            cli.subcommand,
+            Some(Commands::Ping { json: true })
+        ));
+    }
+
+    #[test]
+    fn test_cli_audit_verify() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from(["capsule-run", "audit", "verify", "/var/log/audit.jsonl"])
+            .unwrap();
+        assert!(matches!(
+            cli.subcommand,
+            Some(Commands::Audit {
+                action: AuditCommand::Verify { path: Some(ref path) }
+            }) if path == "/var/log/audit.jsonl"
+        ));
+        assert!(Cli::try_parse_from(["capsule-run", "audit"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_output_format() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from(["capsule-run", "echo", "hi"]).unwrap();
+        assert_eq!(cli.output, OutputFormat::Json);
+        let cli = Cli::try_parse_from(["capsule-run", "--output", "text", "--", "ls"]).unwrap();
+        assert_eq!(cli.output, OutputFormat::Text);
+        assert!(Cli::try_parse_from(["capsule-run", "--output", "yaml", "--", "ls"]).is_err());
+        let cli = Cli::try_parse_from(["capsule-run", "-vv", "--log-format", "json", "--", "ls"])
+            .unwrap();
+        assert_eq!((cli.verbose, cli.log_format), (2, LogFormat::Json));
+        assert_eq!(cli.log_sink, vec![LogSink::Stderr]);
+        let cli = Cli::try_parse_from(["capsule-run", "--log-sink", "syslog,journald", "--", "ls"])
+            .unwrap();
+        assert_eq!(cli.log_sink, vec![LogSink::Syslog, LogSink::Journald]);
+        assert!(Cli::try_parse_from(["capsule-run", "--log-sink", "file", "--", "ls"]).is_err());
+
+        let cli = Cli::try_parse_from([
+            "capsule-run",
+            "--passthrough",
+            "--report",
+            "report.json",
+            "--",
+            "make",
+        ])
+        .unwrap();
+        assert!(cli.passthrough);
+        assert_eq!(cli.report.as_deref(), Some("report.json"));
+        assert!(Cli::try_parse_from([
+            "capsule-run",
+            "--passthrough",
+            "--output",
+            "json",
+            "--",
+            "make"
+        ])
+        .is_err());
+    }
 }
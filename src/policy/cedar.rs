@@ -0,0 +1,192 @@
+use super::{Caller, PolicyDecision, PolicyEngine};
+use crate::api::schema::ExecutionRequest;
+use crate::config::CedarConfig;
+use crate::error::{CapsuleError, CapsuleResult};
+use cedar_policy::{
+    Authorizer, Context, Decision, Entities, Entity, EntityId, EntityTypeName, EntityUid,
+    PolicySet, Request,
+};
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Evaluates an embedded Cedar policy set. Each request is
+///
+/// - principal `Capsule::User::"<caller id>"`, a member of one
+///   `Capsule::Group::"<name>"` per caller group
+/// - action `Capsule::Action::"execute"`
+/// - resource `Capsule::Program::"<command basename>"`
+///
+/// with the request's command, network, limits, environment keys, profile
+/// and host paths in the context. As in Cedar itself, anything not
+/// permitted is denied.
+pub struct CedarPolicy {
+    policies: PolicySet,
+    entities: Entities,
+    authorizer: Authorizer,
+}
+
+impl CedarPolicy {
+    pub fn load(config: &CedarConfig) -> CapsuleResult<Self> {
+        let read = |path: &str| {
+            std::fs::read_to_string(path).map_err(|e| {
+                CapsuleError::Config(format!("Failed to read Cedar file {}: {}", path, e))
+            })
+        };
+        let policies = PolicySet::from_str(&read(&config.policies)?).map_err(|e| {
+            CapsuleError::Config(format!(
+                "Invalid Cedar policies in {}: {}",
+                config.policies, e
+            ))
+        })?;
+        let entities = match &config.entities {
+            Some(path) => Entities::from_json_str(&read(path)?, None).map_err(|e| {
+                CapsuleError::Config(format!("Invalid Cedar entities in {}: {}", path, e))
+            })?,
+            None => Entities::empty(),
+        };
+        Ok(Self::new(policies, entities))
+    }
+
+    pub fn new(policies: PolicySet, entities: Entities) -> Self {
+        Self {
+            policies,
+            entities,
+            authorizer: Authorizer::new(),
+        }
+    }
+
+    fn authorize(
+        &self,
+        request: &ExecutionRequest,
+        caller: &Caller,
+    ) -> Result<PolicyDecision, String> {
+        let groups = caller
+            .groups
+            .iter()
+            .map(|group| uid("Capsule::Group", group))
+            .collect::<Result<HashSet<_>, _>>()?;
+        let principal = uid("Capsule::User", &caller.id)?;
+        let entities = self
+            .entities
+            .clone()
+            .add_entities([Entity::new_no_attrs(principal.clone(), groups)], None)
+            .map_err(|e| e.to_string())?;
+
+        let program = request.command.first().map(String::as_str).unwrap_or("");
+        let program = program.rsplit('/').next().unwrap_or(program);
+        let cedar_request = Request::new(
+            principal,
+            uid("Capsule::Action", "execute")?,
+            uid("Capsule::Program", program)?,
+            Context::from_json_value(context(request), None).map_err(|e| e.to_string())?,
+            None,
+        )
+        .map_err(|e| e.to_string())?;
+
+        let response = self
+            .authorizer
+            .is_authorized(&cedar_request, &self.policies, &entities);
+        if response.decision() == Decision::Allow {
+            return Ok(PolicyDecision::Allow);
+        }
+        let diagnostics = response.diagnostics();
+        // Name policies by their `@id` annotation where they have one
+        let mut reasons: Vec<String> = diagnostics
+            .reason()
+            .map(|id| {
+                self.policies
+                    .policy(id)
+                    .and_then(|policy| policy.annotation("id"))
+                    .map_or_else(|| id.to_string(), str::to_string)
+            })
+            .collect();
+        reasons.extend(diagnostics.errors().map(|e| e.to_string()));
+        Ok(PolicyDecision::Deny(if reasons.is_empty() {
+            "Denied by Cedar policy: no policy permits it".to_string()
+        } else {
+            format!("Denied by Cedar policy: {}", reasons.join(", "))
+        }))
+    }
+}
+
+impl PolicyEngine for CedarPolicy {
+    fn evaluate(&self, request: &ExecutionRequest, caller: &Caller) -> PolicyDecision {
+        self.authorize(request, caller)
+            .unwrap_or_else(|e| PolicyDecision::Deny(format!("Cedar policy check failed: {}", e)))
+    }
+}
+
+fn uid(type_name: &str, id: &str) -> Result<EntityUid, String> {
+    let type_name = EntityTypeName::from_str(type_name).map_err(|e| e.to_string())?;
+    Ok(EntityUid::from_type_name_and_id(
+        type_name,
+        EntityId::new(id),
+    ))
+}
+
+/// Cedar longs are signed 64-bit, so larger values saturate
+fn context(request: &ExecutionRequest) -> serde_json::Value {
+    let long = |value: u64| i64::try_from(value).unwrap_or(i64::MAX);
+    let mut environment_keys: Vec<&String> = request.environment.keys().collect();
+    environment_keys.sort();
+    serde_json::json!({
+        "command": request.command,
+        "network": request.isolation.network,
+        "memory_bytes": long(request.resources.memory_bytes),
+        "timeout_ms": long(request.timeout_ms),
+        "environment_keys": environment_keys,
+        "profile": request.profile.clone().unwrap_or_default(),
+        "readonly_paths": request.isolation.readonly_paths,
+        "writable_paths": request.isolation.writable_paths,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICIES: &str = r#"
+        permit(principal, action == Capsule::Action::"execute", resource == Capsule::Program::"python3");
+        @id("no-network-for-contractors")
+        forbid(principal in Capsule::Group::"contractors", action, resource)
+        when { context.network };
+    "#;
+
+    fn request(command: &str, network: bool) -> ExecutionRequest {
+        serde_json::from_value(serde_json::json!({
+            "command": [command, "-c", "print(1)"],
+            "isolation": { "network": network },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_cedar_policy() {
+        let policy = CedarPolicy::new(PolicySet::from_str(POLICIES).unwrap(), Entities::empty());
+        let alice = Caller {
+            id: "alice".to_string(),
+            groups: vec!["contractors".to_string()],
+        };
+        let bob = Caller {
+            id: "bob".to_string(),
+            groups: vec![],
+        };
+
+        assert_eq!(
+            policy.evaluate(&request("/usr/bin/python3", false), &alice),
+            PolicyDecision::Allow
+        );
+        assert_eq!(
+            policy.evaluate(&request("python3", true), &bob),
+            PolicyDecision::Allow
+        );
+        assert_eq!(
+            policy.evaluate(&request("python3", true), &alice),
+            PolicyDecision::Deny("Denied by Cedar policy: no-network-for-contractors".to_string())
+        );
+        assert!(matches!(
+            policy.evaluate(&request("bash", false), &bob),
+            PolicyDecision::Deny(reason) if reason.contains("no policy permits it")
+        ));
+    }
+}
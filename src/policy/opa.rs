@@ -0,0 +1,128 @@
+use super::{Caller, PolicyDecision, PolicyEngine};
+use crate::api::schema::ExecutionRequest;
+use crate::config::OpaConfig;
+use serde_json::Value;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const DEFAULT_TIMEOUT_MS: u64 = 1000;
+
+/// Asks an OPA server, usually a sidecar, through its data API with the
+/// host's curl. The input document is `{"request": ..., "caller": ...}`.
+/// An unreachable server or an unexpected answer denies the request.
+pub struct OpaPolicy {
+    url: String,
+    timeout_ms: u64,
+}
+
+impl OpaPolicy {
+    pub fn new(config: &OpaConfig) -> Self {
+        Self {
+            url: config.url.clone(),
+            timeout_ms: config.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS),
+        }
+    }
+
+    fn query(&self, input: &Value) -> Result<Value, String> {
+        let max_time = format!("{:.3}", self.timeout_ms as f64 / 1000.0);
+        let mut curl = Command::new("curl")
+            .args(["--fail", "--silent", "--show-error"])
+            .args(["--proto", "=http,https"])
+            .args(["--max-time", &max_time])
+            .args(["--header", "Content-Type: application/json"])
+            .args(["--data-binary", "@-"])
+            .arg("--")
+            .arg(&self.url)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to run curl: {}", e))?;
+        if let Some(mut stdin) = curl.stdin.take() {
+            stdin
+                .write_all(input.to_string().as_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+        let output = curl.wait_with_output().map_err(|e| e.to_string())?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+        }
+        serde_json::from_slice(&output.stdout).map_err(|e| format!("invalid response: {}", e))
+    }
+}
+
+impl PolicyEngine for OpaPolicy {
+    fn evaluate(&self, request: &ExecutionRequest, caller: &Caller) -> PolicyDecision {
+        let input = serde_json::json!({
+            "input": { "request": request, "caller": caller },
+        });
+        match self.query(&input) {
+            Ok(response) => decision(&response),
+            Err(e) => PolicyDecision::Deny(format!("OPA policy check failed: {}", e)),
+        }
+    }
+}
+
+/// The rule's `result` is either a boolean or an object with `allow` and
+/// an optional `reason`
+fn decision(response: &Value) -> PolicyDecision {
+    let denied = |reason: Option<&str>| match reason {
+        Some(reason) => PolicyDecision::Deny(format!("Denied by OPA policy: {}", reason)),
+        None => PolicyDecision::Deny("Denied by OPA policy".to_string()),
+    };
+    match response.get("result") {
+        Some(Value::Bool(true)) => PolicyDecision::Allow,
+        Some(Value::Bool(false)) => denied(None),
+        Some(Value::Object(result)) => match result.get("allow") {
+            Some(Value::Bool(true)) => PolicyDecision::Allow,
+            _ => denied(result.get("reason").and_then(Value::as_str)),
+        },
+        None => PolicyDecision::Deny(
+            "OPA returned no decision; is the policy loaded at that path?".to_string(),
+        ),
+        Some(_) => {
+            PolicyDecision::Deny("OPA returned a decision that is not allow/deny".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_decision() {
+        assert_eq!(decision(&json!({"result": true})), PolicyDecision::Allow);
+        assert_eq!(
+            decision(&json!({"result": {"allow": true}})),
+            PolicyDecision::Allow
+        );
+        assert_eq!(
+            decision(&json!({"result": {"allow": false, "reason": "network"}})),
+            PolicyDecision::Deny("Denied by OPA policy: network".to_string())
+        );
+        assert!(matches!(
+            decision(&json!({})),
+            PolicyDecision::Deny(reason) if reason.contains("no decision")
+        ));
+        assert!(matches!(
+            decision(&json!({"result": "yes"})),
+            PolicyDecision::Deny(_)
+        ));
+    }
+
+    #[test]
+    fn test_unreachable_server_denies() {
+        let policy = OpaPolicy::new(&OpaConfig {
+            url: "http://127.0.0.1:9/v1/data/capsule/allow".to_string(),
+            timeout_ms: Some(500),
+        });
+        let request: ExecutionRequest =
+            serde_json::from_value(json!({ "command": ["true"] })).unwrap();
+        assert!(matches!(
+            policy.evaluate(&request, &Caller::default()),
+            PolicyDecision::Deny(reason) if reason.starts_with("OPA policy check failed")
+        ));
+    }
+}
@@ -0,0 +1,205 @@
+use crate::error::{CapsuleResult, SandboxError};
+use std::fs::{self, File};
+use std::io::Seek;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Compression of a workspace archive, chosen by its file name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_path(path: &str) -> Option<Self> {
+        if path.ends_with(".tar.zst") || path.ends_with(".tzst") {
+            Some(Self::Zstd)
+        } else if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(Self::Gzip)
+        } else if path.ends_with(".tar") {
+            Some(Self::None)
+        } else {
+            None
+        }
+    }
+
+    /// The flag GNU tar and bsdtar both accept for this compression
+    fn tar_flag(self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Gzip => Some("--gzip"),
+            Self::Zstd => Some("--zstd"),
+        }
+    }
+}
+
+/// A tarball that populates /workspace before the run or captures it after.
+///
+/// The file is opened before setup, since on Linux the host filesystem is
+/// out of reach once the supervisor has pivoted into the sandbox root. `tar`
+/// itself runs afterwards from the sandbox's read-only /usr.
+pub struct WorkspaceArchive {
+    file: File,
+    path: String,
+    compression: Compression,
+}
+
+impl WorkspaceArchive {
+    /// Open an archive to extract into the workspace.
+    pub fn open(path: &str) -> CapsuleResult<Self> {
+        Self::new(path, "open", |path| File::open(path))
+    }
+
+    /// Create (or truncate) the archive the workspace is captured into.
+    pub fn create(path: &str) -> CapsuleResult<Self> {
+        Self::new(path, "create", |path| {
+            if let Some(parent) = Path::new(path).parent() {
+                fs::create_dir_all(parent)?;
+            }
+            File::create(path)
+        })
+    }
+
+    /// An uncompressed scratch archive in the host's temp directory, gone
+    /// once dropped. `label` names it in errors.
+    pub fn temporary(label: &str) -> CapsuleResult<Self> {
+        let file = tempfile::tempfile().map_err(|e| {
+            SandboxError::FilesystemSetup(format!(
+                "Failed to create scratch archive for {}: {}",
+                label, e
+            ))
+        })?;
+        Ok(Self {
+            file,
+            path: label.to_string(),
+            compression: Compression::None,
+        })
+    }
+
+    /// The name is checked first, so an unsupported output is never created.
+    fn new(
+        path: &str,
+        action: &str,
+        open_file: impl FnOnce(&str) -> std::io::Result<File>,
+    ) -> CapsuleResult<Self> {
+        let compression = Compression::from_path(path).ok_or_else(|| {
+            SandboxError::FilesystemSetup(format!(
+                "Unsupported workspace archive {} (expected .tar, .tar.gz or .tar.zst)",
+                path
+            ))
+        })?;
+        let file = open_file(path).map_err(|e| {
+            SandboxError::FilesystemSetup(format!(
+                "Failed to {} workspace archive {}: {}",
+                action, path, e
+            ))
+        })?;
+        Ok(Self {
+            file,
+            path: path.to_string(),
+            compression,
+        })
+    }
+
+    /// Unpack the archive into `workspace`. Ownership is not restored: inside
+    /// a user namespace only the mapped IDs could be.
+    pub fn extract(&self, workspace: &Path) -> CapsuleResult<()> {
+        let mut input = self.file.try_clone().map_err(|e| self.error(e))?;
+        // A scratch archive is read back from the same handle it was written to
+        input
+            .seek(std::io::SeekFrom::Start(0))
+            .map_err(|e| self.error(e))?;
+        self.tar(
+            workspace,
+            &["-x", "--no-same-owner", "-f", "-"],
+            Stdio::from(input),
+            Stdio::null(),
+        )
+    }
+
+    /// Pack the contents of `workspace` into the archive.
+    pub fn capture(&self, workspace: &Path) -> CapsuleResult<()> {
+        let output = self.file.try_clone().map_err(|e| self.error(e))?;
+        self.tar(
+            workspace,
+            &["-c", "-f", "-", "."],
+            Stdio::null(),
+            Stdio::from(output),
+        )
+    }
+
+    fn tar(
+        &self,
+        workspace: &Path,
+        args: &[&str],
+        stdin: Stdio,
+        stdout: Stdio,
+    ) -> CapsuleResult<()> {
+        let output = Command::new("tar")
+            .arg("-C")
+            .arg(workspace)
+            .args(self.compression.tar_flag())
+            .args(args)
+            .stdin(stdin)
+            .stdout(stdout)
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(|e| self.error(e))?;
+        if !output.status.success() {
+            return Err(self
+                .error(String::from_utf8_lossy(&output.stderr).trim())
+                .into());
+        }
+        Ok(())
+    }
+
+    fn error(&self, reason: impl std::fmt::Display) -> SandboxError {
+        SandboxError::FilesystemSetup(format!("tar failed for {}: {}", self.path, reason))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_from_path() {
+        assert_eq!(
+            Compression::from_path("ws.tar.zst"),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::from_path("ws.tgz"), Some(Compression::Gzip));
+        assert_eq!(Compression::from_path("ws.tar"), Some(Compression::None));
+        assert_eq!(Compression::from_path("ws.zip"), None);
+    }
+
+    #[test]
+    fn test_capture_and_extract_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        fs::create_dir_all(source.join("src")).unwrap();
+        fs::write(source.join("src/main.rs"), "fn main() {}").unwrap();
+
+        let path = dir.path().join("workspace.tar.gz");
+        let path = path.to_str().unwrap();
+        if WorkspaceArchive::create(path)
+            .and_then(|archive| archive.capture(&source))
+            .is_err()
+        {
+            return; // tar or gzip is not installed
+        }
+
+        let target = dir.path().join("target");
+        fs::create_dir_all(&target).unwrap();
+        WorkspaceArchive::open(path)
+            .unwrap()
+            .extract(&target)
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(target.join("src/main.rs")).unwrap(),
+            "fn main() {}"
+        );
+    }
+}
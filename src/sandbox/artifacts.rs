@@ -0,0 +1,247 @@
+use crate::api::schema::Artifact;
+use crate::error::{CapsuleResult, SandboxError};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+
+/// More files than this are an error rather than a silently short list
+const MAX_ARTIFACTS: usize = 1000;
+
+/// The host directory artifacts are copied into.
+///
+/// Opened before setup: on Linux the host filesystem is out of reach once
+/// the supervisor has pivoted into the sandbox root, but a directory handle
+/// keeps working.
+pub struct ArtifactSink {
+    dir: File,
+}
+
+impl ArtifactSink {
+    pub fn open(path: &Path) -> CapsuleResult<Self> {
+        let failed = |e: io::Error| {
+            SandboxError::FilesystemSetup(format!(
+                "Failed to open output directory {}: {}",
+                path.display(),
+                e
+            ))
+        };
+        fs::create_dir_all(path).map_err(failed)?;
+        let dir = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY)
+            .open(path)
+            .map_err(failed)?;
+        Ok(Self { dir })
+    }
+
+    /// Create `relative` and any missing parents below the directory.
+    fn create(&self, relative: &Path) -> io::Result<File> {
+        let mut parent = Path::new("").to_path_buf();
+        for component in relative.parent().into_iter().flat_map(Path::components) {
+            parent.push(component);
+            let name = c_path(&parent)?;
+            if unsafe { libc::mkdirat(self.dir.as_raw_fd(), name.as_ptr(), 0o755) } != 0 {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::AlreadyExists {
+                    return Err(error);
+                }
+            }
+        }
+
+        let name = c_path(relative)?;
+        let fd = unsafe {
+            libc::openat(
+                self.dir.as_raw_fd(),
+                name.as_ptr(),
+                libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                0o644 as libc::c_uint,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Collect the regular files under `workspace` that match `patterns`.
+///
+/// With a sink each file is copied there; otherwise contents are inlined
+/// while they fit in `inline_bytes`, and larger files are listed with their
+/// size and checksum only. Symlinks are skipped, not followed.
+pub fn collect(
+    workspace: &Path,
+    patterns: &[String],
+    sink: Option<&ArtifactSink>,
+    inline_bytes: usize,
+) -> CapsuleResult<Vec<Artifact>> {
+    let mut paths = Vec::new();
+    walk(workspace, Path::new(""), &mut paths);
+    paths.sort();
+
+    let mut artifacts = Vec::new();
+    let mut inline_left = inline_bytes as u64;
+    for relative in paths {
+        let name = relative.to_string_lossy();
+        if !patterns.iter().any(|pattern| glob_match(pattern, &name)) {
+            continue;
+        }
+        if artifacts.len() == MAX_ARTIFACTS {
+            return Err(SandboxError::FilesystemSetup(format!(
+                "More than {} files match the requested outputs",
+                MAX_ARTIFACTS
+            ))
+            .into());
+        }
+
+        let failed = |e: io::Error| {
+            SandboxError::FilesystemSetup(format!("Failed to collect output {}: {}", name, e))
+        };
+        let mut source = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(workspace.join(&relative))
+            .map_err(failed)?;
+        let size = source.metadata().map_err(failed)?.len();
+
+        let mut copy = sink
+            .map(|sink| sink.create(&relative))
+            .transpose()
+            .map_err(failed)?;
+        let mut contents = (copy.is_none() && size <= inline_left).then(Vec::new);
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = source.read(&mut buffer).map_err(failed)?;
+            if read == 0 {
+                break;
+            }
+            let chunk = &buffer[..read];
+            hasher.update(chunk);
+            if let Some(copy) = copy.as_mut() {
+                copy.write_all(chunk).map_err(failed)?;
+            }
+            if let Some(contents) = contents.as_mut() {
+                contents.extend_from_slice(chunk);
+            }
+        }
+
+        // The file may have grown since it was measured
+        let contents = contents.filter(|contents| contents.len() as u64 <= inline_left);
+        if let Some(contents) = &contents {
+            inline_left -= contents.len() as u64;
+        }
+        artifacts.push(Artifact {
+            path: name.into_owned(),
+            size,
+            sha256: format!("{:x}", hasher.finalize()),
+            base64: contents.map(|contents| STANDARD.encode(contents)),
+        });
+    }
+    Ok(artifacts)
+}
+
+/// Regular files below `root`, relative to it; symlinks are not followed.
+pub(super) fn walk(root: &Path, relative: &Path, paths: &mut Vec<std::path::PathBuf>) {
+    let Ok(entries) = fs::read_dir(root.join(relative)) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let path = relative.join(entry.file_name());
+        if file_type.is_dir() {
+            walk(root, &path, paths);
+        } else if file_type.is_file() {
+            paths.push(path);
+        }
+    }
+}
+
+/// Whether `path` matches `pattern`, compared one `/`-separated segment at a
+/// time: `**` spans any number of segments, `*` and `?` stay within one.
+pub(crate) fn glob_match(pattern: &str, path: &str) -> bool {
+    fn segments(pattern: &[&str], path: &[&str]) -> bool {
+        match pattern.split_first() {
+            None => path.is_empty(),
+            Some((&"**", rest)) => (0..=path.len()).any(|skip| segments(rest, &path[skip..])),
+            Some((first, rest)) => path.split_first().is_some_and(|(name, tail)| {
+                wildcard(first.as_bytes(), name.as_bytes()) && segments(rest, tail)
+            }),
+        }
+    }
+
+    fn wildcard(pattern: &[u8], name: &[u8]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some((b'*', rest)) => (0..=name.len()).any(|skip| wildcard(rest, &name[skip..])),
+            Some((b'?', rest)) => !name.is_empty() && wildcard(rest, &name[1..]),
+            Some((c, rest)) => name.first() == Some(c) && wildcard(rest, &name[1..]),
+        }
+    }
+
+    let pattern: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+    let path: Vec<&str> = path.split('/').collect();
+    segments(&pattern, &path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("report.xml", "report.xml"));
+        assert!(!glob_match("report.xml", "sub/report.xml"));
+        assert!(glob_match("dist/**", "dist/app.js"));
+        assert!(glob_match("dist/**", "dist/assets/logo.png"));
+        assert!(!glob_match("dist/**", "distribution/app.js"));
+        assert!(glob_match("**/*.log", "build.log"));
+        assert!(glob_match("**/*.log", "logs/2024/run.log"));
+        assert!(glob_match("out/?.txt", "out/a.txt"));
+        assert!(!glob_match("out/*.txt", "out/nested/a.txt"));
+    }
+
+    #[test]
+    fn test_collect_inline_and_to_sink() {
+        let workspace = tempfile::tempdir().unwrap();
+        fs::create_dir_all(workspace.path().join("dist/assets")).unwrap();
+        fs::write(workspace.path().join("dist/app.js"), "app").unwrap();
+        fs::write(workspace.path().join("dist/assets/big.bin"), vec![7u8; 64]).unwrap();
+        fs::write(workspace.path().join("notes.txt"), "skip").unwrap();
+        std::os::unix::fs::symlink("/etc/passwd", workspace.path().join("dist/passwd")).unwrap();
+
+        let patterns = vec!["dist/**".to_string()];
+        let artifacts = collect(workspace.path(), &patterns, None, 16).unwrap();
+        let paths: Vec<&str> = artifacts.iter().map(|a| a.path.as_str()).collect();
+        assert_eq!(paths, vec!["dist/app.js", "dist/assets/big.bin"]);
+        assert_eq!(artifacts[0].base64.as_deref(), Some("YXBw"));
+        assert_eq!(artifacts[0].size, 3);
+        // Over the inline cap: listed without contents
+        assert_eq!(artifacts[1].base64, None);
+        assert_eq!(artifacts[1].size, 64);
+
+        let output = tempfile::tempdir().unwrap();
+        let sink = ArtifactSink::open(&output.path().join("collected")).unwrap();
+        let copied = collect(workspace.path(), &patterns, Some(&sink), 1 << 20).unwrap();
+        assert!(copied.iter().all(|a| a.base64.is_none()));
+        assert_eq!(copied[0].sha256, artifacts[0].sha256);
+        assert_eq!(
+            fs::read(output.path().join("collected/dist/assets/big.bin")).unwrap(),
+            vec![7u8; 64]
+        );
+    }
+}
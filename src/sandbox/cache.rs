@@ -0,0 +1,258 @@
+use super::artifacts::walk;
+use crate::api::schema::{BindMount, CacheMount, IsolationConfig};
+use crate::error::{CapsuleResult, SandboxError};
+#[cfg(target_os = "linux")]
+use crate::sandbox::quota::ProjectQuota;
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Cap for caches that set no `size_bytes`
+const DEFAULT_CACHE_SIZE: u64 = 2_147_483_648; // 2 GB
+/// Where root keeps caches; other users keep them under ~/.cache
+const SYSTEM_CACHE_ROOT: &str = "/var/cache/capsule-run";
+/// Tenants' caches live apart under this directory of the cache root,
+/// which no cache name can collide with since names cannot start with '.'
+const TENANT_CACHES: &str = ".tenants";
+/// How long a run waits for a cache another run holds, unless the request
+/// sets `queue_timeout_ms`
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(600);
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Whose caches a run mounts, and how long it waits for them.
+#[derive(Debug, Clone, Default)]
+pub struct CacheScope {
+    /// The run's tenant; runs of different tenants never share a cache
+    pub tenant: Option<String>,
+    /// `DEFAULT_LOCK_TIMEOUT` when unset
+    pub lock_timeout: Option<Duration>,
+}
+
+impl CacheScope {
+    fn root(&self) -> PathBuf {
+        let root = cache_root();
+        match &self.tenant {
+            Some(tenant) => root.join(TENANT_CACHES).join(tenant),
+            None => root,
+        }
+    }
+}
+
+/// A named cache directory on the host, held for the life of an execution.
+///
+/// Executions sharing a cache take turns: the lock is held until the
+/// sandbox is dropped, so a package manager never sees another run's
+/// half-written entries. It is an flock, so the kernel releases it even if
+/// capsule-run is killed.
+pub struct CacheDir {
+    path: PathBuf,
+    destination: String,
+    /// What the run may add once the cache is trimmed to its size
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))] // No project quotas on macOS
+    headroom_bytes: u64,
+    #[cfg(target_os = "linux")]
+    quota: Option<ProjectQuota>,
+    /// Holds the flock; closing it releases the cache
+    _lock: File,
+}
+
+impl CacheDir {
+    /// Lock the cache, waiting for any execution using it up to the
+    /// scope's timeout, and trim it to its size limit.
+    pub fn acquire(cache: &CacheMount, scope: &CacheScope) -> CapsuleResult<Self> {
+        Self::acquire_in(
+            &scope.root(),
+            cache,
+            scope.lock_timeout.unwrap_or(DEFAULT_LOCK_TIMEOUT),
+        )
+    }
+
+    fn acquire_in(root: &Path, cache: &CacheMount, timeout: Duration) -> CapsuleResult<Self> {
+        let failed = |e: std::io::Error| {
+            SandboxError::FilesystemSetup(format!("Failed to open cache {}: {}", cache.name, e))
+        };
+        let path = root.join(&cache.name);
+        fs::create_dir_all(&path).map_err(failed)?;
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(root.join(format!("{}.lock", cache.name)))
+            .map_err(failed)?;
+        let started = Instant::now();
+        while unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let error = std::io::Error::last_os_error();
+            if error.raw_os_error() != Some(libc::EWOULDBLOCK) {
+                return Err(failed(error).into());
+            }
+            if started.elapsed() >= timeout {
+                return Err(SandboxError::FilesystemSetup(format!(
+                    "Cache {} was still in use by another run after {}ms",
+                    cache.name,
+                    timeout.as_millis()
+                ))
+                .into());
+            }
+            std::thread::sleep(LOCK_POLL_INTERVAL);
+        }
+
+        let limit = cache.size_bytes.unwrap_or(DEFAULT_CACHE_SIZE);
+        let used = trim(&path, limit);
+        Ok(Self {
+            path,
+            destination: cache.path.clone(),
+            headroom_bytes: limit.saturating_sub(used),
+            #[cfg(target_os = "linux")]
+            quota: None,
+            _lock: lock,
+        })
+    }
+
+    /// Hold what the run writes to the cache to its size with a project
+    /// quota, so it cannot outgrow it until the next run trims it. Called
+    /// before the supervisor unshares its user namespace.
+    #[cfg(target_os = "linux")]
+    pub fn apply_quota(&mut self) -> CapsuleResult<()> {
+        // A zero block limit would mean no limit at all
+        let limit = self.headroom_bytes.max(1024);
+        self.quota = Some(ProjectQuota::apply(
+            &self.path,
+            limit,
+            uuid::Uuid::new_v4(),
+        )?);
+        Ok(())
+    }
+}
+
+/// The bind mounts `with_cache_mounts` adds for `caches`, without locking
+/// or creating anything.
+#[cfg(target_os = "linux")]
+pub fn planned_mounts(caches: &[CacheMount], scope: &CacheScope) -> Vec<BindMount> {
+    let root = scope.root();
+    caches
+        .iter()
+        .map(|cache| BindMount {
+            source: root.join(&cache.name).to_string_lossy().into_owned(),
+            destination: cache.path.clone(),
+            readonly: false,
+        })
+        .collect()
+}
+
+/// `isolation` with each cache appended to its bind mounts, after the
+/// caller's own so a cache can sit inside one of them.
+pub fn with_cache_mounts(isolation: &IsolationConfig, caches: &[CacheDir]) -> IsolationConfig {
+    let mut isolation = isolation.clone();
+    isolation
+        .bind_mounts
+        .extend(caches.iter().map(|cache| BindMount {
+            source: cache.path.to_string_lossy().into_owned(),
+            destination: cache.destination.clone(),
+            readonly: false,
+        }));
+    isolation
+}
+
+fn cache_root() -> PathBuf {
+    if unsafe { libc::geteuid() } == 0 {
+        return PathBuf::from(SYSTEM_CACHE_ROOT);
+    }
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("capsule-run")
+}
+
+/// Evict the least recently modified files until `dir` holds at most
+/// `limit_bytes`, returning what it holds then.
+fn trim(dir: &Path, limit_bytes: u64) -> u64 {
+    let mut paths = Vec::new();
+    walk(dir, Path::new(""), &mut paths);
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = paths
+        .into_iter()
+        .filter_map(|relative| {
+            let path = dir.join(relative);
+            let metadata = fs::symlink_metadata(&path).ok()?;
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            Some((modified, metadata.len(), path))
+        })
+        .collect();
+    files.sort();
+
+    let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+    for (_, size, path) in files {
+        if total <= limit_bytes {
+            break;
+        }
+        if fs::remove_file(&path).is_ok() {
+            total -= size;
+        }
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_acquire_trims_oldest_files() {
+        let root = tempfile::tempdir().unwrap();
+        let cache = CacheMount {
+            name: "pip".to_string(),
+            path: "/root/.cache/pip".to_string(),
+            size_bytes: Some(10),
+        };
+        let dir = root.path().join("pip");
+        fs::create_dir_all(dir.join("wheels")).unwrap();
+        let now = SystemTime::now();
+        for (name, age) in [("old", 60), ("wheels/recent", 30), ("newest", 0)] {
+            let file = File::create(dir.join(name)).unwrap();
+            file.set_len(5).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+
+        let acquired = CacheDir::acquire_in(root.path(), &cache, DEFAULT_LOCK_TIMEOUT).unwrap();
+        assert!(!dir.join("old").exists());
+        assert_eq!(acquired.headroom_bytes, 0);
+        assert!(dir.join("wheels/recent").exists());
+        assert!(dir.join("newest").exists());
+
+        let isolation = with_cache_mounts(&IsolationConfig::default(), &[acquired]);
+        let mount = isolation.bind_mounts.last().unwrap();
+        assert_eq!(mount.source, dir.to_string_lossy());
+        assert_eq!(mount.destination, "/root/.cache/pip");
+        assert!(!mount.readonly);
+    }
+
+    #[test]
+    fn test_acquire_times_out() {
+        let root = tempfile::tempdir().unwrap();
+        let cache = CacheMount {
+            name: "npm".to_string(),
+            path: "/root/.npm".to_string(),
+            size_bytes: None,
+        };
+        let held = CacheDir::acquire_in(root.path(), &cache, DEFAULT_LOCK_TIMEOUT).unwrap();
+        let error = CacheDir::acquire_in(root.path(), &cache, LOCK_POLL_INTERVAL)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("still in use"), "{}", error);
+        drop(held);
+        assert!(CacheDir::acquire_in(root.path(), &cache, LOCK_POLL_INTERVAL).is_ok());
+    }
+
+    #[test]
+    fn test_tenant_caches_are_apart() {
+        let shared = CacheScope::default().root();
+        let tenant = CacheScope {
+            tenant: Some("eval".to_string()),
+            lock_timeout: None,
+        }
+        .root();
+        assert_eq!(tenant, shared.join(".tenants/eval"));
+    }
+}
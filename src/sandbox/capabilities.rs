@@ -0,0 +1,154 @@
+use crate::api::schema::{HostCapabilities, ResourceEnforcement};
+
+/// Probe the host for the isolation features capsule-run can use.
+///
+/// Probes are side-effect free: user namespace support is tested in a
+/// short-lived child, and nothing is created in the cgroup hierarchy.
+pub fn probe() -> HostCapabilities {
+    let mut capabilities = HostCapabilities {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        user_namespaces: false,
+        time_namespaces: false,
+        cgroup_v2: false,
+        cgroup_delegated: false,
+        cgroup_controllers: Vec::new(),
+        seccomp: false,
+        seccomp_notify: false,
+        landlock_abi: None,
+        sandbox_exec: false,
+        nested_sandbox: false,
+        endpoint_security: false,
+        resource_enforcement: ResourceEnforcement::None,
+    };
+
+    #[cfg(target_os = "linux")]
+    linux::probe(&mut capabilities);
+    #[cfg(target_os = "macos")]
+    macos::probe(&mut capabilities);
+
+    capabilities
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) use linux::landlock_abi;
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::*;
+    use crate::sandbox::cgroups::CgroupManager;
+    use std::fs;
+    use std::path::Path;
+
+    pub fn probe(capabilities: &mut HostCapabilities) {
+        capabilities.user_namespaces = unshare_succeeds(libc::CLONE_NEWUSER);
+        capabilities.time_namespaces = Path::new("/proc/self/ns/time").exists();
+
+        if let Ok(mount) = CgroupManager::find_cgroup_mount() {
+            capabilities.cgroup_v2 = true;
+            // The same check CgroupManager::setup makes before falling back to systemd
+            capabilities.cgroup_delegated = CgroupManager::is_writable(&mount.join("capsule-run"));
+            capabilities.cgroup_controllers = fs::read_to_string(mount.join("cgroup.controllers"))
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(str::to_string)
+                .collect();
+        }
+
+        let seccomp_actions =
+            fs::read_to_string("/proc/sys/kernel/seccomp/actions_avail").unwrap_or_default();
+        capabilities.seccomp = cfg!(feature = "seccomp") && !seccomp_actions.is_empty();
+        capabilities.seccomp_notify = capabilities.seccomp
+            && seccomp_actions
+                .split_whitespace()
+                .any(|action| action == "user_notif");
+
+        capabilities.landlock_abi = landlock_abi();
+
+        let memory_controller = capabilities
+            .cgroup_controllers
+            .iter()
+            .any(|c| c == "memory");
+        capabilities.resource_enforcement = if capabilities.cgroup_delegated && memory_controller {
+            ResourceEnforcement::Cgroup
+        } else {
+            ResourceEnforcement::Rlimit
+        };
+    }
+
+    /// Whether a forked child can unshare `flags`. Probing in a child keeps
+    /// the supervisor's namespaces untouched, and unlike the supervisor the
+    /// child is single-threaded, which CLONE_NEWUSER requires.
+    fn unshare_succeeds(flags: libc::c_int) -> bool {
+        match unsafe { libc::fork() } {
+            -1 => false,
+            0 => unsafe { libc::_exit(if libc::unshare(flags) == 0 { 0 } else { 1 }) },
+            pid => {
+                let mut status = 0;
+                let waited = unsafe { libc::waitpid(pid, &mut status, 0) };
+                waited == pid && libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+            }
+        }
+    }
+
+    pub fn landlock_abi() -> Option<u32> {
+        // LANDLOCK_CREATE_RULESET_VERSION asks for the ABI version instead of a ruleset
+        const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1 << 0;
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<libc::c_void>(),
+                0usize,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        };
+        (abi > 0).then_some(abi as u32)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+    use super::*;
+    use crate::sandbox::macos::already_sandboxed;
+    use std::path::Path;
+
+    pub fn probe(capabilities: &mut HostCapabilities) {
+        capabilities.sandbox_exec = Path::new("/usr/bin/sandbox-exec").exists();
+        capabilities.nested_sandbox = already_sandboxed();
+        capabilities.endpoint_security =
+            cfg!(feature = "endpoint-security") && unsafe { libc::geteuid() } == 0;
+        capabilities.resource_enforcement = ResourceEnforcement::Rlimit;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_reports_platform() {
+        let capabilities = probe();
+        assert_eq!(capabilities.os, std::env::consts::OS);
+        assert_eq!(capabilities.version, env!("CARGO_PKG_VERSION"));
+
+        // Platform features are never reported on the wrong platform
+        if cfg!(not(target_os = "linux")) {
+            assert!(!capabilities.user_namespaces && !capabilities.seccomp);
+        }
+        if cfg!(not(target_os = "macos")) {
+            assert!(!capabilities.sandbox_exec && !capabilities.endpoint_security);
+        }
+        if !capabilities.seccomp {
+            assert!(!capabilities.seccomp_notify);
+        }
+    }
+
+    #[test]
+    fn test_probe_serializes_feature_matrix() {
+        let json = serde_json::to_value(probe()).unwrap();
+        assert!(json["user_namespaces"].is_boolean());
+        assert!(json["cgroup_controllers"].is_array());
+        assert!(json["resource_enforcement"].is_string());
+    }
+}
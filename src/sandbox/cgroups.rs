@@ -1,17 +1,32 @@
 use crate::api::schema::ResourceLimits;
 use crate::error::{CapsuleResult, SandboxError};
+use crate::sandbox::degradation::Degradations;
+use crate::sandbox::devices::{self, Device};
+use crate::sandbox::events::CgroupEventWatcher;
+use crate::sandbox::trace;
+use std::collections::BTreeMap;
 use std::fs::{self, File, OpenOptions};
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+const CONTROLLERS: [&str; 5] = ["memory", "cpu", "cpuset", "pids", "io"];
+const SCOPE_WAIT_TIMEOUT: Duration = Duration::from_secs(2);
+const CPU_PERIOD_US: u64 = 100_000;
+
 pub struct CgroupManager {
     cgroup_path: PathBuf,
+    cgroup_base: PathBuf,
     #[allow(dead_code)] // Used for future tracking and debugging features
     execution_id: Uuid,
+    delegated_scope: Option<String>,
+    /// Created by this manager, and removed when it is dropped
+    owned: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct ResourceUsage {
     pub memory_bytes: u64,
     pub cpu_time_us: u64,
@@ -19,6 +34,13 @@ pub struct ResourceUsage {
     pub kernel_time_us: u64,
     pub io_bytes_read: u64,
     pub io_bytes_written: u64,
+    pub cpu_throttled_us: u64,
+    pub disk_bytes: u64,
+    pub major_faults: u64,
+    pub minor_faults: u64,
+    pub voluntary_ctx_switches: u64,
+    pub involuntary_ctx_switches: u64,
+    pub pids_peak: u64,
 }
 
 impl CgroupManager {
@@ -30,20 +52,76 @@ impl CgroupManager {
 
         Ok(Self {
             cgroup_path,
+            cgroup_base,
             execution_id,
+            delegated_scope: None,
+            owned: true,
         })
     }
 
-    pub fn setup(&self, limits: &ResourceLimits) -> CapsuleResult<()> {
+    /// Read the usage of an execution's existing cgroup, such as another
+    /// process's. Dropping the manager leaves the cgroup in place.
+    pub fn observe(cgroup_path: PathBuf, execution_id: Uuid) -> CapsuleResult<Self> {
+        Ok(Self {
+            cgroup_path,
+            cgroup_base: Self::find_cgroup_mount()?,
+            execution_id,
+            delegated_scope: None,
+            owned: false,
+        })
+    }
+
+    /// `workspace_path` is the host path backing /workspace, if any; its block
+    /// device is the one io.max limits apply to. Limits that cannot be applied are
+    /// recorded in `degradations`.
+    pub fn setup(
+        &mut self,
+        limits: &ResourceLimits,
+        workspace_path: Option<&Path>,
+        degradations: &mut Degradations,
+    ) -> CapsuleResult<()> {
+        // Unprivileged users cannot write under the cgroup2 root; ask the
+        // user's systemd manager for a delegated subtree instead
+        if !Self::is_writable(&self.cgroup_path) {
+            self.delegate_via_systemd()?;
+        }
+
         self.create_cgroup()?;
         self.set_memory_limit(limits.memory_bytes)?;
         self.set_cpu_limit(limits.cpu_shares)?;
+        self.set_cpu_quota(limits.cpu_quota_percent, degradations)?;
+        self.set_cpuset(limits, degradations)?;
         self.set_pids_limit(limits.max_pids)?;
-        self.set_io_limits()?;
+        self.set_io_limits(limits, workspace_path, degradations)?;
         self.add_current_process()?;
         Ok(())
     }
 
+    /// Create the cgroup and set a memory limit as `setup` does, without
+    /// moving a command in, then remove it. Where the hierarchy is not
+    /// writable this asks systemd for a delegated scope as `setup` does,
+    /// which holds the calling process until it exits.
+    pub fn probe_write(&mut self) -> CapsuleResult<()> {
+        if !Self::is_writable(&self.cgroup_path) {
+            self.delegate_via_systemd()?;
+            self.create_cgroup()?;
+            // The leaf holds this process; systemd removes the scope at exit
+            return self.set_memory_limit(ResourceLimits::default().memory_bytes);
+        }
+        self.create_cgroup()?;
+        let written = self.set_memory_limit(ResourceLimits::default().memory_bytes);
+        // rmdir, as gc does: the interface files cannot be unlinked one by one
+        let removed = fs::remove_dir(&self.cgroup_path).map_err(|e| {
+            SandboxError::CgroupSetup(format!(
+                "Failed to remove cgroup {}: {}",
+                self.cgroup_path.display(),
+                e
+            ))
+            .into()
+        });
+        written.and(removed)
+    }
+
     pub fn cleanup(&self) -> CapsuleResult<()> {
         if self.cgroup_path.exists() {
             fs::remove_dir_all(&self.cgroup_path).map_err(|e| {
@@ -61,6 +139,8 @@ impl CgroupManager {
         let memory = self.get_memory_usage()?;
         let (cpu_time, user_time, kernel_time) = self.get_cpu_usage()?;
         let (io_read, io_written) = self.get_io_usage()?;
+        let throttled = self.get_cpu_throttled()?;
+        let (major_faults, minor_faults) = self.get_page_faults();
 
         Ok(ResourceUsage {
             memory_bytes: memory,
@@ -69,10 +149,19 @@ impl CgroupManager {
             kernel_time_us: kernel_time,
             io_bytes_read: io_read,
             io_bytes_written: io_written,
+            cpu_throttled_us: throttled,
+            // Filled in by the sandbox from the filesystem
+            disk_bytes: 0,
+            major_faults,
+            minor_faults,
+            // Not tracked per cgroup; the sandbox takes these from rusage
+            voluntary_ctx_switches: 0,
+            involuntary_ctx_switches: 0,
+            pids_peak: self.get_pids_peak(),
         })
     }
 
-    fn find_cgroup_mount() -> CapsuleResult<PathBuf> {
+    pub(crate) fn find_cgroup_mount() -> CapsuleResult<PathBuf> {
         let mounts = fs::read_to_string("/proc/mounts").map_err(|e| {
             SandboxError::CgroupSetup(format!("Failed to read /proc/mounts: {}", e))
         })?;
@@ -87,6 +176,116 @@ impl CgroupManager {
         Err(SandboxError::CgroupSetup("cgroups v2 not mounted".to_string()).into())
     }
 
+    /// Name of the transient systemd scope owning the cgroup, when running rootless.
+    pub fn delegated_scope(&self) -> Option<&str> {
+        self.delegated_scope.as_deref()
+    }
+
+    /// Allow only the essential devices and `devices` to be opened.
+    pub fn restrict_devices(&self, devices: &[Device]) -> CapsuleResult<()> {
+        devices::restrict(&self.cgroup_path, devices)
+    }
+
+    /// Start watching memory.events and pids.events for limit hits.
+    pub fn watch_events(&self) -> CapsuleResult<CgroupEventWatcher> {
+        CgroupEventWatcher::new(&self.cgroup_path)
+    }
+
+    pub(crate) fn is_writable(path: &Path) -> bool {
+        // The capsule-run directory may not exist yet; check the closest ancestor that does
+        let existing = path.ancestors().find(|p| p.exists());
+        existing.is_some_and(|p| nix::unistd::access(p, nix::unistd::AccessFlags::W_OK).is_ok())
+    }
+
+    fn delegate_via_systemd(&mut self) -> CapsuleResult<()> {
+        let scope = format!("capsule-{}.scope", self.execution_id);
+        let pid = std::process::id().to_string();
+
+        // Equivalent of `systemd-run --user --scope -p Delegate=yes`, but adopting
+        // the already-running supervisor instead of spawning a new command
+        let output = Command::new("busctl")
+            .args([
+                "--user",
+                "call",
+                "org.freedesktop.systemd1",
+                "/org/freedesktop/systemd1",
+                "org.freedesktop.systemd1.Manager",
+                "StartTransientUnit",
+                "ssa(sv)a(sa(sv))",
+                &scope,
+                "fail",
+                "2",
+                "PIDs",
+                "au",
+                "1",
+                &pid,
+                "Delegate",
+                "b",
+                "true",
+                "0",
+            ])
+            .output()
+            .map_err(|e| {
+                SandboxError::CgroupSetup(format!(
+                    "No write access to cgroup hierarchy and busctl is unavailable: {}",
+                    e
+                ))
+            })?;
+
+        if !output.status.success() {
+            return Err(SandboxError::CgroupSetup(format!(
+                "Failed to create delegated systemd scope {}: {}",
+                scope,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+            .into());
+        }
+
+        let scope_path = self.wait_for_scope(&scope)?;
+
+        // cgroup v2 only allows controllers to be enabled on cgroups without
+        // member processes, so move the supervisor into a leaf below the scope
+        self.cgroup_path = scope_path.join("capsule");
+        fs::create_dir_all(&self.cgroup_path).map_err(|e| {
+            SandboxError::CgroupSetup(format!(
+                "Failed to create cgroup directory {}: {}",
+                self.cgroup_path.display(),
+                e
+            ))
+        })?;
+        self.add_current_process()?;
+        self.delegated_scope = Some(scope);
+
+        Ok(())
+    }
+
+    fn wait_for_scope(&self, scope: &str) -> CapsuleResult<PathBuf> {
+        // StartTransientUnit only queues a job; poll until the move has happened
+        let deadline = Instant::now() + SCOPE_WAIT_TIMEOUT;
+        loop {
+            let content = fs::read_to_string("/proc/self/cgroup").map_err(|e| {
+                SandboxError::CgroupSetup(format!("Failed to read /proc/self/cgroup: {}", e))
+            })?;
+
+            if let Some(path) = content
+                .lines()
+                .find_map(|line| line.strip_prefix("0::"))
+                .filter(|path| path.ends_with(scope))
+            {
+                return Ok(self.cgroup_base.join(path.trim_start_matches('/')));
+            }
+
+            if Instant::now() >= deadline {
+                return Err(SandboxError::CgroupSetup(format!(
+                    "Timed out waiting for systemd scope {}",
+                    scope
+                ))
+                .into());
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
     fn create_cgroup(&self) -> CapsuleResult<()> {
         if let Some(parent) = self.cgroup_path.parent() {
             fs::create_dir_all(parent).map_err(|e| {
@@ -106,8 +305,24 @@ impl CgroupManager {
             ))
         })?;
 
-        let controllers = "+memory +cpu +pids +io";
-        self.write_cgroup_file("cgroup.subtree_control", controllers)?;
+        // Controllers are enabled on the parent so they show up in our cgroup.
+        // User managers usually only delegate a subset (often memory and pids).
+        if let Some(parent) = self.cgroup_path.parent() {
+            let available =
+                fs::read_to_string(parent.join("cgroup.controllers")).unwrap_or_default();
+            let controllers: Vec<String> = CONTROLLERS
+                .iter()
+                .filter(|c| available.split_whitespace().any(|a| a == **c))
+                .map(|c| format!("+{}", c))
+                .collect();
+
+            if !controllers.is_empty() {
+                Self::write_file(
+                    &parent.join("cgroup.subtree_control"),
+                    &controllers.join(" "),
+                )?;
+            }
+        }
 
         Ok(())
     }
@@ -123,18 +338,194 @@ impl CgroupManager {
     }
 
     fn set_cpu_limit(&self, cpu_shares: u32) -> CapsuleResult<()> {
+        if !self.has_controller("cpu") {
+            return Ok(());
+        }
         self.write_cgroup_file("cpu.weight", &cpu_shares.to_string())?;
         Ok(())
     }
 
+    fn set_cpu_quota(
+        &self,
+        quota_percent: Option<u32>,
+        degradations: &mut Degradations,
+    ) -> CapsuleResult<()> {
+        let Some(percent) = quota_percent else {
+            return Ok(());
+        };
+
+        // Unlike cpu.weight this is a hard cap, so skipping it must be
+        // recorded: without it the execution can use the whole host
+        if !self.has_controller("cpu") {
+            return degradations.record(
+                "cpu_quota",
+                "cpu controller not available for cpu.max quota",
+            );
+        }
+
+        let quota = CPU_PERIOD_US * percent as u64 / 100;
+        let written = self.write_cgroup_file("cpu.max", &format!("{} {}", quota, CPU_PERIOD_US));
+        Self::degrade(written, "cpu_quota", degradations)
+    }
+
+    fn set_cpuset(
+        &self,
+        limits: &ResourceLimits,
+        degradations: &mut Degradations,
+    ) -> CapsuleResult<()> {
+        // Without the controller the sandbox falls back to sched_setaffinity
+        if !self.has_controller("cpuset") {
+            return Ok(());
+        }
+
+        let mut written = Ok(());
+        if let Some(cpus) = &limits.cpuset {
+            written = self.write_cgroup_file("cpuset.cpus", cpus);
+        }
+        if let Some(mems) = &limits.cpuset_mems {
+            written = written.and_then(|()| self.write_cgroup_file("cpuset.mems", mems));
+        }
+        Self::degrade(written, "cpuset", degradations)
+    }
+
     fn set_pids_limit(&self, max_pids: u32) -> CapsuleResult<()> {
         self.write_cgroup_file("pids.max", &max_pids.to_string())?;
         Ok(())
     }
 
-    fn set_io_limits(&self) -> CapsuleResult<()> {
-        self.write_cgroup_file("io.weight", "100")?;
-        Ok(())
+    fn set_io_limits(
+        &self,
+        limits: &ResourceLimits,
+        workspace_path: Option<&Path>,
+        degradations: &mut Degradations,
+    ) -> CapsuleResult<()> {
+        let has_io_max = Self::has_io_max(limits);
+
+        if !self.has_controller("io") {
+            if has_io_max {
+                return degradations
+                    .record("io_limits", "io controller not available for io.max limits");
+            }
+            return Ok(());
+        }
+        Self::degrade(
+            self.write_cgroup_file("io.weight", "100"),
+            "io_limits",
+            degradations,
+        )?;
+
+        if !has_io_max {
+            return Ok(());
+        }
+
+        // tmpfs and overlay workspaces have no block device to throttle
+        let Some(workspace_path) = workspace_path else {
+            return degradations.record("io_limits", "/workspace is a tmpfs");
+        };
+        let Some((major, minor)) = Self::backing_block_device(workspace_path) else {
+            return degradations.record(
+                "io_limits",
+                format!("{} is not on a block device", workspace_path.display()),
+            );
+        };
+
+        let written = self.write_cgroup_file("io.max", &Self::io_max(limits, major, minor));
+        Self::degrade(written, "io_limits", degradations)
+    }
+
+    /// Record a controller's limit that could not be written as degraded,
+    /// keeping the cgroup and the limits already in it
+    fn degrade(
+        written: CapsuleResult<()>,
+        feature: &str,
+        degradations: &mut Degradations,
+    ) -> CapsuleResult<()> {
+        match written {
+            Ok(()) => Ok(()),
+            Err(e) => degradations.record(feature, e.to_string()),
+        }
+    }
+
+    pub fn has_io_max(limits: &ResourceLimits) -> bool {
+        limits.io_read_bps.is_some()
+            || limits.io_write_bps.is_some()
+            || limits.io_read_iops.is_some()
+            || limits.io_write_iops.is_some()
+    }
+
+    fn io_max(limits: &ResourceLimits, major: u64, minor: u64) -> String {
+        let format_limit = |limit: Option<u64>| limit.map_or("max".to_string(), |l| l.to_string());
+        format!(
+            "{}:{} rbps={} wbps={} riops={} wiops={}",
+            major,
+            minor,
+            format_limit(limits.io_read_bps),
+            format_limit(limits.io_write_bps),
+            format_limit(limits.io_read_iops),
+            format_limit(limits.io_write_iops),
+        )
+    }
+
+    /// The interface files `setup` would write and their values, given the
+    /// controllers available to capsule-run's cgroup.
+    pub fn plan(
+        limits: &ResourceLimits,
+        controllers: &[String],
+        workspace_path: Option<&Path>,
+    ) -> BTreeMap<String, String> {
+        let has_controller = |name: &str| controllers.iter().any(|c| c == name);
+        let mut values = BTreeMap::new();
+        let mut set = |file: &str, value: String| {
+            values.insert(file.to_string(), value);
+        };
+
+        set("memory.max", limits.memory_bytes.to_string());
+        set("memory.swap.max", "0".to_string());
+        set("memory.low", (limits.memory_bytes / 2).to_string());
+        set("pids.max", limits.max_pids.to_string());
+        if has_controller("cpu") {
+            set("cpu.weight", limits.cpu_shares.to_string());
+            if let Some(percent) = limits.cpu_quota_percent {
+                let quota = CPU_PERIOD_US * percent as u64 / 100;
+                set("cpu.max", format!("{} {}", quota, CPU_PERIOD_US));
+            }
+        }
+        if has_controller("cpuset") {
+            if let Some(cpus) = &limits.cpuset {
+                set("cpuset.cpus", cpus.clone());
+            }
+            if let Some(mems) = &limits.cpuset_mems {
+                set("cpuset.mems", mems.clone());
+            }
+        }
+        if has_controller("io") {
+            set("io.weight", "100".to_string());
+            if let Some((major, minor)) = workspace_path
+                .filter(|_| Self::has_io_max(limits))
+                .and_then(Self::backing_block_device)
+            {
+                set("io.max", Self::io_max(limits, major, minor));
+            }
+        }
+        values
+    }
+
+    fn backing_block_device(path: &Path) -> Option<(u64, u64)> {
+        let dev = nix::sys::stat::stat(path).ok()?.st_dev;
+        let (major, minor) = (libc::major(dev) as u64, libc::minor(dev) as u64);
+        if major == 0 {
+            return None;
+        }
+
+        // io.max only accepts whole disks, so resolve partitions to their parent
+        let sys_path = fs::canonicalize(format!("/sys/dev/block/{}:{}", major, minor)).ok()?;
+        if !sys_path.join("partition").exists() {
+            return Some((major, minor));
+        }
+
+        let parent_dev = fs::read_to_string(sys_path.parent()?.join("dev")).ok()?;
+        let (major, minor) = parent_dev.trim().split_once(':')?;
+        Some((major.parse().ok()?, minor.parse().ok()?))
     }
 
     fn add_current_process(&self) -> CapsuleResult<()> {
@@ -143,30 +534,54 @@ impl CgroupManager {
         Ok(())
     }
 
+    /// Controllers enabled for the execution's cgroup.
+    pub fn controllers(&self) -> Vec<String> {
+        self.read_cgroup_file("cgroup.controllers")
+            .map(|c| c.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+
+    fn has_controller(&self, controller: &str) -> bool {
+        self.read_cgroup_file("cgroup.controllers")
+            .map(|c| c.split_whitespace().any(|c| c == controller))
+            .unwrap_or(false)
+    }
+
     fn write_cgroup_file(&self, filename: &str, content: &str) -> CapsuleResult<()> {
-        let file_path = self.cgroup_path.join(filename);
+        Self::write_file(&self.cgroup_path.join(filename), content)
+    }
 
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(false)
-            .open(&file_path)
-            .map_err(|e| {
+    fn write_file(file_path: &Path, content: &str) -> CapsuleResult<()> {
+        let detail = || {
+            format!(
+                "file={} value={}",
+                trace::quote(file_path.display()),
+                trace::quote(content.trim())
+            )
+        };
+        trace::step("cgroup_write", detail, || {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(false)
+                .open(file_path)
+                .map_err(|e| {
+                    SandboxError::CgroupSetup(format!(
+                        "Failed to open cgroup file {}: {}",
+                        file_path.display(),
+                        e
+                    ))
+                })?;
+
+            file.write_all(content.as_bytes()).map_err(|e| {
                 SandboxError::CgroupSetup(format!(
-                    "Failed to open cgroup file {}: {}",
+                    "Failed to write to cgroup file {}: {}",
                     file_path.display(),
                     e
                 ))
             })?;
 
-        file.write_all(content.as_bytes()).map_err(|e| {
-            SandboxError::CgroupSetup(format!(
-                "Failed to write to cgroup file {}: {}",
-                file_path.display(),
-                e
-            ))
-        })?;
-
-        Ok(())
+            Ok(())
+        })
     }
 
     fn read_cgroup_file(&self, filename: &str) -> CapsuleResult<String> {
@@ -199,6 +614,56 @@ impl CgroupManager {
         })
     }
 
+    /// High-water mark of memory.current, or `None` on kernels before 5.19
+    /// that do not provide memory.peak.
+    pub fn get_memory_peak(&self) -> Option<u64> {
+        self.read_cgroup_file("memory.peak")
+            .ok()
+            .and_then(|content| content.trim().parse().ok())
+    }
+
+    /// Major and minor page faults from memory.stat (pgfault counts both).
+    fn get_page_faults(&self) -> (u64, u64) {
+        let Ok(content) = self.read_cgroup_file("memory.stat") else {
+            return (0, 0);
+        };
+
+        let mut faults = 0u64;
+        let mut major = 0u64;
+        for line in content.lines() {
+            match line.split_once(' ') {
+                Some(("pgfault", value)) => faults = value.trim().parse().unwrap_or(0),
+                Some(("pgmajfault", value)) => major = value.trim().parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+
+        (major, faults.saturating_sub(major))
+    }
+
+    /// Tasks in the cgroup now.
+    pub fn get_pids_current(&self) -> u64 {
+        self.read_cgroup_file("pids.current")
+            .ok()
+            .and_then(|content| content.trim().parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// memory.max, or `None` when it is unlimited.
+    pub fn get_memory_limit(&self) -> Option<u64> {
+        self.read_cgroup_file("memory.max")
+            .ok()
+            .and_then(|content| content.trim().parse().ok())
+    }
+
+    /// Highest number of tasks in the cgroup (pids.peak, Linux 6.1+).
+    fn get_pids_peak(&self) -> u64 {
+        self.read_cgroup_file("pids.peak")
+            .ok()
+            .and_then(|content| content.trim().parse().ok())
+            .unwrap_or(0)
+    }
+
     fn get_cpu_usage(&self) -> CapsuleResult<(u64, u64, u64)> {
         let content = self.read_cgroup_file("cpu.stat")?;
 
@@ -227,6 +692,16 @@ impl CgroupManager {
         Ok((usage_usec, user_usec, system_usec))
     }
 
+    fn get_cpu_throttled(&self) -> CapsuleResult<u64> {
+        let content = self.read_cgroup_file("cpu.stat")?;
+
+        Ok(content
+            .lines()
+            .find_map(|line| line.strip_prefix("throttled_usec "))
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(0))
+    }
+
     fn get_io_usage(&self) -> CapsuleResult<(u64, u64)> {
         let content = self.read_cgroup_file("io.stat")?;
 
@@ -272,39 +747,114 @@ impl CgroupManager {
 
 impl Drop for CgroupManager {
     fn drop(&mut self) {
-        let _ = self.cleanup();
+        if self.owned {
+            let _ = self.cleanup();
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::api::schema::EnforcementPolicy;
 
     #[test]
     fn test_cgroup_manager_creation() {
         let execution_id = Uuid::new_v4();
         let result = CgroupManager::new(execution_id);
 
-        match result {
-            Ok(manager) => {
-                assert!(manager
-                    .cgroup_path
-                    .to_string_lossy()
-                    .contains(&execution_id.to_string()));
-            }
-            Err(_) => {}
+        if let Ok(manager) = result {
+            assert!(manager
+                .cgroup_path
+                .to_string_lossy()
+                .contains(&execution_id.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_setup_degrades_missing_controller() {
+        // A stand-in cgroup delegating memory and pids, but not cpu or io
+        let dir = tempfile::tempdir().unwrap();
+        let cgroup_path = dir.path().join("capsule-run").join("id");
+        fs::create_dir_all(&cgroup_path).unwrap();
+        fs::write(cgroup_path.join("cgroup.controllers"), "memory pids\n").unwrap();
+        for file in [
+            "memory.max",
+            "memory.swap.max",
+            "memory.low",
+            "pids.max",
+            "cgroup.procs",
+        ] {
+            fs::write(cgroup_path.join(file), "").unwrap();
         }
+        let mut manager = CgroupManager {
+            cgroup_path: cgroup_path.clone(),
+            cgroup_base: dir.path().to_path_buf(),
+            execution_id: Uuid::new_v4(),
+            delegated_scope: None,
+            owned: false,
+        };
+        let limits = ResourceLimits {
+            memory_bytes: 64 * 1024 * 1024,
+            max_pids: 32,
+            cpu_quota_percent: Some(50),
+            io_read_bps: Some(1024 * 1024),
+            ..Default::default()
+        };
+
+        let mut degradations = Degradations::new(EnforcementPolicy::BestEffort);
+        manager.setup(&limits, None, &mut degradations).unwrap();
+        let features: Vec<&str> = degradations
+            .features()
+            .iter()
+            .map(|f| f.feature.as_str())
+            .collect();
+        assert_eq!(features, vec!["cpu_quota", "io_limits"]);
+        assert_eq!(
+            fs::read_to_string(cgroup_path.join("memory.max")).unwrap(),
+            (64 * 1024 * 1024).to_string()
+        );
+        assert_eq!(
+            fs::read_to_string(cgroup_path.join("pids.max")).unwrap(),
+            "32"
+        );
+
+        let mut strict = Degradations::new(EnforcementPolicy::Strict);
+        let error = manager.setup(&limits, None, &mut strict).unwrap_err();
+        assert!(matches!(
+            error,
+            crate::error::CapsuleError::SandboxSetup(SandboxError::IsolationUnavailable {
+                ref feature,
+                ..
+            }) if feature == "cpu_quota"
+        ));
+    }
+
+    #[test]
+    fn test_is_writable_uses_existing_ancestor() {
+        let dir = std::env::temp_dir().join(format!("capsule-cgtest-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        assert!(CgroupManager::is_writable(
+            &dir.join("capsule-run").join("id")
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_backing_block_device_virtual_fs() {
+        // procfs has an anonymous device number
+        assert_eq!(
+            CgroupManager::backing_block_device(Path::new("/proc")),
+            None
+        );
     }
 
     #[test]
     fn test_find_cgroup_mount() {
         let result = CgroupManager::find_cgroup_mount();
 
-        match result {
-            Ok(path) => {
-                assert!(path.exists());
-            }
-            Err(_) => {}
+        if let Ok(path) = result {
+            assert!(path.exists());
         }
     }
 }
@@ -0,0 +1,136 @@
+use super::artifacts::walk;
+use crate::api::schema::{ChangeKind, WorkspaceChange};
+use crate::error::{CapsuleResult, SandboxError};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{self, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// Hashing more files than this would dominate the run
+const MAX_TRACKED_FILES: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileState {
+    size: u64,
+    sha256: String,
+}
+
+/// Size and content hash of every regular file in the workspace.
+#[derive(Debug, Default)]
+pub struct WorkspaceSnapshot {
+    files: BTreeMap<String, FileState>,
+}
+
+impl WorkspaceSnapshot {
+    pub fn take(workspace: &Path) -> CapsuleResult<Self> {
+        let mut paths = Vec::new();
+        walk(workspace, Path::new(""), &mut paths);
+        if paths.len() > MAX_TRACKED_FILES {
+            return Err(SandboxError::FilesystemSetup(format!(
+                "Too many workspace files to track changes: {} (max: {})",
+                paths.len(),
+                MAX_TRACKED_FILES
+            ))
+            .into());
+        }
+
+        let mut files = BTreeMap::new();
+        for relative in paths {
+            // Files removed mid-walk are simply absent from the snapshot
+            if let Ok(state) = hash_file(&workspace.join(&relative)) {
+                files.insert(relative.to_string_lossy().into_owned(), state);
+            }
+        }
+        Ok(Self { files })
+    }
+
+    /// Files created, modified or deleted between `self` and `after`, by path.
+    pub fn changes(&self, after: &WorkspaceSnapshot) -> Vec<WorkspaceChange> {
+        let mut changes = Vec::new();
+        for (path, state) in &after.files {
+            let change = match self.files.get(path) {
+                None => ChangeKind::Created,
+                Some(before) if before != state => ChangeKind::Modified,
+                Some(_) => continue,
+            };
+            changes.push(WorkspaceChange {
+                path: path.clone(),
+                change,
+                size: state.size,
+                sha256: state.sha256.clone(),
+            });
+        }
+        for (path, state) in &self.files {
+            if !after.files.contains_key(path) {
+                changes.push(WorkspaceChange {
+                    path: path.clone(),
+                    change: ChangeKind::Deleted,
+                    size: state.size,
+                    sha256: state.sha256.clone(),
+                });
+            }
+        }
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        changes
+    }
+}
+
+fn hash_file(path: &Path) -> io::Result<FileState> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    Ok(FileState {
+        size,
+        sha256: format!("{:x}", hasher.finalize()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_workspace_changes() {
+        let workspace = tempfile::tempdir().unwrap();
+        let root = workspace.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/lib.rs"), "old").unwrap();
+        fs::write(root.join("README"), "same").unwrap();
+        fs::write(root.join("stale.log"), "gone").unwrap();
+        let before = WorkspaceSnapshot::take(root).unwrap();
+
+        fs::write(root.join("src/lib.rs"), "new!").unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::remove_file(root.join("stale.log")).unwrap();
+        let after = WorkspaceSnapshot::take(root).unwrap();
+
+        let changes = before.changes(&after);
+        let summary: Vec<(&str, ChangeKind, u64)> = changes
+            .iter()
+            .map(|c| (c.path.as_str(), c.change, c.size))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("src/lib.rs", ChangeKind::Modified, 4),
+                ("src/main.rs", ChangeKind::Created, 12),
+                ("stale.log", ChangeKind::Deleted, 4),
+            ]
+        );
+    }
+}
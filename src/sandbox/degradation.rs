@@ -0,0 +1,71 @@
+use crate::api::schema::{DegradedFeature, EnforcementPolicy};
+use crate::error::{CapsuleResult, SandboxError};
+
+/// Requested isolation features the host could not provide, checked against
+/// the request's enforcement policy as they are found during setup.
+#[derive(Debug, Default)]
+pub struct Degradations {
+    policy: EnforcementPolicy,
+    features: Vec<DegradedFeature>,
+}
+
+impl Degradations {
+    pub fn new(policy: EnforcementPolicy) -> Self {
+        Self {
+            policy,
+            features: Vec::new(),
+        }
+    }
+
+    /// Record that `feature` was not applied. Under strict enforcement this
+    /// is an error; under best effort it is a warning and a response entry.
+    pub fn record(&mut self, feature: &str, reason: impl Into<String>) -> CapsuleResult<()> {
+        let reason = reason.into();
+        if self.policy == EnforcementPolicy::Strict {
+            return Err(SandboxError::IsolationUnavailable {
+                feature: feature.to_string(),
+                reason,
+            }
+            .into());
+        }
+
+        tracing::warn!(feature, %reason, "Isolation feature not applied");
+        self.features.push(DegradedFeature {
+            feature: feature.to_string(),
+            reason,
+        });
+        Ok(())
+    }
+
+    pub fn features(&self) -> &[DegradedFeature] {
+        &self.features
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::CapsuleError;
+
+    #[test]
+    fn test_best_effort_records_feature() {
+        let mut degradations = Degradations::new(EnforcementPolicy::BestEffort);
+        degradations
+            .record("cgroup_limits", "cgroups v2 not mounted")
+            .unwrap();
+        assert_eq!(degradations.features()[0].feature, "cgroup_limits");
+    }
+
+    #[test]
+    fn test_strict_fails_fast() {
+        let mut degradations = Degradations::new(EnforcementPolicy::Strict);
+        let result = degradations.record("seccomp", "not compiled in");
+        assert!(matches!(
+            result,
+            Err(CapsuleError::SandboxSetup(
+                SandboxError::IsolationUnavailable { .. }
+            ))
+        ));
+        assert!(degradations.features().is_empty());
+    }
+}
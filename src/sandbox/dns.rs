@@ -0,0 +1,348 @@
+use crate::api::schema::DnsConfig;
+use crate::error::{CapsuleResult, SandboxError};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Where the stub listens inside the execution's network namespace.
+pub const STUB_ADDRESS: Ipv4Addr = Ipv4Addr::LOCALHOST;
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(2);
+/// How often the stub checks whether it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+const HEADER_LEN: usize = 12;
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// Contents of the sandbox's /etc/resolv.conf.
+///
+/// Settings missing from `dns` are carried over from `host_conf`; with a
+/// domain allowlist the only nameserver is the stub.
+pub fn resolv_conf(dns: &DnsConfig, host_conf: &str) -> String {
+    let host_lines = |keyword: &str| -> Vec<String> {
+        host_conf
+            .lines()
+            .filter(|line| line.split_whitespace().next() == Some(keyword))
+            .map(str::to_string)
+            .collect()
+    };
+
+    let mut lines = if !dns.allowed_domains.is_empty() {
+        vec![format!("nameserver {}", STUB_ADDRESS)]
+    } else if !dns.nameservers.is_empty() {
+        dns.nameservers
+            .iter()
+            .map(|nameserver| format!("nameserver {}", nameserver))
+            .collect()
+    } else {
+        host_lines("nameserver")
+    };
+
+    if dns.search.is_empty() {
+        lines.extend(host_lines("search"));
+    } else {
+        lines.push(format!("search {}", dns.search.join(" ")));
+    }
+    lines.extend(host_lines("options"));
+
+    let mut conf = lines.join("\n");
+    conf.push('\n');
+    conf
+}
+
+/// Contents of the sandbox's /etc/hosts: `extra_hosts` followed by the
+/// host's entries. Lookups stop at the first match, so added names win.
+pub fn hosts_file(extra_hosts: &HashMap<String, IpAddr>, host_hosts: &str) -> String {
+    let mut entries: Vec<_> = extra_hosts.iter().collect();
+    entries.sort();
+
+    let mut hosts = String::from("# Added by capsule-run\n");
+    for (name, address) in entries {
+        hosts.push_str(&format!("{}\t{}\n", address, name));
+    }
+    hosts.push_str(host_hosts);
+    if !hosts.ends_with('\n') {
+        hosts.push('\n');
+    }
+    hosts
+}
+
+/// First nameserver listed in a resolv.conf.
+pub fn first_nameserver(conf: &str) -> Option<IpAddr> {
+    nameservers(conf).into_iter().next()
+}
+
+/// Every nameserver listed in a resolv.conf, in order.
+pub fn nameservers(conf: &str) -> Vec<IpAddr> {
+    conf.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next()) {
+                (Some("nameserver"), Some(address)) => address.parse().ok(),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// A UDP resolver inside the execution's network namespace that answers
+/// NXDOMAIN for names outside the allowlist and forwards the rest.
+///
+/// The upstream socket is created while still in the host's network
+/// namespace, so forwarded queries are sent from the host and a loopback
+/// resolver such as systemd-resolved works. The listener is bound after
+/// joining the execution's namespace, and the thread is only started once
+/// setup has forked, since threads do not survive a fork.
+pub struct DnsStub {
+    allowed_domains: Vec<String>,
+    upstream: UdpSocket,
+    listener: Option<UdpSocket>,
+    stop: Arc<AtomicBool>,
+}
+
+impl DnsStub {
+    /// Connect to `upstream`; call from the host's network namespace.
+    pub fn connect(upstream: IpAddr, allowed_domains: &[String]) -> CapsuleResult<Self> {
+        let local: SocketAddr = match upstream {
+            IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            IpAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)
+            .and_then(|socket| socket.connect((upstream, 53)).map(|_| socket))
+            .and_then(|socket| {
+                socket
+                    .set_read_timeout(Some(UPSTREAM_TIMEOUT))
+                    .map(|_| socket)
+            })
+            .map_err(|e| {
+                SandboxError::NetworkSetup(format!(
+                    "Failed to reach DNS upstream {}: {}",
+                    upstream, e
+                ))
+            })?;
+
+        Ok(Self {
+            allowed_domains: allowed_domains
+                .iter()
+                .map(|domain| domain.trim_end_matches('.').to_ascii_lowercase())
+                .collect(),
+            upstream: socket,
+            listener: None,
+            stop: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Bind port 53 on loopback; call from the execution's network namespace.
+    pub fn listen(&mut self) -> CapsuleResult<()> {
+        let listener = UdpSocket::bind((STUB_ADDRESS, 53))
+            .and_then(|socket| socket.set_read_timeout(Some(POLL_INTERVAL)).map(|_| socket))
+            .map_err(|e| SandboxError::NetworkSetup(format!("Failed to bind DNS stub: {}", e)))?;
+        self.listener = Some(listener);
+        Ok(())
+    }
+
+    /// Serve queries until the stub is dropped.
+    pub fn start(&mut self) -> CapsuleResult<()> {
+        let Some(listener) = self.listener.take() else {
+            return Err(SandboxError::NetworkSetup("DNS stub is not listening".to_string()).into());
+        };
+        let upstream = self
+            .upstream
+            .try_clone()
+            .map_err(|e| SandboxError::NetworkSetup(format!("Failed to start DNS stub: {}", e)))?;
+        let allowed_domains = self.allowed_domains.clone();
+        let stop = self.stop.clone();
+
+        thread::Builder::new()
+            .name("dns-stub".to_string())
+            .spawn(move || serve(&listener, &upstream, &allowed_domains, &stop))
+            .map_err(|e| SandboxError::NetworkSetup(format!("Failed to start DNS stub: {}", e)))?;
+        Ok(())
+    }
+}
+
+impl Drop for DnsStub {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn serve(
+    listener: &UdpSocket,
+    upstream: &UdpSocket,
+    allowed_domains: &[String],
+    stop: &AtomicBool,
+) {
+    let mut query = [0u8; 512];
+    let mut response = [0u8; 4096];
+
+    while !stop.load(Ordering::Relaxed) {
+        let Ok((len, client)) = listener.recv_from(&mut query) else {
+            continue;
+        };
+        let query = &query[..len];
+
+        let allowed = query_name(query).is_some_and(|name| is_allowed(&name, allowed_domains));
+        if !allowed {
+            if let Some(refusal) = nxdomain(query) {
+                let _ = listener.send_to(&refusal, client);
+            }
+            continue;
+        }
+
+        if upstream.send(query).is_err() {
+            continue;
+        }
+        // Skip late answers to earlier queries that timed out
+        while let Ok(len) = upstream.recv(&mut response) {
+            if len >= 2 && response[..2] == query[..2] {
+                let _ = listener.send_to(&response[..len], client);
+                break;
+            }
+        }
+    }
+}
+
+/// The first question's name, lowercased and without the trailing dot.
+fn query_name(packet: &[u8]) -> Option<String> {
+    let mut labels = Vec::new();
+    let mut offset = HEADER_LEN;
+    loop {
+        let len = *packet.get(offset)? as usize;
+        if len == 0 {
+            break;
+        }
+        // Compression pointers and extended labels never appear in a question
+        if len > 63 {
+            return None;
+        }
+        let label = packet.get(offset + 1..offset + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+        offset += 1 + len;
+    }
+    Some(labels.join("."))
+}
+
+fn is_allowed(name: &str, allowed_domains: &[String]) -> bool {
+    allowed_domains.iter().any(|domain| {
+        name == domain
+            || name
+                .strip_suffix(domain.as_str())
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    })
+}
+
+/// An NXDOMAIN answer to `query`, echoing its question.
+fn nxdomain(query: &[u8]) -> Option<Vec<u8>> {
+    // Header, the name and the type and class
+    let mut end = HEADER_LEN;
+    loop {
+        let len = *query.get(end)? as usize;
+        end += 1 + len;
+        if len == 0 {
+            break;
+        }
+    }
+    end += 4;
+    let mut response = query.get(..end)?.to_vec();
+
+    // Keep the opcode and RD; set QR and RA
+    response[2] = 0x80 | (response[2] & 0x79);
+    response[3] = 0x80 | RCODE_NXDOMAIN;
+    // One question, no answer, authority or additional records
+    response[4..12].copy_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(id: u16, name: &str) -> Vec<u8> {
+        let mut packet = id.to_be_bytes().to_vec();
+        packet.extend([0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        for label in name.split('.') {
+            packet.push(label.len() as u8);
+            packet.extend(label.as_bytes());
+        }
+        packet.extend([0, 0, 1, 0, 1]);
+        packet
+    }
+
+    #[test]
+    fn test_resolv_conf() {
+        let host = "# generated\nnameserver 127.0.0.53\nsearch corp.example\noptions edns0\n";
+
+        let dns = DnsConfig {
+            nameservers: vec!["1.1.1.1".parse().unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(
+            resolv_conf(&dns, host),
+            "nameserver 1.1.1.1\nsearch corp.example\noptions edns0\n"
+        );
+
+        let dns = DnsConfig {
+            search: vec!["svc.local".to_string()],
+            allowed_domains: vec!["github.com".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            resolv_conf(&dns, host),
+            "nameserver 127.0.0.1\nsearch svc.local\noptions edns0\n"
+        );
+    }
+
+    #[test]
+    fn test_hosts_file() {
+        let extra_hosts = HashMap::from([
+            ("b.internal".to_string(), "10.1.2.3".parse().unwrap()),
+            ("a.internal".to_string(), "fd00::1".parse().unwrap()),
+        ]);
+        assert_eq!(
+            hosts_file(&extra_hosts, "127.0.0.1\tlocalhost"),
+            "# Added by capsule-run\nfd00::1\ta.internal\n10.1.2.3\tb.internal\n127.0.0.1\tlocalhost\n"
+        );
+    }
+
+    #[test]
+    fn test_first_nameserver() {
+        assert_eq!(
+            first_nameserver("search x\nnameserver 10.0.0.2\nnameserver 10.0.0.3\n"),
+            Some("10.0.0.2".parse().unwrap())
+        );
+        assert_eq!(first_nameserver("search x\n"), None);
+        assert_eq!(
+            nameservers("nameserver 10.0.0.2\nnameserver fd00::53\nnameserver bogus\n"),
+            vec![
+                "10.0.0.2".parse::<IpAddr>().unwrap(),
+                "fd00::53".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_query_name_and_allowlist() {
+        let allowed = vec!["github.com".to_string()];
+        let name = query_name(&query(7, "API.GitHub.com")).unwrap();
+        assert_eq!(name, "api.github.com");
+        assert!(is_allowed(&name, &allowed));
+        assert!(is_allowed("github.com", &allowed));
+        assert!(!is_allowed("evilgithub.com", &allowed));
+        assert!(!is_allowed("github.com.evil.net", &allowed));
+        assert_eq!(query_name(&[0; 5]), None);
+    }
+
+    #[test]
+    fn test_nxdomain_echoes_question() {
+        let query = query(0x1234, "example.org");
+        let response = nxdomain(&query).unwrap();
+        assert_eq!(&response[..2], &[0x12, 0x34]);
+        assert_eq!(response[2] & 0x80, 0x80);
+        assert_eq!(response[2] & 0x01, 0x01);
+        assert_eq!(response[3] & 0x0f, RCODE_NXDOMAIN);
+        assert_eq!(&response[HEADER_LEN..], &query[HEADER_LEN..]);
+        assert!(nxdomain(&query[..HEADER_LEN + 3]).is_none());
+    }
+}
@@ -0,0 +1,523 @@
+use crate::api::schema::{CheckStatus, DoctorCheck, HostCapabilities};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Free space in the sandbox parent below which doctor warns
+const MIN_TMP_FREE_BYTES: u64 = 268_435_456; // 256 MB
+
+/// Check the host for what sandbox setup needs, saying how to fix what is
+/// missing. `capabilities` is the result of `capabilities::probe`.
+pub fn diagnose(capabilities: &HostCapabilities) -> Vec<DoctorCheck> {
+    let mut checks = Vec::new();
+
+    #[cfg(target_os = "linux")]
+    linux::diagnose(capabilities, &mut checks);
+    #[cfg(target_os = "macos")]
+    macos::diagnose(capabilities, &mut checks);
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        let _ = capabilities;
+        checks.push(check(
+            "platform",
+            CheckStatus::Fail,
+            format!("{} has no sandbox backend", std::env::consts::OS),
+            Some("Run capsule-run on Linux or macOS"),
+        ));
+    }
+
+    checks
+}
+
+/// Exercise the primitives every run needs rather than only looking them
+/// up, for health checks that drain a host whose kernel settings changed.
+/// `capabilities` is the result of `capabilities::probe`, which creates a
+/// user namespace in a child. On Linux a cgroup is also created and limited,
+/// through the systemd user manager when runs need it to delegate one, and
+/// the default seccomp filter is installed in a child. `config` is the file
+/// runs would load, if any.
+pub fn ping(capabilities: &HostCapabilities, config: Option<&Path>) -> Vec<DoctorCheck> {
+    #[cfg(target_os = "linux")]
+    let mut checks = linux::ping(capabilities);
+    #[cfg(target_os = "macos")]
+    let mut checks = vec![macos::sandbox_exec(capabilities, true)];
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    let mut checks = diagnose(capabilities);
+    checks.push(config_file(config));
+    checks
+}
+
+/// Whether `path` loads and passes `config validate`, since every run
+/// would fail on it otherwise
+fn config_file(path: Option<&Path>) -> DoctorCheck {
+    let Some(path) = path else {
+        return check(
+            "config",
+            CheckStatus::Pass,
+            "No config file; runs use the built-in defaults",
+            None,
+        );
+    };
+    let errors = match crate::config::Config::check_file(path) {
+        Ok(report) => report.errors,
+        Err(e) => vec![e.to_string()],
+    };
+    if errors.is_empty() {
+        check(
+            "config",
+            CheckStatus::Pass,
+            format!("{} is valid", path.display()),
+            None,
+        )
+    } else {
+        check(
+            "config",
+            CheckStatus::Fail,
+            format!("{}: {}", path.display(), errors.join("; ")),
+            Some("Run capsule-run config validate and fix the errors"),
+        )
+    }
+}
+
+fn check(
+    name: &str,
+    status: CheckStatus,
+    detail: impl Into<String>,
+    remediation: Option<&str>,
+) -> DoctorCheck {
+    DoctorCheck {
+        name: name.to_string(),
+        status,
+        detail: detail.into(),
+        remediation: remediation.map(str::to_string),
+    }
+}
+
+/// Whether `dir`, where sandbox roots are created, is writable and has room
+#[cfg_attr(not(any(target_os = "linux", target_os = "macos")), allow(dead_code))]
+fn tmp_space(dir: &Path) -> DoctorCheck {
+    let path = CString::new(dir.as_os_str().as_bytes()).unwrap_or_default();
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    let usable = unsafe {
+        libc::access(path.as_ptr(), libc::W_OK) == 0 && libc::statvfs(path.as_ptr(), &mut stat) == 0
+    };
+    if !usable {
+        return check(
+            "tmp_space",
+            CheckStatus::Fail,
+            format!("{} is not writable", dir.display()),
+            Some("Make it writable by this user; sandbox roots are created there"),
+        );
+    }
+    #[allow(clippy::unnecessary_cast)] // The field types differ between platforms
+    let free = stat.f_bavail as u64 * stat.f_frsize as u64;
+    let detail = format!("{} MB free in {}", free / 1_048_576, dir.display());
+    if free < MIN_TMP_FREE_BYTES {
+        check(
+            "tmp_space",
+            CheckStatus::Warn,
+            detail,
+            Some("Free space there; on Linux `capsule-run gc` removes sandbox roots left by crashed runs"),
+        )
+    } else {
+        check("tmp_space", CheckStatus::Pass, detail, None)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::*;
+    use crate::sandbox::cgroups::CgroupManager;
+    use crate::sandbox::executable;
+    use crate::sandbox::gc::SANDBOX_PARENT;
+    use std::fs;
+
+    pub fn diagnose(capabilities: &HostCapabilities, checks: &mut Vec<DoctorCheck>) {
+        checks.push(user_namespaces(capabilities.user_namespaces, sysctl));
+        checks.extend(cgroups(capabilities));
+        checks.push(seccomp(capabilities));
+        checks.push(tmp_space(Path::new(SANDBOX_PARENT)));
+        let user = std::env::var("USER").unwrap_or_default();
+        let subuid = fs::read_to_string("/etc/subuid").unwrap_or_default();
+        checks.push(subordinate_ids(&subuid, &user, unsafe { libc::getuid() }));
+    }
+
+    pub fn ping(capabilities: &HostCapabilities) -> Vec<DoctorCheck> {
+        vec![
+            user_namespaces(capabilities.user_namespaces, sysctl),
+            cgroup_write(capabilities),
+            seccomp_filter(capabilities),
+        ]
+    }
+
+    /// Write to a fresh cgroup as a run would, asking the systemd user
+    /// manager for a delegated scope first where runs have to
+    fn cgroup_write(capabilities: &HostCapabilities) -> DoctorCheck {
+        if !capabilities.cgroup_v2 {
+            return check(
+                "cgroup_write",
+                CheckStatus::Warn,
+                "No cgroup v2 hierarchy is mounted; resource limits fall back to rlimits",
+                Some("Boot with systemd.unified_cgroup_hierarchy=1"),
+            );
+        }
+        let written = CgroupManager::new(uuid::Uuid::new_v4())
+            .and_then(|mut cgroup| cgroup.probe_write().map(|()| cgroup));
+        match written {
+            Ok(cgroup) => check(
+                "cgroup_write",
+                CheckStatus::Pass,
+                match cgroup.delegated_scope() {
+                    Some(scope) => format!("Got delegated scope {} and set memory.max", scope),
+                    None => "Created a cgroup and set memory.max".to_string(),
+                },
+                None,
+            ),
+            Err(e) => check(
+                "cgroup_write",
+                CheckStatus::Warn,
+                format!("{}; resource limits fall back to rlimits", e),
+                Some("Run capsule-run doctor for the cgroup setup"),
+            ),
+        }
+    }
+
+    /// Install the default filter in a child, as a run's child does before
+    /// exec. Without seccomp support this is the doctor check.
+    fn seccomp_filter(capabilities: &HostCapabilities) -> DoctorCheck {
+        #[cfg(feature = "seccomp")]
+        if capabilities.seccomp {
+            use crate::api::schema::SeccompPreset;
+            use crate::sandbox::seccomp::SeccompFilter;
+
+            let compiled = SeccompFilter::new().and_then(|mut filter| {
+                filter.setup_allowlist(SeccompPreset::Default, &[])?;
+                filter.compile()
+            });
+            let installed = compiled.map(|compiled| match unsafe { libc::fork() } {
+                -1 => false,
+                0 => unsafe { libc::_exit(if compiled.install().is_ok() { 0 } else { 1 }) },
+                pid => {
+                    let mut status = 0;
+                    let waited = unsafe { libc::waitpid(pid, &mut status, 0) };
+                    waited == pid && libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+                }
+            });
+            return match installed {
+                Ok(true) => check(
+                    "seccomp",
+                    CheckStatus::Pass,
+                    "Installed the default filter in a child",
+                    None,
+                ),
+                Ok(false) => check(
+                    "seccomp",
+                    CheckStatus::Fail,
+                    "A child could not install the default filter",
+                    Some("Check that no_new_privs and seccomp are not blocked for capsule-run"),
+                ),
+                Err(e) => check(
+                    "seccomp",
+                    CheckStatus::Fail,
+                    e.to_string(),
+                    Some("Check the libseccomp installation"),
+                ),
+            };
+        }
+        seccomp(capabilities)
+    }
+
+    fn sysctl(name: &str) -> Option<String> {
+        let path = Path::new("/proc/sys").join(name.replace('.', "/"));
+        fs::read_to_string(path)
+            .ok()
+            .map(|value| value.trim().to_string())
+    }
+
+    /// Which of the knobs distributions use to restrict unprivileged user
+    /// namespaces is set, read through `sysctl`.
+    pub(super) fn user_namespaces(
+        available: bool,
+        sysctl: impl Fn(&str) -> Option<String>,
+    ) -> DoctorCheck {
+        if available {
+            return check(
+                "user_namespaces",
+                CheckStatus::Pass,
+                "Unprivileged user namespaces can be created",
+                None,
+            );
+        }
+        let set = |name: &str, value: &str| sysctl(name).as_deref() == Some(value);
+        let (cause, remediation) = if set("kernel.unprivileged_userns_clone", "0") {
+            (
+                "kernel.unprivileged_userns_clone is 0",
+                "sudo sysctl -w kernel.unprivileged_userns_clone=1",
+            )
+        } else if set("kernel.apparmor_restrict_unprivileged_userns", "1") {
+            (
+                "AppArmor restricts unprivileged user namespaces",
+                "Give capsule-run an AppArmor profile allowing `userns,`, or run \
+                 sudo sysctl -w kernel.apparmor_restrict_unprivileged_userns=0",
+            )
+        } else if set("user.max_user_namespaces", "0") {
+            (
+                "user.max_user_namespaces is 0",
+                "sudo sysctl -w user.max_user_namespaces=15000",
+            )
+        } else {
+            (
+                "unshare(CLONE_NEWUSER) is refused, usually by a container's seccomp profile",
+                "Allow unshare in the surrounding container, for example with Docker's \
+                 --security-opt seccomp=unconfined",
+            )
+        };
+        check(
+            "user_namespaces",
+            CheckStatus::Fail,
+            format!("{}; runs fail with E2001", cause),
+            Some(remediation),
+        )
+    }
+
+    fn cgroups(capabilities: &HostCapabilities) -> Vec<DoctorCheck> {
+        if !capabilities.cgroup_v2 {
+            return vec![check(
+                "cgroup_v2",
+                CheckStatus::Warn,
+                "No cgroup v2 hierarchy is mounted; resource limits fall back to rlimits",
+                Some("Boot with systemd.unified_cgroup_hierarchy=1"),
+            )];
+        }
+
+        let runtime_bus = std::env::var_os("XDG_RUNTIME_DIR")
+            .is_some_and(|dir| Path::new(&dir).join("bus").exists());
+        let user_bus = std::env::var_os("DBUS_SESSION_BUS_ADDRESS").is_some() || runtime_bus;
+        let busctl = executable::resolve(
+            "busctl",
+            &std::env::var("PATH").unwrap_or_default(),
+            Path::new("/"),
+        )
+        .is_some();
+        let delegation = if capabilities.cgroup_delegated {
+            check(
+                "cgroup_v2",
+                CheckStatus::Pass,
+                "The cgroup hierarchy is writable",
+                None,
+            )
+        } else if user_bus && busctl {
+            check(
+                "cgroup_v2",
+                CheckStatus::Pass,
+                "Each run asks the systemd user manager for a delegated scope",
+                None,
+            )
+        } else {
+            check(
+                "cgroup_v2",
+                CheckStatus::Fail,
+                "The cgroup hierarchy is not writable and the systemd user manager cannot \
+                 delegate one; runs fail with E2002",
+                Some(
+                    "Run from a systemd login session with busctl installed \
+                     (loginctl enable-linger $USER keeps the user manager running), or as root",
+                ),
+            )
+        };
+
+        let missing: Vec<&str> = ["memory", "cpu", "pids"]
+            .into_iter()
+            .filter(|controller| {
+                !capabilities
+                    .cgroup_controllers
+                    .iter()
+                    .any(|c| c == controller)
+            })
+            .collect();
+        let controllers = if missing.is_empty() {
+            check(
+                "cgroup_controllers",
+                CheckStatus::Pass,
+                capabilities.cgroup_controllers.join(" "),
+                None,
+            )
+        } else {
+            check(
+                "cgroup_controllers",
+                CheckStatus::Warn,
+                format!(
+                    "{} not available; those limits are not enforced",
+                    missing.join(", ")
+                ),
+                Some(
+                    "Enable them in the parent's cgroup.subtree_control, or for systemd \
+                     delegation set Delegate=cpu memory pids in a user@.service drop-in",
+                ),
+            )
+        };
+        vec![delegation, controllers]
+    }
+
+    fn seccomp(capabilities: &HostCapabilities) -> DoctorCheck {
+        if !cfg!(feature = "seccomp") {
+            return check(
+                "seccomp",
+                CheckStatus::Warn,
+                "Built without the seccomp feature; syscalls are not filtered",
+                Some("Rebuild with --features seccomp and libseccomp installed"),
+            );
+        }
+        if !capabilities.seccomp {
+            return check(
+                "seccomp",
+                CheckStatus::Warn,
+                "The kernel does not support seccomp filters; syscalls are not filtered",
+                Some("Use a kernel built with CONFIG_SECCOMP_FILTER"),
+            );
+        }
+        let detail = if capabilities.seccomp_notify {
+            "Filters and user notification are supported"
+        } else {
+            "Filters are supported; user notification needs Linux 5.0+"
+        };
+        check("seccomp", CheckStatus::Pass, detail, None)
+    }
+
+    /// The `user` or `uid` entry of /etc/subuid. capsule-run maps only the
+    /// caller's uid; setuid helpers such as newuidmap need a range for more.
+    pub(super) fn subordinate_ids(subuid: &str, user: &str, uid: u32) -> DoctorCheck {
+        let uid = uid.to_string();
+        let range = subuid.lines().find_map(|line| {
+            let mut fields = line.split(':');
+            let owner = fields.next()?;
+            let (start, count) = (fields.next()?, fields.next()?);
+            (owner == user || owner == uid).then(|| format!("{} ids from {}", count, start))
+        });
+        match range {
+            Some(range) => check("subordinate_ids", CheckStatus::Pass, range, None),
+            None if uid == "0" => check(
+                "subordinate_ids",
+                CheckStatus::Pass,
+                "Not needed as root",
+                None,
+            ),
+            None => check(
+                "subordinate_ids",
+                CheckStatus::Warn,
+                format!(
+                    "No /etc/subuid range for {}; only uid {} is mapped, so files owned by \
+                     other users appear as nobody",
+                    user, uid
+                ),
+                Some("sudo usermod --add-subuids 100000-165535 --add-subgids 100000-165535 $USER"),
+            ),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+    use super::*;
+
+    pub fn diagnose(capabilities: &HostCapabilities, checks: &mut Vec<DoctorCheck>) {
+        checks.push(sandbox_exec(capabilities, false));
+        checks.push(tmp_space(&std::env::temp_dir()));
+    }
+
+    /// Whether Seatbelt profiles can be applied; with `exercise`, by
+    /// running /usr/bin/true under one
+    pub fn sandbox_exec(capabilities: &HostCapabilities, exercise: bool) -> DoctorCheck {
+        if !capabilities.sandbox_exec {
+            check(
+                "sandbox_exec",
+                CheckStatus::Fail,
+                "/usr/bin/sandbox-exec is missing; runs fail with E2007",
+                Some("Restore it from the macOS installation; it ships with the system"),
+            )
+        } else if capabilities.nested_sandbox {
+            check(
+                "sandbox_exec",
+                CheckStatus::Fail,
+                "capsule-run already runs inside a Seatbelt sandbox, which cannot be nested",
+                Some("Run capsule-run outside the sandboxed app or terminal"),
+            )
+        } else if exercise && !applies_profile() {
+            check(
+                "sandbox_exec",
+                CheckStatus::Fail,
+                "sandbox-exec could not run /usr/bin/true under a profile",
+                Some("Check the system log for sandboxd errors"),
+            )
+        } else {
+            check(
+                "sandbox_exec",
+                CheckStatus::Pass,
+                "Seatbelt profiles can be applied",
+                None,
+            )
+        }
+    }
+
+    fn applies_profile() -> bool {
+        std::process::Command::new("/usr/bin/sandbox-exec")
+            .args(["-p", "(version 1)(allow default)", "/usr/bin/true"])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::linux::*;
+    use super::*;
+
+    #[test]
+    fn test_user_namespaces_remediation() {
+        assert_eq!(user_namespaces(true, |_| None).status, CheckStatus::Pass);
+        let restricted = user_namespaces(false, |name| {
+            (name == "kernel.apparmor_restrict_unprivileged_userns").then(|| "1".to_string())
+        });
+        assert_eq!(restricted.status, CheckStatus::Fail);
+        assert!(restricted.detail.contains("AppArmor"));
+        assert!(restricted
+            .remediation
+            .unwrap()
+            .contains("apparmor_restrict_unprivileged_userns=0"));
+    }
+
+    #[test]
+    fn test_subordinate_ids() {
+        let subuid = "alice:100000:65536\n1001:200000:65536\n";
+        assert_eq!(
+            subordinate_ids(subuid, "alice", 1000).detail,
+            "65536 ids from 100000"
+        );
+        assert_eq!(
+            subordinate_ids(subuid, "bob", 1001).status,
+            CheckStatus::Pass
+        );
+        let missing = subordinate_ids(subuid, "carol", 1002);
+        assert_eq!(missing.status, CheckStatus::Warn);
+        assert!(missing.remediation.is_some());
+    }
+
+    #[test]
+    fn test_config_file() {
+        assert_eq!(config_file(None).status, CheckStatus::Pass);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        crate::config::Config::default()
+            .save_to_file(&path)
+            .unwrap();
+        assert_eq!(config_file(Some(&path)).status, CheckStatus::Pass);
+
+        std::fs::write(&path, "{\"defaults\": 1}").unwrap();
+        let broken = config_file(Some(&path));
+        assert_eq!(broken.status, CheckStatus::Fail);
+        assert!(broken.remediation.unwrap().contains("config validate"));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::error::{CapsuleResult, SandboxError};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use std::fs;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+
+/// A resource limit the kernel enforced against the execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitEvent {
+    OomKill,
+    PidsLimit,
+}
+
+/// Watches memory.events and pids.events with inotify so limit hits are
+/// reported as they happen instead of by polling.
+///
+/// cgroup.events `populated` is not used for exit detection: the supervisor
+/// itself lives in the cgroup, so it never drops to 0 while we wait. Child
+/// exit is tracked with a pidfd instead (see `open_pidfd`).
+pub struct CgroupEventWatcher {
+    inotify: Inotify,
+    memory_events: PathBuf,
+    pids_events: PathBuf,
+    oom_kills: u64,
+    pids_max_hits: u64,
+}
+
+impl CgroupEventWatcher {
+    pub fn new(cgroup_path: &Path) -> CapsuleResult<Self> {
+        let inotify =
+            Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC).map_err(|e| {
+                SandboxError::CgroupSetup(format!("Failed to initialize inotify: {}", e))
+            })?;
+
+        let memory_events = cgroup_path.join("memory.events");
+        let pids_events = cgroup_path.join("pids.events");
+
+        for path in [&memory_events, &pids_events] {
+            // Kernel-generated changes to cgroup event files show up as IN_MODIFY
+            if path.exists() {
+                inotify
+                    .add_watch(path.as_path(), AddWatchFlags::IN_MODIFY)
+                    .map_err(|e| {
+                        SandboxError::CgroupSetup(format!(
+                            "Failed to watch {}: {}",
+                            path.display(),
+                            e
+                        ))
+                    })?;
+            }
+        }
+
+        Ok(Self {
+            oom_kills: read_counter(&memory_events, "oom_kill"),
+            pids_max_hits: read_counter(&pids_events, "max"),
+            inotify,
+            memory_events,
+            pids_events,
+        })
+    }
+
+    /// Consume pending notifications and return the limits hit since the last call.
+    pub fn drain(&mut self) -> Vec<LimitEvent> {
+        // Only the wakeup matters; the counters are re-read below
+        while let Ok(events) = self.inotify.read_events() {
+            if events.is_empty() {
+                break;
+            }
+        }
+
+        let mut events = Vec::new();
+
+        let oom_kills = read_counter(&self.memory_events, "oom_kill");
+        if oom_kills > self.oom_kills {
+            self.oom_kills = oom_kills;
+            events.push(LimitEvent::OomKill);
+        }
+
+        let pids_max_hits = read_counter(&self.pids_events, "max");
+        if pids_max_hits > self.pids_max_hits {
+            self.pids_max_hits = pids_max_hits;
+            events.push(LimitEvent::PidsLimit);
+        }
+
+        events
+    }
+}
+
+impl AsRawFd for CgroupEventWatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inotify.as_fd().as_raw_fd()
+    }
+}
+
+fn read_counter(path: &Path, key: &str) -> u64 {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|content| {
+            content.lines().find_map(|line| {
+                let (name, value) = line.split_once(' ')?;
+                (name == key).then(|| value.trim().parse().ok())?
+            })
+        })
+        .unwrap_or(0)
+}
+
+/// Open a pidfd that becomes readable when the process exits (Linux 5.3+).
+pub fn open_pidfd(pid: u32) -> Option<OwnedFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        return None;
+    }
+    Some(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_counter() {
+        let path = std::env::temp_dir().join(format!("capsule-events-{}", std::process::id()));
+        fs::write(&path, "low 0\nhigh 0\nmax 3\noom 1\noom_kill 2\n").unwrap();
+
+        assert_eq!(read_counter(&path, "oom_kill"), 2);
+        assert_eq!(read_counter(&path, "max"), 3);
+        assert_eq!(read_counter(&path, "missing"), 0);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_pidfd_readable_on_exit() {
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pidfd = open_pidfd(child.id());
+        child.wait().unwrap();
+
+        if let Some(pidfd) = pidfd {
+            let mut fds = [libc::pollfd {
+                fd: pidfd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            }];
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), 1, 1000) };
+            assert_eq!(ready, 1);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Used when neither the request nor capsule-run's environment sets PATH,
+/// as by execvp
+const DEFAULT_PATH: &str = "/bin:/usr/bin";
+
+/// PATH the command is searched on: the request's, else the one it
+/// inherits from capsule-run.
+pub fn search_path(environment: &HashMap<String, String>) -> String {
+    environment
+        .get("PATH")
+        .cloned()
+        .or_else(|| std::env::var("PATH").ok())
+        .unwrap_or_else(|| DEFAULT_PATH.to_string())
+}
+
+/// Where execvp would find `program` in the current root, with `cwd` as the
+/// command's working directory.
+pub fn resolve(program: &str, search_path: &str, cwd: &Path) -> Option<PathBuf> {
+    if program.contains('/') {
+        let path = cwd.join(program);
+        return is_executable(&path).then_some(path);
+    }
+    // An empty entry is the working directory
+    search_path
+        .split(':')
+        .map(|dir| cwd.join(dir).join(program))
+        .find(|path| is_executable(path))
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Hex SHA-256 of the file at `path`, following symlinks as exec does.
+pub fn sha256(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("bin");
+        std::fs::create_dir(&bin).unwrap();
+        std::fs::write(bin.join("tool"), "#!/bin/sh\n").unwrap();
+        std::fs::write(bin.join("data"), "").unwrap();
+        std::fs::set_permissions(bin.join("tool"), std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let search_path = format!("/nonexistent:{}", bin.display());
+        assert_eq!(
+            resolve("tool", &search_path, Path::new("/")),
+            Some(bin.join("tool"))
+        );
+        assert_eq!(resolve("data", &search_path, Path::new("/")), None);
+        assert_eq!(resolve("bin/tool", "", dir.path()), Some(bin.join("tool")));
+        assert_eq!(resolve("tool", "bin", dir.path()), Some(bin.join("tool")));
+    }
+}
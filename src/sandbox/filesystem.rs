@@ -1,44 +1,281 @@
-use crate::api::schema::{BindMount, IsolationConfig};
+use crate::api::schema::{
+    BindMount, IsolationConfig, PlannedMount, SocketMount, DEFAULT_BASE_MOUNTS,
+};
+use crate::api::PathPolicy;
 use crate::error::{CapsuleResult, SandboxError};
+use crate::sandbox::devices::Device;
+use crate::sandbox::dns;
+use crate::sandbox::gc::{self, RootLock};
+use crate::sandbox::quota::ProjectQuota;
+use crate::sandbox::trace;
 use nix::mount::{mount, umount2, MntFlags, MsFlags};
 use nix::sys::stat::mknod;
-use nix::unistd::{chdir, pivot_root};
+use nix::unistd::{chdir, chown, pivot_root, Gid, Uid};
+use nix::NixPath;
+use std::ffi::CString;
 use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use uuid::Uuid;
 
+const DEFAULT_SHM_SIZE: u64 = 67_108_864; // 64 MB
+const DEFAULT_TMP_SIZE: u64 = 67_108_864; // 64 MB
+const DEFAULT_WORKSPACE_SIZE: u64 = 536_870_912; // 512 MB
+/// Directory in a capped /workspace that backs /tmp
+const WORKSPACE_TMP_PREFIX: &str = ".capsule-tmp";
+
 pub struct FilesystemManager {
     root_path: PathBuf,
     old_root_path: PathBuf,
     #[allow(dead_code)] // Used for future tracking and debugging features
     execution_id: Uuid,
+    workspace_accounting: WorkspaceAccounting,
+    /// /tmp is a directory in the capped /workspace
+    tmp_in_workspace: bool,
+    pivoted: bool,
+    /// Every mount made under the root, in order, so cleanup can undo them
+    mounts: Mutex<Vec<PathBuf>>,
+    /// Marks the root as in use for `gc`
+    lock: Option<RootLock>,
+    /// Paths a bind source may resolve to through a symlink
+    path_policy: PathPolicy,
+}
+
+/// How disk usage of /workspace is capped and measured.
+enum WorkspaceAccounting {
+    /// Plain directory on the host filesystem; not measured
+    Unlimited,
+    /// Size-limited tmpfs
+    Tmpfs,
+    /// Project quota on a bind-mounted host directory
+    ProjectQuota(ProjectQuota),
 }
 
 impl FilesystemManager {
     pub fn new(execution_id: Uuid) -> CapsuleResult<Self> {
-        let root_path = PathBuf::from("/tmp").join(format!("capsule-{}", execution_id));
+        let root_path = PathBuf::from(gc::SANDBOX_PARENT).join(format!("capsule-{}", execution_id));
         let old_root_path = root_path.join("old_root");
 
         Ok(Self {
             root_path,
             old_root_path,
             execution_id,
+            workspace_accounting: WorkspaceAccounting::Unlimited,
+            tmp_in_workspace: false,
+            pivoted: false,
+            mounts: Mutex::new(Vec::new()),
+            lock: None,
+            path_policy: PathPolicy::default(),
         })
     }
 
-    pub fn setup_isolation(&self, config: &IsolationConfig) -> CapsuleResult<()> {
+    pub fn set_path_policy(&mut self, paths: PathPolicy) {
+        self.path_policy = paths;
+    }
+
+    /// Cap a writable host /workspace with a project quota. Called before the
+    /// supervisor unshares its user namespace, outside of which quotas
+    /// cannot be set.
+    pub fn apply_disk_quota(
+        &mut self,
+        config: &IsolationConfig,
+        disk_bytes: Option<u64>,
+    ) -> CapsuleResult<()> {
+        let Some(limit) = disk_bytes else {
+            return Ok(());
+        };
+        if !Self::workspace_is_writable_host_dir(config) {
+            return Ok(());
+        }
+        if let Some(source) = self.workspace_source(config, disk_bytes) {
+            let quota = ProjectQuota::apply(&source, limit, self.execution_id)?;
+            self.workspace_accounting = WorkspaceAccounting::ProjectQuota(quota);
+        }
+        Ok(())
+    }
+
+    pub fn setup_isolation(
+        &mut self,
+        config: &IsolationConfig,
+        disk_bytes: Option<u64>,
+    ) -> CapsuleResult<()> {
+        // Taken before the root exists, so gc never sees it unlocked
+        self.lock = Some(RootLock::acquire(&self.root_path)?);
         self.create_root_filesystem()?;
-        self.setup_essential_mounts()?;
+        self.setup_essential_mounts(config, disk_bytes)?;
+        self.setup_shm(config)?;
+        if let Some(dns) = &config.dns {
+            let host_conf = fs::read_to_string("/etc/resolv.conf").unwrap_or_default();
+            self.shadow_etc_file("resolv.conf", &dns::resolv_conf(dns, &host_conf))?;
+        }
+        if !config.extra_hosts.is_empty() {
+            let host_hosts = fs::read_to_string("/etc/hosts").unwrap_or_default();
+            self.shadow_etc_file("hosts", &dns::hosts_file(&config.extra_hosts, &host_hosts))?;
+        }
+        // Mounted before other paths so mounts nested under /workspace stay visible
+        if Self::workspace_is_tmpfs(config, disk_bytes) {
+            self.mount_workspace_tmpfs(disk_bytes.unwrap_or(DEFAULT_WORKSPACE_SIZE))?;
+        }
         self.setup_readonly_paths(&config.readonly_paths)?;
         self.setup_writable_paths(&config.writable_paths)?;
         self.setup_bind_mounts(&config.bind_mounts)?;
+        self.setup_unix_sockets(&config.unix_sockets)?;
+        self.setup_devices(&config.devices)?;
+        if Self::tmp_in_workspace(config, disk_bytes) {
+            self.mount_tmp_in_workspace()?;
+        }
+        // Last, so they cover paths that arrived through any mount above
+        self.setup_tmpfs_paths(&config.tmpfs_paths, disk_bytes.unwrap_or(DEFAULT_TMP_SIZE))?;
+        self.setup_masked_paths(&config.masked_paths)?;
+        self.setup_workspace_permissions(config)?;
         self.perform_pivot_root()?;
+        self.pivoted = true;
         self.setup_working_directory(&config.working_directory)?;
         self.cleanup_old_root()?;
 
         Ok(())
     }
 
+    /// The mounts `setup_isolation` would make, in the same order. Host paths
+    /// that do not exist are left out, as setup skips them; `caches` are the
+    /// cache binds, which are created on setup.
+    pub fn plan_mounts(
+        config: &IsolationConfig,
+        disk_bytes: Option<u64>,
+        caches: &[BindMount],
+    ) -> Vec<PlannedMount> {
+        let bind = |source: &str, destination: &str, readonly: bool| PlannedMount {
+            destination: destination.to_string(),
+            source: source.to_string(),
+            readonly,
+            options: None,
+            hidden_by: None,
+        };
+        let special =
+            |fstype: &str, destination: &str, readonly: bool, options: String| PlannedMount {
+                destination: destination.to_string(),
+                source: fstype.to_string(),
+                readonly,
+                options: Some(options),
+                hidden_by: None,
+            };
+        let tmp_size = disk_bytes.unwrap_or(DEFAULT_TMP_SIZE);
+
+        let base_mounts: Vec<&str> = match &config.base_mounts {
+            Some(paths) => paths.iter().map(String::as_str).collect(),
+            None => DEFAULT_BASE_MOUNTS.to_vec(),
+        };
+        let mut mounts: Vec<PlannedMount> = base_mounts
+            .into_iter()
+            .filter(|source| Path::new(source).exists())
+            .map(|source| bind(source, source, true))
+            .collect();
+        mounts.extend([
+            special("tmpfs", "/dev", false, "size=5M,mode=755".to_string()),
+            special("proc", "/proc", false, "hidepid=2,gid=proc".to_string()),
+            PlannedMount {
+                destination: "/sys".to_string(),
+                source: "sysfs".to_string(),
+                readonly: true,
+                options: None,
+                hidden_by: None,
+            },
+            special("tmpfs", "/var", false, "size=32M,mode=755".to_string()),
+        ]);
+        let tmp_in_workspace = Self::tmp_in_workspace(config, disk_bytes);
+        if !tmp_in_workspace {
+            let tmp = special(
+                "tmpfs",
+                "/tmp",
+                false,
+                format!("size={},mode=1777", tmp_size),
+            );
+            mounts.insert(mounts.len() - 1, tmp);
+        }
+        mounts.push(if config.host_ipc {
+            bind("/dev/shm", "/dev/shm", false)
+        } else {
+            let shm_size = config.shm_size.unwrap_or(DEFAULT_SHM_SIZE);
+            special(
+                "tmpfs",
+                "/dev/shm",
+                false,
+                format!("size={},mode=1777", shm_size),
+            )
+        });
+        // Generated files; written in place when /etc is not a base mount
+        if config.dns.is_some() {
+            mounts.push(bind("generated", "/etc/resolv.conf", true));
+        }
+        if !config.extra_hosts.is_empty() {
+            mounts.push(bind("generated", "/etc/hosts", true));
+        }
+        if Self::workspace_is_tmpfs(config, disk_bytes) {
+            let size = disk_bytes.unwrap_or(DEFAULT_WORKSPACE_SIZE);
+            mounts.push(special(
+                "tmpfs",
+                "/workspace",
+                false,
+                format!("size={},mode=755", size),
+            ));
+        }
+        for (paths, readonly) in [
+            (&config.readonly_paths, true),
+            (&config.writable_paths, false),
+        ] {
+            mounts.extend(
+                paths
+                    .iter()
+                    .filter(|path| Path::new(path).exists())
+                    .map(|path| bind(path, path, readonly)),
+            );
+        }
+        mounts.extend(
+            config
+                .bind_mounts
+                .iter()
+                .filter(|m| Path::new(&m.source).exists())
+                .chain(caches)
+                .map(|m| bind(&m.source, &m.destination, m.readonly)),
+        );
+        mounts.extend(
+            config
+                .unix_sockets
+                .iter()
+                .map(|socket| bind(&socket.source, socket.destination(), true)),
+        );
+        mounts.extend(
+            config
+                .devices
+                .iter()
+                .map(|device| bind(device, device, false)),
+        );
+        if tmp_in_workspace {
+            mounts.push(bind(
+                &format!("/workspace/{}", WORKSPACE_TMP_PREFIX),
+                "/tmp",
+                false,
+            ));
+        }
+        mounts.extend(
+            config
+                .tmpfs_paths
+                .iter()
+                .map(|path| special("tmpfs", path, false, format!("size={},mode=755", tmp_size))),
+        );
+        // A file is masked with /dev/null, a directory with an empty tmpfs
+        mounts.extend(
+            config
+                .masked_paths
+                .iter()
+                .map(|path| bind("masked", path, true)),
+        );
+        mounts
+    }
+
     fn create_root_filesystem(&self) -> CapsuleResult<()> {
         fs::create_dir_all(&self.root_path).map_err(|e| {
             SandboxError::FilesystemSetup(format!(
@@ -56,20 +293,8 @@ impl FilesystemManager {
             ))
         })?;
 
-        let essential_dirs = [
-            "bin",
-            "sbin",
-            "usr",
-            "lib",
-            "lib64",
-            "etc",
-            "dev",
-            "proc",
-            "sys",
-            "tmp",
-            "var",
-            "workspace",
-        ];
+        // Base mounts create their own mount points, so an empty root stays empty
+        let essential_dirs = ["dev", "proc", "sys", "tmp", "var", "workspace"];
 
         for dir in &essential_dirs {
             let dir_path = self.root_path.join(dir);
@@ -85,20 +310,176 @@ impl FilesystemManager {
         Ok(())
     }
 
-    fn setup_essential_mounts(&self) -> CapsuleResult<()> {
-        // Mount essential system directories as read-only
-        let readonly_mounts = [
-            ("/bin", "bin"),
-            ("/sbin", "sbin"),
-            ("/usr", "usr"),
-            ("/lib", "lib"),
-            ("/lib64", "lib64"),
-            ("/etc", "etc"),
-        ];
+    /// Host path whose filesystem backs /workspace, or None for a tmpfs.
+    pub fn workspace_source(
+        &self,
+        config: &IsolationConfig,
+        disk_bytes: Option<u64>,
+    ) -> Option<PathBuf> {
+        if let Some(bind_mount) = config
+            .bind_mounts
+            .iter()
+            .find(|m| m.destination.trim_end_matches('/') == "/workspace")
+        {
+            return Some(PathBuf::from(&bind_mount.source));
+        }
 
-        for (source, target) in &readonly_mounts {
+        if Self::workspace_is_host_dir(config) {
+            return Some(PathBuf::from("/workspace"));
+        }
+
+        if Self::workspace_is_tmpfs(config, disk_bytes) {
+            return None;
+        }
+
+        // The sandbox root is created under /tmp
+        Some(
+            self.root_path
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| PathBuf::from("/tmp")),
+        )
+    }
+
+    /// A disk cap always needs the tmpfs, since a plain directory is unmeasured.
+    fn workspace_is_tmpfs(config: &IsolationConfig, disk_bytes: Option<u64>) -> bool {
+        !Self::workspace_is_host_dir(config) && (config.workspace_tmpfs || disk_bytes.is_some())
+    }
+
+    /// With a disk cap, /tmp lives in a writable /workspace, so that the two
+    /// share the cap rather than each getting all of it
+    fn tmp_in_workspace(config: &IsolationConfig, disk_bytes: Option<u64>) -> bool {
+        disk_bytes.is_some()
+            && (!Self::workspace_is_host_dir(config)
+                || Self::workspace_is_writable_host_dir(config))
+    }
+
+    fn workspace_is_writable_host_dir(config: &IsolationConfig) -> bool {
+        let is_workspace = |path: &str| path.trim_end_matches('/') == "/workspace";
+        match config
+            .bind_mounts
+            .iter()
+            .rfind(|m| is_workspace(&m.destination))
+        {
+            Some(bind_mount) => !bind_mount.readonly,
+            None => config.writable_paths.iter().any(|p| is_workspace(p)),
+        }
+    }
+
+    fn workspace_is_host_dir(config: &IsolationConfig) -> bool {
+        config
+            .bind_mounts
+            .iter()
+            .any(|m| m.destination.trim_end_matches('/') == "/workspace")
+            || config
+                .writable_paths
+                .iter()
+                .chain(&config.readonly_paths)
+                .any(|p| p.trim_end_matches('/') == "/workspace")
+    }
+
+    fn mount_workspace_tmpfs(&mut self, limit_bytes: u64) -> CapsuleResult<()> {
+        let workspace_path = self.root_path.join("workspace");
+        self.tracked_mount(
+            Some("tmpfs"),
+            &workspace_path,
+            Some("tmpfs"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            Some(format!("size={},mode=755", limit_bytes).as_str()),
+        )
+        .map_err(|e| {
+            SandboxError::FilesystemSetup(format!("Failed to mount /workspace tmpfs: {}", e))
+        })?;
+
+        self.workspace_accounting = WorkspaceAccounting::Tmpfs;
+        Ok(())
+    }
+
+    /// Back /tmp with a directory in /workspace, hidden there under an empty
+    /// read-only tmpfs. It is named after the execution, as concurrent runs
+    /// may share a host workspace.
+    fn mount_tmp_in_workspace(&mut self) -> CapsuleResult<()> {
+        let backing = self
+            .root_path
+            .join("workspace")
+            .join(self.workspace_tmp_name());
+        let failed = |e: &dyn std::fmt::Display| {
+            SandboxError::FilesystemSetup(format!("Failed to put /tmp in /workspace: {}", e))
+        };
+        fs::create_dir(&backing)
+            .and_then(|()| fs::set_permissions(&backing, fs::Permissions::from_mode(0o1777)))
+            .map_err(|e| failed(&e))?;
+        self.tmp_in_workspace = true;
+
+        self.tracked_mount(
+            Some(&backing),
+            &self.root_path.join("tmp"),
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )
+        .map_err(|e| failed(&e))?;
+        self.tracked_mount(
+            Some("tmpfs"),
+            &backing,
+            Some("tmpfs"),
+            MsFlags::MS_RDONLY | MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+            Some("size=4k,mode=555"),
+        )
+        .map_err(|e| failed(&e))?;
+        Ok(())
+    }
+
+    fn workspace_tmp_name(&self) -> String {
+        format!("{}-{}", WORKSPACE_TMP_PREFIX, self.execution_id.simple())
+    }
+
+    /// Bytes currently used in /tmp plus /workspace when the workspace is capped.
+    pub fn disk_usage(&self) -> u64 {
+        // Counted with the workspace when /tmp is in it
+        let tmp_usage = if self.tmp_in_workspace {
+            0
+        } else {
+            Self::statvfs_used(&self.sandbox_path("tmp"))
+        };
+        let workspace_usage = match &self.workspace_accounting {
+            WorkspaceAccounting::Unlimited => 0,
+            WorkspaceAccounting::Tmpfs => Self::statvfs_used(&self.sandbox_path("workspace")),
+            WorkspaceAccounting::ProjectQuota(quota) => quota.usage().unwrap_or(0),
+        };
+        tmp_usage + workspace_usage
+    }
+
+    fn sandbox_path(&self, dir: &str) -> PathBuf {
+        if self.pivoted {
+            Path::new("/").join(dir)
+        } else {
+            self.root_path.join(dir)
+        }
+    }
+
+    fn statvfs_used(path: &Path) -> u64 {
+        match nix::sys::statvfs::statvfs(path) {
+            Ok(stats) => (stats.blocks() - stats.blocks_free()) * stats.fragment_size(),
+            Err(_) => 0,
+        }
+    }
+
+    fn setup_essential_mounts(
+        &self,
+        config: &IsolationConfig,
+        disk_bytes: Option<u64>,
+    ) -> CapsuleResult<()> {
+        // Mount the base system directories as read-only
+        let base_mounts: Vec<&str> = match &config.base_mounts {
+            Some(paths) => paths.iter().map(String::as_str).collect(),
+            None => DEFAULT_BASE_MOUNTS.to_vec(),
+        };
+        for source in base_mounts {
             if Path::new(source).exists() {
-                let target_path = self.root_path.join(target);
+                let target_path = self
+                    .root_path
+                    .join(source.strip_prefix('/').unwrap_or(source));
                 self.bind_mount_readonly(Path::new(source), &target_path)?;
             }
         }
@@ -108,7 +489,7 @@ impl FilesystemManager {
 
         // Mount /proc with restricted access
         let proc_path = self.root_path.join("proc");
-        mount(
+        self.tracked_mount(
             Some("proc"),
             &proc_path,
             Some("proc"),
@@ -119,7 +500,7 @@ impl FilesystemManager {
 
         // Mount /sys as read-only
         let sys_path = self.root_path.join("sys");
-        mount(
+        self.tracked_mount(
             Some("sysfs"),
             &sys_path,
             Some("sysfs"),
@@ -128,20 +509,22 @@ impl FilesystemManager {
         )
         .map_err(|e| SandboxError::FilesystemSetup(format!("Failed to mount /sys: {}", e)))?;
 
-        // Mount /tmp as tmpfs
-        let tmp_path = self.root_path.join("tmp");
-        mount(
-            Some("tmpfs"),
-            &tmp_path,
-            Some("tmpfs"),
-            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
-            Some("size=64M,mode=1777"),
-        )
-        .map_err(|e| SandboxError::FilesystemSetup(format!("Failed to mount /tmp: {}", e)))?;
+        // Mount /tmp as tmpfs, unless it is to share the workspace's cap
+        if !Self::tmp_in_workspace(config, disk_bytes) {
+            let tmp_path = self.root_path.join("tmp");
+            self.tracked_mount(
+                Some("tmpfs"),
+                &tmp_path,
+                Some("tmpfs"),
+                MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                Some(format!("size={},mode=1777", disk_bytes.unwrap_or(DEFAULT_TMP_SIZE)).as_str()),
+            )
+            .map_err(|e| SandboxError::FilesystemSetup(format!("Failed to mount /tmp: {}", e)))?;
+        }
 
         // Mount /var as tmpfs
         let var_path = self.root_path.join("var");
-        mount(
+        self.tracked_mount(
             Some("tmpfs"),
             &var_path,
             Some("tmpfs"),
@@ -157,7 +540,7 @@ impl FilesystemManager {
         let dev_path = self.root_path.join("dev");
 
         // Mount /dev as tmpfs
-        mount(
+        self.tracked_mount(
             Some("tmpfs"),
             &dev_path,
             Some("tmpfs"),
@@ -211,6 +594,90 @@ impl FilesystemManager {
         Ok(())
     }
 
+    fn setup_shm(&self, config: &IsolationConfig) -> CapsuleResult<()> {
+        let shm_path = self.root_path.join("dev").join("shm");
+        fs::create_dir_all(&shm_path).map_err(|e| {
+            SandboxError::FilesystemSetup(format!(
+                "Failed to create directory {}: {}",
+                shm_path.display(),
+                e
+            ))
+        })?;
+
+        // Sharing host IPC only makes sense together with the host's POSIX
+        // shared memory segments
+        if config.host_ipc {
+            return self.bind_mount_writable(Path::new("/dev/shm"), &shm_path);
+        }
+
+        let shm_size = config.shm_size.unwrap_or(DEFAULT_SHM_SIZE);
+        self.tracked_mount(
+            Some("tmpfs"),
+            &shm_path,
+            Some("tmpfs"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+            Some(format!("size={},mode=1777", shm_size).as_str()),
+        )
+        .map_err(|e| SandboxError::FilesystemSetup(format!("Failed to mount /dev/shm: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// Shadow the host's /etc/`name`, which the read-only /etc exposes,
+    /// with `contents`.
+    fn shadow_etc_file(&self, name: &str, contents: &str) -> CapsuleResult<()> {
+        let host_file = Path::new("/etc").join(name);
+
+        // A symlink, as resolv.conf is under systemd-resolved, is followed
+        // inside the sandbox; a target outside the bound directories is
+        // simply created
+        let target = match fs::read_link(&host_file) {
+            Ok(link) => lexical_normalize(&Path::new("/etc").join(link)),
+            Err(_) => host_file,
+        };
+        let target = self
+            .root_path
+            .join(target.strip_prefix("/").unwrap_or(&target));
+        if target.exists() {
+            let generated = self.root_path.join("run").join(name);
+            write_file(&generated, contents)?;
+            return self.bind_mount_readonly(&generated, &target);
+        }
+        write_file(&target, contents)
+    }
+
+    fn setup_workspace_permissions(&self, config: &IsolationConfig) -> CapsuleResult<()> {
+        let workspace_path = self.root_path.join("workspace");
+
+        // Ownership first: chown clears setuid/setgid bits the mode may set
+        if config.workspace_uid.is_some() || config.workspace_gid.is_some() {
+            chown(
+                &workspace_path,
+                config.workspace_uid.map(Uid::from_raw),
+                config.workspace_gid.map(Gid::from_raw),
+            )
+            .map_err(|e| {
+                SandboxError::FilesystemSetup(format!(
+                    "Failed to change ownership of /workspace: {}",
+                    e
+                ))
+            })?;
+        }
+
+        if let Some(mode) = config.workspace_mode {
+            fs::set_permissions(&workspace_path, fs::Permissions::from_mode(mode)).map_err(
+                |e| {
+                    SandboxError::FilesystemSetup(format!(
+                        "Failed to set /workspace mode to {:o}: {}",
+                        mode, e
+                    ))
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+
     fn setup_readonly_paths(&self, readonly_paths: &[String]) -> CapsuleResult<()> {
         for path in readonly_paths {
             let source = Path::new(path);
@@ -282,9 +749,128 @@ impl FilesystemManager {
         Ok(())
     }
 
+    fn setup_unix_sockets(&self, sockets: &[SocketMount]) -> CapsuleResult<()> {
+        use std::os::unix::fs::FileTypeExt;
+
+        for socket in sockets {
+            // Not followed, so a symlink cannot redirect the mount elsewhere
+            let source = Path::new(&socket.source);
+            let is_socket = fs::symlink_metadata(source)
+                .map(|metadata| metadata.file_type().is_socket())
+                .unwrap_or(false);
+            if !is_socket {
+                return Err(SandboxError::FilesystemSetup(format!(
+                    "{} is not a Unix socket",
+                    source.display()
+                ))
+                .into());
+            }
+
+            let destination = socket.destination();
+            let target = self
+                .root_path
+                .join(destination.strip_prefix('/').unwrap_or(destination));
+            // Connecting needs no write access to the mount
+            self.bind_mount_readonly(source, &target)?;
+        }
+        Ok(())
+    }
+
+    /// Bind the host's nodes in; mknod is refused in a user namespace.
+    /// Which devices may be opened is enforced by the cgroup, not here.
+    fn setup_devices(&self, devices: &[String]) -> CapsuleResult<()> {
+        for device in devices {
+            Device::lookup(device)?;
+            let target = self
+                .root_path
+                .join(device.strip_prefix('/').unwrap_or(device));
+            self.bind_mount_writable(Path::new(device), &target)?;
+        }
+        Ok(())
+    }
+
+    fn setup_tmpfs_paths(&self, paths: &[String], size_bytes: u64) -> CapsuleResult<()> {
+        for path in paths {
+            let target = self.root_path.join(path.strip_prefix('/').unwrap_or(path));
+            fs::create_dir_all(&target).map_err(|e| {
+                SandboxError::FilesystemSetup(format!(
+                    "Failed to create tmpfs mount point {}: {}",
+                    path, e
+                ))
+            })?;
+            let target = self.contained(path, &target)?;
+            self.tracked_mount(
+                Some("tmpfs"),
+                &target,
+                Some("tmpfs"),
+                MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                Some(format!("size={},mode=755", size_bytes).as_str()),
+            )
+            .map_err(|e| {
+                SandboxError::FilesystemSetup(format!("Failed to mount tmpfs at {}: {}", path, e))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Hide each path the way OCI runtimes do: /dev/null over a file, an
+    /// empty read-only tmpfs over a directory. Missing paths need no hiding.
+    fn setup_masked_paths(&self, paths: &[String]) -> CapsuleResult<()> {
+        for path in paths {
+            let target = self.root_path.join(path.strip_prefix('/').unwrap_or(path));
+            if fs::symlink_metadata(&target).is_err() {
+                continue;
+            }
+            let target = self.contained(path, &target)?;
+
+            let masked = if target.is_dir() {
+                self.tracked_mount(
+                    Some("tmpfs"),
+                    &target,
+                    Some("tmpfs"),
+                    MsFlags::MS_RDONLY
+                        | MsFlags::MS_NOSUID
+                        | MsFlags::MS_NODEV
+                        | MsFlags::MS_NOEXEC,
+                    Some("size=4k,mode=755"),
+                )
+            } else {
+                self.tracked_mount(
+                    Some(&self.root_path.join("dev/null")),
+                    &target,
+                    None::<&str>,
+                    MsFlags::MS_BIND,
+                    None::<&str>,
+                )
+            };
+            masked.map_err(|e| {
+                SandboxError::FilesystemSetup(format!("Failed to mask {}: {}", path, e))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// `target` with symlinks resolved, refused if that leaves the sandbox
+    /// root: an absolute link in a bound /etc points at the host's files.
+    fn contained(&self, path: &str, target: &Path) -> CapsuleResult<PathBuf> {
+        let resolved = fs::canonicalize(target).map_err(|e| {
+            SandboxError::FilesystemSetup(format!("Failed to resolve {}: {}", path, e))
+        })?;
+        if !resolved.starts_with(&self.root_path) {
+            return Err(SandboxError::FilesystemSetup(format!(
+                "{} resolves outside the sandbox root",
+                path
+            ))
+            .into());
+        }
+        Ok(resolved)
+    }
+
     fn bind_mount_readonly(&self, source: &Path, target: &Path) -> CapsuleResult<()> {
+        let opened = BindSource::open(source, &self.path_policy)?;
+
         // Create target if it doesn't exist
-        if source.is_dir() {
+        if opened.is_dir {
             fs::create_dir_all(target).map_err(|e| {
                 SandboxError::FilesystemSetup(format!(
                     "Failed to create target directory {}: {}",
@@ -312,8 +898,8 @@ impl FilesystemManager {
         }
 
         // Bind mount
-        mount(
-            Some(source),
+        self.tracked_mount(
+            Some(&opened.proc_path()),
             target,
             None::<&str>,
             MsFlags::MS_BIND,
@@ -329,7 +915,7 @@ impl FilesystemManager {
         })?;
 
         // Remount as readonly
-        mount(
+        self.tracked_mount(
             None::<&str>,
             target,
             None::<&str>,
@@ -348,8 +934,10 @@ impl FilesystemManager {
     }
 
     fn bind_mount_writable(&self, source: &Path, target: &Path) -> CapsuleResult<()> {
+        let opened = BindSource::open(source, &self.path_policy)?;
+
         // Create target if it doesn't exist
-        if source.is_dir() {
+        if opened.is_dir {
             fs::create_dir_all(target).map_err(|e| {
                 SandboxError::FilesystemSetup(format!(
                     "Failed to create target directory {}: {}",
@@ -377,8 +965,8 @@ impl FilesystemManager {
         }
 
         // Bind mount as writable
-        mount(
-            Some(source),
+        self.tracked_mount(
+            Some(&opened.proc_path()),
             target,
             None::<&str>,
             MsFlags::MS_BIND,
@@ -396,6 +984,45 @@ impl FilesystemManager {
         Ok(())
     }
 
+    /// `mount`, remembering the target unless it is a remount
+    fn tracked_mount<P1, P3, P4>(
+        &self,
+        source: Option<&P1>,
+        target: &Path,
+        fstype: Option<&P3>,
+        flags: MsFlags,
+        data: Option<&P4>,
+    ) -> nix::Result<()>
+    where
+        P1: ?Sized + NixPath,
+        P3: ?Sized + NixPath,
+        P4: ?Sized + NixPath,
+    {
+        let detail = || {
+            let mut detail = format!(
+                "target={} source={} fstype={} flags={}",
+                trace::quote(target.display()),
+                trace::quote(nix_path_string(source)),
+                trace::quote(nix_path_string(fstype)),
+                trace::quote(format!("{:?}", flags))
+            );
+            let options = nix_path_string(data);
+            if !options.is_empty() {
+                detail += &format!(" options={}", trace::quote(options));
+            }
+            detail
+        };
+        trace::step("mount", detail, || {
+            mount(source, target, fstype, flags, data)
+        })?;
+        if !flags.contains(MsFlags::MS_REMOUNT) {
+            if let Ok(mut mounts) = self.mounts.lock() {
+                mounts.push(target.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
     fn perform_pivot_root(&self) -> CapsuleResult<()> {
         pivot_root(&self.root_path, &self.old_root_path)
             .map_err(|e| SandboxError::FilesystemSetup(format!("Failed to pivot root: {}", e)))?;
@@ -447,6 +1074,44 @@ impl FilesystemManager {
     }
 
     pub fn cleanup(&self) -> CapsuleResult<()> {
+        // /tmp's directory in a host workspace is removed through the
+        // workspace bind, while it is still mounted
+        if self.tmp_in_workspace && !matches!(self.workspace_accounting, WorkspaceAccounting::Tmpfs)
+        {
+            let backing = self
+                .sandbox_path("workspace")
+                .join(self.workspace_tmp_name());
+            let _ = umount2(&backing, MntFlags::MNT_DETACH);
+            let _ = umount2(&self.sandbox_path("tmp"), MntFlags::MNT_DETACH);
+            fs::remove_dir_all(&backing).map_err(|e| {
+                SandboxError::FilesystemSetup(format!(
+                    "Failed to remove /tmp from the workspace: {}",
+                    e
+                ))
+            })?;
+        }
+        // Detach the tmpfs so its pages are freed as soon as nothing holds it
+        if matches!(self.workspace_accounting, WorkspaceAccounting::Tmpfs) {
+            let _ = umount2(&self.sandbox_path("workspace"), MntFlags::MNT_DETACH);
+        }
+        // Setup failed before the pivot: the mounts are still visible here
+        // under the root, and removing it through a writable bind would
+        // delete host files
+        if !self.pivoted {
+            if let Ok(mut mounts) = self.mounts.lock() {
+                gc::detach_all(&mounts);
+                mounts.clear();
+            }
+            let leaked = gc::mounts_under(&self.root_path);
+            if !leaked.is_empty() {
+                return Err(SandboxError::FilesystemSetup(format!(
+                    "{} mounts remain under {}; leaving it for `capsule-run gc`",
+                    leaked.len(),
+                    self.root_path.display()
+                ))
+                .into());
+            }
+        }
         if self.root_path.exists() {
             fs::remove_dir_all(&self.root_path).map_err(|e| {
                 SandboxError::FilesystemSetup(format!(
@@ -455,11 +1120,145 @@ impl FilesystemManager {
                     e
                 ))
             })?;
+            let _ = fs::remove_file(gc::lock_path(&self.root_path));
         }
         Ok(())
     }
 }
 
+fn nix_path_string<P: ?Sized + NixPath>(path: Option<&P>) -> String {
+    path.and_then(|path| {
+        path.with_nix_path(|path| path.to_string_lossy().into_owned())
+            .ok()
+    })
+    .unwrap_or_default()
+}
+
+fn write_file(path: &Path, contents: &str) -> CapsuleResult<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| {
+            SandboxError::FilesystemSetup(format!(
+                "Failed to create parent directory for {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+    }
+    fs::write(path, contents).map_err(|e| {
+        SandboxError::FilesystemSetup(format!("Failed to write {}: {}", path.display(), e))
+    })?;
+    Ok(())
+}
+
+/// A bind mount source, opened so that what gets mounted is what was checked.
+///
+/// Sources are validated as strings, so a symlink inside an allowed
+/// directory could otherwise point a mount at /etc/shadow. The source is
+/// resolved once; a path that went through a symlink must still be a safe
+/// path once resolved, which keeps links like /lib -> usr/lib working. The
+/// resolved path is then opened one component at a time without following
+/// symlinks, and the mount is made from the open descriptor, so swapping a
+/// link in after the check fails instead of redirecting the mount.
+struct BindSource {
+    fd: OwnedFd,
+    is_dir: bool,
+}
+
+impl BindSource {
+    fn open(source: &Path, paths: &PathPolicy) -> CapsuleResult<Self> {
+        let failed = |e: std::io::Error| {
+            SandboxError::FilesystemSetup(format!(
+                "Failed to open bind mount source {}: {}",
+                source.display(),
+                e
+            ))
+        };
+        let resolved = fs::canonicalize(source).map_err(failed)?;
+        if resolved != lexical_normalize(source) && !paths.allows(&resolved.to_string_lossy()) {
+            return Err(SandboxError::FilesystemSetup(format!(
+                "Bind mount source {} resolves through a symlink to {}, which is not allowed",
+                source.display(),
+                resolved.display()
+            ))
+            .into());
+        }
+
+        let fd = open_nofollow(&resolved).map_err(failed)?;
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } != 0 {
+            return Err(failed(std::io::Error::last_os_error()).into());
+        }
+        Ok(Self {
+            fd,
+            is_dir: stat.st_mode & libc::S_IFMT == libc::S_IFDIR,
+        })
+    }
+
+    /// Mounting this binds the opened file, whatever its path now names
+    fn proc_path(&self) -> PathBuf {
+        PathBuf::from(format!("/proc/self/fd/{}", self.fd.as_raw_fd()))
+    }
+}
+
+/// Open `path`, which must be absolute, as an O_PATH descriptor, failing
+/// with ELOOP if any component is a symlink.
+fn open_nofollow(path: &Path) -> std::io::Result<OwnedFd> {
+    let root = unsafe {
+        libc::open(
+            c"/".as_ptr(),
+            libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC,
+        )
+    };
+    if root < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut fd = unsafe { OwnedFd::from_raw_fd(root) };
+
+    for component in path.components() {
+        let std::path::Component::Normal(name) = component else {
+            continue;
+        };
+        let name = CString::new(name.as_bytes())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let next = unsafe {
+            libc::openat(
+                fd.as_raw_fd(),
+                name.as_ptr(),
+                libc::O_PATH | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            )
+        };
+        if next < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        fd = unsafe { OwnedFd::from_raw_fd(next) };
+
+        // O_PATH | O_NOFOLLOW opens a symlink itself rather than failing
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if stat.st_mode & libc::S_IFMT == libc::S_IFLNK {
+            return Err(std::io::Error::from_raw_os_error(libc::ELOOP));
+        }
+    }
+    Ok(fd)
+}
+
+/// Resolve `.` and `..` without touching the filesystem.
+pub(crate) fn lexical_normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            std::path::Component::CurDir => {}
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
 impl Drop for FilesystemManager {
     fn drop(&mut self) {
         let _ = self.cleanup();
@@ -483,6 +1282,106 @@ mod tests {
             .contains(&execution_id.to_string()));
     }
 
+    #[test]
+    fn test_bind_source_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir_all(&data).unwrap();
+        std::os::unix::fs::symlink(&data, dir.path().join("link")).unwrap();
+        std::os::unix::fs::symlink("/etc/passwd", data.join("passwd")).unwrap();
+
+        let paths = PathPolicy::default();
+        assert!(BindSource::open(&data, &paths).unwrap().is_dir);
+        // Links to safe paths are followed once, at resolution
+        assert!(BindSource::open(&dir.path().join("link"), &paths).is_ok());
+        assert!(BindSource::open(&data.join("passwd"), &paths).is_err());
+
+        let resolved = fs::canonicalize(&data).unwrap();
+        assert!(open_nofollow(&resolved).is_ok());
+        let through_link = fs::canonicalize(dir.path()).unwrap().join("link");
+        assert_eq!(
+            open_nofollow(&through_link).unwrap_err().raw_os_error(),
+            Some(libc::ELOOP)
+        );
+    }
+
+    #[test]
+    fn test_lexical_normalize() {
+        assert_eq!(
+            lexical_normalize(Path::new("/etc/../run/systemd/resolve/stub-resolv.conf")),
+            PathBuf::from("/run/systemd/resolve/stub-resolv.conf")
+        );
+        assert_eq!(
+            lexical_normalize(Path::new("/etc/./resolv.conf")),
+            PathBuf::from("/etc/resolv.conf")
+        );
+    }
+
+    #[test]
+    fn test_workspace_source() {
+        let manager = FilesystemManager::new(Uuid::new_v4()).unwrap();
+        let mut isolation = IsolationConfig::default();
+        assert_eq!(manager.workspace_source(&isolation, None), None);
+
+        isolation.workspace_tmpfs = false;
+        assert_eq!(
+            manager.workspace_source(&isolation, None),
+            Some(PathBuf::from("/tmp"))
+        );
+        assert_eq!(manager.workspace_source(&isolation, Some(1 << 30)), None);
+
+        isolation.bind_mounts.push(BindMount {
+            source: "/srv/project".to_string(),
+            destination: "/workspace".to_string(),
+            readonly: false,
+        });
+        assert_eq!(
+            manager.workspace_source(&isolation, Some(1 << 30)),
+            Some(PathBuf::from("/srv/project"))
+        );
+    }
+
+    #[test]
+    fn test_tmp_shares_workspace_cap() {
+        let mut isolation = IsolationConfig::default();
+        let tmp = |isolation: &IsolationConfig, disk_bytes| {
+            FilesystemManager::plan_mounts(isolation, disk_bytes, &[])
+                .into_iter()
+                .filter(|m| m.destination == "/tmp")
+                .map(|m| m.source)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(tmp(&isolation, None), vec!["tmpfs"]);
+        assert!(FilesystemManager::tmp_in_workspace(
+            &isolation,
+            Some(1 << 30)
+        ));
+        assert_eq!(
+            tmp(&isolation, Some(1 << 30)),
+            vec!["/workspace/.capsule-tmp"]
+        );
+
+        isolation.bind_mounts.push(BindMount {
+            source: "/srv/project".to_string(),
+            destination: "/workspace".to_string(),
+            readonly: false,
+        });
+        assert!(FilesystemManager::tmp_in_workspace(
+            &isolation,
+            Some(1 << 30)
+        ));
+
+        // A read-only workspace cannot hold /tmp, which keeps its own tmpfs
+        isolation.bind_mounts[0].readonly = true;
+        assert!(!FilesystemManager::tmp_in_workspace(
+            &isolation,
+            Some(1 << 30)
+        ));
+        assert!(!FilesystemManager::workspace_is_writable_host_dir(
+            &isolation
+        ));
+    }
+
     #[test]
     fn test_essential_directories() {
         let execution_id = Uuid::new_v4();
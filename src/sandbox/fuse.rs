@@ -0,0 +1,171 @@
+use crate::api::schema::FuseMount;
+use crate::error::{CapsuleResult, SandboxError};
+use nix::mount::{mount, MsFlags};
+use std::fs::{self, OpenOptions};
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+
+/// Argument replaced by the daemon's `/dev/fd/N` mountpoint
+const MOUNTPOINT_PLACEHOLDER: &str = "{mountpoint}";
+
+/// One `isolation.fuse_mounts` entry, from the open /dev/fuse connection to
+/// the running daemon.
+///
+/// The connection is opened before the cgroup and seccomp are set up and
+/// mounted while capsule-run still holds CAP_SYS_ADMIN in the user namespace.
+/// Once the daemon has the connection the supervisor closes its copy, so the
+/// kernel aborts the mount with ENOTCONN if the daemon dies instead of
+/// leaving accesses hanging.
+pub struct FuseSession {
+    mount: FuseMount,
+    device: Option<OwnedFd>,
+    daemon: Option<Child>,
+}
+
+impl FuseSession {
+    pub fn open(mount: &FuseMount) -> CapsuleResult<Self> {
+        // std opens with O_CLOEXEC; the daemon's copy is made inheritable in
+        // its own pre_exec
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/fuse")
+            .map_err(|e| {
+                SandboxError::FilesystemSetup(format!(
+                    "Failed to open /dev/fuse for {}: {}",
+                    mount.path, e
+                ))
+            })?;
+        Ok(Self {
+            mount: mount.clone(),
+            device: Some(device.into()),
+            daemon: None,
+        })
+    }
+
+    /// Mount the connection at its path, as seen by the current process.
+    pub fn mount(&self) -> CapsuleResult<()> {
+        let failed = |e: String| {
+            SandboxError::FilesystemSetup(format!(
+                "Failed to mount FUSE filesystem at {}: {}",
+                self.mount.path, e
+            ))
+        };
+        let device = self
+            .device
+            .as_ref()
+            .ok_or_else(|| failed("already started".to_string()))?;
+        fs::create_dir_all(&self.mount.path).map_err(|e| failed(e.to_string()))?;
+
+        let mut flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV;
+        if self.mount.readonly {
+            flags |= MsFlags::MS_RDONLY;
+        }
+        // allow_other so a command running as another uid still reaches it;
+        // default_permissions has the kernel check modes for the daemon
+        let options = format!(
+            "fd={},rootmode=40000,user_id={},group_id={},allow_other,default_permissions",
+            device.as_raw_fd(),
+            nix::unistd::getuid(),
+            nix::unistd::getgid()
+        );
+        mount(
+            Some(daemon_name(&self.mount)),
+            Path::new(&self.mount.path),
+            Some("fuse"),
+            flags,
+            Some(options.as_str()),
+        )
+        .map_err(|e| failed(e.to_string()))?;
+        Ok(())
+    }
+
+    /// The daemon's command, to be finished with the sandbox's per-process
+    /// setup and passed to `start`.
+    pub fn command(&self) -> CapsuleResult<Command> {
+        let device = self.device.as_ref().ok_or_else(|| {
+            SandboxError::FilesystemSetup(format!(
+                "FUSE daemon for {} already started",
+                self.mount.path
+            ))
+        })?;
+        let fd = device.as_raw_fd();
+        let mut argv = daemon_argv(&self.mount.command, fd);
+        let mut cmd = Command::new(argv.remove(0));
+        cmd.args(argv)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::fcntl(fd, libc::F_SETFD, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(cmd)
+    }
+
+    pub fn start(&mut self, mut cmd: Command) -> CapsuleResult<()> {
+        let daemon = cmd.spawn().map_err(|e| {
+            SandboxError::FilesystemSetup(format!(
+                "Failed to start FUSE daemon for {}: {}",
+                self.mount.path, e
+            ))
+        })?;
+        self.daemon = Some(daemon);
+        self.device = None;
+        Ok(())
+    }
+}
+
+impl Drop for FuseSession {
+    fn drop(&mut self) {
+        if let Some(mut daemon) = self.daemon.take() {
+            let _ = daemon.kill();
+            let _ = daemon.wait();
+        }
+    }
+}
+
+fn daemon_argv(command: &[String], fd: i32) -> Vec<String> {
+    let mountpoint = format!("/dev/fd/{}", fd);
+    let mut argv: Vec<String> = command
+        .iter()
+        .map(|arg| match arg.as_str() {
+            MOUNTPOINT_PLACEHOLDER => mountpoint.clone(),
+            _ => arg.clone(),
+        })
+        .collect();
+    if !command.iter().any(|arg| arg == MOUNTPOINT_PLACEHOLDER) {
+        argv.push(mountpoint);
+    }
+    argv
+}
+
+/// Shown as the mount's source in /proc/mounts
+fn daemon_name(mount: &FuseMount) -> &str {
+    let program = mount.command.first().map(String::as_str).unwrap_or("fuse");
+    program.rsplit('/').next().unwrap_or(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daemon_argv() {
+        let command = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            daemon_argv(&command(&["s3fs", "bucket", "{mountpoint}", "-o", "ro"]), 7),
+            command(&["s3fs", "bucket", "/dev/fd/7", "-o", "ro"])
+        );
+        assert_eq!(
+            daemon_argv(&command(&["/usr/bin/sshfs", "host:/data"]), 9),
+            command(&["/usr/bin/sshfs", "host:/data", "/dev/fd/9"])
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use crate::error::{CapsuleResult, SandboxError};
+use crate::sandbox::cgroups::CgroupManager;
+use nix::mount::{umount2, MntFlags};
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Sandbox roots are created here as `capsule-<execution id>`
+pub const SANDBOX_PARENT: &str = "/tmp";
+
+/// How old residue must be for the collection every run starts with
+pub const STARTUP_MIN_AGE: Duration = Duration::from_secs(3600);
+
+/// An execution creates its cgroup before it locks its root, so an empty,
+/// unlocked cgroup younger than this may still be starting up
+const CGROUP_MIN_AGE: Duration = Duration::from_secs(60);
+
+/// What `collect` found and cleaned up.
+#[derive(Debug, Default, Serialize)]
+pub struct GcReport {
+    /// Stale sandbox roots removed
+    pub removed: Vec<PathBuf>,
+    /// Empty cgroups removed from under `capsule-run`
+    pub cgroups_removed: Vec<PathBuf>,
+    /// Mounts detached from stale roots
+    pub unmounted: usize,
+    /// Roots and cgroups left alone because their execution is still running
+    pub live: usize,
+    /// Stale roots and cgroups left alone because they are newer than the
+    /// minimum age
+    pub recent: usize,
+    /// Stale roots that could not be cleaned, with the reason
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Lock held by a running execution for as long as its root exists.
+///
+/// A crashed capsule-run cannot clean up after itself, and once pivoted it
+/// cannot even see its root on the host. The lock is an flock, so the kernel
+/// drops it when the process dies, which is what marks the root as stale.
+pub struct RootLock {
+    _file: File,
+}
+
+impl RootLock {
+    pub fn acquire(root: &Path) -> CapsuleResult<Self> {
+        let failed = |e: std::io::Error| {
+            SandboxError::FilesystemSetup(format!(
+                "Failed to lock sandbox root {}: {}",
+                root.display(),
+                e
+            ))
+        };
+        let file = open_lock(root).map_err(failed)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            return Err(failed(std::io::Error::last_os_error()).into());
+        }
+        Ok(Self { _file: file })
+    }
+}
+
+pub fn lock_path(root: &Path) -> PathBuf {
+    root.with_extension("lock")
+}
+
+fn open_lock(root: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path(root))
+}
+
+/// Mount points at or below `root` in this mount namespace, innermost last.
+pub fn mounts_under(root: &Path) -> Vec<PathBuf> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(|point| PathBuf::from(unescape_mount_point(point)))
+        .filter(|point| point.starts_with(root))
+        .collect()
+}
+
+/// Detach `mounts` innermost first, returning how many were detached.
+pub fn detach_all(mounts: &[PathBuf]) -> usize {
+    mounts
+        .iter()
+        .rev()
+        .filter(|point| umount2(point.as_path(), MntFlags::MNT_DETACH).is_ok())
+        .count()
+}
+
+/// Remove what crashed executions left behind and was last changed at
+/// least `min_age` ago: sandbox roots under `SANDBOX_PARENT` and cgroups
+/// under the cgroup v2 hierarchy's `capsule-run`.
+pub fn collect_all(min_age: Duration) -> CapsuleResult<GcReport> {
+    let mut report = collect(Path::new(SANDBOX_PARENT), min_age)?;
+    if let Ok(mount) = CgroupManager::find_cgroup_mount() {
+        collect_cgroups(&mount.join("capsule-run"), min_age, &mut report);
+    }
+    Ok(report)
+}
+
+/// Remove sandbox roots under `parent` whose execution is gone, detaching
+/// anything still mounted in them first. A root that keeps a mount is never
+/// removed: deleting through a writable bind would delete host files.
+pub fn collect(parent: &Path, min_age: Duration) -> CapsuleResult<GcReport> {
+    let mut report = GcReport::default();
+    let entries = fs::read_dir(parent).map_err(|e| {
+        SandboxError::FilesystemSetup(format!("Failed to read {}: {}", parent.display(), e))
+    })?;
+
+    for entry in entries.flatten() {
+        let root = entry.path();
+        let is_root = entry.file_name().to_string_lossy().starts_with("capsule-")
+            && root.extension().is_none()
+            && entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+        if !is_root {
+            continue;
+        }
+
+        // Held until the root is gone, so a new execution cannot reuse it
+        let lock = match open_lock(&root) {
+            Ok(lock) => lock,
+            Err(e) => {
+                report.failed.push((root, e.to_string()));
+                continue;
+            }
+        };
+        if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            report.live += 1;
+            continue;
+        }
+        if younger_than(&root, min_age) {
+            report.recent += 1;
+            continue;
+        }
+
+        report.unmounted += detach_all(&mounts_under(&root));
+        let remaining = mounts_under(&root);
+        if !remaining.is_empty() {
+            report.failed.push((
+                root,
+                format!("{} mounts could not be detached", remaining.len()),
+            ));
+            continue;
+        }
+        match fs::remove_dir_all(&root) {
+            Ok(()) => {
+                let _ = fs::remove_file(lock_path(&root));
+                report.removed.push(root);
+            }
+            Err(e) => report.failed.push((root, e.to_string())),
+        }
+    }
+    Ok(report)
+}
+
+/// Remove execution cgroups under `parent` that hold no processes. The
+/// kernel refuses to remove a populated cgroup, and one whose root is still
+/// locked belongs to a running execution that has not started its command.
+pub fn collect_cgroups(parent: &Path, min_age: Duration, report: &mut GcReport) {
+    let Ok(entries) = fs::read_dir(parent) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let cgroup = entry.path();
+        if !entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            continue;
+        }
+        let root = Path::new(SANDBOX_PARENT)
+            .join(format!("capsule-{}", entry.file_name().to_string_lossy()));
+        if populated(&cgroup) || root_locked(&root) {
+            report.live += 1;
+            continue;
+        }
+        if younger_than(&cgroup, min_age.max(CGROUP_MIN_AGE)) {
+            report.recent += 1;
+            continue;
+        }
+        // A cgroup is removed with rmdir, interface files and all
+        match fs::remove_dir(&cgroup) {
+            Ok(()) => report.cgroups_removed.push(cgroup),
+            Err(e) if e.raw_os_error() == Some(libc::EBUSY) => report.live += 1,
+            Err(e) => report.failed.push((cgroup, e.to_string())),
+        }
+    }
+}
+
+fn populated(cgroup: &Path) -> bool {
+    fs::read_to_string(cgroup.join("cgroup.events"))
+        .unwrap_or_default()
+        .lines()
+        .any(|line| line == "populated 1")
+}
+
+/// Whether a running execution holds `root`'s lock. Unlike `collect`, this
+/// never creates the lock file.
+fn root_locked(root: &Path) -> bool {
+    let Ok(lock) = File::open(lock_path(root)) else {
+        return false;
+    };
+    if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB) } != 0 {
+        return true;
+    }
+    unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_UN) };
+    false
+}
+
+fn younger_than(path: &Path, min_age: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age < min_age)
+}
+
+/// mountinfo escapes space, tab, newline and backslash as `\ooo`
+fn unescape_mount_point(point: &str) -> String {
+    let bytes = point.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4).and_then(|digits| {
+            let digits = std::str::from_utf8(digits).ok()?;
+            u8::from_str_radix(digits, 8).ok()
+        });
+        match (bytes[i], octal) {
+            (b'\\', Some(byte)) => {
+                unescaped.push(byte);
+                i += 4;
+            }
+            (byte, _) => {
+                unescaped.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unescape_mount_point() {
+        assert_eq!(unescape_mount_point("/tmp/a\\040b"), "/tmp/a b");
+        assert_eq!(unescape_mount_point("/proc"), "/proc");
+    }
+
+    #[test]
+    fn test_collect_skips_live_roots() {
+        let parent = tempfile::tempdir().unwrap();
+        let live = parent.path().join("capsule-live");
+        let stale = parent.path().join("capsule-stale");
+        let unrelated = parent.path().join("other");
+        for dir in [&live, &stale, &unrelated] {
+            fs::create_dir_all(dir.join("workspace")).unwrap();
+        }
+        let _lock = RootLock::acquire(&live).unwrap();
+
+        // Just created, so too recent for any minimum age
+        let report = collect(parent.path(), Duration::from_secs(3600)).unwrap();
+        assert!(report.removed.is_empty());
+        assert_eq!((report.live, report.recent), (1, 1));
+
+        let report = collect(parent.path(), Duration::ZERO).unwrap();
+        assert_eq!(report.removed, vec![stale.clone()]);
+        assert_eq!(report.live, 1);
+        assert!(live.exists());
+        assert!(!stale.exists());
+        assert!(!lock_path(&stale).exists());
+        assert!(unrelated.exists());
+    }
+
+    #[test]
+    fn test_collect_cgroups_skips_populated() {
+        let parent = tempfile::tempdir().unwrap();
+        let empty = parent.path().join(uuid::Uuid::new_v4().to_string());
+        let populated = parent.path().join(uuid::Uuid::new_v4().to_string());
+        fs::create_dir(&empty).unwrap();
+        fs::create_dir(&populated).unwrap();
+        fs::write(populated.join("cgroup.events"), "populated 1\nfrozen 0\n").unwrap();
+
+        let mut report = GcReport::default();
+        collect_cgroups(parent.path(), Duration::ZERO, &mut report);
+        // Younger than the floor every cgroup gets
+        assert!(report.cgroups_removed.is_empty());
+        assert_eq!((report.live, report.recent), (1, 1));
+
+        let old = SystemTime::now() - Duration::from_secs(600);
+        File::open(&empty).unwrap().set_modified(old).unwrap();
+        let mut report = GcReport::default();
+        collect_cgroups(parent.path(), Duration::ZERO, &mut report);
+        assert_eq!(report.cgroups_removed, vec![empty.clone()]);
+        assert!(populated.exists());
+    }
+}
@@ -0,0 +1,210 @@
+use crate::api::schema::{InputFile, RemoteInput};
+use crate::error::{CapsuleResult, SandboxError};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const DEFAULT_MODE: u32 = 0o644;
+/// Larger downloads are cut off by curl and fail the request
+const MAX_DOWNLOAD_BYTES: u64 = 2_147_483_648; // 2 GB
+const CONNECT_TIMEOUT_SECS: &str = "30";
+
+/// Write the request's input files under `workspace`, replacing any that
+/// exist. A symlink at a file's own path is refused rather than followed.
+pub fn write_input_files(workspace: &Path, files: &[InputFile]) -> CapsuleResult<()> {
+    for file in files {
+        let failed = |e: &dyn std::fmt::Display| {
+            SandboxError::FilesystemSetup(format!(
+                "Failed to write input file {}: {}",
+                file.path, e
+            ))
+        };
+
+        let data = file.data().map_err(|e| failed(&e))?;
+        let mut output =
+            create_in_workspace(workspace, &file.path, file.mode.unwrap_or(DEFAULT_MODE))
+                .map_err(|e| failed(&e))?;
+        output.write_all(&data).map_err(|e| failed(&e))?;
+    }
+    Ok(())
+}
+
+/// A remote input downloaded to a scratch file on the host, its digest
+/// already checked.
+pub struct Download {
+    dest: String,
+    file: File,
+}
+
+/// Fetch each input with the host's curl before setup, while the host
+/// network is reachable whatever the sandbox's network settings.
+pub fn download(inputs: &[RemoteInput]) -> CapsuleResult<Vec<Download>> {
+    inputs
+        .iter()
+        .map(|input| {
+            let failed = |e: &dyn std::fmt::Display| {
+                SandboxError::FilesystemSetup(format!("Failed to download {}: {}", input.url, e))
+            };
+
+            let mut curl = Command::new("curl")
+                .args(["--fail", "--silent", "--show-error", "--location"])
+                .args(["--proto", "=http,https", "--proto-redir", "=http,https"])
+                .args(["--connect-timeout", CONNECT_TIMEOUT_SECS])
+                .args(["--max-filesize", &MAX_DOWNLOAD_BYTES.to_string()])
+                .arg("--")
+                .arg(&input.url)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| failed(&e))?;
+            let mut file = tempfile::tempfile().map_err(|e| failed(&e))?;
+            let received = curl
+                .stdout
+                .take()
+                .map(|stdout| receive(stdout, &mut file, &input.sha256));
+            let output = curl.wait_with_output().map_err(|e| failed(&e))?;
+            if !output.status.success() {
+                return Err(failed(&String::from_utf8_lossy(&output.stderr).trim()).into());
+            }
+            match received {
+                Some(Ok(())) => Ok(Download {
+                    dest: input.dest.clone(),
+                    file,
+                }),
+                Some(Err(e)) => Err(failed(&e).into()),
+                None => Err(failed(&"curl produced no output").into()),
+            }
+        })
+        .collect()
+}
+
+/// Copy the downloads into `workspace`; call after setup.
+pub fn write_downloads(workspace: &Path, downloads: &[Download]) -> CapsuleResult<()> {
+    for download in downloads {
+        let failed = |e: io::Error| {
+            SandboxError::FilesystemSetup(format!(
+                "Failed to write remote input {}: {}",
+                download.dest, e
+            ))
+        };
+        let mut input = download.file.try_clone().map_err(failed)?;
+        input.seek(io::SeekFrom::Start(0)).map_err(failed)?;
+        let mut output =
+            create_in_workspace(workspace, &download.dest, DEFAULT_MODE).map_err(failed)?;
+        io::copy(&mut input, &mut output).map_err(failed)?;
+    }
+    Ok(())
+}
+
+/// Stream `input` into `file`, failing unless its SHA-256 is `expected`.
+fn receive(mut input: impl Read, file: &mut File, expected: &str) -> io::Result<()> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = input.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read])?;
+    }
+
+    let actual = format!("{:x}", hasher.finalize());
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("sha256 mismatch: expected {}, got {}", expected, actual),
+        ));
+    }
+    Ok(())
+}
+
+/// Create or truncate `relative` under `workspace` with `mode`, creating
+/// missing parents. A symlink at the file's own path is not followed.
+fn create_in_workspace(workspace: &Path, relative: &str, mode: u32) -> io::Result<File> {
+    let path = workspace.join(relative);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(&path)?;
+    // Set explicitly so the umask does not narrow the requested mode
+    file.set_permissions(fs::Permissions::from_mode(mode))?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_input_files() {
+        let workspace = tempfile::tempdir().unwrap();
+        let files = vec![
+            InputFile {
+                path: "scripts/run.sh".to_string(),
+                contents: Some("echo hi\n".to_string()),
+                base64: None,
+                mode: Some(0o755),
+            },
+            InputFile {
+                path: "data.bin".to_string(),
+                contents: None,
+                base64: Some("AAEC".to_string()),
+                mode: None,
+            },
+        ];
+        write_input_files(workspace.path(), &files).unwrap();
+
+        let script = workspace.path().join("scripts/run.sh");
+        assert_eq!(fs::read_to_string(&script).unwrap(), "echo hi\n");
+        assert_eq!(
+            fs::metadata(&script).unwrap().permissions().mode() & 0o7777,
+            0o755
+        );
+        let data = workspace.path().join("data.bin");
+        assert_eq!(fs::read(&data).unwrap(), vec![0, 1, 2]);
+        assert_eq!(
+            fs::metadata(&data).unwrap().permissions().mode() & 0o7777,
+            0o644
+        );
+
+        // A planted symlink is not followed out of the workspace
+        let outside = workspace.path().join("outside");
+        fs::write(&outside, "keep").unwrap();
+        fs::remove_file(&data).unwrap();
+        std::os::unix::fs::symlink(&outside, &data).unwrap();
+        assert!(write_input_files(workspace.path(), &files).is_err());
+        assert_eq!(fs::read_to_string(&outside).unwrap(), "keep");
+    }
+
+    #[test]
+    fn test_receive_verifies_digest() {
+        let digest = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let mut file = tempfile::tempfile().unwrap();
+        receive(&b"abc"[..], &mut file, &digest.to_uppercase()).unwrap();
+
+        let workspace = tempfile::tempdir().unwrap();
+        let downloads = [Download {
+            dest: "vendor/abc.txt".to_string(),
+            file,
+        }];
+        write_downloads(workspace.path(), &downloads).unwrap();
+        assert_eq!(
+            fs::read_to_string(workspace.path().join("vendor/abc.txt")).unwrap(),
+            "abc"
+        );
+
+        let mut file = tempfile::tempfile().unwrap();
+        let error = receive(&b"abd"[..], &mut file, digest).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,8 +1,53 @@
-use crate::api::schema::{IsolationConfig, ResourceLimits};
-use crate::error::CapsuleResult;
+#[cfg(feature = "endpoint-security")]
+use super::macos_audit::EndpointSecurityAudit;
+use super::macos_launch::{launcher_argv, QosClamp};
+use super::macos_network::PfNetworkDeny;
+use super::macos_watchdog::{ResourceWatchdog, WatchLimits};
+use super::macos_workspace::MacOSWorkspace;
+use super::Degradations;
+use crate::api::schema::{
+    AuditEvent, DegradedFeature, IsolationConfig, MacOSProfile, NetworkMode, ResourceEnforcement,
+    ResourceLimits, SandboxReport, SeccompMode, SeccompPreset,
+};
+use crate::error::{CapsuleResult, SandboxError};
+use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::Mutex;
 use uuid::Uuid;
 
+/// Credential and privacy stores under every home directory, denied even
+/// when a home directory is otherwise readable.
+const SENSITIVE_HOME_PATHS: [&str; 13] = [
+    "Library/Keychains",
+    "Library/Application Support/com.apple.TCC",
+    "Library/Cookies",
+    "Library/Safari",
+    "Library/Application Support/Google/Chrome",
+    "Library/Application Support/Chromium",
+    "Library/Application Support/BraveSoftware",
+    "Library/Application Support/Microsoft Edge",
+    "Library/Application Support/Arc",
+    "Library/Application Support/Firefox",
+    ".ssh",
+    ".gnupg",
+    ".aws",
+];
+
+/// System-wide counterparts, matching the Linux dangerous paths.
+const SENSITIVE_SYSTEM_PATHS: [&str; 5] = [
+    "/Library/Keychains",
+    "/Library/Application Support/com.apple.TCC",
+    "/private/etc/ssh",
+    "/private/etc/sudoers",
+    "/private/etc/master.passwd",
+];
+
+// libsandbox, part of libSystem
+extern "C" {
+    fn sandbox_check(pid: libc::pid_t, operation: *const c_char, filter_type: c_int, ...) -> c_int;
+}
+
 /// macOS-specific sandbox implementation using system frameworks
 pub struct MacOSSandbox {
     pub execution_id: Uuid,
@@ -10,6 +55,14 @@ pub struct MacOSSandbox {
     isolation_config: Option<IsolationConfig>,
     sandbox_profile: Option<String>,
     process_limits: ProcessLimits,
+    qos_clamp: Option<QosClamp>,
+    watchdog: Mutex<Option<ResourceWatchdog>>,
+    network_deny: Mutex<Option<PfNetworkDeny>>,
+    workspace: Option<MacOSWorkspace>,
+    working_directory: Option<PathBuf>,
+    degradations: Degradations,
+    #[cfg(feature = "endpoint-security")]
+    audit: Mutex<Option<EndpointSecurityAudit>>,
 }
 
 #[derive(Debug, Clone)]
@@ -18,9 +71,10 @@ pub struct ProcessLimits {
     pub max_cpu_time_seconds: Option<u64>,
     pub max_file_descriptors: Option<u32>,
     pub max_processes: Option<u32>,
+    pub max_file_size_bytes: Option<u64>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct MacOSResourceUsage {
     pub memory_bytes: u64,
     pub cpu_time_us: u64,
@@ -28,6 +82,13 @@ pub struct MacOSResourceUsage {
     pub kernel_time_us: u64,
     pub io_bytes_read: u64,
     pub io_bytes_written: u64,
+    pub cpu_throttled_us: u64,
+    pub disk_bytes: u64,
+    pub major_faults: u64,
+    pub minor_faults: u64,
+    pub voluntary_ctx_switches: u64,
+    pub involuntary_ctx_switches: u64,
+    pub pids_peak: u64,
 }
 
 // Type alias for compatibility with the main interface
@@ -45,7 +106,16 @@ impl MacOSSandbox {
                 max_cpu_time_seconds: None,
                 max_file_descriptors: Some(1024), // Safe default
                 max_processes: Some(64),          // Safe default
+                max_file_size_bytes: None,
             },
+            qos_clamp: None,
+            watchdog: Mutex::new(None),
+            network_deny: Mutex::new(None),
+            workspace: None,
+            working_directory: None,
+            degradations: Degradations::default(),
+            #[cfg(feature = "endpoint-security")]
+            audit: Mutex::new(None),
         })
     }
 
@@ -56,6 +126,8 @@ impl MacOSSandbox {
     ) -> CapsuleResult<()> {
         self.resource_limits = Some(resources.clone());
         self.isolation_config = Some(isolation.clone());
+        self.degradations = Degradations::new(isolation.enforcement);
+        self.record_linux_only_features(resources, isolation)?;
 
         // Convert resource limits to macOS process limits
         self.process_limits.max_memory_bytes = if resources.memory_bytes > 0 {
@@ -64,13 +136,57 @@ impl MacOSSandbox {
             None
         };
 
-        // Note: macOS doesn't have direct CPU time limits in ResourceLimits
-        // We could potentially use cpu_shares to derive a relative limit
+        // There is no cpu.weight or cpu.max: shares below the default clamp the
+        // command's QoS so it yields to interactive work, and the quota is
+        // enforced by the watchdog
+        self.qos_clamp = QosClamp::for_limits(resources);
         self.process_limits.max_cpu_time_seconds = None;
 
-        // Generate macOS sandbox profile
-        self.sandbox_profile = Some(self.generate_sandbox_profile(isolation)?);
+        self.process_limits.max_file_size_bytes = resources.max_file_size_bytes;
+        self.process_limits.max_file_descriptors = Some(resources.max_open_files);
 
+        // Bind mounts and the working directory are recreated in a per-execution workspace
+        let workspace = MacOSWorkspace::create(self.execution_id, &isolation.bind_mounts)?;
+        self.working_directory = if isolation.working_directory.is_empty() {
+            None
+        } else {
+            Some(workspace.resolve(&isolation.working_directory)?)
+        };
+        self.workspace = Some(workspace);
+
+        // Seatbelt profiles cannot be nested: under an outer sandbox the command
+        // inherits that one, and network denial falls back to pf
+        if already_sandboxed() {
+            self.degradations.record(
+                "sandbox_profile",
+                "capsule-run is already running under a Seatbelt sandbox",
+            )?;
+            self.sandbox_profile = None;
+            if !isolation.network {
+                match PfNetworkDeny::install(self.execution_id) {
+                    Ok(deny) => *self.network_deny.lock().unwrap() = Some(deny),
+                    Err(e) => self
+                        .degradations
+                        .record("network_isolation", e.to_string())?,
+                }
+            }
+        } else {
+            self.sandbox_profile = Some(self.generate_sandbox_profile(isolation)?);
+        }
+
+        Ok(())
+    }
+
+    /// Requested settings that only Linux primitives provide.
+    fn record_linux_only_features(
+        &mut self,
+        resources: &ResourceLimits,
+        isolation: &IsolationConfig,
+    ) -> CapsuleResult<()> {
+        for feature in linux_only_features(resources, isolation) {
+            self.degradations
+                .record(feature, "not supported on macOS")?;
+        }
         Ok(())
     }
 
@@ -81,20 +197,38 @@ impl MacOSSandbox {
         profile.push_str("(version 1)\n");
         profile.push_str("(deny default)\n");
 
-        // Allow basic system operations
+        // Allow basic system operations; strict keeps the command to one process
         profile.push_str("(allow process-exec)\n");
-        profile.push_str("(allow process-fork)\n");
-        profile.push_str("(allow signal)\n");
+        if isolation.macos_profile == MacOSProfile::Strict {
+            profile.push_str("(allow signal (target self))\n");
+        } else {
+            profile.push_str("(allow process-fork)\n");
+            profile.push_str("(allow signal (target same-sandbox))\n");
+            // dyld and most tools stat parent directories of everything they open
+            profile.push_str("(allow file-read-metadata)\n");
+        }
+        profile.push_str("(allow sysctl-read)\n");
 
-        // Allow reading system libraries and frameworks
+        // Allow reading system libraries, frameworks and binaries
         profile.push_str("(allow file-read*\n");
         profile.push_str("    (subpath \"/System\")\n");
         profile.push_str("    (subpath \"/usr/lib\")\n");
         profile.push_str("    (subpath \"/usr/share\")\n");
+        profile.push_str("    (subpath \"/usr/bin\")\n");
+        profile.push_str("    (subpath \"/usr/libexec\")\n");
+        profile.push_str("    (subpath \"/bin\")\n");
         profile.push_str("    (subpath \"/Library/Frameworks\")\n");
         profile.push_str("    (subpath \"/private/var/db/dyld\")\n");
+        profile.push_str("    (subpath \"/private/etc\")\n");
+        profile.push_str("    (literal \"/dev/urandom\")\n");
+        profile.push_str("    (literal \"/dev/random\")\n");
+        profile.push_str("    (literal \"/dev/zero\")\n");
         profile.push_str(")\n");
 
+        if isolation.macos_profile == MacOSProfile::Developer {
+            profile.push_str(&Self::developer_rules());
+        }
+
         // Allow basic I/O operations
         profile.push_str("(allow file-read-data file-write-data\n");
         profile.push_str("    (literal \"/dev/null\")\n");
@@ -107,7 +241,7 @@ impl MacOSSandbox {
         if !isolation.writable_paths.is_empty() {
             profile.push_str("(allow file*\n");
             for path in &isolation.writable_paths {
-                profile.push_str(&format!("    (subpath \"{}\")\n", path));
+                profile.push_str(&format!("    (subpath {})\n", sbpl_path(path)));
             }
             profile.push_str(")\n");
         }
@@ -116,17 +250,37 @@ impl MacOSSandbox {
         if !isolation.readonly_paths.is_empty() {
             profile.push_str("(allow file-read*\n");
             for path in &isolation.readonly_paths {
-                profile.push_str(&format!("    (subpath \"{}\")\n", path));
+                profile.push_str(&format!("    (subpath {})\n", sbpl_path(path)));
             }
             profile.push_str(")\n");
         }
 
-        // Working directory access
-        if !isolation.working_directory.is_empty() {
+        // Working directory access; symlinked mounts resolve to their source
+        if let Some(working_directory) = &self.working_directory {
+            profile.push_str(&format!(
+                "(allow file* (subpath {}))\n",
+                sbpl_path(&working_directory.to_string_lossy())
+            ));
+        }
+
+        // Emulated bind mounts. Clones live under the workspace root; symlinked
+        // mounts are granted on their source. Read-only denials come last so
+        // they win over any broader grant above.
+        if let Some(workspace) = &self.workspace {
             profile.push_str(&format!(
-                "(allow file* (subpath \"{}\"))\n",
-                isolation.working_directory
+                "(allow file* (subpath {}))\n",
+                sbpl_path(&workspace.root().to_string_lossy())
             ));
+            for mount in workspace.mounts() {
+                let path = mount.linked_source.as_ref().unwrap_or(&mount.target);
+                let path = sbpl_path(&path.to_string_lossy());
+                if mount.readonly {
+                    profile.push_str(&format!("(allow file-read* (subpath {}))\n", path));
+                    profile.push_str(&format!("(deny file-write* (subpath {}))\n", path));
+                } else {
+                    profile.push_str(&format!("(allow file* (subpath {}))\n", path));
+                }
+            }
         }
 
         // Network access
@@ -136,28 +290,112 @@ impl MacOSSandbox {
             profile.push_str("(deny network*)\n");
         }
 
+        // Host sockets stay at their own path; there is no mount namespace
+        for socket in &isolation.unix_sockets {
+            profile.push_str(&format!(
+                "(allow network-outbound (remote unix-socket (path-literal {})))\n",
+                sbpl_path(&socket.source)
+            ));
+        }
+
         // Deny dangerous operations (using valid macOS sandbox operations)
         profile.push_str("(deny system-privilege)\n");
         profile.push_str("(deny system-audit)\n");
         profile.push_str("(deny system-socket)\n");
 
+        // After every grant, so readable or mounted home directories still
+        // keep keychains, TCC, keys and browser profiles out of reach
+        profile.push_str(&Self::sensitive_path_rules());
+
+        if !isolation.masked_paths.is_empty() {
+            profile.push_str("(deny file-read* file-write*\n");
+            for path in &isolation.masked_paths {
+                profile.push_str(&format!("    (subpath {})\n", sbpl_path(path)));
+            }
+            profile.push_str(")\n");
+        }
+
+        // User rules go last so they can override anything generated above
+        for rule in &isolation.macos_profile_extra {
+            profile.push_str(rule);
+            profile.push('\n');
+        }
+
         Ok(profile)
     }
 
+    /// Denials for keychains, the TCC database, SSH and cloud keys and browser
+    /// profiles, for every user and for the system.
+    fn sensitive_path_rules() -> String {
+        let mut rules = String::new();
+
+        rules.push_str("(deny file-read* file-write*\n");
+        for path in SENSITIVE_SYSTEM_PATHS {
+            rules.push_str(&format!("    (subpath {})\n", sbpl_path(path)));
+        }
+        // Regexes cover every account under /Users and root's home, and the
+        // current $HOME if it lives elsewhere
+        for path in SENSITIVE_HOME_PATHS {
+            rules.push_str(&format!(
+                "    (regex #\"^/(Users/[^/]+|private/var/root)/{}(/|$)\")\n",
+                regex_escape(path)
+            ));
+        }
+        if let Ok(home) = std::env::var("HOME") {
+            let home = sbpl_path(&home);
+            let home = home.trim_matches('"');
+            if !home.starts_with("/Users/") && home != "/private/var/root" {
+                for path in SENSITIVE_HOME_PATHS {
+                    rules.push_str(&format!("    (subpath \"{}/{}\")\n", home, path));
+                }
+            }
+        }
+        rules.push_str(")\n");
+
+        rules
+    }
+
+    /// Paths and services Xcode and Homebrew toolchains need beyond the default profile.
+    fn developer_rules() -> String {
+        let mut rules = String::new();
+
+        rules.push_str("(allow file-read*\n");
+        for path in [
+            "/Applications/Xcode.app",
+            "/Library/Developer",
+            "/usr/local",
+            "/opt/homebrew",
+            "/private/var/select",
+        ] {
+            rules.push_str(&format!("    (subpath {})\n", sbpl_path(path)));
+        }
+        if let Ok(home) = std::env::var("HOME") {
+            rules.push_str(&format!(
+                "    (subpath {})\n",
+                sbpl_path(&format!("{}/Library/Developer", home))
+            ));
+        }
+        rules.push_str(")\n");
+
+        // xcrun, clang's module cache and swiftpm keep state under the
+        // per-user temp and cache dirs (DARWIN_USER_TEMP_DIR/CACHE_DIR)
+        rules.push_str("(allow file* (subpath \"/private/var/folders\"))\n");
+
+        // xcode-select, opendirectoryd lookups and code signing go through Mach services
+        rules.push_str("(allow mach-lookup)\n");
+        rules.push_str("(allow ipc-posix-shm-read*)\n");
+        rules.push_str("(allow process-info* (target same-sandbox))\n");
+
+        rules
+    }
+
     pub fn get_resource_usage(&self) -> CapsuleResult<ResourceUsage> {
         // Use rusage to get basic resource information
         let usage = unsafe {
             let mut usage: libc::rusage = std::mem::zeroed();
             let result = libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage);
             if result != 0 {
-                return Ok(ResourceUsage {
-                    memory_bytes: 0,
-                    cpu_time_us: 0,
-                    user_time_us: 0,
-                    kernel_time_us: 0,
-                    io_bytes_read: 0,
-                    io_bytes_written: 0,
-                });
+                return Ok(ResourceUsage::default());
             }
             usage
         };
@@ -171,80 +409,158 @@ impl MacOSSandbox {
                 + usage.ru_stime.tv_usec as u64,
             io_bytes_read: usage.ru_inblock as u64 * 512, // Approximate
             io_bytes_written: usage.ru_oublock as u64 * 512, // Approximate
+            cpu_throttled_us: 0,
+            disk_bytes: 0,
+            major_faults: usage.ru_majflt as u64,
+            minor_faults: usage.ru_minflt as u64,
+            voluntary_ctx_switches: usage.ru_nvcsw as u64,
+            involuntary_ctx_switches: usage.ru_nivcsw as u64,
+            pids_peak: 0,
         })
     }
 
-    pub fn check_oom_killed(&self) -> CapsuleResult<bool> {
-        // macOS doesn't have the same OOM concept as Linux
-        // We can check if we're approaching memory limits
-        if let Some(max_memory) = self.process_limits.max_memory_bytes {
-            let usage = self.get_resource_usage()?;
-            Ok(usage.memory_bytes > max_memory)
-        } else {
-            Ok(false)
+    /// Start enforcing the memory limit and CPU quota on the spawned command's process tree.
+    pub fn track_process(&self, pid: u32) {
+        let limits = WatchLimits {
+            memory_bytes: self.process_limits.max_memory_bytes,
+            cpu_quota_percent: self
+                .resource_limits
+                .as_ref()
+                .and_then(|r| r.cpu_quota_percent),
+        };
+        *self.watchdog.lock().unwrap() = Some(ResourceWatchdog::start(pid, limits));
+
+        #[cfg(feature = "endpoint-security")]
+        match EndpointSecurityAudit::start(pid) {
+            Ok(audit) => *self.audit.lock().unwrap() = Some(audit),
+            Err(e) => tracing::warn!(error = %e, "No audit trail will be reported"),
         }
     }
 
-    pub fn prepare_command(&self, cmd: &mut Command) -> CapsuleResult<()> {
-        use std::os::unix::process::CommandExt;
-
-        // Apply resource limits using pre_exec hook
-        if let Some(limits) = &self.resource_limits {
-            let limits_clone = limits.clone();
-            let process_limits = self.process_limits.clone();
-
-            unsafe {
-                cmd.pre_exec(move || Self::apply_limits_in_child(&limits_clone, &process_limits));
-            }
+    /// File opens, execs and socket connections seen by Endpoint Security.
+    pub fn audit_trail(&self) -> Option<Vec<AuditEvent>> {
+        #[cfg(feature = "endpoint-security")]
+        {
+            self.audit
+                .lock()
+                .unwrap()
+                .take()
+                .map(EndpointSecurityAudit::finish)
+        }
+        #[cfg(not(feature = "endpoint-security"))]
+        {
+            None
         }
+    }
 
-        // For now, skip sandbox-exec integration to focus on basic functionality
-        // TODO: Implement proper sandbox-exec integration later
-        if let Some(_profile) = &self.sandbox_profile {
-            // For now, just set an environment variable to indicate sandboxing is active
-            cmd.env("CAPSULE_SANDBOX_ACTIVE", "1");
+    /// Seatbelt profile and pf fallback in force for the command.
+    pub fn report(&self) -> SandboxReport {
+        SandboxReport {
+            backend: "seatbelt".to_string(),
+            namespaces: Vec::new(),
+            cgroup_controllers: Vec::new(),
+            seccomp: false,
+            seccomp_mode: None,
+            macos_profile: self
+                .sandbox_profile
+                .as_ref()
+                .and(self.isolation_config.as_ref())
+                .map(|isolation| isolation.macos_profile),
+            pf_network_deny: self.network_deny.lock().unwrap().is_some(),
+            bridge_address: None,
+            resource_enforcement: ResourceEnforcement::Rlimit,
         }
+    }
 
-        Ok(())
+    /// Requested features that were not applied, under best-effort enforcement.
+    pub fn degraded_features(&self) -> Vec<DegradedFeature> {
+        self.degradations.features().to_vec()
     }
 
-    fn apply_limits_in_child(
-        limits: &ResourceLimits,
-        process_limits: &ProcessLimits,
-    ) -> Result<(), std::io::Error> {
-        unsafe {
-            // Set memory limit (RLIMIT_AS - virtual memory)
-            if limits.memory_bytes > 0 {
-                let limit = libc::rlimit {
-                    rlim_cur: limits.memory_bytes,
-                    rlim_max: limits.memory_bytes,
-                };
-                if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
-                    eprintln!("Warning: Failed to set memory limit");
-                    // Don't fail the process, just warn
-                }
-            }
+    /// Whether the watchdog killed the process tree for exceeding the memory limit.
+    pub fn check_oom_killed(&self) -> CapsuleResult<bool> {
+        Ok(self
+            .watchdog
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(ResourceWatchdog::memory_limit_exceeded))
+    }
 
-            // Set file descriptor limit (RLIMIT_NOFILE)
-            if let Some(fd_limit) = process_limits.max_file_descriptors {
-                let limit = libc::rlimit {
-                    rlim_cur: fd_limit as u64,
-                    rlim_max: fd_limit as u64,
-                };
-                if libc::setrlimit(libc::RLIMIT_NOFILE, &limit) != 0 {
-                    eprintln!("Warning: Failed to set file descriptor limit");
-                }
-            }
+    /// Whether the watchdog killed the process tree for exceeding the CPU quota.
+    pub fn check_cpu_limit_exceeded(&self) -> bool {
+        self.watchdog
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(ResourceWatchdog::cpu_limit_exceeded)
+    }
 
-            // Set process limit (RLIMIT_NPROC)
-            if let Some(proc_limit) = process_limits.max_processes {
-                let limit = libc::rlimit {
-                    rlim_cur: proc_limit as u64,
-                    rlim_max: proc_limit as u64,
-                };
-                if libc::setrlimit(libc::RLIMIT_NPROC, &limit) != 0 {
-                    eprintln!("Warning: Failed to set process limit");
-                }
+    /// Highest combined footprint of the process tree, sampled every 10ms.
+    pub fn peak_memory(&self) -> Option<u64> {
+        self.watchdog
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(ResourceWatchdog::peak_bytes)
+    }
+
+    /// Command that runs `argv` under the sandbox profile and process limits.
+    ///
+    /// The QoS clamp, profile, rlimits and umask are applied by a launcher
+    /// rather than a `pre_exec` hook, so std can spawn it with posix_spawn
+    /// instead of fork.
+    pub fn command(&self, argv: &[String]) -> Command {
+        let umask = self.isolation_config.as_ref().and_then(|c| c.umask);
+        let argv = launcher_argv(
+            self.qos_clamp,
+            self.sandbox_profile.as_deref(),
+            self.resource_limits.as_ref().map(|_| &self.process_limits),
+            umask,
+            argv,
+        );
+
+        let mut cmd = Command::new(&argv[0]);
+        cmd.args(&argv[1..]);
+        cmd
+    }
+
+    /// Host path the command sees as /workspace
+    pub fn workspace_path(&self) -> CapsuleResult<PathBuf> {
+        match &self.workspace {
+            Some(workspace) => workspace.resolve("/workspace"),
+            None => Err(SandboxError::FilesystemSetup(
+                "The sandbox workspace is not set up".to_string(),
+            )
+            .into()),
+        }
+    }
+
+    pub fn prepare_command(&self, cmd: &mut Command) -> CapsuleResult<()> {
+        use std::os::unix::process::CommandExt;
+
+        if let Some(working_directory) = &self.working_directory {
+            cmd.current_dir(working_directory);
+        }
+        if let Some(workspace) = &self.workspace {
+            // Lets commands reach mount destinations that only exist in the workspace
+            cmd.env("CAPSULE_ROOT", workspace.root());
+        }
+        if self.sandbox_profile.is_some() {
+            cmd.env("CAPSULE_SANDBOX_ACTIVE", "1");
+        }
+
+        // Only the pf fallback needs a hook, which makes std fork instead of posix_spawn
+        if let Some(gid) = self.network_deny.lock().unwrap().as_ref().map(|d| d.gid()) {
+            unsafe {
+                cmd.pre_exec(move || {
+                    // The pf rules match the primary group of the socket's owner
+                    let gid = gid as libc::gid_t;
+                    if libc::setgroups(1, &gid) != 0 || libc::setgid(gid) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
             }
         }
 
@@ -252,6 +568,12 @@ impl MacOSSandbox {
     }
 
     pub fn cleanup(&self) -> CapsuleResult<()> {
+        self.watchdog.lock().unwrap().take();
+        self.network_deny.lock().unwrap().take();
+        if let Some(workspace) = &self.workspace {
+            workspace.cleanup()?;
+        }
+
         // Clean up temporary sandbox profile
         let profile_path = format!("/tmp/capsule-{}.sb", self.execution_id);
         let _ = std::fs::remove_file(profile_path); // Ignore errors
@@ -260,6 +582,86 @@ impl MacOSSandbox {
     }
 }
 
+/// Requested settings that only Linux primitives provide, by the feature
+/// name they are reported under.
+pub fn linux_only_features(
+    resources: &ResourceLimits,
+    isolation: &IsolationConfig,
+) -> Vec<&'static str> {
+    let seccomp = isolation.seccomp != SeccompPreset::Default
+        || isolation.seccomp_profile.is_some()
+        || !isolation.extra_syscalls.is_empty()
+        || isolation.seccomp_mode != SeccompMode::Kill
+        || !isolation.notify_syscalls.is_empty();
+    let io_limits = resources.io_read_bps.is_some()
+        || resources.io_write_bps.is_some()
+        || resources.io_read_iops.is_some()
+        || resources.io_write_iops.is_some();
+
+    [
+        (seccomp, "seccomp"),
+        (isolation.time_offset.is_some(), "time_namespace"),
+        (!isolation.tmpfs_paths.is_empty(), "tmpfs_paths"),
+        (isolation.base_mounts.is_some(), "base_mounts"),
+        (!isolation.devices.is_empty(), "devices"),
+        (!isolation.fuse_mounts.is_empty(), "fuse_mounts"),
+        (resources.cpuset.is_some(), "cpuset"),
+        (io_limits, "io_limits"),
+        (resources.disk_bytes.is_some(), "disk_limit"),
+        (!isolation.caches.is_empty(), "cache_size"),
+        (resources.network_bps.is_some(), "network_bandwidth"),
+        (
+            isolation.network_mode == NetworkMode::Bridge,
+            "bridge_network",
+        ),
+        (isolation.network_policy.is_some(), "network_policy"),
+        (isolation.dns.is_some(), "dns"),
+        (!isolation.extra_hosts.is_empty(), "extra_hosts"),
+        (isolation.proxy.is_some(), "proxy_enforcement"),
+        (isolation.track_destinations, "track_destinations"),
+        (
+            isolation
+                .unix_sockets
+                .iter()
+                .any(|socket| socket.destination() != socket.source),
+            "unix_socket_destination",
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(requested, feature)| requested.then_some(feature))
+    .collect()
+}
+
+/// Whether this process already runs under a Seatbelt sandbox.
+pub(crate) fn already_sandboxed() -> bool {
+    // SANDBOX_FILTER_NONE with no operation asks only whether a sandbox is active
+    unsafe { sandbox_check(libc::getpid(), std::ptr::null(), 0) != 0 }
+}
+
+/// Quote `path` as an SBPL string literal. Rules match resolved paths, so
+/// symlinks such as /tmp -> /private/tmp are followed when the path exists.
+fn sbpl_path(path: &str) -> String {
+    let resolved = std::fs::canonicalize(Path::new(path))
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| path.to_string());
+    format!(
+        "\"{}\"",
+        resolved.replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
+
+/// Escape regex metacharacters for an SBPL `#"..."` literal.
+fn regex_escape(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        if "\\.^$|?*+()[]{}\"".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
 impl Drop for MacOSSandbox {
     fn drop(&mut self) {
         let _ = self.cleanup();
@@ -288,7 +690,7 @@ mod tests {
             working_directory: "/tmp".to_string(),
             readonly_paths: vec!["/usr".to_string()],
             writable_paths: vec!["/tmp".to_string()],
-            bind_mounts: vec![],
+            ..Default::default()
         };
 
         let result = sandbox.setup(&ResourceLimits::default(), &isolation);
@@ -297,10 +699,139 @@ mod tests {
 
         let profile = sandbox.sandbox_profile.clone().unwrap();
         assert!(profile.contains("(deny network*)"));
-        assert!(profile.contains("/tmp"));
+        assert!(profile.contains("/private/tmp"));
         assert!(profile.contains("/usr"));
     }
 
+    #[test]
+    fn test_named_profiles_and_extra_rules() {
+        let mut isolation = IsolationConfig {
+            macos_profile: MacOSProfile::Strict,
+            macos_profile_extra: vec!["(allow file-read* (subpath \"/opt/tools\"))".to_string()],
+            ..Default::default()
+        };
+
+        let sandbox = MacOSSandbox::new(Uuid::new_v4()).unwrap();
+        let strict = sandbox.generate_sandbox_profile(&isolation).unwrap();
+        assert!(!strict.contains("(allow process-fork)"));
+        assert!(strict
+            .trim_end()
+            .ends_with("(allow file-read* (subpath \"/opt/tools\"))"));
+
+        isolation.macos_profile = MacOSProfile::Developer;
+        let developer = sandbox.generate_sandbox_profile(&isolation).unwrap();
+        assert!(developer.contains("(allow process-fork)"));
+        assert!(developer.contains("/Applications/Xcode.app"));
+        assert!(developer.contains("(allow mach-lookup)"));
+    }
+
+    #[test]
+    fn test_sensitive_paths_denied_after_grants() {
+        let isolation = IsolationConfig {
+            readonly_paths: vec!["/Users".to_string()],
+            ..Default::default()
+        };
+        let sandbox = MacOSSandbox::new(Uuid::new_v4()).unwrap();
+        let profile = sandbox.generate_sandbox_profile(&isolation).unwrap();
+
+        let grant = profile.find("(subpath \"/Users\")").unwrap();
+        let deny = profile.find("(deny file-read* file-write*").unwrap();
+        assert!(deny > grant);
+        assert!(profile.contains("Library/Keychains(/|$)"));
+        assert!(profile.contains("Library/Application Support/com\\.apple\\.TCC"));
+        assert!(profile.contains("/\\.ssh(/|$)"));
+        assert!(profile.contains("(subpath \"/private/etc/ssh\")"));
+    }
+
+    #[test]
+    fn test_masked_paths_denied() {
+        let isolation = IsolationConfig {
+            readonly_paths: vec!["/opt/tools".to_string()],
+            masked_paths: vec!["/opt/tools/secrets".to_string()],
+            ..Default::default()
+        };
+        let sandbox = MacOSSandbox::new(Uuid::new_v4()).unwrap();
+        let profile = sandbox.generate_sandbox_profile(&isolation).unwrap();
+
+        let grant = profile.find("(subpath \"/opt/tools\")").unwrap();
+        let mask = profile.find("(subpath \"/opt/tools/secrets\")").unwrap();
+        assert!(mask > grant);
+    }
+
+    #[test]
+    fn test_keychain_read_is_denied() {
+        let mut sandbox = MacOSSandbox::new(Uuid::new_v4()).unwrap();
+        let home = std::env::var("HOME").unwrap();
+        let isolation = IsolationConfig {
+            readonly_paths: vec![home.clone()],
+            ..Default::default()
+        };
+        sandbox
+            .setup(&ResourceLimits::default(), &isolation)
+            .unwrap();
+
+        let mut cmd =
+            sandbox.command(&["/bin/ls".to_string(), format!("{}/Library/Keychains", home)]);
+        sandbox.prepare_command(&mut cmd).unwrap();
+        assert!(!cmd.status().unwrap().success());
+    }
+
+    #[test]
+    fn test_linux_only_features_under_strict_enforcement() {
+        let resources = ResourceLimits {
+            cpuset: Some("0".to_string()),
+            ..Default::default()
+        };
+        let mut isolation = IsolationConfig::default();
+
+        let mut sandbox = MacOSSandbox::new(Uuid::new_v4()).unwrap();
+        sandbox.setup(&resources, &isolation).unwrap();
+        assert_eq!(sandbox.degraded_features()[0].feature, "cpuset");
+
+        let report = sandbox.report();
+        assert_eq!(report.backend, "seatbelt");
+        assert_eq!(report.macos_profile.is_some(), !already_sandboxed());
+
+        isolation.enforcement = crate::api::schema::EnforcementPolicy::Strict;
+        let mut sandbox = MacOSSandbox::new(Uuid::new_v4()).unwrap();
+        assert!(sandbox.setup(&resources, &isolation).is_err());
+    }
+
+    #[test]
+    fn test_sbpl_path_escapes_quotes() {
+        assert_eq!(sbpl_path("/nonexistent/a\"b"), "\"/nonexistent/a\\\"b\"");
+    }
+
+    #[test]
+    fn test_profile_is_enforced() {
+        let mut sandbox = MacOSSandbox::new(Uuid::new_v4()).unwrap();
+        let dir = std::env::temp_dir().join(format!("capsule-sb-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let isolation = IsolationConfig {
+            working_directory: dir.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        sandbox
+            .setup(&ResourceLimits::default(), &isolation)
+            .unwrap();
+
+        // Writing inside the working directory is allowed, outside it is not
+        let outside = std::env::temp_dir().join(format!("capsule-sb-{}", Uuid::new_v4()));
+        let mut cmd = sandbox.command(&[
+            "/usr/bin/touch".to_string(),
+            dir.join("inside").to_string_lossy().into_owned(),
+            outside.to_string_lossy().into_owned(),
+        ]);
+        sandbox.prepare_command(&mut cmd).unwrap();
+        let status = cmd.status().unwrap();
+
+        assert!(!status.success());
+        assert!(dir.join("inside").exists());
+        assert!(!outside.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
     #[test]
     fn test_resource_usage() {
         let execution_id = Uuid::new_v4();
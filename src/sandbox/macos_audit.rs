@@ -0,0 +1,258 @@
+use crate::api::schema::{AuditEvent, AuditEventKind};
+use crate::error::{CapsuleResult, ExecutionError};
+use std::collections::{HashMap, HashSet};
+use std::ffi::c_void;
+use std::os::raw::{c_int, c_ulong};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Entries kept per execution; later events are dropped.
+const MAX_EVENTS: usize = 10_000;
+
+// es_event_type_t values from EndpointSecurity/ESTypes.h
+const ES_EVENT_TYPE_NOTIFY_EXEC: u32 = 9;
+const ES_EVENT_TYPE_NOTIFY_OPEN: u32 = 10;
+const ES_EVENT_TYPE_NOTIFY_FORK: u32 = 11;
+const ES_EVENT_TYPE_NOTIFY_UIPC_CONNECT: u32 = 78;
+
+// Offsets into es_message_t, es_process_t and es_file_t on 64-bit targets.
+// These structs have only been extended at the end since macOS 10.15.
+const MESSAGE_PROCESS: usize = 40;
+const MESSAGE_EVENT_TYPE: usize = 96;
+const MESSAGE_EVENT: usize = 104;
+/// audit_token_t.val[5]
+const PROCESS_PID: usize = 20;
+const PROCESS_PPID: usize = 32;
+const PROCESS_EXECUTABLE: usize = 112;
+/// es_event_open_t.file
+const OPEN_FILE: usize = 8;
+
+type EsClient = c_void;
+type EsMessage = c_void;
+
+#[link(name = "EndpointSecurity")]
+extern "C" {
+    fn es_new_client(client: *mut *mut EsClient, handler: *const BlockLiteral) -> c_int;
+    fn es_subscribe(client: *mut EsClient, events: *const u32, event_count: u32) -> c_int;
+    fn es_delete_client(client: *mut EsClient) -> c_int;
+}
+
+extern "C" {
+    static _NSConcreteGlobalBlock: c_void;
+}
+
+/// Layout of a clang block literal without captures. es_new_client copies
+/// the handler block; a global block is never actually copied, so a static
+/// one can be handed over without a blocks runtime crate.
+#[repr(C)]
+struct BlockLiteral {
+    isa: *const c_void,
+    flags: c_int,
+    reserved: c_int,
+    invoke: unsafe extern "C" fn(*const BlockLiteral, *mut EsClient, *const EsMessage),
+    descriptor: *const BlockDescriptor,
+}
+
+#[repr(C)]
+struct BlockDescriptor {
+    reserved: c_ulong,
+    size: c_ulong,
+}
+
+const BLOCK_IS_GLOBAL: c_int = 1 << 28;
+
+static DESCRIPTOR: BlockDescriptor = BlockDescriptor {
+    reserved: 0,
+    size: std::mem::size_of::<BlockLiteral>() as c_ulong,
+};
+
+struct HandlerBlock(BlockLiteral);
+
+// Immutable after creation; ES only reads it
+unsafe impl Send for HandlerBlock {}
+unsafe impl Sync for HandlerBlock {}
+
+fn handler_block() -> &'static BlockLiteral {
+    static BLOCK: OnceLock<HandlerBlock> = OnceLock::new();
+    &BLOCK
+        .get_or_init(|| {
+            HandlerBlock(BlockLiteral {
+                isa: unsafe { &_NSConcreteGlobalBlock },
+                flags: BLOCK_IS_GLOBAL,
+                reserved: 0,
+                invoke: handle_message,
+                descriptor: &DESCRIPTOR,
+            })
+        })
+        .0
+}
+
+/// Trails by client, since the global handler block cannot capture state.
+fn trails() -> &'static Mutex<HashMap<usize, Arc<Mutex<Trail>>>> {
+    static TRAILS: OnceLock<Mutex<HashMap<usize, Arc<Mutex<Trail>>>>> = OnceLock::new();
+    TRAILS.get_or_init(Default::default)
+}
+
+struct Trail {
+    pids: HashSet<u32>,
+    events: Vec<AuditEvent>,
+}
+
+/// Audit trail of a process tree from Endpoint Security, the macOS
+/// counterpart to the seccomp audit on Linux.
+///
+/// Records executions, file opens and connections to Unix domain sockets.
+/// Endpoint Security has no events for TCP or UDP connections; those need a
+/// Network Extension filter. The client is created after spawn, so activity
+/// from the first moments of the command can be missed; processes forked
+/// before the subscription are picked up through their parent pid.
+pub struct EndpointSecurityAudit {
+    client: *mut EsClient,
+    trail: Arc<Mutex<Trail>>,
+}
+
+// The client pointer is only passed back to es_delete_client
+unsafe impl Send for EndpointSecurityAudit {}
+
+impl EndpointSecurityAudit {
+    pub fn start(root_pid: u32) -> CapsuleResult<Self> {
+        let trail = Arc::new(Mutex::new(Trail {
+            pids: HashSet::from([root_pid]),
+            events: Vec::new(),
+        }));
+
+        let mut client = std::ptr::null_mut();
+        // Registered before any event can arrive for this client
+        let mut registry = trails().lock().unwrap();
+        let result = unsafe { es_new_client(&mut client, handler_block()) };
+        if result != 0 {
+            return Err(ExecutionError::MonitoringError(format!(
+                "Endpoint Security client unavailable (es_new_client result {}); \
+                 it needs root and the endpoint-security entitlement",
+                result
+            ))
+            .into());
+        }
+        registry.insert(client as usize, Arc::clone(&trail));
+        drop(registry);
+
+        let events = [
+            ES_EVENT_TYPE_NOTIFY_EXEC,
+            ES_EVENT_TYPE_NOTIFY_OPEN,
+            ES_EVENT_TYPE_NOTIFY_FORK,
+            ES_EVENT_TYPE_NOTIFY_UIPC_CONNECT,
+        ];
+        let audit = Self { client, trail };
+        if unsafe { es_subscribe(client, events.as_ptr(), events.len() as u32) } != 0 {
+            return Err(ExecutionError::MonitoringError(
+                "Failed to subscribe to Endpoint Security events".to_string(),
+            )
+            .into());
+        }
+
+        Ok(audit)
+    }
+
+    /// Stop auditing and return the events recorded, in arrival order.
+    pub fn finish(self) -> Vec<AuditEvent> {
+        std::mem::take(&mut self.trail.lock().unwrap().events)
+    }
+}
+
+impl Drop for EndpointSecurityAudit {
+    fn drop(&mut self) {
+        unsafe {
+            es_delete_client(self.client);
+        }
+        trails().lock().unwrap().remove(&(self.client as usize));
+    }
+}
+
+unsafe extern "C" fn handle_message(
+    _block: *const BlockLiteral,
+    client: *mut EsClient,
+    message: *const EsMessage,
+) {
+    let Some(trail) = trails().lock().unwrap().get(&(client as usize)).cloned() else {
+        return;
+    };
+
+    let message = message as *const u8;
+    let process = read::<*const u8>(message, MESSAGE_PROCESS);
+    let pid = read::<u32>(process, PROCESS_PID);
+    let ppid = read::<u32>(process, PROCESS_PPID);
+    let event = message.add(MESSAGE_EVENT);
+
+    let mut trail = trail.lock().unwrap();
+    if !trail.pids.contains(&pid) {
+        if !trail.pids.contains(&ppid) {
+            return;
+        }
+        trail.pids.insert(pid);
+    }
+
+    let (kind, file) = match read::<u32>(message, MESSAGE_EVENT_TYPE) {
+        ES_EVENT_TYPE_NOTIFY_FORK => {
+            // es_event_fork_t.child
+            let child = read::<*const u8>(event, 0);
+            trail.pids.insert(read::<u32>(child, PROCESS_PID));
+            return;
+        }
+        ES_EVENT_TYPE_NOTIFY_EXEC => {
+            // es_event_exec_t.target
+            let target = read::<*const u8>(event, 0);
+            (
+                AuditEventKind::Exec,
+                read::<*const u8>(target, PROCESS_EXECUTABLE),
+            )
+        }
+        ES_EVENT_TYPE_NOTIFY_OPEN => (AuditEventKind::Open, read::<*const u8>(event, OPEN_FILE)),
+        // es_event_uipc_connect_t.file
+        ES_EVENT_TYPE_NOTIFY_UIPC_CONNECT => (AuditEventKind::Connect, read::<*const u8>(event, 0)),
+        _ => return,
+    };
+
+    if trail.events.len() < MAX_EVENTS {
+        trail.events.push(AuditEvent {
+            pid,
+            kind,
+            path: file_path(file),
+        });
+    }
+}
+
+unsafe fn read<T: Copy>(base: *const u8, offset: usize) -> T {
+    std::ptr::read_unaligned(base.add(offset) as *const T)
+}
+
+/// es_file_t.path, an es_string_token_t { length, data }
+unsafe fn file_path(file: *const u8) -> String {
+    if file.is_null() {
+        return String::new();
+    }
+    let length = read::<usize>(file, 0);
+    let data = read::<*const u8>(file, std::mem::size_of::<usize>());
+    if data.is_null() {
+        return String::new();
+    }
+    String::from_utf8_lossy(std::slice::from_raw_parts(data, length)).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_literal_layout() {
+        // Must match struct Block_literal_1 from the clang blocks ABI
+        assert_eq!(std::mem::size_of::<BlockLiteral>(), 32);
+        assert_eq!(DESCRIPTOR.size, 32);
+    }
+
+    #[test]
+    fn test_file_path_reads_string_token() {
+        let path = b"/tmp/input.txt";
+        let token: [usize; 2] = [path.len(), path.as_ptr() as usize];
+        let path = unsafe { file_path(token.as_ptr() as *const u8) };
+        assert_eq!(path, "/tmp/input.txt");
+    }
+}
@@ -0,0 +1,207 @@
+use super::macos::ProcessLimits;
+use crate::api::schema::ResourceLimits;
+
+const TASKPOLICY: &str = "/usr/sbin/taskpolicy";
+const SANDBOX_EXEC: &str = "/usr/bin/sandbox-exec";
+const SHELL: &str = "/bin/bash";
+
+/// Default `cpu_shares`, the same weight cgroups give an unconfigured group
+const DEFAULT_CPU_SHARES: u32 = 1024;
+
+/// QoS ceiling for a command, the closest macOS has to a lower CPU weight.
+///
+/// A clamped process and everything it spawns are scheduled behind
+/// interactive work and, at the background tier, also get throttled disk and
+/// network I/O, so a CPU-hungry command cannot make the machine unusable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QosClamp {
+    Utility,
+    Background,
+}
+
+impl QosClamp {
+    /// Clamp for `resources`: below half the default shares runs in the
+    /// background tier, anything else below the default (or a quota under
+    /// one core) at utility.
+    pub fn for_limits(resources: &ResourceLimits) -> Option<Self> {
+        if resources.cpu_shares < DEFAULT_CPU_SHARES / 2 {
+            Some(Self::Background)
+        } else if resources.cpu_shares < DEFAULT_CPU_SHARES
+            || resources.cpu_quota_percent.is_some_and(|quota| quota < 100)
+        {
+            Some(Self::Utility)
+        } else {
+            None
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Utility => "utility",
+            Self::Background => "background",
+        }
+    }
+}
+
+/// Build the argv that applies the QoS clamp, sandbox profile, rlimits and
+/// umask and then execs `command`.
+///
+/// Doing this with `pre_exec` forces std to fork the supervisor, which is
+/// slow on macOS once it has a large address space and several threads.
+/// Without `pre_exec` std launches through posix_spawn, so the setup moves
+/// into the launched program instead: `taskpolicy` sets the clamp,
+/// `sandbox-exec` enters the profile and a short bash script sets limits
+/// before `exec "$@"`. Every step is an exec, not a fork, and limits set after the profile still hold because
+/// lowering an rlimit is always permitted.
+pub fn launcher_argv(
+    qos: Option<QosClamp>,
+    profile: Option<&str>,
+    limits: Option<&ProcessLimits>,
+    umask: Option<u32>,
+    command: &[String],
+) -> Vec<String> {
+    let mut argv = Vec::new();
+
+    if let Some(qos) = qos {
+        argv.extend([
+            TASKPOLICY.to_string(),
+            "-c".to_string(),
+            qos.as_str().to_string(),
+        ]);
+    }
+
+    if let Some(profile) = profile {
+        argv.extend([
+            SANDBOX_EXEC.to_string(),
+            "-p".to_string(),
+            profile.to_string(),
+        ]);
+    }
+
+    if let Some(script) = setup_script(limits, umask) {
+        // -p: ignore BASH_ENV and exported functions from the request's environment
+        argv.extend([
+            SHELL.to_string(),
+            "-p".to_string(),
+            "-c".to_string(),
+            script,
+            "capsule-run".to_string(),
+        ]);
+    }
+
+    argv.extend(command.iter().cloned());
+    argv
+}
+
+/// Shell commands applying `limits` and `umask`, or `None` when there is nothing to apply.
+fn setup_script(limits: Option<&ProcessLimits>, umask: Option<u32>) -> Option<String> {
+    let mut steps = Vec::new();
+
+    if let Some(limits) = limits {
+        if let Some(fd_limit) = limits.max_file_descriptors {
+            steps.push(ulimit("-n", fd_limit as u64, "file descriptor"));
+        }
+        if let Some(file_size) = limits.max_file_size_bytes {
+            // bash counts in 1024-byte blocks
+            steps.push(ulimit("-f", file_size.div_ceil(1024), "file size"));
+        }
+        if let Some(proc_limit) = limits.max_processes {
+            steps.push(ulimit("-u", proc_limit as u64, "process"));
+        }
+    }
+
+    if let Some(mask) = umask {
+        steps.push(format!("umask {:03o}", mask & 0o777));
+    }
+
+    if steps.is_empty() {
+        return None;
+    }
+    steps.push("exec \"$@\"".to_string());
+    Some(steps.join("; "))
+}
+
+/// Limits are best effort, as with setrlimit before: warn and carry on.
+fn ulimit(flag: &str, value: u64, name: &str) -> String {
+    format!(
+        "ulimit {} {} 2>/dev/null || echo 'Warning: Failed to set {} limit' >&2",
+        flag, value, name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command() -> Vec<String> {
+        vec!["echo".to_string(), "hello world".to_string()]
+    }
+
+    #[test]
+    fn test_plain_command_is_unchanged() {
+        assert_eq!(launcher_argv(None, None, None, None, &command()), command());
+    }
+
+    #[test]
+    fn test_launcher_argv() {
+        let limits = ProcessLimits {
+            max_memory_bytes: None,
+            max_cpu_time_seconds: None,
+            max_file_descriptors: Some(256),
+            max_processes: None,
+            max_file_size_bytes: Some(1500),
+        };
+        let argv = launcher_argv(
+            None,
+            Some("(version 1)"),
+            Some(&limits),
+            Some(0o22),
+            &command(),
+        );
+
+        assert_eq!(&argv[..3], [SANDBOX_EXEC, "-p", "(version 1)"]);
+        assert_eq!(&argv[3..6], [SHELL, "-p", "-c"]);
+        assert!(argv[6].contains("ulimit -n 256"));
+        assert!(argv[6].contains("ulimit -f 2 "));
+        assert!(argv[6].contains("umask 022"));
+        assert!(argv[6].ends_with("exec \"$@\""));
+        assert_eq!(&argv[8..], command());
+    }
+
+    #[test]
+    fn test_launcher_runs_command() {
+        let argv = launcher_argv(None, None, None, Some(0o77), &command());
+        let output = std::process::Command::new(&argv[0])
+            .args(&argv[1..])
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hello world\n");
+    }
+
+    #[test]
+    fn test_qos_clamp_for_limits() {
+        let limits = |cpu_shares, cpu_quota_percent| ResourceLimits {
+            cpu_shares,
+            cpu_quota_percent,
+            ..Default::default()
+        };
+        assert_eq!(QosClamp::for_limits(&limits(1024, None)), None);
+        assert_eq!(QosClamp::for_limits(&limits(2048, Some(150))), None);
+        assert_eq!(
+            QosClamp::for_limits(&limits(1024, Some(50))),
+            Some(QosClamp::Utility)
+        );
+        assert_eq!(
+            QosClamp::for_limits(&limits(768, None)),
+            Some(QosClamp::Utility)
+        );
+        assert_eq!(
+            QosClamp::for_limits(&limits(256, None)),
+            Some(QosClamp::Background)
+        );
+
+        let argv = launcher_argv(Some(QosClamp::Background), None, None, None, &command());
+        assert_eq!(&argv[..3], [TASKPOLICY, "-c", "background"]);
+        assert_eq!(&argv[3..], command());
+    }
+}
@@ -0,0 +1,142 @@
+use crate::error::{CapsuleResult, SandboxError};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use uuid::Uuid;
+
+/// Base of the gids handed to network-denied commands. Far above the range
+/// Directory Services assigns, so no real group is affected by the rules.
+const NETWORK_DENY_GID_BASE: u32 = 0x7a00_0000;
+
+/// Network isolation for a command through a pf anchor, for when the sandbox
+/// profile cannot be applied (capsule-run itself running under Seatbelt).
+///
+/// The command runs with a per-execution primary gid and the anchor blocks
+/// TCP and UDP for sockets owned by that group, which pf can match where it
+/// cannot match a pid. The anchor lives under `com.apple/`, which the default
+/// /etc/pf.conf already evaluates, so pf.conf is left untouched. Loading
+/// rules and enabling pf need root.
+pub struct PfNetworkDeny {
+    anchor: String,
+    gid: u32,
+    /// Reference taken with `pfctl -E`, released on drop so pf is disabled
+    /// again only if nothing else enabled it
+    token: Option<String>,
+}
+
+impl PfNetworkDeny {
+    pub fn install(execution_id: Uuid) -> CapsuleResult<Self> {
+        if unsafe { libc::geteuid() } != 0 {
+            return Err(SandboxError::SandboxProfile(
+                "network isolation needs the sandbox profile or root for the pf fallback"
+                    .to_string(),
+            )
+            .into());
+        }
+
+        let mut deny = Self {
+            anchor: format!("com.apple/capsule-run.{}", execution_id.simple()),
+            gid: network_deny_gid(execution_id),
+            token: None,
+        };
+
+        pfctl(&["-a", &deny.anchor, "-f", "-"], Some(&deny.rules()))?;
+        let enabled = pfctl(&["-E"], None)?;
+        deny.token = parse_token(&enabled);
+
+        Ok(deny)
+    }
+
+    /// Primary gid the command must run with for the rules to apply
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    fn rules(&self) -> String {
+        format!("block drop quick proto {{ tcp udp }} group {}\n", self.gid)
+    }
+}
+
+impl Drop for PfNetworkDeny {
+    fn drop(&mut self) {
+        let _ = pfctl(&["-a", &self.anchor, "-F", "all"], None);
+        if let Some(token) = &self.token {
+            let _ = pfctl(&["-X", token], None);
+        }
+    }
+}
+
+fn network_deny_gid(execution_id: Uuid) -> u32 {
+    NETWORK_DENY_GID_BASE | (execution_id.as_u128() as u32 & 0x00ff_ffff)
+}
+
+/// pfctl prints the enable reference as "Token : <number>" on stderr.
+fn parse_token(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "Token").then(|| value.trim().to_string())
+    })
+}
+
+fn pfctl(args: &[&str], input: Option<&str>) -> CapsuleResult<String> {
+    let pf_error = |detail: String| {
+        SandboxError::SandboxProfile(format!("pfctl {}: {}", args.join(" "), detail))
+    };
+
+    let mut child = Command::new("/sbin/pfctl")
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| pf_error(e.to_string()))?;
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin
+            .write_all(input.as_bytes())
+            .map_err(|e| pf_error(e.to_string()))?;
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| pf_error(e.to_string()))?;
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    if !output.status.success() {
+        return Err(pf_error(stderr.trim().to_string()).into());
+    }
+    Ok(stderr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network_deny_gid_range() {
+        let gid = network_deny_gid(Uuid::new_v4());
+        assert!(gid >= NETWORK_DENY_GID_BASE);
+        assert!(gid < NETWORK_DENY_GID_BASE + 0x0100_0000);
+    }
+
+    #[test]
+    fn test_parse_token() {
+        let output = "No ALTQ support in kernel\npf enabled\nToken : 11834186713287417047\n";
+        assert_eq!(parse_token(output).as_deref(), Some("11834186713287417047"));
+        assert_eq!(parse_token("pf enabled\n"), None);
+    }
+
+    #[test]
+    fn test_rules_block_group() {
+        let deny = PfNetworkDeny {
+            anchor: "com.apple/capsule-run.test".to_string(),
+            gid: 42,
+            token: None,
+        };
+        assert!(deny.rules().contains("proto { tcp udp } group 42"));
+        // Nothing was loaded, so dropping must not touch the real pf state
+        std::mem::forget(deny);
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+/// CPU usage is averaged over this window before the quota is enforced, so
+/// bursts such as process startup or a short compile step are tolerated.
+const CPU_WINDOW: Duration = Duration::from_secs(5);
+/// Upper bound on descendants followed per process when walking the tree.
+const MAX_CHILDREN: usize = 1024;
+
+#[repr(C)]
+struct MachTimebaseInfo {
+    numer: u32,
+    denom: u32,
+}
+
+// libSystem; the libc crate's binding is deprecated in favour of mach2
+extern "C" {
+    fn mach_timebase_info(info: *mut MachTimebaseInfo) -> libc::c_int;
+}
+
+/// Limits enforced on a process tree by [`ResourceWatchdog`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WatchLimits {
+    pub memory_bytes: Option<u64>,
+    /// Average CPU usage allowed, in percent of one core
+    pub cpu_quota_percent: Option<u32>,
+}
+
+/// Enforces memory and CPU limits on a process tree from the supervisor.
+///
+/// XNU ignores RLIMIT_AS, and per-process jetsam limits (memorystatus) need
+/// root and only cover a single process, so the tree's physical footprint,
+/// the figure Activity Monitor shows as "Memory", is sampled instead. There
+/// is no cpu.max either: the tree's CPU time is sampled alongside and
+/// averaged over a sliding window. When either limit is exceeded every
+/// process in the tree is sent SIGKILL and the execution is reported as a
+/// memory or CPU limit kill.
+pub struct ResourceWatchdog {
+    state: Arc<WatchState>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct WatchState {
+    stop: AtomicBool,
+    memory_exceeded: AtomicBool,
+    cpu_exceeded: AtomicBool,
+    peak_bytes: AtomicU64,
+}
+
+impl ResourceWatchdog {
+    /// Watch `root_pid` and its descendants; without limits it only records the peak.
+    pub fn start(root_pid: u32, limits: WatchLimits) -> Self {
+        let state = Arc::new(WatchState::default());
+        let handle = {
+            let state = Arc::clone(&state);
+            thread::spawn(move || Self::watch(root_pid as libc::pid_t, limits, &state))
+        };
+
+        Self {
+            state,
+            handle: Some(handle),
+        }
+    }
+
+    /// Whether the tree was killed for exceeding the memory limit.
+    pub fn memory_limit_exceeded(&self) -> bool {
+        self.state.memory_exceeded.load(Ordering::Acquire)
+    }
+
+    /// Whether the tree was killed for exceeding the CPU quota.
+    pub fn cpu_limit_exceeded(&self) -> bool {
+        self.state.cpu_exceeded.load(Ordering::Acquire)
+    }
+
+    /// Highest combined footprint seen so far.
+    pub fn peak_bytes(&self) -> u64 {
+        self.state.peak_bytes.load(Ordering::Relaxed)
+    }
+
+    fn watch(root: libc::pid_t, limits: WatchLimits, state: &WatchState) {
+        let timebase = Timebase::new();
+        let mut cpu = CpuWindow::default();
+
+        while !state.stop.load(Ordering::Relaxed) {
+            let tree = process_tree(root);
+            if tree.is_empty() {
+                break;
+            }
+
+            let usage: Vec<_> = tree.iter().filter_map(|&pid| rusage(pid)).collect();
+            let footprint: u64 = usage.iter().map(|info| info.ri_phys_footprint).sum();
+            state.peak_bytes.fetch_max(footprint, Ordering::Relaxed);
+
+            // Reaped descendants are accounted in their parent's child times
+            let cpu_ticks: u64 = usage
+                .iter()
+                .map(|info| {
+                    info.ri_user_time
+                        + info.ri_system_time
+                        + info.ri_child_user_time
+                        + info.ri_child_system_time
+                })
+                .sum();
+            let cpu_percent = cpu.record(Instant::now(), timebase.nanos(cpu_ticks));
+
+            // Flags are set before killing so a reaped SIGKILL is always attributed
+            let exceeded = if limits.memory_bytes.is_some_and(|limit| footprint > limit) {
+                Some(&state.memory_exceeded)
+            } else if let (Some(quota), Some(percent)) = (limits.cpu_quota_percent, cpu_percent) {
+                (percent > quota as u64).then_some(&state.cpu_exceeded)
+            } else {
+                None
+            };
+
+            if let Some(flag) = exceeded {
+                flag.store(true, Ordering::Release);
+                for pid in tree {
+                    unsafe {
+                        libc::kill(pid, libc::SIGKILL);
+                    }
+                }
+                break;
+            }
+
+            thread::sleep(SAMPLE_INTERVAL);
+        }
+    }
+}
+
+impl Drop for ResourceWatchdog {
+    fn drop(&mut self) {
+        self.state.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Tree CPU time samples covering the last [`CPU_WINDOW`].
+#[derive(Default)]
+struct CpuWindow {
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl CpuWindow {
+    /// Record the tree's total CPU time and return its average usage in
+    /// percent of one core over the window, once a full window is covered.
+    fn record(&mut self, now: Instant, cpu_nanos: u64) -> Option<u64> {
+        // A child that exited but is not yet reaped drops out of the sum for
+        // a moment; never let the total go backwards
+        let cpu_nanos = self
+            .samples
+            .back()
+            .map_or(cpu_nanos, |&(_, last)| last.max(cpu_nanos));
+        self.samples.push_back((now, cpu_nanos));
+
+        // Keep the newest sample that is still at least a window old
+        while self
+            .samples
+            .get(1)
+            .is_some_and(|&(at, _)| now.duration_since(at) >= CPU_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+
+        let &(start, start_nanos) = self.samples.front()?;
+        let elapsed = now.duration_since(start);
+        if elapsed < CPU_WINDOW {
+            return None;
+        }
+        Some(((cpu_nanos - start_nanos) as u128 * 100 / elapsed.as_nanos()) as u64)
+    }
+}
+
+/// Converts rusage CPU times, which are in mach absolute time units
+/// (nanoseconds on Intel, 125/3 ns ticks on Apple silicon).
+struct Timebase {
+    numer: u64,
+    denom: u64,
+}
+
+impl Timebase {
+    fn new() -> Self {
+        let mut info = MachTimebaseInfo { numer: 0, denom: 0 };
+        if unsafe { mach_timebase_info(&mut info) } != 0 || info.denom == 0 {
+            return Self { numer: 1, denom: 1 };
+        }
+        Self {
+            numer: info.numer as u64,
+            denom: info.denom as u64,
+        }
+    }
+
+    fn nanos(&self, ticks: u64) -> u64 {
+        (ticks as u128 * self.numer as u128 / self.denom as u128) as u64
+    }
+}
+
+/// `root` and all of its live descendants, or empty once `root` has exited.
+fn process_tree(root: libc::pid_t) -> Vec<libc::pid_t> {
+    if rusage(root).is_none() {
+        return Vec::new();
+    }
+
+    let mut tree = vec![root];
+    let mut next = 0;
+    while next < tree.len() {
+        tree.extend(child_pids(tree[next]));
+        next += 1;
+    }
+    tree
+}
+
+fn child_pids(pid: libc::pid_t) -> Vec<libc::pid_t> {
+    let mut pids = vec![0 as libc::pid_t; MAX_CHILDREN];
+    let bytes = unsafe {
+        libc::proc_listchildpids(
+            pid,
+            pids.as_mut_ptr().cast(),
+            (pids.len() * std::mem::size_of::<libc::pid_t>()) as libc::c_int,
+        )
+    };
+    // Returns the number of pids on current releases, bytes on older ones
+    let count = (bytes.max(0) as usize).min(pids.len());
+    pids.truncate(count);
+    pids.retain(|&child| child > 0);
+    pids
+}
+
+fn rusage(pid: libc::pid_t) -> Option<libc::rusage_info_v4> {
+    let mut info: libc::rusage_info_v4 = unsafe { std::mem::zeroed() };
+    let result = unsafe {
+        libc::proc_pid_rusage(
+            pid,
+            libc::RUSAGE_INFO_V4,
+            &mut info as *mut libc::rusage_info_v4 as *mut libc::rusage_info_t,
+        )
+    };
+    (result == 0).then_some(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn test_watchdog_kills_tree_over_limit() {
+        // Holds ~64 MiB resident until killed
+        let mut child = std::process::Command::new("/usr/bin/perl")
+            .args(["-e", "$x = 'a' x (64 * 1024 * 1024); sleep 30"])
+            .spawn()
+            .unwrap();
+
+        let limits = WatchLimits {
+            memory_bytes: Some(16 * 1024 * 1024),
+            ..Default::default()
+        };
+        let watchdog = ResourceWatchdog::start(child.id(), limits);
+        let status = child.wait().unwrap();
+
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+        assert!(watchdog.memory_limit_exceeded());
+        assert!(!watchdog.cpu_limit_exceeded());
+        assert!(watchdog.peak_bytes() > 16 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_watchdog_kills_tree_over_cpu_quota() {
+        let mut child = std::process::Command::new("/usr/bin/perl")
+            .args(["-e", "1 while 1"])
+            .spawn()
+            .unwrap();
+
+        let limits = WatchLimits {
+            cpu_quota_percent: Some(20),
+            ..Default::default()
+        };
+        let watchdog = ResourceWatchdog::start(child.id(), limits);
+        let status = child.wait().unwrap();
+
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+        assert!(watchdog.cpu_limit_exceeded());
+    }
+
+    #[test]
+    fn test_watchdog_records_peak_without_limit() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("0.2")
+            .spawn()
+            .unwrap();
+
+        let watchdog = ResourceWatchdog::start(child.id(), WatchLimits::default());
+        assert!(child.wait().unwrap().success());
+        assert!(!watchdog.memory_limit_exceeded());
+    }
+
+    #[test]
+    fn test_cpu_window_average() {
+        let mut window = CpuWindow::default();
+        let start = Instant::now();
+        assert_eq!(window.record(start, 0), None);
+
+        // Half a core for the first window
+        let second = start + CPU_WINDOW;
+        assert_eq!(
+            window.record(second, CPU_WINDOW.as_nanos() as u64 / 2),
+            Some(50)
+        );
+
+        // A dip from an unreaped child does not count as negative usage
+        let third = second + CPU_WINDOW;
+        assert_eq!(window.record(third, 0), Some(0));
+    }
+}
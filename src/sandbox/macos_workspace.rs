@@ -0,0 +1,252 @@
+use crate::api::schema::BindMount;
+use crate::error::{CapsuleResult, SandboxError};
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use uuid::Uuid;
+
+/// Don't let clonefile follow a symlink at the source.
+const CLONE_NOFOLLOW: u32 = 0x0001;
+
+/// Per-execution directory standing in for the mount namespace on macOS.
+///
+/// There is no chroot or bind mount without root, so each bind mount
+/// destination is recreated under the workspace root instead: writable
+/// mounts become symlinks to the source, and read-only mounts become APFS
+/// clones (copy-on-write, so cheap) whose edits never reach the source. When
+/// the source is on a volume that cannot clone, a symlink is used and the
+/// sandbox profile alone keeps it read-only.
+///
+/// Sources are canonicalized first, so paths that reach the host through
+/// symlinks, /tmp or an App Translocation mount are granted by their real
+/// location, which is what Seatbelt matches.
+pub struct MacOSWorkspace {
+    root: PathBuf,
+    mounts: Vec<EmulatedMount>,
+}
+
+/// A bind mount recreated under the workspace root.
+#[derive(Debug)]
+pub struct EmulatedMount {
+    pub destination: PathBuf,
+    pub target: PathBuf,
+    /// Host path the command reaches through `target`, if it is a symlink
+    pub linked_source: Option<PathBuf>,
+    pub readonly: bool,
+}
+
+impl MacOSWorkspace {
+    pub fn create(execution_id: Uuid, bind_mounts: &[BindMount]) -> CapsuleResult<Self> {
+        let tmp = fs::canonicalize(std::env::temp_dir()).map_err(|e| {
+            SandboxError::FilesystemSetup(format!("Failed to resolve temp directory: {}", e))
+        })?;
+        let root = tmp.join(format!("capsule-{}", execution_id));
+        create_dir(&root)?;
+
+        let mut workspace = Self {
+            root,
+            mounts: Vec::new(),
+        };
+        for bind_mount in bind_mounts {
+            let mount = workspace.emulate(bind_mount)?;
+            workspace.mounts.push(mount);
+        }
+
+        Ok(workspace)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn mounts(&self) -> &[EmulatedMount] {
+        &self.mounts
+    }
+
+    /// Host path for `path` as the command would see it under Linux isolation.
+    ///
+    /// Paths under a bind mount destination map into the workspace; other
+    /// existing host paths are used as they are, and anything else (such as
+    /// the default /workspace) is created inside the workspace root.
+    pub fn resolve(&self, path: &str) -> CapsuleResult<PathBuf> {
+        let path = Path::new(path);
+        // Longest destination first so nested mounts win
+        let mount = self
+            .mounts
+            .iter()
+            .filter(|m| path.starts_with(&m.destination))
+            .max_by_key(|m| m.destination.as_os_str().len());
+        if let Some(mount) = mount {
+            let rest = path.strip_prefix(&mount.destination).unwrap_or(path);
+            return Ok(mount.target.join(rest));
+        }
+
+        if path.exists() {
+            return Ok(path.to_path_buf());
+        }
+
+        let target = self.root.join(relative(path)?);
+        create_dir(&target)?;
+        Ok(target)
+    }
+
+    fn emulate(&self, bind_mount: &BindMount) -> CapsuleResult<EmulatedMount> {
+        let source = fs::canonicalize(&bind_mount.source).map_err(|e| {
+            SandboxError::FilesystemSetup(format!(
+                "Bind mount source {} is not accessible: {}",
+                bind_mount.source, e
+            ))
+        })?;
+        let destination = PathBuf::from(&bind_mount.destination);
+        let target = self.root.join(relative(&destination)?);
+        if let Some(parent) = target.parent() {
+            create_dir(parent)?;
+        }
+
+        let cloned = bind_mount.readonly && clone(&source, &target);
+        if !cloned {
+            std::os::unix::fs::symlink(&source, &target).map_err(|e| {
+                SandboxError::FilesystemSetup(format!(
+                    "Failed to link {} to {}: {}",
+                    target.display(),
+                    source.display(),
+                    e
+                ))
+            })?;
+        }
+
+        Ok(EmulatedMount {
+            destination,
+            target,
+            linked_source: (!cloned).then_some(source),
+            readonly: bind_mount.readonly,
+        })
+    }
+
+    pub fn cleanup(&self) -> CapsuleResult<()> {
+        // remove_dir_all unlinks symlinks without following them
+        if self.root.exists() {
+            fs::remove_dir_all(&self.root).map_err(|e| {
+                SandboxError::FilesystemSetup(format!(
+                    "Failed to cleanup workspace {}: {}",
+                    self.root.display(),
+                    e
+                ))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MacOSWorkspace {
+    fn drop(&mut self) {
+        let _ = self.cleanup();
+    }
+}
+
+/// `path` relative to /, rejecting anything that could escape the workspace root.
+fn relative(path: &Path) -> CapsuleResult<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::RootDir | Component::CurDir => {}
+            Component::Normal(part) => relative.push(part),
+            _ => {
+                return Err(SandboxError::FilesystemSetup(format!(
+                    "Path {} must be absolute and free of '..'",
+                    path.display()
+                ))
+                .into())
+            }
+        }
+    }
+    Ok(relative)
+}
+
+fn create_dir(path: &Path) -> CapsuleResult<()> {
+    fs::create_dir_all(path).map_err(|e| {
+        SandboxError::FilesystemSetup(format!(
+            "Failed to create directory {}: {}",
+            path.display(),
+            e
+        ))
+        .into()
+    })
+}
+
+/// APFS copy-on-write clone of a file or directory tree.
+fn clone(source: &Path, target: &Path) -> bool {
+    let (Ok(source), Ok(target)) = (
+        CString::new(source.as_os_str().as_bytes()),
+        CString::new(target.as_os_str().as_bytes()),
+    ) else {
+        return false;
+    };
+    unsafe { libc::clonefile(source.as_ptr(), target.as_ptr(), CLONE_NOFOLLOW) == 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bind(source: &Path, destination: &str, readonly: bool) -> BindMount {
+        BindMount {
+            source: source.to_string_lossy().into_owned(),
+            destination: destination.to_string(),
+            readonly,
+        }
+    }
+
+    #[test]
+    fn test_emulated_mounts() {
+        let source = std::env::temp_dir().join(format!("capsule-src-{}", Uuid::new_v4()));
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("input.txt"), "data").unwrap();
+
+        let workspace = MacOSWorkspace::create(
+            Uuid::new_v4(),
+            &[
+                bind(&source, "/workspace", false),
+                bind(&source, "/data/ro", true),
+            ],
+        )
+        .unwrap();
+
+        let writable = workspace.resolve("/workspace/input.txt").unwrap();
+        assert_eq!(fs::read_to_string(&writable).unwrap(), "data");
+        assert!(workspace.mounts()[0].linked_source.is_some());
+
+        // Edits to a cloned read-only mount stay in the workspace
+        let readonly = workspace.resolve("/data/ro/input.txt").unwrap();
+        if workspace.mounts()[1].linked_source.is_none() {
+            fs::write(&readonly, "changed").unwrap();
+            assert_eq!(
+                fs::read_to_string(source.join("input.txt")).unwrap(),
+                "data"
+            );
+        }
+
+        let root = workspace.root().to_path_buf();
+        drop(workspace);
+        assert!(!root.exists());
+        assert!(source.join("input.txt").exists());
+        let _ = fs::remove_dir_all(&source);
+    }
+
+    #[test]
+    fn test_resolve_unmounted_paths() {
+        let workspace = MacOSWorkspace::create(Uuid::new_v4(), &[]).unwrap();
+        assert_eq!(workspace.resolve("/usr").unwrap(), PathBuf::from("/usr"));
+
+        let created = workspace.resolve("/capsule-nonexistent/work").unwrap();
+        assert!(created.starts_with(workspace.root()));
+        assert!(created.is_dir());
+    }
+
+    #[test]
+    fn test_relative_rejects_parent() {
+        assert!(relative(Path::new("/a/../b")).is_err());
+        assert_eq!(relative(Path::new("/a/b")).unwrap(), PathBuf::from("a/b"));
+    }
+}
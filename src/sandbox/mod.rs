@@ -1,15 +1,66 @@
+pub mod archive;
+pub mod artifacts;
+pub mod cache;
+pub mod capabilities;
 #[cfg(target_os = "linux")]
 pub mod cgroups;
+pub mod changes;
+pub mod degradation;
+#[cfg(target_os = "linux")]
+pub mod devices;
+#[cfg(target_os = "linux")]
+pub mod dns;
+pub mod doctor;
+#[cfg(target_os = "linux")]
+pub mod events;
+pub mod executable;
 #[cfg(target_os = "linux")]
 pub mod filesystem;
 #[cfg(target_os = "linux")]
+pub mod fuse;
+#[cfg(target_os = "linux")]
+pub mod gc;
+pub mod inputs;
+#[cfg(target_os = "linux")]
 pub mod namespaces;
+#[cfg(target_os = "linux")]
+pub mod network;
+pub mod plan;
+#[cfg(target_os = "linux")]
+pub mod process;
+#[cfg(target_os = "linux")]
+pub mod quota;
+pub mod registry;
 #[cfg(all(target_os = "linux", feature = "seccomp"))]
 pub mod seccomp;
+#[cfg(all(target_os = "linux", feature = "seccomp"))]
+pub mod seccomp_audit;
+#[cfg(all(target_os = "linux", feature = "seccomp"))]
+#[allow(dead_code)] // Library API; the CLI has no way to supply a policy
+pub mod seccomp_notify;
+#[cfg(all(target_os = "linux", feature = "seccomp"))]
+pub mod seccomp_profile;
+pub mod source;
+#[cfg(all(target_os = "linux", feature = "seccomp"))]
+pub mod syscall_recorder;
+pub mod trace;
 
 #[cfg(target_os = "macos")]
 pub mod macos;
+#[cfg(all(target_os = "macos", feature = "endpoint-security"))]
+pub mod macos_audit;
+#[cfg(target_os = "macos")]
+pub mod macos_launch;
+#[cfg(target_os = "macos")]
+pub mod macos_network;
+#[cfg(target_os = "macos")]
+pub mod macos_watchdog;
+#[cfg(target_os = "macos")]
+pub mod macos_workspace;
 
+use crate::api::schema::{
+    AuditEvent, DegradedFeature, DeniedSyscall, ResourceEnforcement, SandboxReport,
+};
 #[cfg(any(target_os = "linux", target_os = "macos"))]
 use crate::api::schema::{IsolationConfig, ResourceLimits};
 #[cfg(any(target_os = "linux", target_os = "macos"))]
@@ -19,14 +70,27 @@ use crate::error::SandboxError;
 #[cfg(any(target_os = "linux", target_os = "macos"))]
 use uuid::Uuid;
 
+pub use degradation::Degradations;
+
 #[cfg(target_os = "linux")]
 pub use cgroups::{CgroupManager, ResourceUsage};
 #[cfg(target_os = "linux")]
+pub use dns::DnsStub;
+#[cfg(target_os = "linux")]
+pub use events::{CgroupEventWatcher, LimitEvent};
+#[cfg(target_os = "linux")]
 pub use filesystem::FilesystemManager;
 #[cfg(target_os = "linux")]
 pub use namespaces::NamespaceManager;
+#[cfg(target_os = "linux")]
+pub use network::BridgeNetwork;
+#[cfg(target_os = "linux")]
+pub use process::{ChildSetup, RlimitFallback};
 #[cfg(all(target_os = "linux", feature = "seccomp"))]
 pub use seccomp::SeccompFilter;
+#[cfg(all(target_os = "linux", feature = "seccomp"))]
+#[allow(unused_imports)] // Library API; the CLI has no way to supply a policy
+pub use seccomp_notify::{SyscallDecision, SyscallNotification, SyscallPolicy};
 
 #[cfg(target_os = "macos")]
 pub use macos::{MacOSSandbox, ResourceUsage};
@@ -46,7 +110,7 @@ pub struct SeccompFilter;
 pub struct FilesystemManager;
 
 #[cfg(not(any(target_os = "linux", target_os = "macos")))]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct ResourceUsage {
     pub memory_bytes: u64,
     pub cpu_time_us: u64,
@@ -54,6 +118,24 @@ pub struct ResourceUsage {
     pub kernel_time_us: u64,
     pub io_bytes_read: u64,
     pub io_bytes_written: u64,
+    pub cpu_throttled_us: u64,
+    pub disk_bytes: u64,
+    pub major_faults: u64,
+    pub minor_faults: u64,
+    pub voluntary_ctx_switches: u64,
+    pub involuntary_ctx_switches: u64,
+    pub pids_peak: u64,
+}
+
+/// Traffic of a bridged execution, as seen from the host end of its veth pair.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkUsage {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    /// With `isolation.track_destinations`
+    pub destinations: Option<Vec<crate::api::schema::NetworkDestination>>,
 }
 
 #[cfg(target_os = "linux")]
@@ -61,10 +143,38 @@ pub struct Sandbox {
     #[allow(dead_code)] // Used for future tracking and debugging features
     pub execution_id: Uuid,
     pub namespace_manager: NamespaceManager,
-    pub cgroup_manager: CgroupManager,
+    /// `None` when cgroups v2 is unavailable or could not be set up
+    pub cgroup_manager: Option<CgroupManager>,
     pub filesystem_manager: FilesystemManager,
     #[cfg(feature = "seccomp")]
     pub seccomp_filter: SeccompFilter,
+    #[cfg(feature = "seccomp")]
+    seccomp_mode: crate::api::schema::SeccompMode,
+    #[cfg(feature = "seccomp")]
+    seccomp_audit: std::sync::Mutex<Option<seccomp_audit::SeccompAuditLog>>,
+    #[cfg(feature = "seccomp")]
+    syscall_policy: Option<std::sync::Arc<dyn SyscallPolicy>>,
+    /// The checked copy of `isolation.seccomp_profile`
+    #[cfg(feature = "seccomp")]
+    seccomp_profile: Option<seccomp_profile::OciSeccompProfile>,
+    /// Compiled in `setup` and installed in the child right before exec
+    #[cfg(feature = "seccomp")]
+    seccomp_program: Option<std::sync::Arc<seccomp::CompiledFilter>>,
+    #[cfg(feature = "seccomp")]
+    notify_channel: std::sync::Mutex<Option<std::sync::Arc<seccomp_notify::NotifyChannel>>>,
+    #[cfg(feature = "seccomp")]
+    seccomp_notifier: std::sync::Mutex<Option<seccomp_notify::SeccompNotifier>>,
+    pub child_setup: ChildSetup,
+    enforcement: ResourceEnforcement,
+    degradations: Degradations,
+    /// Set in `network_mode` bridge
+    bridge_network: Option<BridgeNetwork>,
+    /// Set when bridged with `dns.allowed_domains`
+    dns_stub: Option<DnsStub>,
+    /// `isolation.caches`, locked until the sandbox is dropped
+    caches: Vec<cache::CacheDir>,
+    /// `isolation.fuse_mounts`; dropping them stops the daemons
+    fuse_sessions: Vec<fuse::FuseSession>,
 }
 
 #[cfg(target_os = "macos")]
@@ -72,6 +182,8 @@ pub struct Sandbox {
     #[allow(dead_code)] // Used for future tracking and debugging features
     pub execution_id: Uuid,
     pub macos_sandbox: MacOSSandbox,
+    /// `isolation.caches`, locked until the sandbox is dropped
+    caches: Vec<cache::CacheDir>,
 }
 
 #[cfg(not(any(target_os = "linux", target_os = "macos")))]
@@ -84,7 +196,8 @@ pub struct Sandbox {
 impl Sandbox {
     pub fn new(execution_id: Uuid) -> CapsuleResult<Self> {
         let namespace_manager = NamespaceManager::new();
-        let cgroup_manager = CgroupManager::new(execution_id)?;
+        // A missing cgroup2 mount is handled in setup by falling back to rlimits
+        let cgroup_manager = CgroupManager::new(execution_id).ok();
         let filesystem_manager = FilesystemManager::new(execution_id)?;
         #[cfg(feature = "seccomp")]
         let seccomp_filter = SeccompFilter::new()?;
@@ -96,6 +209,27 @@ impl Sandbox {
             filesystem_manager,
             #[cfg(feature = "seccomp")]
             seccomp_filter,
+            #[cfg(feature = "seccomp")]
+            seccomp_mode: crate::api::schema::SeccompMode::Kill,
+            #[cfg(feature = "seccomp")]
+            seccomp_audit: std::sync::Mutex::new(None),
+            #[cfg(feature = "seccomp")]
+            syscall_policy: None,
+            #[cfg(feature = "seccomp")]
+            seccomp_profile: None,
+            #[cfg(feature = "seccomp")]
+            seccomp_program: None,
+            #[cfg(feature = "seccomp")]
+            notify_channel: std::sync::Mutex::new(None),
+            #[cfg(feature = "seccomp")]
+            seccomp_notifier: std::sync::Mutex::new(None),
+            child_setup: ChildSetup::default(),
+            enforcement: ResourceEnforcement::None,
+            degradations: Degradations::default(),
+            bridge_network: None,
+            dns_stub: None,
+            caches: Vec::new(),
+            fuse_sessions: Vec::new(),
         })
     }
 
@@ -103,36 +237,314 @@ impl Sandbox {
         &mut self,
         resources: &ResourceLimits,
         isolation: &IsolationConfig,
+        timeout_ms: u64,
+        cache_scope: &cache::CacheScope,
     ) -> CapsuleResult<()> {
+        self.degradations = Degradations::new(isolation.enforcement);
+        self.caches = isolation
+            .caches
+            .iter()
+            .map(|cache| cache::CacheDir::acquire(cache, cache_scope))
+            .collect::<CapsuleResult<_>>()?;
+        for cache in &mut self.caches {
+            if let Err(e) = cache.apply_quota() {
+                self.degradations.record(
+                    "cache_size",
+                    format!("{}; caches are trimmed to size only before each run", e),
+                )?;
+            }
+        }
+        let isolation = &cache::with_cache_mounts(isolation, &self.caches);
+        trace::step("disk_quota", String::new, || {
+            self.filesystem_manager
+                .apply_disk_quota(isolation, resources.disk_bytes)
+        })?;
+
+        // The bridge's namespace is joined before the others are unshared,
+        // while capsule-run still has privileges over the host network
+        if isolation.network && isolation.network_mode == crate::api::schema::NetworkMode::Bridge {
+            let bridge = trace::step("network", String::new, || {
+                BridgeNetwork::setup(self.execution_id, isolation, resources.network_bps)
+            })?;
+            let mut dns_stub = self.connect_dns_stub(isolation)?;
+            bridge.enter()?;
+            if let Some(stub) = dns_stub.as_mut() {
+                stub.listen()?;
+            }
+            self.bridge_network = Some(bridge);
+            self.dns_stub = dns_stub;
+        }
+
         // Stage 1: Setup privileged operations
-        self.namespace_manager.setup_namespaces(isolation.network)?;
-        self.cgroup_manager.setup(resources)?;
+        trace::step("unshare", String::new, || {
+            self.namespace_manager.setup_namespaces(isolation)
+        })?;
+        self.child_setup = ChildSetup::from_config(resources, isolation)?;
+        self.setup_abstract_socket_scope(isolation)?;
+        // Opened before the cgroup's device allowlist could refuse it
+        self.fuse_sessions = isolation
+            .fuse_mounts
+            .iter()
+            .map(fuse::FuseSession::open)
+            .collect::<CapsuleResult<_>>()?;
+        let workspace_source = self
+            .filesystem_manager
+            .workspace_source(isolation, resources.disk_bytes);
+        trace::step("cgroup", String::new, || {
+            self.setup_resource_limits(resources, workspace_source.as_deref(), timeout_ms)
+        })?;
+        trace::step("devices", String::new, || {
+            self.setup_device_allowlist(isolation)
+        })?;
+
+        // Setup seccomp filter; a custom profile is read from the host, so
+        // this has to happen before the filesystem is pivoted
+        trace::step("seccomp", String::new, || self.setup_seccomp(isolation))?;
 
         // Setup filesystem isolation
-        self.filesystem_manager.setup_isolation(isolation)?;
+        trace::step("filesystem", String::new, || {
+            self.filesystem_manager
+                .setup_isolation(isolation, resources.disk_bytes)
+        })?;
+        for session in &self.fuse_sessions {
+            session.mount()?;
+        }
 
-        // Setup seccomp filter
-        #[cfg(feature = "seccomp")]
-        self.seccomp_filter.setup_allowlist()?;
+        // Stage 2: Enter namespace and apply security restrictions
+        trace::step(
+            "enter_namespaces",
+            String::new,
+            NamespaceManager::enter_namespaces,
+        )?;
+
+        // Drop capabilities
+        trace::step("drop_capabilities", String::new, || {
+            self.drop_capabilities()
+        })?;
 
+        // Compile the seccomp filter; only the command is filtered, in
+        // prepare_command, so the supervisor keeps working
         #[cfg(feature = "seccomp")]
+        {
+            let program = trace::step("seccomp_compile", String::new, || {
+                self.seccomp_filter.compile()
+            })?;
+            self.seccomp_program = Some(std::sync::Arc::new(program));
+        }
+
+        // Threads do not survive the fork in enter_namespaces
+        if let Some(stub) = self.dns_stub.as_mut() {
+            stub.start()?;
+        }
+
+        self.start_fuse_daemons()
+    }
+
+    /// Daemons run like the command, under its seccomp filter and limits
+    fn start_fuse_daemons(&mut self) -> CapsuleResult<()> {
+        let mut sessions = std::mem::take(&mut self.fuse_sessions);
+        for session in &mut sessions {
+            let mut cmd = session.command()?;
+            self.prepare_helper(&mut cmd)?;
+            session.start(cmd)?;
+        }
+        self.fuse_sessions = sessions;
+        Ok(())
+    }
+
+    /// Abstract Unix sockets belong to a network namespace, so they only need
+    /// blocking when the command shares the host's.
+    fn setup_abstract_socket_scope(&mut self, isolation: &IsolationConfig) -> CapsuleResult<()> {
+        if !isolation.block_abstract_sockets || !isolation.network || self.bridge_network.is_some()
+        {
+            return Ok(());
+        }
+
+        match capabilities::landlock_abi() {
+            Some(abi) if abi >= 6 => {
+                self.child_setup.scope_abstract_sockets = true;
+                Ok(())
+            }
+            abi => self.degradations.record(
+                "abstract_socket_block",
+                format!(
+                    "Landlock ABI 6 is required to scope abstract Unix sockets (found {}); \
+                     host services on abstract sockets are reachable",
+                    abi.map_or("none".to_string(), |abi| abi.to_string())
+                ),
+            ),
+        }
+    }
+
+    /// Where /workspace is reachable from the supervisor; `setup` has
+    /// already pivoted into the sandbox root.
+    pub fn workspace_path(&self) -> CapsuleResult<std::path::PathBuf> {
+        Ok(std::path::PathBuf::from("/workspace"))
+    }
+
+    /// Upstream side of the DNS stub, connected from the host's network
+    /// namespace to the first configured or host nameserver.
+    fn connect_dns_stub(&self, isolation: &IsolationConfig) -> CapsuleResult<Option<DnsStub>> {
+        let Some(dns) = isolation
+            .dns
+            .as_ref()
+            .filter(|dns| !dns.allowed_domains.is_empty())
+        else {
+            return Ok(None);
+        };
+
+        let upstream = match dns.nameservers.first() {
+            Some(nameserver) => *nameserver,
+            None => std::fs::read_to_string("/etc/resolv.conf")
+                .ok()
+                .and_then(|conf| dns::first_nameserver(&conf))
+                .ok_or_else(|| {
+                    SandboxError::NetworkSetup(
+                        "No nameserver for the DNS stub to forward to".to_string(),
+                    )
+                })?,
+        };
+        DnsStub::connect(upstream, &dns.allowed_domains).map(Some)
+    }
+
+    /// Decide syscalls listed in `isolation.notify_syscalls` with `policy` at runtime
+    #[cfg(feature = "seccomp")]
+    pub fn set_syscall_policy(&mut self, policy: std::sync::Arc<dyn SyscallPolicy>) {
+        self.syscall_policy = Some(policy);
+    }
+
+    /// Install `profile` for `isolation.seccomp_profile`; the sandbox never
+    /// reads the file itself, so what was checked is what runs
+    #[cfg(feature = "seccomp")]
+    pub fn set_seccomp_profile(&mut self, profile: seccomp_profile::OciSeccompProfile) {
+        self.seccomp_profile = Some(profile);
+    }
+
+    #[cfg(feature = "seccomp")]
+    fn setup_seccomp(&mut self, isolation: &IsolationConfig) -> CapsuleResult<()> {
+        self.seccomp_mode = isolation.seccomp_mode;
+
+        if !isolation.notify_syscalls.is_empty() && self.syscall_policy.is_none() {
+            return Err(SandboxError::SeccompSetup(
+                "notify_syscalls requires a syscall policy on the executor".to_string(),
+            )
+            .into());
+        }
+
+        // The listener only reaches the supervisor once exec has succeeded
+        if let Some(name) = isolation
+            .notify_syscalls
+            .iter()
+            .find(|name| matches!(name.as_str(), "execve" | "execveat"))
+        {
+            return Err(SandboxError::SeccompSetup(format!(
+                "{} cannot be in notify_syscalls: the command would block before it starts",
+                name
+            ))
+            .into());
+        }
+
+        if let Some(path) = &isolation.seccomp_profile {
+            let profile = self.seccomp_profile.as_ref().ok_or_else(|| {
+                SandboxError::SeccompSetup(format!(
+                    "Seccomp profile {} was not loaded and checked by the executor",
+                    path
+                ))
+            })?;
+            self.seccomp_filter = SeccompFilter::from_oci_profile(
+                profile,
+                isolation.seccomp_mode,
+                &isolation.notify_syscalls,
+            )?;
+            return Ok(());
+        }
+
+        let mut filter = SeccompFilter::with_mode(isolation.seccomp_mode)?;
+        filter.route_to_supervisor(&isolation.notify_syscalls)?;
+        filter.setup_allowlist(isolation.seccomp, &isolation.extra_syscalls)?;
+
         if isolation.network {
-            // Replace the existing filter with one that has network access
-            let mut new_filter = SeccompFilter::new()?;
-            new_filter.setup_allowlist()?;
-            self.seccomp_filter = new_filter.with_network_access()?;
+            filter = filter.with_network_access()?;
         }
 
-        // Stage 2: Enter namespace and apply security restrictions
-        NamespaceManager::enter_namespaces()?;
+        self.seccomp_filter = filter;
+        Ok(())
+    }
 
-        // Drop capabilities
-        self.drop_capabilities()?;
+    #[cfg(not(feature = "seccomp"))]
+    fn setup_seccomp(&mut self, isolation: &IsolationConfig) -> CapsuleResult<()> {
+        if isolation.seccomp_profile.is_some()
+            || isolation.seccomp != crate::api::schema::SeccompPreset::Default
+            || !isolation.extra_syscalls.is_empty()
+            || isolation.seccomp_mode != crate::api::schema::SeccompMode::Kill
+            || !isolation.notify_syscalls.is_empty()
+        {
+            return Err(SandboxError::SeccompSetup(
+                "seccomp presets, profiles, modes and notifications require a build with the seccomp feature"
+                    .to_string(),
+            )
+            .into());
+        }
+        self.degradations.record(
+            "seccomp",
+            "capsule-run was built without the seccomp feature",
+        )
+    }
 
-        // Apply seccomp filter (must be last)
-        #[cfg(feature = "seccomp")]
-        self.seccomp_filter.apply()?;
+    fn setup_resource_limits(
+        &mut self,
+        resources: &ResourceLimits,
+        workspace_source: Option<&std::path::Path>,
+        timeout_ms: u64,
+    ) -> CapsuleResult<()> {
+        let result = match self.cgroup_manager.as_mut() {
+            Some(manager) => manager.setup(resources, workspace_source, &mut self.degradations),
+            None => Err(SandboxError::CgroupSetup("cgroups v2 not mounted".to_string()).into()),
+        };
+
+        match result {
+            Ok(()) => self.enforcement = ResourceEnforcement::Cgroup,
+            // Strict enforcement already failed inside the cgroup setup
+            Err(
+                e @ crate::error::CapsuleError::SandboxSetup(SandboxError::IsolationUnavailable {
+                    ..
+                }),
+            ) => return Err(e),
+            Err(e) => {
+                // Dropping the manager removes any partially created cgroup
+                self.cgroup_manager = None;
+                self.degradations.record(
+                    "cgroup_limits",
+                    format!("{}; enforcing resource limits with rlimits instead", e),
+                )?;
+                self.child_setup.rlimits = Some(RlimitFallback::new(resources, timeout_ms));
+                self.enforcement = ResourceEnforcement::Rlimit;
+            }
+        }
+        Ok(())
+    }
 
+    /// Requested devices are bind mounted from the host, so the cgroup is
+    /// what keeps the command to those devices.
+    fn setup_device_allowlist(&mut self, isolation: &IsolationConfig) -> CapsuleResult<()> {
+        if isolation.devices.is_empty() {
+            return Ok(());
+        }
+        let devices = isolation
+            .devices
+            .iter()
+            .map(|path| devices::Device::lookup(path))
+            .collect::<CapsuleResult<Vec<_>>>()?;
+        let result = match &self.cgroup_manager {
+            Some(manager) => manager.restrict_devices(&devices),
+            None => Err(SandboxError::CgroupSetup("no cgroup to attach to".to_string()).into()),
+        };
+        if let Err(e) = result {
+            self.degradations.record(
+                "device_cgroup",
+                format!("{}; device access is limited only by the nodes mounted", e),
+            )?;
+        }
         Ok(())
     }
 
@@ -156,15 +568,241 @@ impl Sandbox {
     }
 
     pub fn get_resource_usage(&self) -> CapsuleResult<ResourceUsage> {
-        self.cgroup_manager.get_usage()
+        let children = process::children_usage();
+        let mut usage = match &self.cgroup_manager {
+            Some(manager) => manager.get_usage()?,
+            None => children.clone(),
+        };
+        // Context switches are only accounted per process, via wait4/getrusage
+        usage.voluntary_ctx_switches = children.voluntary_ctx_switches;
+        usage.involuntary_ctx_switches = children.involuntary_ctx_switches;
+        usage.disk_bytes = self.filesystem_manager.disk_usage();
+        Ok(usage)
+    }
+
+    /// Exact peak memory of the execution, when the kernel tracks it.
+    ///
+    /// Prefers the cgroup's memory.peak; otherwise falls back to the largest
+    /// max RSS among reaped children, which misses memory shared across
+    /// processes but still catches spikes shorter than the sampling interval.
+    pub fn peak_memory(&self) -> Option<u64> {
+        self.cgroup_manager
+            .as_ref()
+            .and_then(|manager| manager.get_memory_peak())
+            .or_else(|| Some(process::children_usage().memory_bytes).filter(|&peak| peak > 0))
     }
 
     pub fn check_oom_killed(&self) -> CapsuleResult<bool> {
-        self.cgroup_manager.check_oom_killed()
+        match &self.cgroup_manager {
+            Some(manager) => manager.check_oom_killed(),
+            None => Ok(false),
+        }
+    }
+
+    /// cpu.max throttles instead of killing, so a CPU quota never ends an execution
+    pub fn check_cpu_limit_exceeded(&self) -> bool {
+        false
+    }
+
+    pub fn enforcement(&self) -> ResourceEnforcement {
+        self.enforcement
+    }
+
+    /// Isolation mechanisms applied by `setup`
+    pub fn report(&self) -> SandboxReport {
+        #[cfg(feature = "seccomp")]
+        let (seccomp, seccomp_mode) = match &self.seccomp_program {
+            Some(_) => (true, Some(self.seccomp_mode)),
+            None => (false, None),
+        };
+        #[cfg(not(feature = "seccomp"))]
+        let (seccomp, seccomp_mode) = (false, None);
+
+        let mut namespaces: Vec<String> = self
+            .namespace_manager
+            .created()
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        if self.bridge_network.is_some() {
+            namespaces.push("network".to_string());
+        }
+
+        SandboxReport {
+            backend: "linux".to_string(),
+            namespaces,
+            cgroup_controllers: self
+                .cgroup_manager
+                .as_ref()
+                .map(CgroupManager::controllers)
+                .unwrap_or_default(),
+            seccomp,
+            seccomp_mode,
+            macos_profile: None,
+            pf_network_deny: false,
+            bridge_address: self.bridge_network.as_ref().map(BridgeNetwork::address),
+            resource_enforcement: self.enforcement,
+        }
+    }
+
+    /// Watcher for OOM kills and pid-limit hits, when limits are enforced by a cgroup
+    pub fn watch_limit_events(&self) -> Option<CgroupEventWatcher> {
+        let manager = self.cgroup_manager.as_ref()?;
+        match manager.watch_events() {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                tracing::warn!(error = %e, "Falling back to polling for OOM kills");
+                None
+            }
+        }
+    }
+
+    /// Command for `argv`; per-process setup is added by `prepare_command`
+    pub fn command(&self, argv: &[String]) -> std::process::Command {
+        let mut cmd = std::process::Command::new(&argv[0]);
+        cmd.args(&argv[1..]);
+        cmd
+    }
+
+    /// Prepare a command for execution with per-process settings (umask,
+    /// rlimits, affinity) and the seccomp filter
+    pub fn prepare_command(&self, cmd: &mut std::process::Command) -> CapsuleResult<()> {
+        self.child_setup.prepare_command(cmd);
+        #[cfg(feature = "seccomp")]
+        self.prepare_seccomp(cmd, true)?;
+        Ok(())
+    }
+
+    /// `prepare_command` for a helper such as a FUSE daemon. Nobody answers
+    /// its notified syscalls, so with the listener closed they fail with
+    /// ENOSYS instead of blocking.
+    fn prepare_helper(&self, cmd: &mut std::process::Command) -> CapsuleResult<()> {
+        self.child_setup.prepare_command(cmd);
+        #[cfg(feature = "seccomp")]
+        self.prepare_seccomp(cmd, false)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "seccomp")]
+    fn prepare_seccomp(&self, cmd: &mut std::process::Command, notify: bool) -> CapsuleResult<()> {
+        use std::os::unix::process::CommandExt;
+
+        let Some(program) = &self.seccomp_program else {
+            return Ok(());
+        };
+        let program = std::sync::Arc::clone(program);
+        let channel = if notify && program.notifies() {
+            Some(std::sync::Arc::new(seccomp_notify::NotifyChannel::new()?))
+        } else {
+            None
+        };
+
+        let child_channel = channel.clone();
+        // Registered after the ChildSetup hook, so the filter is installed last
+        unsafe {
+            cmd.pre_exec(move || {
+                if let Some(listener) = program.install()? {
+                    if let Some(channel) = &child_channel {
+                        channel.send(listener)?;
+                    }
+                    libc::close(listener);
+                }
+                Ok(())
+            });
+        }
+
+        if notify {
+            if let Ok(mut slot) = self.notify_channel.lock() {
+                *slot = channel;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start answering notified syscalls for the command just spawned.
+    pub fn start_syscall_policy(&self) -> CapsuleResult<()> {
+        #[cfg(feature = "seccomp")]
+        {
+            let Some(policy) = &self.syscall_policy else {
+                return Ok(());
+            };
+            let Some(channel) = self
+                .notify_channel
+                .lock()
+                .ok()
+                .and_then(|mut slot| slot.take())
+            else {
+                return Ok(());
+            };
+
+            let notifier = seccomp_notify::SeccompNotifier::start(
+                channel.receive()?,
+                std::sync::Arc::clone(policy),
+            )?;
+            if let Ok(mut slot) = self.seccomp_notifier.lock() {
+                *slot = Some(notifier);
+            }
+        }
+        Ok(())
+    }
+
+    /// The cgroup already covers the command's process tree; nothing to start
+    pub fn track_process(&self, _pid: u32) {}
+
+    /// Start recording syscalls the filter denies to the process tree rooted at `pid`.
+    ///
+    /// Only active in seccomp `log` and `errno` mode; in `kill` mode the first
+    /// denied syscall ends the process anyway.
+    pub fn watch_denied_syscalls(&self, pid: u32) {
+        #[cfg(feature = "seccomp")]
+        if self.seccomp_mode != crate::api::schema::SeccompMode::Kill {
+            match seccomp_audit::SeccompAuditLog::start(pid) {
+                Ok(audit) => {
+                    if let Ok(mut slot) = self.seccomp_audit.lock() {
+                        *slot = Some(audit);
+                    }
+                }
+                Err(e) => tracing::warn!(error = %e, "Denied syscalls will not be reported"),
+            }
+        }
+        #[cfg(not(feature = "seccomp"))]
+        let _ = pid;
+    }
+
+    /// Requested features that were not applied, under best-effort enforcement
+    pub fn degraded_features(&self) -> Vec<DegradedFeature> {
+        self.degradations.features().to_vec()
+    }
+
+    /// Traffic over the bridge network, in `network_mode` bridge
+    pub fn network_usage(&self) -> Option<NetworkUsage> {
+        self.bridge_network.as_ref().and_then(BridgeNetwork::usage)
+    }
+
+    /// Syscalls denied since `watch_denied_syscalls`, or `None` when not recording.
+    pub fn denied_syscalls(&self) -> Option<Vec<DeniedSyscall>> {
+        #[cfg(feature = "seccomp")]
+        {
+            let audit = self.seccomp_audit.lock().ok()?.take()?;
+            Some(audit.finish())
+        }
+        #[cfg(not(feature = "seccomp"))]
+        None
+    }
+
+    /// Endpoint Security is macOS-only; see `denied_syscalls` for the Linux audit
+    pub fn audit_trail(&self) -> Option<Vec<AuditEvent>> {
+        None
     }
 
     pub fn cleanup(&self) -> CapsuleResult<()> {
-        self.cgroup_manager.cleanup()?;
+        #[cfg(feature = "seccomp")]
+        if let Ok(mut notifier) = self.seccomp_notifier.lock() {
+            notifier.take();
+        }
+        if let Some(manager) = &self.cgroup_manager {
+            manager.cleanup()?;
+        }
         self.filesystem_manager.cleanup()?;
         Ok(())
     }
@@ -177,6 +815,7 @@ impl Sandbox {
         Ok(Self {
             execution_id,
             macos_sandbox,
+            caches: Vec::new(),
         })
     }
 
@@ -184,8 +823,23 @@ impl Sandbox {
         &mut self,
         resources: &ResourceLimits,
         isolation: &IsolationConfig,
+        _timeout_ms: u64,
+        cache_scope: &cache::CacheScope,
     ) -> CapsuleResult<()> {
-        self.macos_sandbox.setup(resources, isolation)
+        self.caches = isolation
+            .caches
+            .iter()
+            .map(|cache| cache::CacheDir::acquire(cache, cache_scope))
+            .collect::<CapsuleResult<_>>()?;
+        let isolation = &cache::with_cache_mounts(isolation, &self.caches);
+        trace::step("seatbelt", String::new, || {
+            self.macos_sandbox.setup(resources, isolation)
+        })
+    }
+
+    /// Host directory standing in for /workspace
+    pub fn workspace_path(&self) -> CapsuleResult<std::path::PathBuf> {
+        self.macos_sandbox.workspace_path()
     }
 
     pub fn get_resource_usage(&self) -> CapsuleResult<ResourceUsage> {
@@ -196,14 +850,70 @@ impl Sandbox {
         self.macos_sandbox.check_oom_killed()
     }
 
+    /// Whether the watchdog killed the process tree for exceeding the CPU quota
+    pub fn check_cpu_limit_exceeded(&self) -> bool {
+        self.macos_sandbox.check_cpu_limit_exceeded()
+    }
+
+    /// Peak footprint of the process tree as sampled by the memory watchdog
+    pub fn peak_memory(&self) -> Option<u64> {
+        self.macos_sandbox.peak_memory()
+    }
+
     pub fn cleanup(&self) -> CapsuleResult<()> {
         self.macos_sandbox.cleanup()
     }
 
+    pub fn enforcement(&self) -> ResourceEnforcement {
+        ResourceEnforcement::Rlimit
+    }
+
+    /// Isolation mechanisms applied by `setup`
+    pub fn report(&self) -> SandboxReport {
+        self.macos_sandbox.report()
+    }
+
+    /// Command for `argv`, wrapped in the launcher that applies the profile and limits
+    pub fn command(&self, argv: &[String]) -> std::process::Command {
+        self.macos_sandbox.command(argv)
+    }
+
     /// Prepare a command for execution with macOS sandbox restrictions
     pub fn prepare_command(&self, cmd: &mut std::process::Command) -> CapsuleResult<()> {
         self.macos_sandbox.prepare_command(cmd)
     }
+
+    /// Enforce the memory limit and CPU quota on the spawned command and its descendants
+    pub fn track_process(&self, pid: u32) {
+        self.macos_sandbox.track_process(pid);
+    }
+
+    /// seccomp is Linux-only; nothing is recorded
+    pub fn watch_denied_syscalls(&self, _pid: u32) {}
+
+    /// seccomp is Linux-only; there is no syscall policy to run
+    pub fn start_syscall_policy(&self) -> CapsuleResult<()> {
+        Ok(())
+    }
+
+    pub fn denied_syscalls(&self) -> Option<Vec<DeniedSyscall>> {
+        None
+    }
+
+    /// Requested features that were not applied, under best-effort enforcement
+    pub fn degraded_features(&self) -> Vec<DegradedFeature> {
+        self.macos_sandbox.degraded_features()
+    }
+
+    /// Bridge networking is Linux-only
+    pub fn network_usage(&self) -> Option<NetworkUsage> {
+        None
+    }
+
+    /// Files and sockets the process tree touched, with the `endpoint-security` feature
+    pub fn audit_trail(&self) -> Option<Vec<AuditEvent>> {
+        self.macos_sandbox.audit_trail()
+    }
 }
 
 #[cfg(not(any(target_os = "linux", target_os = "macos")))]
@@ -216,6 +926,8 @@ impl Sandbox {
         &mut self,
         _resources: &crate::api::ResourceLimits,
         _isolation: &crate::api::IsolationConfig,
+        _timeout_ms: u64,
+        _cache_scope: &cache::CacheScope,
     ) -> crate::error::CapsuleResult<()> {
         Err(crate::error::CapsuleError::Config(
             "Sandbox functionality is only available on Linux and macOS".to_string(),
@@ -223,20 +935,67 @@ impl Sandbox {
     }
 
     pub fn get_resource_usage(&self) -> crate::error::CapsuleResult<ResourceUsage> {
-        Ok(ResourceUsage {
-            memory_bytes: 0,
-            cpu_time_us: 0,
-            user_time_us: 0,
-            kernel_time_us: 0,
-            io_bytes_read: 0,
-            io_bytes_written: 0,
-        })
+        Ok(ResourceUsage::default())
     }
 
     pub fn check_oom_killed(&self) -> crate::error::CapsuleResult<bool> {
         Ok(false)
     }
 
+    pub fn check_cpu_limit_exceeded(&self) -> bool {
+        false
+    }
+
+    pub fn peak_memory(&self) -> Option<u64> {
+        None
+    }
+
+    pub fn enforcement(&self) -> ResourceEnforcement {
+        ResourceEnforcement::None
+    }
+
+    pub fn report(&self) -> SandboxReport {
+        SandboxReport {
+            backend: "none".to_string(),
+            namespaces: Vec::new(),
+            cgroup_controllers: Vec::new(),
+            seccomp: false,
+            seccomp_mode: None,
+            macos_profile: None,
+            pf_network_deny: false,
+            bridge_address: None,
+            resource_enforcement: ResourceEnforcement::None,
+        }
+    }
+
+    pub fn denied_syscalls(&self) -> Option<Vec<DeniedSyscall>> {
+        None
+    }
+
+    pub fn degraded_features(&self) -> Vec<DegradedFeature> {
+        Vec::new()
+    }
+
+    pub fn network_usage(&self) -> Option<NetworkUsage> {
+        None
+    }
+
+    pub fn audit_trail(&self) -> Option<Vec<AuditEvent>> {
+        None
+    }
+
+    pub fn workspace_path(&self) -> crate::error::CapsuleResult<std::path::PathBuf> {
+        Err(crate::error::CapsuleError::Config(
+            "Sandbox functionality is only available on Linux and macOS".to_string(),
+        ))
+    }
+
+    pub fn command(&self, argv: &[String]) -> std::process::Command {
+        let mut cmd = std::process::Command::new(&argv[0]);
+        cmd.args(&argv[1..]);
+        cmd
+    }
+
     #[allow(dead_code)]
     pub fn cleanup(&self) -> crate::error::CapsuleResult<()> {
         Ok(())
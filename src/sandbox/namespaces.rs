@@ -1,4 +1,6 @@
 #[cfg(target_os = "linux")]
+use crate::api::schema::{IsolationConfig, TimeOffset};
+#[cfg(target_os = "linux")]
 use crate::error::{CapsuleResult, SandboxError};
 #[cfg(target_os = "linux")]
 use nix::sched::{unshare, CloneFlags};
@@ -11,6 +13,8 @@ use std::os::unix::fs::OpenOptionsExt;
 pub struct NamespaceManager {
     uid: Uid,
     gid: Gid,
+    /// Namespaces created by `setup_namespaces`
+    created: Vec<&'static str>,
 }
 
 impl NamespaceManager {
@@ -18,19 +22,15 @@ impl NamespaceManager {
         Self {
             uid: getuid(),
             gid: getgid(),
+            created: Vec::new(),
         }
     }
 
-    pub fn setup_namespaces(&self, enable_network: bool) -> CapsuleResult<()> {
-        let mut flags = CloneFlags::CLONE_NEWUSER
-            | CloneFlags::CLONE_NEWPID
-            | CloneFlags::CLONE_NEWNS
-            | CloneFlags::CLONE_NEWIPC
-            | CloneFlags::CLONE_NEWUTS;
-
-        if !enable_network {
-            flags |= CloneFlags::CLONE_NEWNET;
-        }
+    pub fn setup_namespaces(&mut self, isolation: &IsolationConfig) -> CapsuleResult<()> {
+        let namespaces = Self::namespaces_for(isolation);
+        let flags = namespaces
+            .iter()
+            .fold(CloneFlags::empty(), |flags, (flag, _)| flags | *flag);
 
         unshare(flags).map_err(|e| {
             let error_msg = match e {
@@ -50,8 +50,65 @@ impl NamespaceManager {
             }
         })?;
 
+        self.created = namespaces.into_iter().map(|(_, name)| name).collect();
         self.setup_user_namespace()?;
 
+        if let Some(time_offset) = &isolation.time_offset {
+            Self::write_time_offsets(time_offset)?;
+        }
+
+        Ok(())
+    }
+
+    /// Names of the namespaces created, in the order they were requested.
+    pub fn created(&self) -> &[&'static str] {
+        &self.created
+    }
+
+    fn namespaces_for(isolation: &IsolationConfig) -> Vec<(CloneFlags, &'static str)> {
+        let mut namespaces = vec![
+            (CloneFlags::CLONE_NEWUSER, "user"),
+            (CloneFlags::CLONE_NEWPID, "pid"),
+            (CloneFlags::CLONE_NEWNS, "mount"),
+            (CloneFlags::CLONE_NEWUTS, "uts"),
+        ];
+
+        if !isolation.host_ipc {
+            namespaces.push((CloneFlags::CLONE_NEWIPC, "ipc"));
+        }
+
+        if !isolation.network {
+            namespaces.push((CloneFlags::CLONE_NEWNET, "network"));
+        }
+
+        // nix has no constant for the time namespace (Linux 5.6+)
+        if isolation.time_offset.is_some() {
+            namespaces.push((CloneFlags::from_bits_retain(libc::CLONE_NEWTIME), "time"));
+        }
+
+        namespaces
+    }
+
+    fn write_time_offsets(time_offset: &TimeOffset) -> CapsuleResult<()> {
+        // Offsets can only be written before the first process enters the
+        // time namespace, which happens on the fork in enter_namespaces().
+        let offsets = format!(
+            "monotonic {} 0\nboottime {} 0\n",
+            time_offset.monotonic_secs, time_offset.boottime_secs
+        );
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open("/proc/self/timens_offsets")
+            .map_err(|e| SandboxError::NamespaceCreation {
+                namespace: format!("time (failed to open timens_offsets: {})", e),
+            })?;
+
+        file.write_all(offsets.as_bytes())
+            .map_err(|e| SandboxError::NamespaceCreation {
+                namespace: format!("time (failed to write clock offsets: {})", e),
+            })?;
+
         Ok(())
     }
 
@@ -178,8 +235,42 @@ mod tests {
     #[test]
     fn test_namespace_manager_creation() {
         let manager = NamespaceManager::new();
-        assert!(manager.uid.as_raw() >= 0);
-        assert!(manager.gid.as_raw() >= 0);
+        assert_eq!(manager.uid, getuid());
+        assert_eq!(manager.gid, getgid());
+    }
+
+    #[test]
+    fn test_time_namespace_flag() {
+        let flags = CloneFlags::from_bits_retain(libc::CLONE_NEWTIME);
+        assert!(!flags.is_empty());
+        assert!(!flags.intersects(CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNS));
+    }
+
+    #[test]
+    fn test_namespaces_for_isolation() {
+        let names = |isolation: &IsolationConfig| -> Vec<&str> {
+            NamespaceManager::namespaces_for(isolation)
+                .into_iter()
+                .map(|(_, name)| name)
+                .collect()
+        };
+
+        let isolation = IsolationConfig {
+            network: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&isolation),
+            ["user", "pid", "mount", "uts", "ipc", "network"]
+        );
+
+        let isolation = IsolationConfig {
+            network: true,
+            host_ipc: true,
+            time_offset: Some(TimeOffset::default()),
+            ..Default::default()
+        };
+        assert_eq!(names(&isolation), ["user", "pid", "mount", "uts", "time"]);
     }
 
     #[test]
@@ -0,0 +1,731 @@
+use crate::api::schema::{EgressDestination, EgressRule, IsolationConfig, NetworkDestination};
+use crate::error::{CapsuleResult, SandboxError};
+use crate::sandbox::NetworkUsage;
+use nix::fcntl::{Flock, FlockArg};
+use nix::sched::{setns, CloneFlags};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, Ipv4Addr, ToSocketAddrs};
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Host bridge every bridged execution is attached to.
+pub const BRIDGE_NAME: &str = "capsule0";
+/// Gateway address of the bridge; executions get addresses from the rest of the /16.
+pub const BRIDGE_GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 88, 0, 1);
+const BRIDGE_PREFIX_LEN: u8 = 16;
+/// Host offsets handed to executions, after the network and gateway addresses
+const FIRST_HOST: u32 = 2;
+const LAST_HOST: u32 = 0xfffe;
+/// One lock file per address in use, held with flock for the life of the
+/// supervisor so an address is released even if capsule-run is killed
+const ADDRESS_LOCK_DIR: &str = "/run/capsule-run/addresses";
+
+/// Routable networking for one execution: a veth pair between a private
+/// network namespace and the `capsule0` bridge, with NAT to the outside.
+///
+/// The namespace is created on the host side with iproute2 and joined with
+/// setns before the other namespaces are unshared, because once capsule-run
+/// is in its own user namespace it has no privileges over the host network.
+/// The namespace name is removed as soon as it has been joined, so the veth
+/// pair disappears with the last process inside it and nothing needs to be
+/// torn down. Needs root.
+pub struct BridgeNetwork {
+    netns: String,
+    host_veth: String,
+    address: Ipv4Addr,
+    /// Held for the life of the execution; dropping it frees the address
+    _address_lock: Flock<File>,
+    /// Opened up front, since /sys is gone once the filesystem is pivoted
+    counters: [File; 4],
+    /// Privileged helper in the namespace that dumps conntrack on request,
+    /// with `track_destinations`
+    conntrack: Mutex<Option<Child>>,
+}
+
+impl BridgeNetwork {
+    pub fn setup(
+        execution_id: Uuid,
+        isolation: &IsolationConfig,
+        network_bps: Option<u64>,
+    ) -> CapsuleResult<Self> {
+        if unsafe { libc::geteuid() } != 0 {
+            return Err(SandboxError::NetworkSetup(
+                "bridge networking needs root to create veth pairs and NAT rules".to_string(),
+            )
+            .into());
+        }
+
+        ensure_bridge()?;
+        let (address, address_lock) = allocate_address(execution_id)?;
+
+        let id = execution_id.simple().to_string();
+        let netns = format!("capsule-{}", id);
+        let host_veth = veth_name(execution_id);
+        let prefix = format!("{}/{}", address, BRIDGE_PREFIX_LEN);
+
+        ip(&["netns", "add", &netns])?;
+        let configured = (|| {
+            ip(&[
+                "link", "add", &host_veth, "type", "veth", "peer", "name", "eth0", "netns", &netns,
+            ])?;
+            ip(&["link", "set", &host_veth, "master", BRIDGE_NAME, "up"])?;
+            ip(&["-n", &netns, "link", "set", "lo", "up"])?;
+            ip(&["-n", &netns, "addr", "add", &prefix, "dev", "eth0"])?;
+            ip(&["-n", &netns, "link", "set", "eth0", "up"])?;
+            ip(&[
+                "-n",
+                &netns,
+                "route",
+                "add",
+                "default",
+                "via",
+                &BRIDGE_GATEWAY.to_string(),
+            ])?;
+            if let Some(bytes_per_sec) = network_bps {
+                limit_bandwidth(&netns, &host_veth, bytes_per_sec)?;
+            }
+            apply_egress_rules(&netns, isolation, &direct_resolvers(isolation))
+        })();
+        if let Err(e) = configured {
+            // Deleting the namespace also removes a half-configured veth pair
+            let _ = ip(&["netns", "del", &netns]);
+            return Err(e);
+        }
+
+        let counter = |name: &str| {
+            File::open(format!("/sys/class/net/{}/statistics/{}", host_veth, name)).map_err(|e| {
+                SandboxError::NetworkSetup(format!("Failed to open {} counter: {}", name, e))
+            })
+        };
+        let conntrack = if isolation.track_destinations {
+            Some(start_conntrack_helper(&netns, isolation)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            counters: [
+                counter("rx_bytes")?,
+                counter("tx_bytes")?,
+                counter("rx_packets")?,
+                counter("tx_packets")?,
+            ],
+            conntrack: Mutex::new(conntrack),
+            netns,
+            host_veth,
+            address,
+            _address_lock: address_lock,
+        })
+    }
+
+    /// Move the calling thread into the execution's network namespace.
+    pub fn enter(&self) -> CapsuleResult<()> {
+        let path = format!("/run/netns/{}", self.netns);
+        let netns = File::open(&path)
+            .map_err(|e| SandboxError::NetworkSetup(format!("Failed to open {}: {}", path, e)))?;
+        setns(&netns, CloneFlags::CLONE_NEWNET).map_err(|e| {
+            SandboxError::NetworkSetup(format!("Failed to join network namespace: {}", e))
+        })?;
+
+        // Joined; the namespace now lives exactly as long as its processes
+        let _ = ip(&["netns", "del", &self.netns]);
+        Ok(())
+    }
+
+    pub fn address(&self) -> Ipv4Addr {
+        self.address
+    }
+
+    /// Traffic of the execution so far. The destination summary can only be
+    /// collected once, so call this after the command has finished.
+    ///
+    /// Counters are read from the host end of the pair, where the
+    /// execution's sends are received and vice versa.
+    pub fn usage(&self) -> Option<NetworkUsage> {
+        let [host_rx_bytes, host_tx_bytes, host_rx_packets, host_tx_packets] = &self.counters;
+        let destinations = self
+            .conntrack
+            .lock()
+            .unwrap()
+            .take()
+            .map(|helper| parse_conntrack(&dump_conntrack(helper)));
+
+        Some(NetworkUsage {
+            rx_bytes: read_counter(host_tx_bytes)?,
+            tx_bytes: read_counter(host_rx_bytes)?,
+            rx_packets: read_counter(host_tx_packets)?,
+            tx_packets: read_counter(host_rx_packets)?,
+            destinations,
+        })
+    }
+}
+
+impl Drop for BridgeNetwork {
+    fn drop(&mut self) {
+        // Only succeeds from the host namespace; otherwise the pair goes away
+        // with the namespace
+        let _ = Command::new("ip")
+            .args(["link", "del", &self.host_veth])
+            .stderr(Stdio::null())
+            .status();
+    }
+}
+
+/// Create the bridge and the NAT and forwarding rules if they are missing.
+fn ensure_bridge() -> CapsuleResult<()> {
+    let subnet = format!("{}/{}", subnet_base(), BRIDGE_PREFIX_LEN);
+
+    if !std::path::Path::new("/sys/class/net")
+        .join(BRIDGE_NAME)
+        .exists()
+    {
+        ip(&["link", "add", BRIDGE_NAME, "type", "bridge"])?;
+        ip(&[
+            "addr",
+            "add",
+            &format!("{}/{}", BRIDGE_GATEWAY, BRIDGE_PREFIX_LEN),
+            "dev",
+            BRIDGE_NAME,
+        ])?;
+        ip(&["link", "set", BRIDGE_NAME, "up"])?;
+    }
+
+    fs::write("/proc/sys/net/ipv4/ip_forward", "1").map_err(|e| {
+        SandboxError::NetworkSetup(format!("Failed to enable IPv4 forwarding: {}", e))
+    })?;
+
+    ensure_iptables_rule(&[
+        "-t",
+        "nat",
+        "POSTROUTING",
+        "-s",
+        &subnet,
+        "!",
+        "-o",
+        BRIDGE_NAME,
+        "-j",
+        "MASQUERADE",
+    ])?;
+    // Hosts running Docker default FORWARD to DROP
+    ensure_iptables_rule(&["FORWARD", "-i", BRIDGE_NAME, "-j", "ACCEPT"])?;
+    ensure_iptables_rule(&[
+        "FORWARD",
+        "-o",
+        BRIDGE_NAME,
+        "-m",
+        "conntrack",
+        "--ctstate",
+        "RELATED,ESTABLISHED",
+        "-j",
+        "ACCEPT",
+    ])
+}
+
+/// Append `rule` (an optional `-t table`, the chain, then the match) unless it exists.
+fn ensure_iptables_rule(rule: &[&str]) -> CapsuleResult<()> {
+    let (table, rest) = match rule {
+        ["-t", table, rest @ ..] => (Some(*table), rest),
+        rest => (None, rest),
+    };
+    let args = |action: &'static str| {
+        let mut args = Vec::new();
+        if let Some(table) = table {
+            args.extend(["-t", table]);
+        }
+        args.push(action);
+        args.extend(rest);
+        args
+    };
+
+    if run("iptables", &args("-C")).is_ok() {
+        return Ok(());
+    }
+    run("iptables", &args("-A")).map(|_| ())
+}
+
+/// Nameservers the sandbox's resolv.conf sends queries to directly: the
+/// configured ones, or else the host's. None with a filtering stub, since
+/// names can then only be resolved through it.
+fn direct_resolvers(isolation: &IsolationConfig) -> Vec<IpAddr> {
+    match &isolation.dns {
+        Some(dns) if !dns.allowed_domains.is_empty() => Vec::new(),
+        Some(dns) if !dns.nameservers.is_empty() => dns.nameservers.clone(),
+        _ => fs::read_to_string("/etc/resolv.conf")
+            .map(|conf| super::dns::nameservers(&conf))
+            .unwrap_or_default(),
+    }
+}
+
+/// With a `network_policy` or a `proxy`, install an nftables ruleset in the
+/// namespace that rejects all output except loopback, replies, the allowed
+/// destinations, the proxy and DNS to `resolvers`.
+fn apply_egress_rules(
+    netns: &str,
+    isolation: &IsolationConfig,
+    resolvers: &[IpAddr],
+) -> CapsuleResult<String> {
+    let proxy = isolation
+        .proxy
+        .as_ref()
+        .map(|proxy| proxy.endpoint().map(|endpoint| (proxy, endpoint)))
+        .transpose()
+        .map_err(SandboxError::NetworkSetup)?;
+    if isolation.network_policy.is_none() && proxy.is_none() {
+        return Ok(String::new());
+    }
+
+    let mut rules = Vec::new();
+    for rule in isolation
+        .network_policy
+        .iter()
+        .flat_map(|policy| &policy.allow)
+    {
+        rules.push(
+            rule.parse::<EgressRule>()
+                .map_err(SandboxError::NetworkSetup)?,
+        );
+    }
+    rules.extend(proxy.as_ref().map(|(_, endpoint)| endpoint.clone()));
+
+    let mut allowed = Vec::new();
+    for rule in &rules {
+        for (address, prefix) in resolve(&rule.destination)? {
+            allowed.push((address, prefix, rule.port));
+        }
+    }
+
+    let mut ruleset = egress_ruleset(&allowed, resolvers);
+    if let Some((_, endpoint)) = proxy.filter(|(proxy, _)| proxy.intercept) {
+        let (address, _) = resolve(&endpoint.destination)?[0];
+        ruleset.push_str(&intercept_ruleset(address, endpoint.port.unwrap_or(80)));
+    }
+
+    run_with_input("ip", &["netns", "exec", netns, "nft", "-f", "-"], &ruleset)
+}
+
+/// Send web traffic to the proxy whatever its destination. NAT output runs
+/// before the filter, which then sees the proxy as the destination.
+fn intercept_ruleset(proxy: Ipv4Addr, port: u16) -> String {
+    format!(
+        "table ip capsule_proxy {{\n\
+         \tchain output {{\n\
+         \t\ttype nat hook output priority -100;\n\
+         \t\tip daddr != {proxy} tcp dport {{ 80, 443 }} dnat to {proxy}:{port}\n\
+         \t}}\n\
+         }}\n",
+    )
+}
+
+/// Addresses a destination stands for, looking hostnames up on the host.
+fn resolve(destination: &EgressDestination) -> CapsuleResult<Vec<(Ipv4Addr, u8)>> {
+    match destination {
+        EgressDestination::Network(address, prefix) => Ok(vec![(*address, *prefix)]),
+        EgressDestination::Host(host) => {
+            let addresses: Vec<_> = (host.as_str(), 0)
+                .to_socket_addrs()
+                .map_err(|e| {
+                    SandboxError::NetworkSetup(format!("Failed to resolve {}: {}", host, e))
+                })?
+                .filter_map(|address| match address.ip() {
+                    std::net::IpAddr::V4(address) => Some((address, 32)),
+                    std::net::IpAddr::V6(_) => None,
+                })
+                .collect();
+            if addresses.is_empty() {
+                return Err(
+                    SandboxError::NetworkSetup(format!("{} has no IPv4 addresses", host)).into(),
+                );
+            }
+            Ok(addresses)
+        }
+    }
+}
+
+fn egress_ruleset(allowed: &[(Ipv4Addr, u8, Option<u16>)], resolvers: &[IpAddr]) -> String {
+    let mut rules = String::from(
+        "table inet capsule_egress {\n\
+         \tchain output {\n\
+         \t\ttype filter hook output priority 0; policy drop;\n\
+         \t\toifname \"lo\" accept\n\
+         \t\tct state established,related accept\n",
+    );
+    // Port 53 only to the resolvers, or it would carry anything anywhere
+    for (family, ipv6) in [("ip", false), ("ip6", true)] {
+        let addresses: Vec<String> = resolvers
+            .iter()
+            .filter(|address| address.is_ipv6() == ipv6)
+            .map(IpAddr::to_string)
+            .collect();
+        if !addresses.is_empty() {
+            rules.push_str(&format!(
+                "\t\t{} daddr {{ {} }} meta l4proto {{ tcp, udp }} th dport 53 accept\n",
+                family,
+                addresses.join(", ")
+            ));
+        }
+    }
+    for (address, prefix, port) in allowed {
+        rules.push_str(&format!("\t\tip daddr {}/{}", address, prefix));
+        if let Some(port) = port {
+            rules.push_str(&format!(" meta l4proto {{ tcp, udp }} th dport {}", port));
+        }
+        rules.push_str(" accept\n");
+    }
+    // Fail connections straight away rather than letting them time out
+    rules.push_str("\t\treject with icmpx type admin-prohibited\n\t}\n}\n");
+    rules
+}
+
+/// Shape both directions with a token bucket: traffic to the execution
+/// leaves through the host end of the pair, its own through `eth0`.
+fn limit_bandwidth(netns: &str, host_veth: &str, bytes_per_sec: u64) -> CapsuleResult<()> {
+    let tbf = tbf_args(bytes_per_sec);
+    let tbf: Vec<&str> = tbf.iter().map(String::as_str).collect();
+
+    let mut host = vec!["qdisc", "add", "dev", host_veth, "root"];
+    host.extend(&tbf);
+    run("tc", &host)?;
+
+    let mut sandbox = vec![
+        "netns", "exec", netns, "tc", "qdisc", "add", "dev", "eth0", "root",
+    ];
+    sandbox.extend(&tbf);
+    ip(&sandbox).map(|_| ())
+}
+
+fn tbf_args(bytes_per_sec: u64) -> Vec<String> {
+    // A tenth of a second's worth, and at least a few full-size packets
+    let burst = (bytes_per_sec / 10).max(16 * 1024);
+    vec![
+        "tbf".to_string(),
+        "rate".to_string(),
+        format!("{}bit", bytes_per_sec * 8),
+        "burst".to_string(),
+        burst.to_string(),
+        "latency".to_string(),
+        "50ms".to_string(),
+    ]
+}
+
+/// Turn on conntrack accounting in the namespace and start a helper that
+/// dumps the table once a line is written to it. The helper keeps root in
+/// the host's user namespace, which the sandboxed supervisor gives up.
+fn start_conntrack_helper(netns: &str, isolation: &IsolationConfig) -> CapsuleResult<Child> {
+    // Connections are only tracked once a ruleset uses conntrack
+    if isolation.network_policy.is_none() && isolation.proxy.is_none() {
+        run_with_input(
+            "ip",
+            &["netns", "exec", netns, "nft", "-f", "-"],
+            "table inet capsule_accounting {\n\
+             \tchain output {\n\
+             \t\ttype filter hook output priority 0;\n\
+             \t\tct state new accept\n\
+             \t}\n\
+             }\n",
+        )?;
+    }
+    ip(&[
+        "netns",
+        "exec",
+        netns,
+        "sysctl",
+        "-qw",
+        "net.netfilter.nf_conntrack_acct=1",
+    ])?;
+
+    Command::new("ip")
+        .args([
+            "netns",
+            "exec",
+            netns,
+            "sh",
+            "-c",
+            "read request && exec conntrack -L -o extended",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| {
+            SandboxError::NetworkSetup(format!("Failed to start conntrack helper: {}", e)).into()
+        })
+}
+
+fn dump_conntrack(mut helper: Child) -> String {
+    let mut dump = String::new();
+    if let (Some(mut stdin), Some(mut stdout)) = (helper.stdin.take(), helper.stdout.take()) {
+        if stdin.write_all(b"dump\n").is_ok() {
+            let _ = stdout.read_to_string(&mut dump);
+        }
+    }
+    dump
+}
+
+/// Aggregate `conntrack -L -o extended` lines per protocol, destination and port.
+fn parse_conntrack(dump: &str) -> Vec<NetworkDestination> {
+    let mut destinations: BTreeMap<(String, IpAddr, Option<u16>), NetworkDestination> =
+        BTreeMap::new();
+
+    for line in dump.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let Some(protocol) = fields.get(2) else {
+            continue;
+        };
+        // Each key appears for the original direction, then for the reply
+        let values = |key: &str| -> Vec<&str> {
+            fields
+                .iter()
+                .filter_map(|field| field.strip_prefix(key))
+                .collect()
+        };
+        let Some(address) = values("dst=")
+            .first()
+            .and_then(|a| a.parse::<IpAddr>().ok())
+        else {
+            continue;
+        };
+        if address.is_loopback() {
+            continue;
+        }
+        let port = values("dport=").first().and_then(|port| port.parse().ok());
+        let bytes: Vec<u64> = values("bytes=")
+            .iter()
+            .filter_map(|b| b.parse().ok())
+            .collect();
+
+        let destination = destinations
+            .entry((protocol.to_string(), address, port))
+            .or_insert_with(|| NetworkDestination {
+                protocol: protocol.to_string(),
+                address,
+                port,
+                connections: 0,
+                bytes_sent: 0,
+                bytes_received: 0,
+            });
+        destination.connections += 1;
+        destination.bytes_sent += bytes.first().copied().unwrap_or(0);
+        destination.bytes_received += bytes.get(1).copied().unwrap_or(0);
+    }
+
+    destinations.into_values().collect()
+}
+
+/// Claim a free address, starting from one derived from the execution id.
+fn allocate_address(execution_id: Uuid) -> CapsuleResult<(Ipv4Addr, Flock<File>)> {
+    fs::create_dir_all(ADDRESS_LOCK_DIR).map_err(|e| {
+        SandboxError::NetworkSetup(format!("Failed to create {}: {}", ADDRESS_LOCK_DIR, e))
+    })?;
+
+    let hosts = LAST_HOST - FIRST_HOST + 1;
+    let start = (execution_id.as_u128() % hosts as u128) as u32;
+    for offset in 0..hosts {
+        let address = host_address(FIRST_HOST + (start + offset) % hosts);
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(format!("{}/{}", ADDRESS_LOCK_DIR, address))
+            .map_err(|e| {
+                SandboxError::NetworkSetup(format!("Failed to open address lock: {}", e))
+            })?;
+        if let Ok(lock) = Flock::lock(lock, FlockArg::LockExclusiveNonblock) {
+            return Ok((address, lock));
+        }
+    }
+
+    Err(SandboxError::NetworkSetup(format!("No free addresses left on {}", BRIDGE_NAME)).into())
+}
+
+fn subnet_base() -> Ipv4Addr {
+    host_address(0)
+}
+
+fn host_address(host: u32) -> Ipv4Addr {
+    let network = u32::from(BRIDGE_GATEWAY) & !((1 << (32 - BRIDGE_PREFIX_LEN)) - 1);
+    Ipv4Addr::from(network | host)
+}
+
+/// Host-side interface name; IFNAMSIZ leaves room for 15 characters.
+fn veth_name(execution_id: Uuid) -> String {
+    format!("vcap{}", &execution_id.simple().to_string()[..8])
+}
+
+fn read_counter(file: &File) -> Option<u64> {
+    // sysfs regenerates the value on every read from offset 0
+    let mut file = file;
+    file.seek(SeekFrom::Start(0)).ok()?;
+    let mut value = String::new();
+    file.read_to_string(&mut value).ok()?;
+    value.trim().parse().ok()
+}
+
+fn ip(args: &[&str]) -> CapsuleResult<String> {
+    run("ip", args)
+}
+
+fn run(program: &str, args: &[&str]) -> CapsuleResult<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| SandboxError::NetworkSetup(format!("Failed to run {}: {}", program, e)))?;
+    if !output.status.success() {
+        return Err(SandboxError::NetworkSetup(format!(
+            "{} {}: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn run_with_input(program: &str, args: &[&str], input: &str) -> CapsuleResult<String> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| SandboxError::NetworkSetup(format!("Failed to run {}: {}", program, e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes()).map_err(|e| {
+            SandboxError::NetworkSetup(format!("Failed to write to {}: {}", program, e))
+        })?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| SandboxError::NetworkSetup(format!("Failed to run {}: {}", program, e)))?;
+    if !output.status.success() {
+        return Err(SandboxError::NetworkSetup(format!(
+            "{} {}: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_addresses() {
+        assert_eq!(subnet_base(), Ipv4Addr::new(10, 88, 0, 0));
+        assert_eq!(host_address(FIRST_HOST), Ipv4Addr::new(10, 88, 0, 2));
+        assert_eq!(host_address(0x0203), Ipv4Addr::new(10, 88, 2, 3));
+        assert_eq!(host_address(LAST_HOST), Ipv4Addr::new(10, 88, 255, 254));
+    }
+
+    #[test]
+    fn test_veth_name_fits_ifnamsiz() {
+        let name = veth_name(Uuid::new_v4());
+        assert!(name.starts_with("vcap"));
+        assert!(name.len() <= 15);
+    }
+
+    #[test]
+    fn test_bridge_network_needs_root() {
+        if unsafe { libc::geteuid() } == 0 {
+            return; // Would reconfigure the host network
+        }
+        let isolation = IsolationConfig {
+            network: true,
+            network_mode: crate::api::schema::NetworkMode::Bridge,
+            ..Default::default()
+        };
+        assert!(BridgeNetwork::setup(Uuid::new_v4(), &isolation, None).is_err());
+    }
+
+    #[test]
+    fn test_egress_ruleset() {
+        let allowed = [
+            (Ipv4Addr::new(10, 0, 0, 0), 8, None),
+            (Ipv4Addr::new(140, 82, 112, 3), 32, Some(443)),
+        ];
+        assert!(!egress_ruleset(&allowed, &[]).contains("dport 53"));
+
+        let resolvers = [
+            "10.0.0.2".parse().unwrap(),
+            "1.1.1.1".parse().unwrap(),
+            "2606:4700::1111".parse().unwrap(),
+        ];
+        let rules = egress_ruleset(&allowed, &resolvers);
+        assert!(rules.contains("policy drop;"));
+        assert!(rules.contains(
+            "\t\tip daddr { 10.0.0.2, 1.1.1.1 } meta l4proto { tcp, udp } th dport 53 accept\n"
+        ));
+        assert!(rules.contains(
+            "\t\tip6 daddr { 2606:4700::1111 } meta l4proto { tcp, udp } th dport 53 accept\n"
+        ));
+        // No rule lets port 53 through to any other address
+        assert!(rules
+            .lines()
+            .filter(|rule| rule.contains("dport 53"))
+            .all(|rule| rule.contains(" daddr { ")));
+        assert!(rules.contains("\t\tip daddr 10.0.0.0/8 accept\n"));
+        assert!(rules.contains(
+            "\t\tip daddr 140.82.112.3/32 meta l4proto { tcp, udp } th dport 443 accept\n"
+        ));
+        assert!(rules
+            .trim_end()
+            .ends_with("reject with icmpx type admin-prohibited\n\t}\n}"));
+    }
+
+    #[test]
+    fn test_parse_conntrack() {
+        let dump = "\
+ipv4     2 tcp      6 117 TIME_WAIT src=10.88.0.2 dst=140.82.112.3 sport=51234 dport=443 packets=12 bytes=1500 src=140.82.112.3 dst=10.88.0.2 sport=443 dport=51234 packets=10 bytes=6000 [ASSURED] mark=0 use=1
+ipv4     2 tcp      6 118 TIME_WAIT src=10.88.0.2 dst=140.82.112.3 sport=51240 dport=443 packets=5 bytes=500 src=140.82.112.3 dst=10.88.0.2 sport=443 dport=51240 packets=4 bytes=1000 [ASSURED] mark=0 use=1
+ipv4     2 udp      17 20 src=10.88.0.2 dst=1.1.1.1 sport=40000 dport=53 packets=1 bytes=60 src=1.1.1.1 dst=10.88.0.2 sport=53 dport=40000 packets=1 bytes=120 mark=0 use=1
+ipv4     2 icmp     1 29 src=10.88.0.2 dst=8.8.8.8 type=8 code=0 id=7 packets=1 bytes=84 src=8.8.8.8 dst=10.88.0.2 type=0 code=0 id=7 packets=1 bytes=84 mark=0 use=1
+ipv4     2 tcp      6 10 CLOSE src=127.0.0.1 dst=127.0.0.1 sport=1 dport=2 packets=1 bytes=40 src=127.0.0.1 dst=127.0.0.1 sport=2 dport=1 packets=1 bytes=40 mark=0 use=1
+conntrack v1.4.7 (conntrack-tools): 5 flow entries have been shown.
+";
+        let destinations = parse_conntrack(dump);
+        assert_eq!(destinations.len(), 3);
+
+        let https = destinations.iter().find(|d| d.protocol == "tcp").unwrap();
+        assert_eq!(https.address, "140.82.112.3".parse::<IpAddr>().unwrap());
+        assert_eq!(https.port, Some(443));
+        assert_eq!(https.connections, 2);
+        assert_eq!(https.bytes_sent, 2000);
+        assert_eq!(https.bytes_received, 7000);
+
+        let ping = destinations.iter().find(|d| d.protocol == "icmp").unwrap();
+        assert_eq!(ping.port, None);
+    }
+
+    #[test]
+    fn test_tbf_args() {
+        assert_eq!(
+            tbf_args(1_000_000).join(" "),
+            "tbf rate 8000000bit burst 100000 latency 50ms"
+        );
+        assert_eq!(tbf_args(1024)[4], "16384");
+    }
+
+    #[test]
+    fn test_intercept_ruleset() {
+        let rules = intercept_ruleset(Ipv4Addr::new(10, 0, 0, 5), 3128);
+        assert!(rules.contains("type nat hook output priority -100;"));
+        assert!(
+            rules.contains("ip daddr != 10.0.0.5 tcp dport { 80, 443 } dnat to 10.0.0.5:3128\n")
+        );
+    }
+
+    #[test]
+    fn test_resolve_network_needs_no_lookup() {
+        assert_eq!(
+            resolve(&EgressDestination::Network(Ipv4Addr::new(10, 1, 0, 0), 16)).unwrap(),
+            vec![(Ipv4Addr::new(10, 1, 0, 0), 16)]
+        );
+    }
+}
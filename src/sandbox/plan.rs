@@ -0,0 +1,369 @@
+use crate::api::schema::{DegradedFeature, ExecutionRequest, HostCapabilities, SandboxPlan};
+use crate::error::CapsuleResult;
+use std::collections::BTreeMap;
+
+/// What setting up the sandbox for `request` would do on a host with
+/// `capabilities`, worked out without creating anything.
+pub fn plan(request: &ExecutionRequest, capabilities: &HostCapabilities) -> SandboxPlan {
+    let mut plan = SandboxPlan {
+        backend: "none".to_string(),
+        mounts: Vec::new(),
+        cgroup: BTreeMap::new(),
+        seccomp: None,
+        unavailable: Vec::new(),
+    };
+
+    #[cfg(target_os = "linux")]
+    linux::plan(request, capabilities, &mut plan);
+    #[cfg(target_os = "macos")]
+    macos::plan(request, capabilities, &mut plan);
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    let _ = (request, capabilities);
+
+    plan
+}
+
+/// Fail before any setup when `command[0]` names nothing the sandbox would
+/// contain, which otherwise only shows up as a spawn failure once the whole
+/// sandbox is built. Only Linux builds its own root; elsewhere the command
+/// sees the host's filesystem.
+pub fn check_executable(request: &ExecutionRequest) -> CapsuleResult<()> {
+    #[cfg(target_os = "linux")]
+    return linux::check_executable(request);
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = request;
+        Ok(())
+    }
+}
+
+#[cfg_attr(not(any(target_os = "linux", target_os = "macos")), allow(dead_code))]
+fn unavailable(plan: &mut SandboxPlan, feature: &str, reason: impl Into<String>) {
+    plan.unavailable.push(DegradedFeature {
+        feature: feature.to_string(),
+        reason: reason.into(),
+    });
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::*;
+    use crate::api::schema::{NetworkMode, PlannedMount};
+    use crate::error::ExecutionError;
+    use crate::sandbox::cgroups::CgroupManager;
+    use crate::sandbox::filesystem::{lexical_normalize, FilesystemManager};
+    use crate::sandbox::{cache, executable};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use uuid::Uuid;
+
+    pub fn plan(
+        request: &ExecutionRequest,
+        capabilities: &HostCapabilities,
+        plan: &mut SandboxPlan,
+    ) {
+        let resources = &request.resources;
+        let isolation = &request.isolation;
+        plan.backend = "linux".to_string();
+        plan.mounts = mounts(request);
+
+        let workspace_source = FilesystemManager::new(Uuid::nil())
+            .ok()
+            .and_then(|manager| manager.workspace_source(isolation, resources.disk_bytes));
+        if capabilities.cgroup_v2 {
+            plan.cgroup = CgroupManager::plan(
+                resources,
+                &capabilities.cgroup_controllers,
+                workspace_source.as_deref(),
+            );
+        }
+
+        #[cfg(feature = "seccomp")]
+        {
+            plan.seccomp = Some(crate::api::schema::SeccompPlan {
+                preset: isolation.seccomp,
+                mode: isolation.seccomp_mode,
+                profile: isolation.seccomp_profile.clone(),
+                allowed: match isolation.seccomp_profile {
+                    Some(_) => Vec::new(),
+                    None => crate::sandbox::seccomp::allowed_syscalls(
+                        isolation.seccomp,
+                        &isolation.extra_syscalls,
+                        isolation.network,
+                        !isolation.notify_syscalls.is_empty(),
+                    ),
+                },
+                notify: isolation.notify_syscalls.clone(),
+            });
+        }
+
+        // The same conditions setup records as degradations or fails on
+        let has_controller = |name: &str| capabilities.cgroup_controllers.iter().any(|c| c == name);
+        let root = unsafe { libc::geteuid() } == 0;
+        if !capabilities.user_namespaces && !root {
+            unavailable(
+                plan,
+                "namespaces",
+                "unprivileged user namespaces are disabled on this host",
+            );
+        }
+        if !capabilities.cgroup_v2 {
+            unavailable(
+                plan,
+                "cgroup_limits",
+                "cgroups v2 not mounted; enforcing resource limits with rlimits instead",
+            );
+        } else {
+            if resources.cpu_quota_percent.is_some() && !has_controller("cpu") {
+                unavailable(
+                    plan,
+                    "cpu_quota",
+                    "cpu controller not available for cpu.max quota",
+                );
+            }
+            if CgroupManager::has_io_max(resources) && !has_controller("io") {
+                unavailable(
+                    plan,
+                    "io_limits",
+                    "io controller not available for io.max limits",
+                );
+            } else if CgroupManager::has_io_max(resources) && !plan.cgroup.contains_key("io.max") {
+                unavailable(plan, "io_limits", "/workspace is not on a block device");
+            }
+        }
+        if !isolation.devices.is_empty() && !capabilities.cgroup_v2 {
+            unavailable(
+                plan,
+                "device_cgroup",
+                "no cgroup to attach to; device access is limited only by the nodes mounted",
+            );
+        }
+        if !capabilities.seccomp {
+            let reason = if cfg!(feature = "seccomp") {
+                "the kernel does not support seccomp filters"
+            } else {
+                "capsule-run was built without the seccomp feature"
+            };
+            unavailable(plan, "seccomp", reason);
+        } else if !isolation.notify_syscalls.is_empty() && !capabilities.seccomp_notify {
+            unavailable(
+                plan,
+                "notify_syscalls",
+                "the kernel does not support seccomp user notification",
+            );
+        }
+        if isolation.time_offset.is_some() && !capabilities.time_namespaces {
+            unavailable(plan, "time_namespace", "the kernel has no time namespaces");
+        }
+        let abstract_sockets_shared = isolation.block_abstract_sockets
+            && isolation.network
+            && isolation.network_mode != NetworkMode::Bridge;
+        if abstract_sockets_shared && capabilities.landlock_abi.is_none_or(|abi| abi < 6) {
+            unavailable(
+                plan,
+                "abstract_socket_block",
+                "Landlock ABI 6 is required to scope abstract Unix sockets; \
+                 host services on abstract sockets are reachable",
+            );
+        }
+    }
+
+    fn mounts(request: &ExecutionRequest) -> Vec<PlannedMount> {
+        let isolation = &request.isolation;
+        let mut mounts = FilesystemManager::plan_mounts(
+            isolation,
+            request.resources.disk_bytes,
+            &cache::planned_mounts(
+                &isolation.caches,
+                &cache::CacheScope {
+                    tenant: request.tenant.clone(),
+                    lock_timeout: None,
+                },
+            ),
+        );
+        mounts.extend(isolation.fuse_mounts.iter().map(|mount| PlannedMount {
+            destination: mount.path.clone(),
+            source: "fuse".to_string(),
+            readonly: mount.readonly,
+            options: None,
+            hidden_by: None,
+        }));
+        for i in 0..mounts.len() {
+            let destination = Path::new(&mounts[i].destination);
+            mounts[i].hidden_by = mounts[i + 1..]
+                .iter()
+                .find(|later| destination.starts_with(&later.destination))
+                .map(|later| later.destination.clone());
+        }
+        mounts
+    }
+
+    /// Search for `command[0]` the way execvp will inside the sandbox.
+    pub fn check_executable(request: &ExecutionRequest) -> CapsuleResult<()> {
+        let Some(program) = request.command.first() else {
+            return Ok(());
+        };
+        let mounts = mounts(request);
+        let cwd = Path::new(&request.isolation.working_directory);
+
+        if program.contains('/') {
+            return match present(&mounts, &cwd.join(program)) {
+                Some(false) => Err(ExecutionError::ExecutableNotFound(program.clone()).into()),
+                _ => Ok(()),
+            };
+        }
+        let search_path = executable::search_path(&request.environment);
+        // An empty entry is the working directory
+        if search_path
+            .split(':')
+            .all(|dir| present(&mounts, &cwd.join(dir).join(program)) == Some(false))
+        {
+            return Err(ExecutionError::ExecutableNotFound(format!(
+                "{} (PATH={})",
+                program, search_path
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Whether an executable file will be at `path` in the sandbox, or None
+    /// when that cannot be known before setup: the workspace is populated
+    /// during setup, and tmpfs and FUSE mounts start out empty or remote.
+    fn present(mounts: &[PlannedMount], path: &Path) -> Option<bool> {
+        let path = lexical_normalize(path);
+        if path.starts_with("/workspace") {
+            return None;
+        }
+        // Later mounts cover earlier ones
+        let Some(mount) = mounts
+            .iter()
+            .rev()
+            .find(|mount| path.starts_with(&mount.destination))
+        else {
+            // The root itself holds nothing but empty mount points
+            return Some(false);
+        };
+        if !mount.source.starts_with('/') {
+            return None;
+        }
+        let host = Path::new(&mount.source).join(path.strip_prefix(&mount.destination).ok()?);
+        match fs::metadata(&host) {
+            Ok(metadata) => Some(metadata.is_file() && metadata.permissions().mode() & 0o111 != 0),
+            // The link may point at something only the sandbox has
+            Err(_) if fs::symlink_metadata(&host).is_ok() => None,
+            Err(_) => Some(false),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+    use super::*;
+    use crate::sandbox::macos::linux_only_features;
+
+    pub fn plan(
+        request: &ExecutionRequest,
+        capabilities: &HostCapabilities,
+        plan: &mut SandboxPlan,
+    ) {
+        if !capabilities.sandbox_exec {
+            unavailable(plan, "sandbox_profile", "sandbox-exec is not installed");
+            return;
+        }
+        plan.backend = "seatbelt".to_string();
+        if capabilities.nested_sandbox {
+            unavailable(
+                plan,
+                "sandbox_profile",
+                "capsule-run is already running under a Seatbelt sandbox",
+            );
+        }
+        for feature in linux_only_features(&request.resources, &request.isolation) {
+            unavailable(plan, feature, "not supported on macOS");
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::api::schema::{ResourceLimits, TimeOffset};
+
+    fn request() -> ExecutionRequest {
+        serde_json::from_value(serde_json::json!({ "command": ["make"] })).unwrap()
+    }
+
+    #[test]
+    fn test_plan_mounts_and_cgroup() {
+        let mut request = request();
+        request.isolation.tmpfs_paths = vec!["/scratch".to_string()];
+        request.isolation.masked_paths = vec!["/scratch/private".to_string()];
+        request.isolation.readonly_paths = vec!["/etc".to_string()];
+        let mut capabilities = crate::sandbox::capabilities::probe();
+        capabilities.cgroup_v2 = true;
+        capabilities.cgroup_controllers = vec!["memory".to_string(), "pids".to_string()];
+        let plan = plan(&request, &capabilities);
+
+        let destinations: Vec<&str> = plan.mounts.iter().map(|m| m.destination.as_str()).collect();
+        let position = |path| destinations.iter().position(|d| *d == path).unwrap();
+        assert!(position("/dev") < position("/dev/shm"));
+        // The base /etc is replaced by the read-only path
+        assert_eq!(
+            plan.mounts[position("/etc")].hidden_by.as_deref(),
+            Some("/etc")
+        );
+        assert_eq!(destinations.last(), Some(&"/scratch/private"));
+        assert_eq!(plan.mounts[position("/scratch")].hidden_by, None);
+        assert_eq!(
+            plan.cgroup.get("memory.max"),
+            Some(&ResourceLimits::default().memory_bytes.to_string())
+        );
+        assert!(!plan.cgroup.contains_key("cpu.weight"));
+    }
+
+    #[test]
+    fn test_plan_reports_unavailable_features() {
+        let mut request = request();
+        request.isolation.time_offset = Some(TimeOffset {
+            monotonic_secs: 10,
+            boottime_secs: 0,
+        });
+        let mut capabilities = crate::sandbox::capabilities::probe();
+        capabilities.cgroup_v2 = false;
+        capabilities.time_namespaces = false;
+        let plan = plan(&request, &capabilities);
+
+        let features: Vec<&str> = plan
+            .unavailable
+            .iter()
+            .map(|f| f.feature.as_str())
+            .collect();
+        assert!(features.contains(&"cgroup_limits"));
+        assert!(features.contains(&"time_namespace"));
+        assert!(plan.cgroup.is_empty());
+    }
+
+    #[test]
+    fn test_check_executable() {
+        let mut request = request();
+        request
+            .environment
+            .insert("PATH".to_string(), "/usr/bin:/bin".to_string());
+        let check = |request: &ExecutionRequest, program: &str| {
+            let mut request = request.clone();
+            request.command = vec![program.to_string()];
+            check_executable(&request)
+        };
+
+        assert!(check(&request, "sh").is_ok());
+        assert!(check(&request, "capsule-no-such-tool").is_err());
+        // Not under any mount
+        assert!(check(&request, "/opt/capsule/tool").is_err());
+        // Written during setup, so only known afterwards
+        assert!(check(&request, "./build.sh").is_ok());
+        request.isolation.tmpfs_paths = vec!["/opt".to_string()];
+        assert!(check(&request, "/opt/capsule/tool").is_ok());
+    }
+}
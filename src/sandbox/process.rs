@@ -0,0 +1,351 @@
+use crate::api::schema::{IsolationConfig, ResourceLimits};
+use crate::api::validation::parse_cpu_list;
+use crate::error::CapsuleResult;
+use crate::sandbox::ResourceUsage;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// Per-process settings applied in the child between fork and exec.
+///
+/// Everything in `apply` runs in a forked child of a multi-threaded process,
+/// so it must stick to async-signal-safe libc calls (no allocation, no locks).
+#[derive(Debug, Clone, Default)]
+pub struct ChildSetup {
+    pub umask: Option<u32>,
+    pub rlimits: Option<RlimitFallback>,
+    pub cpu_affinity: Option<Vec<usize>>,
+    pub max_file_size: Option<u64>,
+    pub max_open_files: Option<u64>,
+    /// Scope abstract Unix sockets to the command with Landlock (ABI 6+)
+    pub scope_abstract_sockets: bool,
+}
+
+/// Limits enforced with setrlimit when cgroups cannot be used, mirroring the
+/// macOS sandbox. RLIMIT_NPROC counts every process of the (mapped) user, so
+/// it is coarser than pids.max.
+#[derive(Debug, Clone)]
+pub struct RlimitFallback {
+    pub address_space_bytes: u64,
+    pub max_processes: u64,
+    pub cpu_time_secs: u64,
+}
+
+impl RlimitFallback {
+    pub fn new(resources: &ResourceLimits, timeout_ms: u64) -> Self {
+        // Without cpu.max, approximate a CPU quota as a share of the wall-clock budget
+        let cpu_time_ms = match resources.cpu_quota_percent {
+            Some(percent) => timeout_ms * percent as u64 / 100,
+            None => timeout_ms,
+        };
+
+        Self {
+            address_space_bytes: resources.memory_bytes,
+            max_processes: resources.max_pids as u64,
+            cpu_time_secs: cpu_time_ms.div_ceil(1000).max(1),
+        }
+    }
+
+    fn apply(&self) -> io::Result<()> {
+        set_rlimit(
+            libc::RLIMIT_AS,
+            self.address_space_bytes,
+            self.address_space_bytes,
+        )?;
+        set_rlimit(libc::RLIMIT_NPROC, self.max_processes, self.max_processes)?;
+        // SIGXCPU at the soft limit, SIGKILL one second later
+        set_rlimit(libc::RLIMIT_CPU, self.cpu_time_secs, self.cpu_time_secs + 1)?;
+        Ok(())
+    }
+}
+
+impl ChildSetup {
+    pub fn from_config(
+        resources: &ResourceLimits,
+        isolation: &IsolationConfig,
+    ) -> CapsuleResult<Self> {
+        // Affinity is applied even when cgroup cpuset works; it is harmless
+        // there and keeps pinning in effect under the rlimit fallback
+        let cpu_affinity = resources
+            .cpuset
+            .as_deref()
+            .map(parse_cpu_list)
+            .transpose()?;
+
+        Ok(Self {
+            umask: isolation.umask,
+            rlimits: None,
+            cpu_affinity,
+            max_file_size: resources.max_file_size_bytes,
+            max_open_files: Some(resources.max_open_files as u64),
+            scope_abstract_sockets: false,
+        })
+    }
+
+    pub fn prepare_command(&self, cmd: &mut Command) {
+        let setup = self.clone();
+        // Build the CPU mask up front; the child must not allocate
+        let cpu_set = self.cpu_affinity.as_deref().map(cpu_set_from);
+        unsafe {
+            cmd.pre_exec(move || setup.apply(cpu_set.as_ref()));
+        }
+    }
+
+    fn apply(&self, cpu_set: Option<&libc::cpu_set_t>) -> io::Result<()> {
+        if let Some(mask) = self.umask {
+            unsafe {
+                libc::umask(mask as libc::mode_t);
+            }
+        }
+
+        if let Some(rlimits) = &self.rlimits {
+            rlimits.apply()?;
+        }
+
+        // Writes past the limit fail with EFBIG after SIGXFSZ
+        if let Some(max_file_size) = self.max_file_size {
+            set_rlimit(libc::RLIMIT_FSIZE, max_file_size, max_file_size)?;
+        }
+
+        if let Some(max_open_files) = self.max_open_files {
+            set_rlimit(libc::RLIMIT_NOFILE, max_open_files, max_open_files)?;
+        }
+
+        if let Some(cpu_set) = cpu_set {
+            let size = std::mem::size_of::<libc::cpu_set_t>();
+            if unsafe { libc::sched_setaffinity(0, size, cpu_set) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        if self.scope_abstract_sockets {
+            scope_abstract_sockets()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Deny connecting to abstract Unix sockets created outside this process
+/// and its descendants, which otherwise reach the host in a shared network
+/// namespace. Needs Landlock ABI 6 (Linux 6.12).
+fn scope_abstract_sockets() -> io::Result<()> {
+    // struct landlock_ruleset_attr; only the scope is restricted
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+        handled_access_net: u64,
+        scoped: u64,
+    }
+    const LANDLOCK_SCOPE_ABSTRACT_UNIX_SOCKET: u64 = 1 << 0;
+
+    let attr = RulesetAttr {
+        handled_access_fs: 0,
+        handled_access_net: 0,
+        scoped: LANDLOCK_SCOPE_ABSTRACT_UNIX_SOCKET,
+    };
+    let ruleset = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr as *const RulesetAttr,
+            std::mem::size_of::<RulesetAttr>(),
+            0u32,
+        )
+    };
+    if ruleset < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Restricting an unprivileged process requires no_new_privs, which the
+    // seccomp filter sets anyway
+    let result = unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            -1
+        } else {
+            libc::syscall(
+                libc::SYS_landlock_restrict_self,
+                ruleset as libc::c_int,
+                0u32,
+            )
+        }
+    };
+    let error = io::Error::last_os_error();
+    unsafe {
+        libc::close(ruleset as libc::c_int);
+    }
+    if result != 0 {
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn cpu_set_from(cpus: &[usize]) -> libc::cpu_set_t {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        set
+    }
+}
+
+fn set_rlimit(resource: libc::__rlimit_resource_t, soft: u64, hard: u64) -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: soft,
+        rlim_max: hard,
+    };
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Usage of all reaped children, used when there is no cgroup to read from.
+pub fn children_usage() -> ResourceUsage {
+    let usage = unsafe {
+        let mut usage: libc::rusage = std::mem::zeroed();
+        if libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) != 0 {
+            return ResourceUsage::default();
+        }
+        usage
+    };
+
+    let user_time_us = usage.ru_utime.tv_sec as u64 * 1_000_000 + usage.ru_utime.tv_usec as u64;
+    let kernel_time_us = usage.ru_stime.tv_sec as u64 * 1_000_000 + usage.ru_stime.tv_usec as u64;
+
+    ResourceUsage {
+        memory_bytes: usage.ru_maxrss as u64 * 1024, // Linux reports KB
+        cpu_time_us: user_time_us + kernel_time_us,
+        user_time_us,
+        kernel_time_us,
+        io_bytes_read: usage.ru_inblock as u64 * 512, // Approximate
+        io_bytes_written: usage.ru_oublock as u64 * 512, // Approximate
+        cpu_throttled_us: 0,
+        disk_bytes: 0,
+        major_faults: usage.ru_majflt as u64,
+        minor_faults: usage.ru_minflt as u64,
+        voluntary_ctx_switches: usage.ru_nvcsw as u64,
+        involuntary_ctx_switches: usage.ru_nivcsw as u64,
+        pids_peak: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_child_setup_from_config() {
+        let isolation = IsolationConfig {
+            umask: Some(0o077),
+            ..Default::default()
+        };
+        let resources = ResourceLimits {
+            cpuset: Some("0".to_string()),
+            ..Default::default()
+        };
+        let setup = ChildSetup::from_config(&resources, &isolation).unwrap();
+        assert_eq!(setup.umask, Some(0o077));
+        assert_eq!(setup.cpu_affinity, Some(vec![0]));
+        assert_eq!(setup.max_open_files, Some(1024));
+    }
+
+    #[test]
+    fn test_children_usage_reports_peak_rss_and_faults() {
+        std::process::Command::new("true").status().unwrap();
+        let usage = children_usage();
+        assert!(usage.memory_bytes > 0);
+        assert!(usage.minor_faults > 0);
+    }
+
+    #[test]
+    fn test_umask_applied_in_child() {
+        let setup = ChildSetup {
+            umask: Some(0o027),
+            ..Default::default()
+        };
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "umask"]);
+        setup.prepare_command(&mut cmd);
+
+        if let Ok(output) = cmd.output() {
+            assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "0027");
+        }
+    }
+
+    #[test]
+    fn test_rlimit_fallback_applied_in_child() {
+        let resources = ResourceLimits {
+            memory_bytes: 256 * 1024 * 1024,
+            ..Default::default()
+        };
+        let setup = ChildSetup {
+            rlimits: Some(RlimitFallback::new(&resources, 2500)),
+            ..Default::default()
+        };
+        assert_eq!(setup.rlimits.as_ref().unwrap().cpu_time_secs, 3);
+
+        let quota = ResourceLimits {
+            cpu_quota_percent: Some(50),
+            ..Default::default()
+        };
+        assert_eq!(RlimitFallback::new(&quota, 10_000).cpu_time_secs, 5);
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "ulimit -v; ulimit -t"]);
+        setup.prepare_command(&mut cmd);
+
+        if let Ok(output) = cmd.output() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let lines: Vec<&str> = stdout.lines().collect();
+            assert_eq!(lines, vec!["262144", "3"]);
+        }
+    }
+
+    #[test]
+    fn test_abstract_sockets_scoped_in_child() {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::{SocketAddr, UnixListener, UnixStream};
+
+        if crate::sandbox::capabilities::landlock_abi().unwrap_or(0) < 6 {
+            return;
+        }
+
+        let name = format!("capsule-abstract-{}", std::process::id());
+        let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let _listener = UnixListener::bind_addr(&addr).unwrap();
+
+        let setup = ChildSetup {
+            scope_abstract_sockets: true,
+            ..Default::default()
+        };
+        let mut cmd = Command::new("true");
+        setup.prepare_command(&mut cmd);
+        // Runs after the scope is applied, and fails the spawn if connect does
+        unsafe {
+            cmd.pre_exec(move || UnixStream::connect_addr(&addr).map(|_| ()));
+        }
+
+        let error = cmd.status().unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::EPERM));
+    }
+
+    #[test]
+    fn test_file_size_limit_applied_in_child() {
+        let path = std::env::temp_dir().join(format!("capsule-fsize-{}", std::process::id()));
+        let setup = ChildSetup {
+            max_file_size: Some(4096),
+            ..Default::default()
+        };
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "head -c 65536 /dev/zero > \"$0\""])
+            .arg(&path);
+        setup.prepare_command(&mut cmd);
+
+        if cmd.status().is_ok() {
+            let written = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            let _ = std::fs::remove_file(&path);
+            assert!(written <= 4096);
+        }
+    }
+}
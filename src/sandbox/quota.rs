@@ -0,0 +1,251 @@
+use crate::error::{CapsuleResult, SandboxError};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+const PRJQUOTA: libc::c_int = 2;
+const FS_IOC_FSGETXATTR: libc::c_ulong = 0x801c_581f;
+const FS_IOC_FSSETXATTR: libc::c_ulong = 0x401c_5820;
+const FS_XFLAG_PROJINHERIT: u32 = 0x0000_0200;
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct FsXattr {
+    fsx_xflags: u32,
+    fsx_extsize: u32,
+    fsx_nextents: u32,
+    fsx_projid: u32,
+    fsx_cowextsize: u32,
+    fsx_pad: [u8; 8],
+}
+
+/// XFS/ext4 project quota on a host directory bind-mounted into the sandbox.
+///
+/// Only files created after the quota is applied inherit the project ID, so
+/// pre-existing content in the directory is not counted. Needs CAP_SYS_ADMIN
+/// in the initial user namespace and a filesystem mounted with `prjquota`,
+/// so it is applied before the supervisor unshares its user namespace.
+///
+/// Nothing in the sandbox can read or undo a project quota, so a keeper
+/// process forked beforehand stays in the host's namespaces. It answers
+/// usage queries over a socket and, once every copy of the socket is closed
+/// (however the supervisor ended), clears the limit and restores the
+/// directory's project ID and flags. Files created during the run keep the
+/// project ID.
+pub struct ProjectQuota {
+    keeper: Mutex<Option<UnixStream>>,
+    keeper_pid: libc::pid_t,
+}
+
+impl ProjectQuota {
+    pub fn apply(path: &Path, limit_bytes: u64, execution_id: Uuid) -> CapsuleResult<Self> {
+        let dir = File::open(path).map_err(|e| {
+            SandboxError::FilesystemSetup(format!(
+                "Failed to open {} for project quota: {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        let id = execution_id.as_bytes();
+        let project_id = u32::from_be_bytes([id[0], id[1], id[2], id[3]]).max(1);
+
+        let mut original = FsXattr::default();
+        if unsafe { libc::ioctl(dir.as_raw_fd(), FS_IOC_FSGETXATTR, &mut original) } != 0 {
+            return Err(Self::error("read project attributes", path));
+        }
+        let mut attr = original;
+        attr.fsx_projid = project_id;
+        attr.fsx_xflags |= FS_XFLAG_PROJINHERIT;
+        if unsafe { libc::ioctl(dir.as_raw_fd(), FS_IOC_FSSETXATTR, &attr) } != 0 {
+            return Err(Self::error("assign project ID", path));
+        }
+
+        let mut dqblk: libc::dqblk = unsafe { std::mem::zeroed() };
+        // Block limits are expressed in 1 KiB quota blocks
+        dqblk.dqb_bhardlimit = limit_bytes.div_ceil(1024);
+        dqblk.dqb_bsoftlimit = dqblk.dqb_bhardlimit;
+        dqblk.dqb_valid = libc::QIF_BLIMITS;
+        let started = if quotactl(&dir, libc::Q_SETQUOTA, project_id, &mut dqblk) != 0 {
+            Err(SandboxError::FilesystemSetup(format!(
+                "Failed to set project quota: {}",
+                std::io::Error::last_os_error()
+            ))
+            .into())
+        } else {
+            start_keeper(&dir, project_id, original)
+        };
+
+        match started {
+            Ok((keeper, keeper_pid)) => Ok(Self {
+                keeper: Mutex::new(Some(keeper)),
+                keeper_pid,
+            }),
+            Err(e) => {
+                release(&dir, project_id, &original);
+                Err(e)
+            }
+        }
+    }
+
+    /// Bytes currently charged to the project.
+    pub fn usage(&self) -> CapsuleResult<u64> {
+        let failed = |e: std::io::Error| {
+            SandboxError::FilesystemSetup(format!("Failed to query project quota: {}", e))
+        };
+        let keeper = self.keeper.lock().unwrap_or_else(|e| e.into_inner());
+        let mut keeper = keeper
+            .as_ref()
+            .ok_or_else(|| failed(std::io::ErrorKind::NotConnected.into()))?;
+        let mut used = [0u8; 8];
+        keeper
+            .write_all(b"?")
+            .and_then(|()| keeper.read_exact(&mut used))
+            .map_err(failed)?;
+        match u64::from_ne_bytes(used) {
+            u64::MAX => Err(failed(std::io::Error::from_raw_os_error(libc::EPERM)).into()),
+            used => Ok(used),
+        }
+    }
+
+    fn error(action: &str, path: &Path) -> crate::error::CapsuleError {
+        SandboxError::FilesystemSetup(format!(
+            "Failed to {} on {}: {} (project quotas need root and a filesystem mounted with prjquota)",
+            action,
+            path.display(),
+            std::io::Error::last_os_error()
+        ))
+        .into()
+    }
+}
+
+impl Drop for ProjectQuota {
+    fn drop(&mut self) {
+        if let Ok(mut keeper) = self.keeper.lock() {
+            keeper.take();
+        }
+        // Waits for the release when this process forked the keeper; other
+        // holders of the socket get ECHILD and leave it to the keeper
+        unsafe { libc::waitpid(self.keeper_pid, std::ptr::null_mut(), 0) };
+    }
+}
+
+/// Fork the keeper, which only makes async-signal-safe calls, as the
+/// supervisor may have other threads.
+fn start_keeper(
+    dir: &File,
+    project_id: u32,
+    original: FsXattr,
+) -> CapsuleResult<(UnixStream, libc::pid_t)> {
+    let (supervisor, keeper) = UnixStream::pair().map_err(|e| {
+        SandboxError::FilesystemSetup(format!("Failed to create quota keeper socket: {}", e))
+    })?;
+
+    match unsafe { libc::fork() } {
+        -1 => Err(SandboxError::FilesystemSetup(format!(
+            "Failed to start quota keeper: {}",
+            std::io::Error::last_os_error()
+        ))
+        .into()),
+        0 => unsafe {
+            // Out of the process group, so Ctrl-C does not skip the release
+            libc::setsid();
+            close_other_fds(&[dir.as_raw_fd(), keeper.as_raw_fd()]);
+            let mut usage: libc::dqblk = std::mem::zeroed();
+            let mut request = 0u8;
+            loop {
+                let read = libc::read(
+                    keeper.as_raw_fd(),
+                    &mut request as *mut u8 as *mut libc::c_void,
+                    1,
+                );
+                if read < 0 && *libc::__errno_location() == libc::EINTR {
+                    continue;
+                }
+                if read <= 0 {
+                    break;
+                }
+                let used = if quotactl(dir, libc::Q_GETQUOTA, project_id, &mut usage) == 0 {
+                    usage.dqb_curspace
+                } else {
+                    u64::MAX
+                };
+                let used = used.to_ne_bytes();
+                libc::write(
+                    keeper.as_raw_fd(),
+                    used.as_ptr() as *const libc::c_void,
+                    used.len(),
+                );
+            }
+            release(dir, project_id, &original);
+            libc::_exit(0)
+        },
+        pid => Ok((supervisor, pid)),
+    }
+}
+
+/// Clear the project's limit and give the directory back its attributes.
+fn release(dir: &File, project_id: u32, original: &FsXattr) {
+    let mut dqblk: libc::dqblk = unsafe { std::mem::zeroed() };
+    dqblk.dqb_valid = libc::QIF_BLIMITS;
+    quotactl(dir, libc::Q_SETQUOTA, project_id, &mut dqblk);
+    unsafe { libc::ioctl(dir.as_raw_fd(), FS_IOC_FSSETXATTR, original) };
+}
+
+fn quotactl(
+    dir: &File,
+    cmd: libc::c_int,
+    project_id: u32,
+    dqblk: &mut libc::dqblk,
+) -> libc::c_long {
+    unsafe {
+        libc::syscall(
+            libc::SYS_quotactl_fd,
+            dir.as_raw_fd(),
+            libc::QCMD(cmd, PRJQUOTA),
+            project_id,
+            dqblk as *mut libc::dqblk,
+        )
+    }
+}
+
+/// Close every descriptor but `keep`, so the keeper holds no locks or pipe
+/// ends of the supervisor's longer than it has to.
+unsafe fn close_other_fds(keep: &[RawFd; 2]) {
+    let (low, high) = (keep[0].min(keep[1]) as u32, keep[0].max(keep[1]) as u32);
+    let close_range = |first: u32, last: u32| {
+        if first <= last {
+            libc::syscall(libc::SYS_close_range, first, last, 0);
+        }
+    };
+    if low > 0 {
+        close_range(0, low - 1);
+    }
+    close_range(low + 1, high.saturating_sub(1));
+    close_range(high + 1, u32::MAX);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fsxattr_layout() {
+        // Must match struct fsxattr from linux/fs.h for the ioctl numbers above
+        assert_eq!(std::mem::size_of::<FsXattr>(), 28);
+    }
+
+    #[test]
+    fn test_apply_fails_on_missing_directory() {
+        let result = ProjectQuota::apply(
+            Path::new("/nonexistent/capsule-quota"),
+            1_048_576,
+            Uuid::new_v4(),
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,340 @@
+use crate::api::schema::{ExecutionStats, RunningExecution};
+use crate::error::{CapsuleError, CapsuleResult};
+use chrono::Utc;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Where root keeps the registry; other users keep it in their runtime dir
+const SYSTEM_STATE_DIR: &str = "/run/capsule-run";
+
+/// Signals `kill` accepts by name
+const SIGNALS: [(&str, libc::c_int); 9] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("TERM", libc::SIGTERM),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+];
+
+/// The directory running executions are registered in.
+pub fn state_dir() -> PathBuf {
+    if unsafe { libc::geteuid() } == 0 {
+        return PathBuf::from(SYSTEM_STATE_DIR);
+    }
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => Path::new(&dir).join("capsule-run"),
+        None => std::env::temp_dir().join(format!("capsule-run-{}", unsafe { libc::geteuid() })),
+    }
+}
+
+/// An execution's entry in the state directory, held while it runs.
+///
+/// The file is opened before setup, since on Linux the supervisor loses
+/// sight of the host's filesystem once it pivots into the sandbox root;
+/// later updates go through the open file. It is flocked like a sandbox
+/// root, so an entry whose lock is free belongs to an execution that has
+/// ended, even if nothing could remove the file.
+pub struct Registration {
+    file: File,
+    path: PathBuf,
+    record: RunningExecution,
+}
+
+impl Registration {
+    pub fn register(dir: &Path, execution_id: Uuid, command: &[String]) -> CapsuleResult<Self> {
+        let failed = |e: std::io::Error| {
+            CapsuleError::Io(std::io::Error::new(
+                e.kind(),
+                format!("Failed to register execution in {}: {}", dir.display(), e),
+            ))
+        };
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .map_err(failed)?;
+        let path = dir.join(format!("{}.json", execution_id));
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .mode(0o600)
+            .open(&path)
+            .map_err(failed)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            return Err(failed(std::io::Error::last_os_error()));
+        }
+        let registration = Self {
+            file,
+            path,
+            record: RunningExecution {
+                execution_id,
+                supervisor_pid: std::process::id(),
+                pid: None,
+                command: command.to_vec(),
+                started_at: Utc::now(),
+                uptime_ms: 0,
+                memory_bytes: None,
+            },
+        };
+        registration.write(&registration.record)?;
+        Ok(registration)
+    }
+
+    /// Record the command's process once it is spawned.
+    pub fn set_pid(&self, pid: u32) -> CapsuleResult<()> {
+        self.write(&RunningExecution {
+            pid: Some(pid),
+            ..self.record.clone()
+        })
+    }
+
+    fn write(&self, record: &RunningExecution) -> CapsuleResult<()> {
+        let mut file = &self.file;
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&serde_json::to_vec(record)?)?;
+        Ok(())
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        // Unreachable after a pivot; `running` removes it once unlocked
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Executions registered in `dir` that are still running, oldest first.
+/// Entries left by executions that ended without removing them are removed.
+pub fn running(dir: &Path) -> Vec<RunningExecution> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut executions: Vec<RunningExecution> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| {
+            let file = File::open(&path).ok()?;
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB) } == 0 {
+                let _ = fs::remove_file(&path);
+                return None;
+            }
+            let mut record: RunningExecution = serde_json::from_reader(&file).ok()?;
+            record.uptime_ms = (Utc::now() - record.started_at).num_milliseconds().max(0) as u64;
+            record.memory_bytes = record.pid.and_then(memory_bytes);
+            Some(record)
+        })
+        .collect();
+    executions.sort_by_key(|record| record.started_at);
+    executions
+}
+
+/// The running execution whose id is or starts with `id`.
+pub fn find(dir: &Path, id: &str) -> CapsuleResult<RunningExecution> {
+    let matches: Vec<RunningExecution> = running(dir)
+        .into_iter()
+        .filter(|record| record.execution_id.to_string().starts_with(id))
+        .collect();
+    let record = match (id.is_empty(), matches.as_slice()) {
+        (false, [record]) => record.clone(),
+        (false, []) | (true, _) => {
+            return Err(CapsuleError::Config(format!("No running execution {}", id)))
+        }
+        _ => {
+            return Err(CapsuleError::Config(format!(
+                "Execution id {} is ambiguous: {} running executions match",
+                id,
+                matches.len()
+            )))
+        }
+    };
+    Ok(record)
+}
+
+/// Send `signal` to the command of the running execution whose id is or
+/// starts with `id`. The command is the sandbox's init on Linux, so killing
+/// it ends everything else in the sandbox too.
+pub fn kill(dir: &Path, id: &str, signal: libc::c_int) -> CapsuleResult<RunningExecution> {
+    let record = find(dir, id)?;
+    let Some(pid) = record.pid else {
+        return Err(CapsuleError::Config(format!(
+            "Execution {} has not started its command yet",
+            record.execution_id
+        )));
+    };
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } != 0 {
+        return Err(CapsuleError::Syscall(format!(
+            "Failed to signal process {}: {}",
+            pid,
+            std::io::Error::last_os_error()
+        )));
+    }
+    Ok(record)
+}
+
+/// Parse `TERM`, `SIGTERM` or `15`.
+pub fn parse_signal(name: &str) -> CapsuleResult<libc::c_int> {
+    let upper = name.to_ascii_uppercase();
+    let bare = upper.strip_prefix("SIG").unwrap_or(&upper);
+    SIGNALS
+        .iter()
+        .find(|(signal, _)| *signal == bare)
+        .map(|(_, number)| *number)
+        .or_else(|| name.parse().ok().filter(|number| *number > 0))
+        .ok_or_else(|| CapsuleError::Config(format!("Invalid signal: {}", name)))
+}
+
+/// Usage of `execution`'s cgroup now. `cpu_percent` is left for the caller,
+/// which has the previous sample.
+#[cfg(target_os = "linux")]
+pub fn stats(execution: &RunningExecution) -> CapsuleResult<ExecutionStats> {
+    use crate::sandbox::cgroups::CgroupManager;
+
+    let cgroup = execution.pid.and_then(cgroup_of).ok_or_else(|| {
+        CapsuleError::Config(format!(
+            "Execution {} has no cgroup of its own; stats needs cgroup limits",
+            execution.execution_id
+        ))
+    })?;
+    let cgroup = CgroupManager::observe(cgroup, execution.execution_id)?;
+    let usage = cgroup.get_usage()?;
+    Ok(ExecutionStats {
+        execution_id: execution.execution_id,
+        timestamp: Utc::now(),
+        memory_bytes: usage.memory_bytes,
+        memory_limit_bytes: cgroup.get_memory_limit(),
+        cpu_time_us: usage.cpu_time_us,
+        cpu_percent: None,
+        pids: cgroup.get_pids_current(),
+        io_bytes_read: usage.io_bytes_read,
+        io_bytes_written: usage.io_bytes_written,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn stats(_execution: &RunningExecution) -> CapsuleResult<ExecutionStats> {
+    Err(CapsuleError::Config(
+        "stats requires Linux cgroups".to_string(),
+    ))
+}
+
+/// CPU use between two samples of the same execution; 100 is one core
+/// fully busy.
+pub fn cpu_percent(previous: &ExecutionStats, current: &ExecutionStats) -> Option<f64> {
+    let wall_us = (current.timestamp - previous.timestamp).num_microseconds()?;
+    (wall_us > 0).then(|| {
+        current.cpu_time_us.saturating_sub(previous.cpu_time_us) as f64 * 100.0 / wall_us as f64
+    })
+}
+
+/// The cgroup `pid` is in, if it is one of capsule-run's: without cgroup
+/// limits the command shares the user's session cgroup.
+#[cfg(target_os = "linux")]
+fn cgroup_of(pid: u32) -> Option<PathBuf> {
+    let cgroup = fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+    let path = cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .filter(|path| path.contains("capsule"))?;
+    let mount = crate::sandbox::cgroups::CgroupManager::find_cgroup_mount().ok()?;
+    Some(mount.join(path.trim_start_matches('/')))
+}
+
+#[cfg(target_os = "linux")]
+fn memory_bytes(pid: u32) -> Option<u64> {
+    let from_cgroup = cgroup_of(pid).and_then(|cgroup| {
+        fs::read_to_string(cgroup.join("memory.current"))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    });
+    from_cgroup.or_else(|| {
+        let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+        let kib: u64 = status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))?
+            .trim()
+            .trim_end_matches("kB")
+            .trim()
+            .parse()
+            .ok()?;
+        Some(kib * 1024)
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn memory_bytes(_pid: u32) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_lists_live_executions() {
+        let dir = tempfile::tempdir().unwrap();
+        let id = Uuid::new_v4();
+        let command = vec!["sleep".to_string(), "60".to_string()];
+        let registration = Registration::register(dir.path(), id, &command).unwrap();
+        registration.set_pid(std::process::id()).unwrap();
+        // Left by an execution that ended without removing it
+        let stale = dir.path().join(format!("{}.json", Uuid::new_v4()));
+        fs::write(&stale, "{}").unwrap();
+
+        let running = running(dir.path());
+        assert_eq!(running.len(), 1);
+        assert_eq!(running[0].execution_id, id);
+        assert_eq!(running[0].command, command);
+        assert_eq!(running[0].pid, Some(std::process::id()));
+        assert!(!stale.exists());
+
+        assert!(kill(dir.path(), "nonexistent", libc::SIGCONT).is_err());
+        let killed = kill(dir.path(), &id.to_string()[..8], libc::SIGCONT).unwrap();
+        assert_eq!(killed.execution_id, id);
+
+        drop(registration);
+        assert!(super::running(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn test_cpu_percent() {
+        let sample = |ms: i64, cpu_time_us: u64| ExecutionStats {
+            execution_id: Uuid::nil(),
+            timestamp: chrono::DateTime::UNIX_EPOCH + chrono::Duration::milliseconds(ms),
+            memory_bytes: 0,
+            memory_limit_bytes: None,
+            cpu_time_us,
+            cpu_percent: None,
+            pids: 1,
+            io_bytes_read: 0,
+            io_bytes_written: 0,
+        };
+        // Two cores busy for a second
+        assert_eq!(
+            cpu_percent(&sample(0, 0), &sample(1000, 2_000_000)),
+            Some(200.0)
+        );
+        assert_eq!(cpu_percent(&sample(1000, 0), &sample(1000, 10)), None);
+    }
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("TERM").unwrap(), libc::SIGTERM);
+        assert_eq!(parse_signal("sigkill").unwrap(), libc::SIGKILL);
+        assert_eq!(parse_signal("9").unwrap(), 9);
+        assert!(parse_signal("BOGUS").is_err());
+        assert!(parse_signal("0").is_err());
+    }
+}
@@ -1,7 +1,174 @@
+use crate::api::schema::{SeccompMode, SeccompPreset};
 use crate::error::{CapsuleResult, SandboxError};
-use libseccomp::{ScmpAction, ScmpArgCompare, ScmpCompareOp, ScmpFilterContext, ScmpSyscall};
+use crate::sandbox::seccomp_profile::OciSeccompProfile;
+use libseccomp::{
+    ScmpAction, ScmpArch, ScmpArgCompare, ScmpCompareOp, ScmpFilterContext, ScmpSyscall,
+};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::fd::{FromRawFd, RawFd};
 use std::sync::{Arc, Mutex};
 
+/// Architectures the built-in allowlist covers. Rules are added by name, so
+/// each architecture gets its own syscall numbers.
+const FILTER_ARCHES: &[ScmpArch] = &[ScmpArch::X8664, ScmpArch::Aarch64];
+
+/// The built-in allowlist: a minimal set of syscalls for sandboxed execution.
+const ALLOWED_SYSCALLS: &[&str] = &[
+    // Essential I/O operations (8 syscalls)
+    "read",
+    "write",
+    "readv",
+    "writev",
+    "close",
+    "lseek",
+    "dup",
+    "dup3",
+    // Minimal file operations (12 syscalls) - modern syscalls only
+    "openat",
+    "fstat",
+    "newfstatat",
+    "getcwd",
+    "chdir",
+    "mkdirat",
+    "unlinkat",
+    "renameat2",
+    "fchmod",
+    "ftruncate",
+    "fsync",
+    "pipe2",
+    // Directory operations (1 syscall)
+    "getdents64",
+    // Essential memory management (5 syscalls)
+    "mmap",
+    "munmap",
+    "mprotect",
+    "madvise",
+    "brk",
+    // Minimal process/thread info (5 syscalls)
+    "getpid",
+    "getuid",
+    "getgid",
+    "gettid",
+    "set_tid_address",
+    // Time operations (2 syscalls)
+    "clock_gettime",
+    "nanosleep",
+    // Essential signal handling (4 syscalls)
+    "rt_sigaction",
+    "rt_sigprocmask",
+    "rt_sigreturn",
+    "sigaltstack",
+    // Process execution and control (4 syscalls)
+    "execve",
+    "wait4",
+    "exit",
+    "exit_group",
+    // Essential polling (3 syscalls)
+    "ppoll",
+    "epoll_create1",
+    "epoll_pwait",
+    // Resource limits (2 syscalls)
+    "prlimit64",
+    "getrlimit",
+    // Thread synchronization (1 syscall)
+    "futex",
+    // fcntl for file descriptor operations (1 syscall)
+    "fcntl",
+    // Additional essential syscalls for compatibility (6 syscalls)
+    "ioctl",       // Terminal operations
+    "getrandom",   // Secure random numbers
+    "sched_yield", // Thread yielding
+    "kill",        // Send signals to own process
+    "tgkill",      // Thread-targeted signals
+    "geteuid",     // Get effective UID
+                   // Total: ~55 syscalls - a reasonable balance between security and functionality
+];
+
+/// Base syscalls the strict preset leaves out, on top of the conditional
+/// prctl and AF_UNIX socket rules.
+const STRICT_EXCLUDED: &[&str] = &["ioctl", "kill"];
+
+/// Added by the build preset: process creation for compilers, shells and
+/// test runners, plus debugging and profiling.
+const BUILD_SYSCALLS: &[&str] = &[
+    "clone",
+    "clone3",
+    "vfork",
+    "fork",
+    "ptrace",
+    "perf_event_open",
+    "getppid",
+    "setpgid",
+    "getpgid",
+    "setsid",
+    "dup2",
+    "pipe",
+    "readlinkat",
+    "faccessat",
+    "faccessat2",
+    "statx",
+    "utimensat",
+    "fchmodat",
+    "fchownat",
+    "symlinkat",
+    "linkat",
+    "umask",
+    "uname",
+    "sysinfo",
+    "getrusage",
+    "fstatfs",
+];
+
+/// Added by the network preset on top of `NETWORK_SYSCALLS`.
+const NETWORK_PRESET_SYSCALLS: &[&str] = &[
+    "epoll_ctl",
+    "io_uring_setup",
+    "io_uring_enter",
+    "io_uring_register",
+];
+
+/// Syscalls that fail with EPERM rather than hitting the default action, so a
+/// command probing for them gets an error it can handle instead of SIGSYS.
+/// A preset that allows one (e.g. io_uring under `network`) takes precedence.
+const RISKY_SYSCALLS: &[&str] = &[
+    "keyctl",
+    "add_key",
+    "request_key",
+    "userfaultfd",
+    "perf_event_open",
+    "bpf",
+    "kexec_load",
+    "kexec_file_load",
+    "mount",
+    "umount2",
+    "io_uring_setup",
+];
+
+/// Network-related syscalls added when network access is enabled.
+const NETWORK_SYSCALLS: &[&str] = &[
+    "socket",
+    "bind",
+    "listen",
+    "accept",
+    "accept4",
+    "connect",
+    "getsockname",
+    "getpeername",
+    "sendto",
+    "recvfrom",
+    "sendmsg",
+    "recvmsg",
+    "shutdown",
+    "setsockopt",
+    "getsockopt",
+];
+
+/// Syscalls the child makes after installing a filter that notifies, to
+/// hand the listener to the supervisor before exec. They are allowed in
+/// every such filter and cannot be notified themselves.
+const NOTIFY_HANDOFF_SYSCALLS: &[&str] = &["sendmsg", "close"];
+
 // Wrapper to make ScmpFilterContext thread-safe
 struct ThreadSafeFilterContext {
     inner: ScmpFilterContext,
@@ -13,146 +180,235 @@ unsafe impl Sync for ThreadSafeFilterContext {}
 
 pub struct SeccompFilter {
     ctx: Arc<Mutex<ThreadSafeFilterContext>>,
+    mode: SeccompMode,
+    notifies: bool,
+}
+
+/// A filter compiled to BPF in the supervisor, so that installing it in the
+/// child between fork and exec takes two syscalls and no allocation.
+pub struct CompiledFilter {
+    program: Vec<libc::sock_filter>,
+    flags: libc::c_ulong,
 }
 
 impl SeccompFilter {
     pub fn new() -> CapsuleResult<Self> {
-        let ctx = ScmpFilterContext::new_filter(ScmpAction::KillProcess).map_err(|e| {
-            SandboxError::SeccompSetup(format!("Failed to create seccomp context: {}", e))
-        })?;
+        Self::with_mode(SeccompMode::Kill)
+    }
+
+    /// Create an empty filter whose default action follows `mode`.
+    pub fn with_mode(mode: SeccompMode) -> CapsuleResult<Self> {
+        let mut ctx = Self::create_context(Self::mode_action(mode))?;
+
+        for &arch in FILTER_ARCHES {
+            ctx.add_arch(arch).map_err(|e| {
+                SandboxError::SeccompSetup(format!("Failed to add architecture {:?}: {}", arch, e))
+            })?;
+        }
+
+        Ok(Self {
+            ctx: Arc::new(Mutex::new(ThreadSafeFilterContext { inner: ctx })),
+            mode,
+            notifies: false,
+        })
+    }
+
+    fn mode_action(mode: SeccompMode) -> ScmpAction {
+        match mode {
+            SeccompMode::Kill => ScmpAction::KillProcess,
+            SeccompMode::Log => ScmpAction::Log,
+            SeccompMode::Errno => ScmpAction::Errno(libc::EPERM),
+        }
+    }
+
+    fn create_context(default_action: ScmpAction) -> CapsuleResult<ScmpFilterContext> {
+        ScmpFilterContext::new_filter(default_action).map_err(|e| {
+            SandboxError::SeccompSetup(format!("Failed to create seccomp context: {}", e)).into()
+        })
+    }
+
+    /// Route `names` to the supervisor's syscall policy (SECCOMP_RET_USER_NOTIF).
+    ///
+    /// libseccomp keeps the first rule added for a syscall, so this has to
+    /// run before the allowlist is set up.
+    pub fn route_to_supervisor(&mut self, names: &[String]) -> CapsuleResult<()> {
+        let mut ctx = self.ctx.lock().unwrap();
+        Self::add_notify_rules(&mut ctx.inner, names)?;
+        self.notifies |= !names.is_empty();
+        Ok(())
+    }
+
+    fn add_notify_rules(ctx: &mut ScmpFilterContext, names: &[String]) -> CapsuleResult<()> {
+        if let Some(name) = names
+            .iter()
+            .find(|name| NOTIFY_HANDOFF_SYSCALLS.contains(&name.as_str()))
+        {
+            return Err(SandboxError::SeccompSetup(format!(
+                "{} cannot be in notify_syscalls: the child needs it to hand the listener to the supervisor",
+                name
+            ))
+            .into());
+        }
+        for name in names {
+            let syscall = ScmpSyscall::from_name(name).map_err(|_| {
+                SandboxError::SeccompSetup(format!("Unknown syscall '{}' in notify_syscalls", name))
+            })?;
+            ctx.add_rule(ScmpAction::Notify, syscall).map_err(|e| {
+                SandboxError::SeccompSetup(format!("Failed to add notify rule for {}: {}", name, e))
+            })?;
+        }
+        if !names.is_empty() {
+            Self::allow_syscalls(ctx, NOTIFY_HANDOFF_SYSCALLS)?;
+        }
+        Ok(())
+    }
+
+    /// Build a filter from an OCI seccomp profile instead of the built-in allowlist.
+    ///
+    /// In `log` and `errno` mode the profile's default action is replaced by
+    /// the mode's, so the profile can be tuned without SIGSYS deaths. Syscalls
+    /// in `notify` are routed to the supervisor whatever the profile says.
+    pub fn from_oci_profile(
+        profile: &OciSeccompProfile,
+        mode: SeccompMode,
+        notify: &[String],
+    ) -> CapsuleResult<Self> {
+        let default_action = match mode {
+            SeccompMode::Kill => profile.default_action()?,
+            _ => Self::mode_action(mode),
+        };
+        let mut ctx = Self::create_context(default_action)?;
+
+        for arch in profile.architectures()? {
+            ctx.add_arch(arch).map_err(|e| {
+                SandboxError::SeccompSetup(format!("Failed to add architecture {:?}: {}", arch, e))
+            })?;
+        }
+
+        Self::add_notify_rules(&mut ctx, notify)?;
+
+        for rule in &profile.syscalls {
+            let action = rule.action()?;
+            // libseccomp rejects rules that repeat the default action
+            if action == default_action {
+                continue;
+            }
+            let conditions = rule.conditions()?;
+
+            for name in &rule.names {
+                // Profiles list syscalls for several architectures; like runc,
+                // skip names the native architecture does not know
+                let Ok(syscall) = ScmpSyscall::from_name(name) else {
+                    continue;
+                };
+
+                let result = if conditions.is_empty() {
+                    ctx.add_rule(action, syscall)
+                } else {
+                    ctx.add_rule_conditional(action, syscall, &conditions)
+                };
+                result.map_err(|e| {
+                    SandboxError::SeccompSetup(format!(
+                        "Failed to add syscall rule for {}: {}",
+                        name, e
+                    ))
+                })?;
+            }
+        }
 
         Ok(Self {
             ctx: Arc::new(Mutex::new(ThreadSafeFilterContext { inner: ctx })),
+            mode,
+            notifies: !notify.is_empty(),
         })
     }
 
-    pub fn setup_allowlist(&mut self) -> CapsuleResult<()> {
+    /// Allow the preset's syscalls plus `extra`, which take precedence over
+    /// the clone3 and risky-syscall errno rules.
+    pub fn setup_allowlist(
+        &mut self,
+        preset: SeccompPreset,
+        extra: &[String],
+    ) -> CapsuleResult<()> {
         let mut ctx = self.ctx.lock().unwrap();
 
-        // Define a minimal set of allowed syscalls for sandboxed execution
-        let mut allowed_syscalls = Vec::new();
-
-        // Essential I/O operations (8 syscalls)
-        allowed_syscalls.extend_from_slice(&[
-            libc::SYS_read,
-            libc::SYS_write,
-            libc::SYS_readv,
-            libc::SYS_writev,
-            libc::SYS_close,
-            libc::SYS_lseek,
-            libc::SYS_dup,
-            libc::SYS_dup3,
-        ]);
-
-        // Minimal file operations (12 syscalls) - modern syscalls only
-        allowed_syscalls.extend_from_slice(&[
-            libc::SYS_openat,
-            libc::SYS_fstat,
-            libc::SYS_newfstatat,
-            libc::SYS_getcwd,
-            libc::SYS_chdir,
-            libc::SYS_mkdirat,
-            libc::SYS_unlinkat,
-            libc::SYS_renameat2,
-            libc::SYS_fchmod,
-            libc::SYS_ftruncate,
-            libc::SYS_fsync,
-            libc::SYS_pipe2,
-        ]);
-
-        // Directory operations (1 syscall)
-        allowed_syscalls.push(libc::SYS_getdents64);
-
-        // Essential memory management (5 syscalls)
-        allowed_syscalls.extend_from_slice(&[
-            libc::SYS_mmap,
-            libc::SYS_munmap,
-            libc::SYS_mprotect,
-            libc::SYS_madvise,
-            libc::SYS_brk,
-        ]);
-
-        // Minimal process/thread info (5 syscalls)
-        allowed_syscalls.extend_from_slice(&[
-            libc::SYS_getpid,
-            libc::SYS_getuid,
-            libc::SYS_getgid,
-            libc::SYS_gettid,
-            libc::SYS_set_tid_address,
-        ]);
-
-        // Time operations (2 syscalls)
-        allowed_syscalls.extend_from_slice(&[libc::SYS_clock_gettime, libc::SYS_nanosleep]);
-
-        // Essential signal handling (4 syscalls)
-        allowed_syscalls.extend_from_slice(&[
-            libc::SYS_rt_sigaction,
-            libc::SYS_rt_sigprocmask,
-            libc::SYS_rt_sigreturn,
-            libc::SYS_sigaltstack,
-        ]);
-
-        // Process execution and control (4 syscalls)
-        allowed_syscalls.extend_from_slice(&[
-            libc::SYS_execve,
-            libc::SYS_wait4,
-            libc::SYS_exit,
-            libc::SYS_exit_group,
-        ]);
-
-        // Essential polling (3 syscalls)
-        allowed_syscalls.extend_from_slice(&[
-            libc::SYS_ppoll,
-            libc::SYS_epoll_create1,
-            libc::SYS_epoll_pwait,
-        ]);
-
-        // Resource limits (2 syscalls)
-        allowed_syscalls.extend_from_slice(&[libc::SYS_prlimit64, libc::SYS_getrlimit]);
-
-        // Thread synchronization (1 syscall)
-        allowed_syscalls.push(libc::SYS_futex);
-
-        // fcntl for file descriptor operations (1 syscall)
-        allowed_syscalls.push(libc::SYS_fcntl);
-
-        // Additional essential syscalls for compatibility (6 syscalls)
-        allowed_syscalls.extend_from_slice(&[
-            libc::SYS_ioctl,       // Terminal operations
-            libc::SYS_getrandom,   // Secure random numbers
-            libc::SYS_sched_yield, // Thread yielding
-            libc::SYS_kill,        // Send signals to own process
-            libc::SYS_tgkill,      // Thread-targeted signals
-            libc::SYS_geteuid,     // Get effective UID
-        ]);
-
-        // Total: ~55 syscalls - a reasonable balance between security and functionality
-
-        for &syscall in &allowed_syscalls {
+        Self::allow_syscalls(&mut ctx.inner, &base_syscalls(preset))?;
+        let extra: Vec<&str> = extra.iter().map(String::as_str).collect();
+        Self::allow_syscalls(&mut ctx.inner, &extra)?;
+
+        match preset {
+            SeccompPreset::Strict | SeccompPreset::Default => {}
+            SeccompPreset::Build => Self::allow_syscalls(&mut ctx.inner, BUILD_SYSCALLS)?,
+            SeccompPreset::Network => {
+                Self::allow_syscalls(&mut ctx.inner, NETWORK_SYSCALLS)?;
+                Self::allow_syscalls(&mut ctx.inner, NETWORK_PRESET_SYSCALLS)?;
+            }
+        }
+
+        // glibc 2.34+ creates threads with clone3 and falls back to clone on
+        // ENOSYS; clone3 flags live in memory, so they cannot be checked here
+        if preset != SeccompPreset::Build && !extra.contains(&"clone3") {
             ctx.inner
-                .add_rule(ScmpAction::Allow, ScmpSyscall::from(syscall as i32))
+                .add_rule(ScmpAction::Errno(libc::ENOSYS), resolve_syscall("clone3")?)
+                .map_err(|e| {
+                    SandboxError::SeccompSetup(format!("Failed to add clone3 rule: {}", e))
+                })?;
+        }
+
+        let allowed_by_preset: &[&str] = match preset {
+            SeccompPreset::Strict | SeccompPreset::Default => &[],
+            SeccompPreset::Build => BUILD_SYSCALLS,
+            SeccompPreset::Network => NETWORK_PRESET_SYSCALLS,
+        };
+        // In errno mode this is already the default action, which libseccomp
+        // rejects as a rule
+        let deny = ScmpAction::Errno(libc::EPERM);
+        let default_denies = ctx.inner.get_act_default().ok() == Some(deny);
+        for &name in RISKY_SYSCALLS {
+            if default_denies || allowed_by_preset.contains(&name) || extra.contains(&name) {
+                continue;
+            }
+            ctx.inner
+                .add_rule(deny, resolve_syscall(name)?)
                 .map_err(|e| {
                     SandboxError::SeccompSetup(format!(
-                        "Failed to add syscall rule for {}: {}",
-                        syscall, e
+                        "Failed to add deny rule for {}: {}",
+                        name, e
                     ))
                 })?;
         }
 
         // Add conditional rules for more dangerous syscalls
-        Self::add_conditional_rules(&mut ctx)?;
+        Self::add_conditional_rules(&mut ctx, preset)?;
 
         Ok(())
     }
 
+    /// Allow syscalls by name. Names are resolved for every architecture in
+    /// the filter, so a syscall missing on one of them (e.g. `open` on
+    /// aarch64) is only skipped there.
+    fn allow_syscalls(ctx: &mut ScmpFilterContext, names: &[&str]) -> CapsuleResult<()> {
+        for &name in names {
+            ctx.add_rule(ScmpAction::Allow, resolve_syscall(name)?)
+                .map_err(|e| {
+                    SandboxError::SeccompSetup(format!(
+                        "Failed to add syscall rule for {}: {}",
+                        name, e
+                    ))
+                })?;
+        }
+        Ok(())
+    }
+
     fn add_conditional_rules(
         ctx: &mut std::sync::MutexGuard<ThreadSafeFilterContext>,
+        preset: SeccompPreset,
     ) -> CapsuleResult<()> {
         // Allow clone only for thread creation (CLONE_THREAD flag)
         ctx.inner
             .add_rule_conditional(
                 ScmpAction::Allow,
-                ScmpSyscall::from(libc::SYS_clone as i32),
+                resolve_syscall("clone")?,
                 &[ScmpArgCompare::new(
                     0,
                     ScmpCompareOp::MaskedEqual(libc::CLONE_THREAD as u64),
@@ -161,12 +417,16 @@ impl SeccompFilter {
             )
             .map_err(|e| SandboxError::SeccompSetup(format!("Failed to add clone rule: {}", e)))?;
 
+        if preset == SeccompPreset::Strict {
+            return Ok(());
+        }
+
         // Allow prctl for specific operations only
         // PR_SET_NAME (15) - allow setting thread name
         ctx.inner
             .add_rule_conditional(
                 ScmpAction::Allow,
-                ScmpSyscall::from(libc::SYS_prctl as i32),
+                resolve_syscall("prctl")?,
                 &[ScmpArgCompare::new(0, ScmpCompareOp::Equal, 15)],
             )
             .map_err(|e| {
@@ -177,7 +437,7 @@ impl SeccompFilter {
         ctx.inner
             .add_rule_conditional(
                 ScmpAction::Allow,
-                ScmpSyscall::from(libc::SYS_prctl as i32),
+                resolve_syscall("prctl")?,
                 &[ScmpArgCompare::new(0, ScmpCompareOp::Equal, 16)],
             )
             .map_err(|e| {
@@ -188,7 +448,7 @@ impl SeccompFilter {
         ctx.inner
             .add_rule_conditional(
                 ScmpAction::Allow,
-                ScmpSyscall::from(libc::SYS_socket as i32),
+                resolve_syscall("socket")?,
                 &[ScmpArgCompare::new(
                     0,
                     ScmpCompareOp::Equal,
@@ -202,55 +462,268 @@ impl SeccompFilter {
         Ok(())
     }
 
-    pub fn apply(&self) -> CapsuleResult<()> {
+    /// Generate the BPF program for installing in the sandboxed child.
+    ///
+    /// The supervisor itself is never filtered, so it can keep monitoring
+    /// and run further executions.
+    pub fn compile(&self) -> CapsuleResult<CompiledFilter> {
         let ctx = self.ctx.lock().unwrap();
-        ctx.inner.load().map_err(|e| {
-            SandboxError::SeccompSetup(format!("Failed to load seccomp filter: {}", e))
-        })?;
+        let compile_error = |e: &dyn std::fmt::Display| {
+            SandboxError::SeccompSetup(format!("Failed to compile seccomp filter: {}", e))
+        };
 
-        Ok(())
+        let fd = unsafe { libc::memfd_create(c"capsule-seccomp".as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(compile_error(&std::io::Error::last_os_error()).into());
+        }
+        let mut file = unsafe { File::from_raw_fd(fd) };
+
+        ctx.inner
+            .export_bpf(&mut file)
+            .map_err(|e| compile_error(&e))?;
+        let mut bytes = Vec::new();
+        file.seek(SeekFrom::Start(0))
+            .and_then(|_| file.read_to_end(&mut bytes))
+            .map_err(|e| compile_error(&e))?;
+
+        let program = bytes
+            .chunks_exact(std::mem::size_of::<libc::sock_filter>())
+            .map(|insn| libc::sock_filter {
+                code: u16::from_ne_bytes([insn[0], insn[1]]),
+                jt: insn[2],
+                jf: insn[3],
+                k: u32::from_ne_bytes([insn[4], insn[5], insn[6], insn[7]]),
+            })
+            .collect();
+
+        let mut flags = 0;
+        // Have the kernel log every non-allowed action so denials can be reported
+        if self.mode != SeccompMode::Kill {
+            flags |= libc::SECCOMP_FILTER_FLAG_LOG;
+        }
+        if self.notifies {
+            flags |= libc::SECCOMP_FILTER_FLAG_NEW_LISTENER;
+        }
+
+        Ok(CompiledFilter { program, flags })
     }
 
     pub fn with_network_access(self) -> CapsuleResult<Self> {
         // Add network-related syscalls when network access is enabled
-        let network_syscalls = [
-            libc::SYS_socket,
-            libc::SYS_bind,
-            libc::SYS_listen,
-            libc::SYS_accept,
-            libc::SYS_accept4,
-            libc::SYS_connect,
-            libc::SYS_getsockname,
-            libc::SYS_getpeername,
-            libc::SYS_sendto,
-            libc::SYS_recvfrom,
-            libc::SYS_sendmsg,
-            libc::SYS_recvmsg,
-            libc::SYS_shutdown,
-            libc::SYS_setsockopt,
-            libc::SYS_getsockopt,
-        ];
-
         {
             let mut ctx = self.ctx.lock().unwrap();
-            for &syscall in &network_syscalls {
-                ctx.inner
-                    .add_rule(ScmpAction::Allow, ScmpSyscall::from(syscall as i32))
-                    .map_err(|e| {
-                        SandboxError::SeccompSetup(format!(
-                            "Failed to add network syscall rule for {}: {}",
-                            syscall, e
-                        ))
-                    })?;
-            }
+            Self::allow_syscalls(&mut ctx.inner, NETWORK_SYSCALLS)?;
         }
 
         Ok(self)
     }
 }
 
+fn base_syscalls(preset: SeccompPreset) -> Vec<&'static str> {
+    ALLOWED_SYSCALLS
+        .iter()
+        .copied()
+        .filter(|name| preset != SeccompPreset::Strict || !STRICT_EXCLUDED.contains(name))
+        .collect()
+}
+
+/// Syscalls `setup_allowlist` and `with_network_access` allow, sorted, with
+/// those a filter that `notifies` allows for the listener handoff.
+pub fn allowed_syscalls(
+    preset: SeccompPreset,
+    extra: &[String],
+    network: bool,
+    notifies: bool,
+) -> Vec<String> {
+    let mut allowed: Vec<String> = base_syscalls(preset)
+        .into_iter()
+        .chain(
+            match preset {
+                SeccompPreset::Strict | SeccompPreset::Default => &[][..],
+                SeccompPreset::Build => BUILD_SYSCALLS,
+                SeccompPreset::Network => NETWORK_PRESET_SYSCALLS,
+            }
+            .iter()
+            .copied(),
+        )
+        .chain(
+            if network || preset == SeccompPreset::Network {
+                NETWORK_SYSCALLS
+            } else {
+                &[]
+            }
+            .iter()
+            .copied(),
+        )
+        .chain(
+            if notifies {
+                NOTIFY_HANDOFF_SYSCALLS
+            } else {
+                &[]
+            }
+            .iter()
+            .copied(),
+        )
+        .map(str::to_string)
+        .chain(extra.iter().cloned())
+        .collect();
+    allowed.sort();
+    allowed.dedup();
+    allowed
+}
+
+impl CompiledFilter {
+    /// Whether installing returns a listener fd for the supervisor
+    pub fn notifies(&self) -> bool {
+        self.flags & libc::SECCOMP_FILTER_FLAG_NEW_LISTENER != 0
+    }
+
+    /// Install the filter in the calling process, returning the notification
+    /// listener fd if the filter routes syscalls to the supervisor.
+    ///
+    /// Async-signal-safe, for use between fork and exec.
+    pub fn install(&self) -> std::io::Result<Option<RawFd>> {
+        let program = libc::sock_fprog {
+            len: self.program.len() as libc::c_ushort,
+            filter: self.program.as_ptr() as *mut libc::sock_filter,
+        };
+
+        // Required to install a filter without CAP_SYS_ADMIN
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                self.flags,
+                &program as *const libc::sock_fprog,
+            )
+        };
+        if result < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(self.notifies().then_some(result as RawFd))
+    }
+}
+
+fn resolve_syscall(name: &str) -> CapsuleResult<ScmpSyscall> {
+    ScmpSyscall::from_name(name)
+        .map_err(|e| SandboxError::SeccompSetup(format!("Unknown syscall {}: {}", name, e)).into())
+}
+
 impl Default for SeccompFilter {
     fn default() -> Self {
         Self::new().expect("Failed to create default seccomp filter")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowlist_resolves_on_every_arch() {
+        for &arch in FILTER_ARCHES {
+            for &name in ALLOWED_SYSCALLS
+                .iter()
+                .chain(BUILD_SYSCALLS)
+                .chain(NETWORK_PRESET_SYSCALLS)
+                .chain(NETWORK_SYSCALLS)
+                .chain(RISKY_SYSCALLS)
+                .filter(|&&name| !LEGACY_SYSCALLS.contains(&name))
+            {
+                let syscall = ScmpSyscall::from_name_by_arch(name, arch).unwrap();
+                assert!(
+                    i32::from(syscall) >= 0,
+                    "{} has no syscall number on {:?}",
+                    name,
+                    arch
+                );
+            }
+        }
+    }
+
+    /// Only present on x86_64; resolution skips them on aarch64
+    const LEGACY_SYSCALLS: &[&str] = &["fork", "vfork", "dup2", "pipe"];
+
+    #[test]
+    fn test_every_preset_builds() {
+        for preset in [
+            SeccompPreset::Strict,
+            SeccompPreset::Default,
+            SeccompPreset::Build,
+            SeccompPreset::Network,
+        ] {
+            for mode in [SeccompMode::Kill, SeccompMode::Log, SeccompMode::Errno] {
+                let mut filter = SeccompFilter::with_mode(mode).unwrap();
+                assert!(
+                    filter.setup_allowlist(preset, &[]).is_ok(),
+                    "{:?}/{:?}",
+                    preset,
+                    mode
+                );
+                // network: true on top of a preset that already allows sockets
+                assert!(filter.with_network_access().is_ok(), "{:?}", preset);
+            }
+        }
+    }
+
+    #[test]
+    fn test_compile_and_install_in_child() {
+        let mut filter = SeccompFilter::with_mode(SeccompMode::Errno).unwrap();
+        filter.setup_allowlist(SeccompPreset::Default, &[]).unwrap();
+        let compiled = filter.compile().unwrap();
+        assert!(!compiled.program.is_empty());
+        assert!(!compiled.notifies());
+        assert_ne!(compiled.flags & libc::SECCOMP_FILTER_FLAG_LOG, 0);
+
+        // Everything but mkdir is allowed, so the failure is down to the filter
+        let profile = OciSeccompProfile::parse(
+            r#"{
+                "defaultAction": "SCMP_ACT_ALLOW",
+                "syscalls": [{ "names": ["mkdir", "mkdirat"], "action": "SCMP_ACT_ERRNO" }]
+            }"#,
+        )
+        .unwrap();
+        let compiled = SeccompFilter::from_oci_profile(&profile, SeccompMode::Kill, &[])
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(compiled.flags, 0);
+
+        let dir = std::env::temp_dir().join(format!("capsule-seccomp-{}", std::process::id()));
+        let compiled = Arc::new(compiled);
+        let mut cmd = std::process::Command::new("mkdir");
+        cmd.arg(&dir);
+        unsafe {
+            use std::os::unix::process::CommandExt;
+            let compiled = Arc::clone(&compiled);
+            cmd.pre_exec(move || compiled.install().map(|_| ()));
+        }
+
+        // Seccomp may be unavailable in restricted containers
+        if let Ok(status) = cmd.status() {
+            assert!(!status.success());
+            assert!(!dir.exists());
+        }
+        // The test process itself is not filtered
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_filter_covers_both_arches() {
+        let mut filter = SeccompFilter::with_mode(SeccompMode::Kill).unwrap();
+        filter
+            .setup_allowlist(SeccompPreset::Default, &["io_uring_setup".to_string()])
+            .unwrap();
+        let filter = filter.with_network_access().unwrap();
+        let ctx = filter.ctx.lock().unwrap();
+        for &arch in FILTER_ARCHES {
+            assert!(ctx.inner.is_arch_present(arch).unwrap());
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use crate::api::schema::DeniedSyscall;
+use crate::error::{CapsuleResult, SandboxError};
+use libseccomp::ScmpSyscall;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Audit record type the kernel uses for logged seccomp actions.
+const AUDIT_SECCOMP: &str = "type=1326";
+const POLL_TIMEOUT_MS: libc::c_int = 50;
+
+/// Collects seccomp audit records for the execution's process tree from the
+/// kernel log while it runs.
+///
+/// Filters built in `log` or `errno` mode set SECCOMP_FILTER_FLAG_LOG, so the
+/// kernel emits a `type=1326` record for every call that was not allowed.
+/// Records are attributed by walking the parent chain of the reported pid
+/// while the process is still alive, which is why this runs on a thread
+/// rather than once after the command exits. Reading /dev/kmsg needs root
+/// or CAP_SYSLOG.
+pub struct SeccompAuditLog {
+    stop_flag: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<BTreeMap<String, u64>>>,
+}
+
+impl SeccompAuditLog {
+    pub fn start(root_pid: u32) -> CapsuleResult<Self> {
+        let mut kmsg = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open("/dev/kmsg")
+            .map_err(|e| {
+                SandboxError::SeccompSetup(format!(
+                    "Cannot read seccomp audit records from /dev/kmsg: {}",
+                    e
+                ))
+            })?;
+
+        // Only records logged from now on are of interest
+        unsafe {
+            libc::lseek(kmsg.as_raw_fd(), 0, libc::SEEK_END);
+        }
+
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop_flag = Arc::clone(&stop_flag);
+            thread::spawn(move || Self::collect(&mut kmsg, root_pid, &stop_flag))
+        };
+
+        Ok(Self {
+            stop_flag,
+            handle: Some(handle),
+        })
+    }
+
+    /// Stop collecting and return the denied syscalls, sorted by name.
+    pub fn finish(mut self) -> Vec<DeniedSyscall> {
+        self.stop_flag.store(true, Ordering::Relaxed);
+
+        let counts = self
+            .handle
+            .take()
+            .and_then(|handle| handle.join().ok())
+            .unwrap_or_default();
+
+        counts
+            .into_iter()
+            .map(|(name, count)| DeniedSyscall { name, count })
+            .collect()
+    }
+
+    fn collect(kmsg: &mut File, root_pid: u32, stop_flag: &AtomicBool) -> BTreeMap<String, u64> {
+        let mut counts = BTreeMap::new();
+        // Each read returns exactly one record
+        let mut buf = [0u8; 8192];
+
+        loop {
+            match kmsg.read(&mut buf) {
+                Ok(len) => {
+                    let record = String::from_utf8_lossy(&buf[..len]);
+                    if let Some((pid, syscall)) = parse_record(&record) {
+                        if is_in_tree(pid, root_pid) {
+                            *counts.entry(syscall_name(syscall)).or_insert(0) += 1;
+                        }
+                    }
+                }
+                // Older records were overwritten in the ring buffer; keep going
+                Err(e) if e.raw_os_error() == Some(libc::EPIPE) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    // Drain what is left once the command has finished
+                    if stop_flag.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let mut fds = [libc::pollfd {
+                        fd: kmsg.as_raw_fd(),
+                        events: libc::POLLIN,
+                        revents: 0,
+                    }];
+                    unsafe {
+                        libc::poll(fds.as_mut_ptr(), 1, POLL_TIMEOUT_MS);
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+
+        counts
+    }
+}
+
+impl Drop for SeccompAuditLog {
+    fn drop(&mut self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Extract the pid and syscall number from a seccomp audit record.
+fn parse_record(record: &str) -> Option<(u32, i32)> {
+    if !record.contains(AUDIT_SECCOMP) {
+        return None;
+    }
+
+    let field = |key: &str| {
+        record
+            .split_whitespace()
+            .find_map(|token| token.strip_prefix(key))
+    };
+
+    let pid = field("pid=")?.parse().ok()?;
+    let syscall = field("syscall=")?.parse().ok()?;
+    Some((pid, syscall))
+}
+
+fn is_in_tree(pid: u32, root_pid: u32) -> bool {
+    let mut current = pid;
+    while current > 1 {
+        if current == root_pid {
+            return true;
+        }
+        match parent_pid(current) {
+            Some(parent) => current = parent,
+            None => return false,
+        }
+    }
+    false
+}
+
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name may contain spaces and parentheses; fields resume after the last ')'
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn syscall_name(number: i32) -> String {
+    ScmpSyscall::from(number)
+        .get_name()
+        .unwrap_or_else(|_| format!("syscall_{}", number))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_record() {
+        let record = "5,1234,5678,-;audit: type=1326 audit(1700000000.123:45): auid=1000 \
+                      uid=1000 gid=1000 ses=2 pid=4242 comm=\"python3\" exe=\"/usr/bin/python3\" \
+                      sig=0 arch=c000003e syscall=321 compat=0 ip=0x7f code=0x7ffc0000";
+        assert_eq!(parse_record(record), Some((4242, 321)));
+        assert_eq!(
+            parse_record("6,1,2,-;audit: type=1400 pid=1 syscall=2"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_in_tree() {
+        let own_pid = std::process::id();
+        assert!(is_in_tree(own_pid, own_pid));
+
+        let mut child = std::process::Command::new("sleep")
+            .arg("5")
+            .spawn()
+            .unwrap();
+        assert!(is_in_tree(child.id(), own_pid));
+        assert!(!is_in_tree(own_pid, child.id()));
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+
+    #[test]
+    fn test_syscall_name() {
+        assert_eq!(
+            syscall_name(ScmpSyscall::from_name("read").unwrap().into()),
+            "read"
+        );
+    }
+}
@@ -0,0 +1,520 @@
+use crate::error::{CapsuleResult, SandboxError};
+use libseccomp::ScmpSyscall;
+use libseccomp_sys::{
+    seccomp_notif, seccomp_notif_resp, seccomp_notify_alloc, seccomp_notify_free,
+    seccomp_notify_id_valid, seccomp_notify_receive, seccomp_notify_respond,
+    SECCOMP_USER_NOTIF_FLAG_CONTINUE,
+};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+const POLL_TIMEOUT_MS: libc::c_int = 50;
+
+/// A syscall the filter routed to the supervisor (SECCOMP_RET_USER_NOTIF).
+#[derive(Debug, Clone)]
+pub struct SyscallNotification {
+    pub pid: u32,
+    pub syscall: String,
+    pub args: [u64; 6],
+}
+
+/// What to do with a notified syscall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallDecision {
+    /// Let the kernel run the syscall as if it had been allowed
+    Allow,
+    /// Fail the syscall with this errno
+    Deny(i32),
+}
+
+/// Runtime policy for syscalls listed in `isolation.notify_syscalls`.
+///
+/// Called on a supervisor thread while the caller is blocked in the syscall,
+/// so decisions should be quick. Closures of the right shape implement it.
+pub trait SyscallPolicy: Send + Sync {
+    fn check(&self, notification: &SyscallNotification) -> SyscallDecision;
+}
+
+impl<F> SyscallPolicy for F
+where
+    F: Fn(&SyscallNotification) -> SyscallDecision + Send + Sync,
+{
+    fn check(&self, notification: &SyscallNotification) -> SyscallDecision {
+        self(notification)
+    }
+}
+
+impl SyscallNotification {
+    /// Destination of a `connect` or `sendto` call, read from the caller's memory.
+    ///
+    /// The caller can change the buffer after it is read, so an allowed
+    /// address is only a hint unless the process is single threaded; the
+    /// kernel re-reads the sockaddr when the syscall continues.
+    pub fn socket_address(&self) -> Option<SocketAddr> {
+        let (ptr, len) = match self.syscall.as_str() {
+            "connect" => (self.args[1], self.args[2]),
+            "sendto" => (self.args[4], self.args[5]),
+            _ => return None,
+        };
+
+        let mut buf = [0u8; std::mem::size_of::<libc::sockaddr_in6>()];
+        let len = (len as usize).min(buf.len());
+        let read = read_process_memory(self.pid, ptr, &mut buf[..len])?;
+        parse_sockaddr(&buf[..read])
+    }
+}
+
+/// Hands the listener fd from the child, which installs the filter right
+/// before exec, to the supervisor over a socketpair (SCM_RIGHTS).
+pub struct NotifyChannel {
+    supervisor: OwnedFd,
+    child: OwnedFd,
+}
+
+/// Control message buffer with the alignment `cmsghdr` requires.
+#[repr(C, align(8))]
+struct ControlBuffer([u8; 64]);
+
+impl NotifyChannel {
+    pub fn new() -> CapsuleResult<Self> {
+        let mut fds = [0; 2];
+        // Both ends are close-on-exec, so the command never sees them
+        let result = unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+                0,
+                fds.as_mut_ptr(),
+            )
+        };
+        if result != 0 {
+            return Err(SandboxError::SeccompSetup(format!(
+                "Failed to create seccomp notify channel: {}",
+                std::io::Error::last_os_error()
+            ))
+            .into());
+        }
+
+        Ok(Self {
+            supervisor: unsafe { OwnedFd::from_raw_fd(fds[0]) },
+            child: unsafe { OwnedFd::from_raw_fd(fds[1]) },
+        })
+    }
+
+    /// Send `fd` to the supervisor. Async-signal-safe, for use between fork and exec.
+    pub fn send(&self, fd: RawFd) -> std::io::Result<()> {
+        let mut byte = [0u8; 1];
+        let mut iov = libc::iovec {
+            iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+            iov_len: byte.len(),
+        };
+        let mut control = ControlBuffer([0; 64]);
+
+        unsafe {
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.0.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32) as _;
+
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as u32) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+
+            if libc::sendmsg(self.child.as_raw_fd(), &msg, 0) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Take the fd the child sent. Called after spawn, by which time the child
+    /// has either sent it or failed to start.
+    pub fn receive(&self) -> CapsuleResult<OwnedFd> {
+        let mut byte = [0u8; 1];
+        let mut iov = libc::iovec {
+            iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+            iov_len: byte.len(),
+        };
+        let mut control = ControlBuffer([0; 64]);
+
+        let fd = unsafe {
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.0.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = control.0.len() as _;
+
+            let flags = libc::MSG_DONTWAIT | libc::MSG_CMSG_CLOEXEC;
+            if libc::recvmsg(self.supervisor.as_raw_fd(), &mut msg, flags) < 0 {
+                None
+            } else {
+                let cmsg = libc::CMSG_FIRSTHDR(&msg);
+                (!cmsg.is_null()
+                    && (*cmsg).cmsg_level == libc::SOL_SOCKET
+                    && (*cmsg).cmsg_type == libc::SCM_RIGHTS)
+                    .then(|| std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const RawFd))
+            }
+        };
+
+        match fd {
+            Some(fd) => Ok(unsafe { OwnedFd::from_raw_fd(fd) }),
+            None => Err(SandboxError::SeccompSetup(
+                "Sandboxed process did not hand over its seccomp listener".to_string(),
+            )
+            .into()),
+        }
+    }
+}
+
+/// Answers user notifications from the seccomp listener fd on a thread,
+/// until stopped or the last filtered process exits.
+pub struct SeccompNotifier {
+    stop_flag: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl SeccompNotifier {
+    pub fn start(listener: OwnedFd, policy: Arc<dyn SyscallPolicy>) -> CapsuleResult<Self> {
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop_flag = Arc::clone(&stop_flag);
+            thread::Builder::new()
+                .name("seccomp-notify".to_string())
+                .spawn(move || Self::serve(listener.as_raw_fd(), policy.as_ref(), &stop_flag))
+                .map_err(|e| {
+                    SandboxError::SeccompSetup(format!(
+                        "Failed to start seccomp notification thread: {}",
+                        e
+                    ))
+                })?
+        };
+
+        Ok(Self {
+            stop_flag,
+            handle: Some(handle),
+        })
+    }
+
+    fn serve(fd: RawFd, policy: &dyn SyscallPolicy, stop_flag: &AtomicBool) {
+        while !stop_flag.load(Ordering::Relaxed) {
+            let mut fds = [libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            }];
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), 1, POLL_TIMEOUT_MS) };
+            if ready <= 0 {
+                continue;
+            }
+            if fds[0].revents & libc::POLLIN == 0 {
+                // POLLHUP: no process is using the filter any more
+                break;
+            }
+
+            // The caller may have been killed between poll and receive
+            let Some(request) = receive(fd) else {
+                continue;
+            };
+            let syscall = ScmpSyscall::from(request.data.nr);
+            let notification = SyscallNotification {
+                pid: request.pid,
+                syscall: syscall
+                    .get_name()
+                    .unwrap_or_else(|_| format!("syscall_{}", request.data.nr)),
+                args: request.data.args,
+            };
+
+            let decision = policy.check(&notification);
+
+            // The pid may have been reused if the caller died while we decided
+            if unsafe { seccomp_notify_id_valid(fd, request.id) } != 0 {
+                continue;
+            }
+            let response = match decision {
+                SyscallDecision::Allow => seccomp_notif_resp {
+                    id: request.id,
+                    val: 0,
+                    error: 0,
+                    flags: SECCOMP_USER_NOTIF_FLAG_CONTINUE,
+                },
+                SyscallDecision::Deny(errno) => seccomp_notif_resp {
+                    id: request.id,
+                    val: 0,
+                    error: -errno.abs(),
+                    flags: 0,
+                },
+            };
+            respond(fd, response);
+        }
+    }
+}
+
+impl Drop for SeccompNotifier {
+    fn drop(&mut self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn receive(fd: RawFd) -> Option<seccomp_notif> {
+    // libseccomp sizes the buffers for the running kernel
+    let mut req: *mut seccomp_notif = std::ptr::null_mut();
+    if unsafe { seccomp_notify_alloc(&mut req, std::ptr::null_mut()) } != 0 {
+        return None;
+    }
+    let result = unsafe { seccomp_notify_receive(fd, req) };
+    let request = (result == 0).then(|| unsafe { *req });
+    unsafe { seccomp_notify_free(req, std::ptr::null_mut()) };
+    request
+}
+
+fn respond(fd: RawFd, response: seccomp_notif_resp) {
+    let mut resp: *mut seccomp_notif_resp = std::ptr::null_mut();
+    if unsafe { seccomp_notify_alloc(std::ptr::null_mut(), &mut resp) } != 0 {
+        return;
+    }
+    unsafe {
+        *resp = response;
+        seccomp_notify_respond(fd, resp);
+        seccomp_notify_free(std::ptr::null_mut(), resp);
+    }
+}
+
+fn read_process_memory(pid: u32, address: u64, buf: &mut [u8]) -> Option<usize> {
+    let local = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let remote = libc::iovec {
+        iov_base: address as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let read = unsafe { libc::process_vm_readv(pid as libc::pid_t, &local, 1, &remote, 1, 0) };
+    (read >= 0).then_some(read as usize)
+}
+
+fn parse_sockaddr(buf: &[u8]) -> Option<SocketAddr> {
+    let family = u16::from_ne_bytes(buf.get(0..2)?.try_into().ok()?);
+    let port = u16::from_be_bytes(buf.get(2..4)?.try_into().ok()?);
+
+    let ip = match family as libc::c_int {
+        libc::AF_INET => {
+            let octets: [u8; 4] = buf.get(4..8)?.try_into().ok()?;
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        libc::AF_INET6 => {
+            let octets: [u8; 16] = buf.get(8..24)?.try_into().ok()?;
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+
+    Some(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sockaddr() {
+        let mut addr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+        addr.sin_family = libc::AF_INET as libc::sa_family_t;
+        addr.sin_port = 443u16.to_be();
+        addr.sin_addr.s_addr = u32::from_ne_bytes([10, 0, 0, 1]);
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                &addr as *const libc::sockaddr_in as *const u8,
+                std::mem::size_of::<libc::sockaddr_in>(),
+            )
+        };
+
+        assert_eq!(parse_sockaddr(bytes), Some("10.0.0.1:443".parse().unwrap()));
+        assert_eq!(parse_sockaddr(&[1, 0]), None);
+    }
+
+    #[test]
+    fn test_socket_address_reads_caller_memory() {
+        let mut addr: libc::sockaddr_in6 = unsafe { std::mem::zeroed() };
+        addr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+        addr.sin6_port = 8080u16.to_be();
+        addr.sin6_addr.s6_addr = Ipv6Addr::LOCALHOST.octets();
+
+        let notification = SyscallNotification {
+            pid: std::process::id(),
+            syscall: "connect".to_string(),
+            args: [
+                3,
+                &addr as *const libc::sockaddr_in6 as u64,
+                std::mem::size_of::<libc::sockaddr_in6>() as u64,
+                0,
+                0,
+                0,
+            ],
+        };
+
+        // process_vm_readv on ourselves may still be blocked by Yama or a container policy
+        if let Some(address) = notification.socket_address() {
+            assert_eq!(address, "[::1]:8080".parse().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_notify_channel_passes_fd() {
+        let channel = NotifyChannel::new().unwrap();
+        let file = std::fs::File::open("/proc/self/status").unwrap();
+        channel.send(file.as_raw_fd()).unwrap();
+
+        let received = channel.receive().unwrap();
+        assert_ne!(received.as_raw_fd(), file.as_raw_fd());
+        let mut content = String::new();
+        use std::io::Read;
+        std::fs::File::from(received)
+            .read_to_string(&mut content)
+            .unwrap();
+        assert!(content.contains("Pid:"));
+
+        // Nothing left to take
+        assert!(channel.receive().is_err());
+    }
+
+    #[test]
+    fn test_policy_decides_child_syscall() {
+        use crate::api::schema::{SeccompMode, SeccompPreset};
+        use crate::sandbox::SeccompFilter;
+        use std::sync::atomic::AtomicUsize;
+
+        // The built-in preset without network, which allows no sendmsg of its own
+        let mut filter = SeccompFilter::with_mode(SeccompMode::Kill).unwrap();
+        filter
+            .route_to_supervisor(&["mkdir".to_string(), "mkdirat".to_string()])
+            .unwrap();
+        filter.setup_allowlist(SeccompPreset::Default, &[]).unwrap();
+        let compiled = filter.compile().unwrap();
+        assert!(compiled.notifies());
+
+        let channel = NotifyChannel::new().unwrap();
+        let dir = std::ffi::CString::new(format!(
+            "{}/capsule-notify-{}",
+            std::env::temp_dir().display(),
+            std::process::id()
+        ))
+        .unwrap();
+
+        // The child does what the pre_exec hook does, then makes the notified
+        // syscall itself, as the preset would not let a dynamic binary start
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            let code = match compiled.install() {
+                Ok(Some(listener)) => {
+                    if channel.send(listener).is_err() {
+                        unsafe { libc::_exit(2) };
+                    }
+                    unsafe { libc::close(listener) };
+                    let created = unsafe { libc::mkdir(dir.as_ptr(), 0o700) } == 0;
+                    let errno = std::io::Error::last_os_error().raw_os_error();
+                    if !created && errno == Some(libc::EACCES) {
+                        0
+                    } else {
+                        3
+                    }
+                }
+                // User notification may be unavailable in restricted containers
+                _ => 4,
+            };
+            unsafe { libc::_exit(code) };
+        }
+
+        // Unlike spawn, fork returns before the child has handed over
+        let mut handoff = libc::pollfd {
+            fd: channel.supervisor.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut handoff, 1, 5000) };
+
+        let checked = Arc::new(AtomicUsize::new(0));
+        let notifier = channel.receive().ok().map(|listener| {
+            let checked = Arc::clone(&checked);
+            let policy = move |n: &SyscallNotification| {
+                assert!(n.syscall.starts_with("mkdir"));
+                checked.fetch_add(1, Ordering::SeqCst);
+                SyscallDecision::Deny(libc::EACCES)
+            };
+            SeccompNotifier::start(listener, Arc::new(policy)).unwrap()
+        });
+
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        drop(notifier);
+        // Killed by SIGSYS if the handoff itself was filtered
+        assert!(libc::WIFEXITED(status), "wait status {}", status);
+        if libc::WEXITSTATUS(status) == 4 {
+            return;
+        }
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+        assert!(checked.load(Ordering::SeqCst) > 0);
+    }
+
+    #[test]
+    fn test_notify_rules() {
+        let mut filter =
+            crate::sandbox::SeccompFilter::with_mode(crate::api::schema::SeccompMode::Kill)
+                .unwrap();
+        assert!(filter.route_to_supervisor(&["connect".to_string()]).is_ok());
+        assert!(filter
+            .route_to_supervisor(&["not_a_real_syscall".to_string()])
+            .is_err());
+        // Allow rules added afterwards do not override the notify rule
+        assert!(filter
+            .setup_allowlist(crate::api::schema::SeccompPreset::Default, &[])
+            .is_ok());
+        // The child hands the listener over with these
+        for name in ["sendmsg", "close"] {
+            assert!(filter.route_to_supervisor(&[name.to_string()]).is_err());
+        }
+        // and a profile denying them does not take them away
+        let profile = crate::sandbox::seccomp_profile::OciSeccompProfile::parse(
+            r#"{
+                "defaultAction": "SCMP_ACT_ERRNO",
+                "syscalls": [{ "names": ["sendmsg"], "action": "SCMP_ACT_KILL" }]
+            }"#,
+        )
+        .unwrap();
+        assert!(crate::sandbox::SeccompFilter::from_oci_profile(
+            &profile,
+            crate::api::schema::SeccompMode::Kill,
+            &["connect".to_string()],
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_closure_policy() {
+        let policy = |n: &SyscallNotification| {
+            if n.syscall == "connect" {
+                SyscallDecision::Deny(libc::EACCES)
+            } else {
+                SyscallDecision::Allow
+            }
+        };
+        let notification = SyscallNotification {
+            pid: 1,
+            syscall: "connect".to_string(),
+            args: [0; 6],
+        };
+        assert_eq!(
+            policy.check(&notification),
+            SyscallDecision::Deny(libc::EACCES)
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use crate::error::{CapsuleResult, SandboxError};
+use libseccomp::{ScmpAction, ScmpArch, ScmpArgCompare, ScmpCompareOp, ScmpSyscall};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+
+/// A seccomp profile in the OCI runtime-spec format (`linux.seccomp`), as
+/// used by runc, Docker and Podman. Unknown keys such as Docker's `archMap`
+/// or `includes` are ignored.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OciSeccompProfile {
+    pub default_action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_errno_ret: Option<u32>,
+    #[serde(default)]
+    pub architectures: Vec<String>,
+    #[serde(default)]
+    pub syscalls: Vec<OciSyscallRule>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OciSyscallRule {
+    pub names: Vec<String>,
+    pub action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub errno_ret: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<OciSyscallArg>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OciSyscallArg {
+    pub index: u32,
+    pub value: u64,
+    #[serde(default)]
+    pub value_two: u64,
+    pub op: String,
+}
+
+impl OciSeccompProfile {
+    pub fn load(path: &Path) -> CapsuleResult<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            SandboxError::SeccompSetup(format!(
+                "Failed to read seccomp profile {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        Self::parse(&content).map_err(|e| {
+            SandboxError::SeccompSetup(format!("Invalid seccomp profile {}: {}", path.display(), e))
+                .into()
+        })
+    }
+
+    /// A profile that allows exactly `syscalls` on the native architecture and
+    /// fails everything else with EPERM, as produced by `profile-record`.
+    pub fn allowing(syscalls: impl IntoIterator<Item = i64>) -> Self {
+        let mut names: Vec<String> = syscalls
+            .into_iter()
+            .map(|nr| {
+                ScmpSyscall::from(nr as i32)
+                    .get_name()
+                    .unwrap_or_else(|_| format!("syscall_{}", nr))
+            })
+            .collect();
+        names.sort();
+        names.dedup();
+
+        Self {
+            default_action: "SCMP_ACT_ERRNO".to_string(),
+            default_errno_ret: None,
+            architectures: vec![native_arch_name().to_string()],
+            syscalls: vec![OciSyscallRule {
+                names,
+                action: "SCMP_ACT_ALLOW".to_string(),
+                errno_ret: None,
+                args: Vec::new(),
+            }],
+        }
+    }
+
+    /// The syscalls the profile lets run, allowed or only logged, with or
+    /// without argument conditions. None when that is the default action,
+    /// as the syscalls cannot be listed.
+    pub fn allowed_names(&self) -> Option<Vec<&str>> {
+        let runs = |action: &str| matches!(action, "SCMP_ACT_ALLOW" | "SCMP_ACT_LOG");
+        if runs(&self.default_action) {
+            return None;
+        }
+        Some(
+            self.syscalls
+                .iter()
+                .filter(|rule| runs(&rule.action))
+                .flat_map(|rule| rule.names.iter().map(String::as_str))
+                .collect(),
+        )
+    }
+
+    pub fn parse(content: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(content)
+    }
+
+    pub fn default_action(&self) -> CapsuleResult<ScmpAction> {
+        parse_action(&self.default_action, self.default_errno_ret)
+    }
+
+    pub fn architectures(&self) -> CapsuleResult<Vec<ScmpArch>> {
+        self.architectures
+            .iter()
+            .map(|arch| {
+                ScmpArch::from_str(arch).map_err(|_| {
+                    SandboxError::SeccompSetup(format!("Unknown seccomp architecture '{}'", arch))
+                        .into()
+                })
+            })
+            .collect()
+    }
+}
+
+impl OciSyscallRule {
+    pub fn action(&self) -> CapsuleResult<ScmpAction> {
+        parse_action(&self.action, self.errno_ret)
+    }
+
+    pub fn conditions(&self) -> CapsuleResult<Vec<ScmpArgCompare>> {
+        self.args.iter().map(OciSyscallArg::to_compare).collect()
+    }
+}
+
+impl OciSyscallArg {
+    fn to_compare(&self) -> CapsuleResult<ScmpArgCompare> {
+        // For SCMP_CMP_MASKED_EQ, `value` is the mask and `valueTwo` the datum
+        let (op, datum) = if self.op == "SCMP_CMP_MASKED_EQ" {
+            (ScmpCompareOp::MaskedEqual(self.value), self.value_two)
+        } else {
+            let op = ScmpCompareOp::from_str(&self.op).map_err(|_| {
+                SandboxError::SeccompSetup(format!("Unknown seccomp operator '{}'", self.op))
+            })?;
+            (op, self.value)
+        };
+
+        Ok(ScmpArgCompare::new(self.index, op, datum))
+    }
+}
+
+fn native_arch_name() -> &'static str {
+    if cfg!(target_arch = "x86_64") {
+        "SCMP_ARCH_X86_64"
+    } else if cfg!(target_arch = "aarch64") {
+        "SCMP_ARCH_AARCH64"
+    } else {
+        "SCMP_ARCH_NATIVE"
+    }
+}
+
+fn parse_action(action: &str, errno_ret: Option<u32>) -> CapsuleResult<ScmpAction> {
+    // ERRNO defaults to EPERM as in runc; TRACE defaults to message 0
+    let value = match action {
+        "SCMP_ACT_TRACE" => errno_ret.unwrap_or(0),
+        _ => errno_ret.unwrap_or(libc::EPERM as u32),
+    };
+
+    ScmpAction::from_str(action, Some(value as i32)).map_err(|_| {
+        SandboxError::SeccompSetup(format!("Unknown seccomp action '{}'", action)).into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: &str = r#"{
+        "defaultAction": "SCMP_ACT_ERRNO",
+        "defaultErrnoRet": 38,
+        "architectures": ["SCMP_ARCH_X86_64", "SCMP_ARCH_AARCH64"],
+        "archMap": [],
+        "syscalls": [
+            { "names": ["read", "write"], "action": "SCMP_ACT_ALLOW" },
+            {
+                "names": ["personality"],
+                "action": "SCMP_ACT_ALLOW",
+                "args": [{ "index": 0, "value": 4294967295, "op": "SCMP_CMP_EQ" }]
+            },
+            {
+                "names": ["clone"],
+                "action": "SCMP_ACT_ALLOW",
+                "args": [{ "index": 0, "value": 2114060288, "valueTwo": 0, "op": "SCMP_CMP_MASKED_EQ" }]
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn test_parse_oci_profile() {
+        let profile = OciSeccompProfile::parse(PROFILE).unwrap();
+        assert_eq!(profile.default_action().unwrap(), ScmpAction::Errno(38));
+        assert_eq!(
+            profile.architectures().unwrap(),
+            vec![ScmpArch::X8664, ScmpArch::Aarch64]
+        );
+        assert_eq!(profile.syscalls.len(), 3);
+        assert_eq!(profile.syscalls[0].action().unwrap(), ScmpAction::Allow);
+        assert_eq!(profile.syscalls[1].conditions().unwrap().len(), 1);
+        assert_eq!(profile.syscalls[2].conditions().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_filter_from_oci_profile() {
+        let mut profile = OciSeccompProfile::parse(PROFILE).unwrap();
+        // Names from other architectures are skipped rather than rejected
+        profile.syscalls[0]
+            .names
+            .push("not_a_real_syscall".to_string());
+        assert!(crate::sandbox::SeccompFilter::from_oci_profile(
+            &profile,
+            crate::api::schema::SeccompMode::Kill,
+            &[]
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_allowing_profile_round_trips() {
+        let profile =
+            OciSeccompProfile::allowing([libc::SYS_write, libc::SYS_read, libc::SYS_read]);
+        assert_eq!(profile.syscalls[0].names, vec!["read", "write"]);
+
+        let json = serde_json::to_string(&profile).unwrap();
+        assert!(json.contains("\"defaultAction\":\"SCMP_ACT_ERRNO\""));
+        assert!(!json.contains("errnoRet"));
+
+        let parsed = OciSeccompProfile::parse(&json).unwrap();
+        assert_eq!(parsed.syscalls[0].names, profile.syscalls[0].names);
+        assert_eq!(
+            parsed.default_action().unwrap(),
+            ScmpAction::Errno(libc::EPERM)
+        );
+    }
+
+    #[test]
+    fn test_errno_defaults_to_eperm() {
+        assert_eq!(
+            parse_action("SCMP_ACT_ERRNO", None).unwrap(),
+            ScmpAction::Errno(libc::EPERM)
+        );
+        assert!(parse_action("SCMP_ACT_BOGUS", None).is_err());
+    }
+
+    #[test]
+    fn test_unknown_operator_rejected() {
+        let arg = OciSyscallArg {
+            index: 0,
+            value: 1,
+            value_two: 0,
+            op: "SCMP_CMP_SOMETIMES".to_string(),
+        };
+        assert!(arg.to_compare().is_err());
+    }
+}
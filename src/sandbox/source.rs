@@ -0,0 +1,149 @@
+use super::archive::WorkspaceArchive;
+use crate::api::schema::{CloneLocation, GitSource};
+use crate::error::{CapsuleResult, SandboxError};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// A git source ready to populate /workspace.
+///
+/// Sandbox clones, the default, are fetched after setup by the supervisor,
+/// which by then shares the sandbox's namespaces and sees only its
+/// filesystem. Host clones, for remotes the config allows, are fetched
+/// before setup with the host's network and credentials, and carried into
+/// the sandbox as a scratch tarball.
+pub enum SourceCheckout {
+    Fetched(WorkspaceArchive),
+    Deferred(GitSource),
+}
+
+impl SourceCheckout {
+    pub fn prepare(source: &GitSource) -> CapsuleResult<Self> {
+        match source.clone {
+            CloneLocation::Sandbox => Ok(Self::Deferred(source.clone())),
+            CloneLocation::Host => {
+                let checkout = tempfile::tempdir().map_err(|e| {
+                    SandboxError::FilesystemSetup(format!(
+                        "Failed to create checkout directory for {}: {}",
+                        source.git, e
+                    ))
+                })?;
+                fetch(checkout.path(), source)?;
+                let archive = WorkspaceArchive::temporary(&source.git)?;
+                archive.capture(checkout.path())?;
+                Ok(Self::Fetched(archive))
+            }
+        }
+    }
+
+    pub fn populate(&self, workspace: &Path) -> CapsuleResult<()> {
+        match self {
+            Self::Fetched(archive) => archive.extract(workspace),
+            Self::Deferred(source) => fetch(workspace, source),
+        }
+    }
+}
+
+/// Check out `source` into `dir`. Init and fetch rather than clone, so a
+/// commit can be fetched shallowly and `dir` may already exist.
+fn fetch(dir: &Path, source: &GitSource) -> CapsuleResult<()> {
+    let depth = source.depth.map(|depth| format!("--depth={}", depth));
+    let mut fetch_args = vec!["fetch", "--quiet", "--no-tags"];
+    fetch_args.extend(depth.as_deref());
+    fetch_args.extend([
+        "--",
+        source.git.as_str(),
+        source.git_ref.as_deref().unwrap_or("HEAD"),
+    ]);
+
+    git(dir, source, &["init", "--quiet"])?;
+    git(dir, source, &fetch_args)?;
+    git(
+        dir,
+        source,
+        &["checkout", "--quiet", "--detach", "FETCH_HEAD"],
+    )
+}
+
+fn git(dir: &Path, source: &GitSource, args: &[&str]) -> CapsuleResult<()> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        // Fail on missing credentials instead of waiting for a password
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| {
+            SandboxError::FilesystemSetup(format!("Failed to run git for {}: {}", source.git, e))
+        })?;
+    if !output.status.success() {
+        return Err(SandboxError::FilesystemSetup(format!(
+            "git {} failed for {}: {}",
+            args[0],
+            source.git,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_host_checkout_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = dir.path().join("remote");
+        fs::create_dir_all(&remote).unwrap();
+        fs::write(remote.join("README"), "first").unwrap();
+        let commit = |message: &str| {
+            Command::new("git")
+                .arg("-C")
+                .arg(&remote)
+                .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+                .args(["commit", "--quiet", "--all", "-m", message])
+                .status()
+        };
+        let initialized = Command::new("git")
+            .arg("-C")
+            .arg(&remote)
+            .args(["init", "--quiet"])
+            .status()
+            .is_ok_and(|status| status.success());
+        if !initialized {
+            return; // git is not installed
+        }
+        Command::new("git")
+            .arg("-C")
+            .arg(&remote)
+            .args(["add", "README"])
+            .status()
+            .unwrap();
+        assert!(commit("first").unwrap().success());
+        fs::write(remote.join("README"), "second").unwrap();
+        assert!(commit("second").unwrap().success());
+
+        let source = GitSource {
+            git: remote.to_string_lossy().into_owned(),
+            git_ref: None,
+            depth: Some(1),
+            clone: CloneLocation::Host,
+        };
+        let workspace = dir.path().join("workspace");
+        fs::create_dir_all(&workspace).unwrap();
+        SourceCheckout::prepare(&source)
+            .unwrap()
+            .populate(&workspace)
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(workspace.join("README")).unwrap(),
+            "second"
+        );
+        assert!(workspace.join(".git").is_dir());
+    }
+}
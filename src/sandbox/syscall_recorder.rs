@@ -0,0 +1,139 @@
+use crate::error::{CapsuleResult, ExecutionError};
+use nix::errno::Errno;
+use nix::sys::ptrace::{self, Options};
+use nix::sys::signal::Signal;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use std::collections::{BTreeSet, HashMap};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+// Not exposed by libc on every target env (e.g. musl)
+const PTRACE_GET_SYSCALL_INFO: libc::c_uint = 0x420e;
+const PTRACE_SYSCALL_INFO_ENTRY: u8 = 1;
+
+/// `struct ptrace_syscall_info` with the entry/seccomp union member. Reading
+/// the syscall number through it keeps the recorder architecture independent,
+/// unlike PTRACE_GETREGS (Linux 5.3+).
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)] // Mirrors the kernel layout; only op and nr are read
+struct SyscallInfo {
+    op: u8,
+    pad: [u8; 3],
+    arch: u32,
+    instruction_pointer: u64,
+    stack_pointer: u64,
+    nr: u64,
+    args: [u64; 6],
+    ret_data: u32,
+    pad2: u32,
+}
+
+/// Syscalls made by a traced command and its descendants.
+pub struct SyscallRecording {
+    pub exit_code: i32,
+    pub syscalls: BTreeSet<i64>,
+}
+
+/// Run `command` under ptrace, following forks and clones, and record every
+/// syscall number it enters.
+///
+/// Tracing starts at the initial execve, so everything the command does is
+/// covered, but the command runs without sandbox restrictions. Tracees are
+/// reaped with `waitpid(-1)`, so the calling process must not have other
+/// children it expects to wait for.
+pub fn record_syscalls(
+    command: &[String],
+    environment: &HashMap<String, String>,
+) -> CapsuleResult<SyscallRecording> {
+    let mut cmd = Command::new(&command[0]);
+    cmd.args(&command[1..]).envs(environment);
+    unsafe {
+        // The child stops with SIGTRAP once execve succeeds
+        cmd.pre_exec(|| ptrace::traceme().map_err(std::io::Error::from));
+    }
+
+    let child = cmd
+        .spawn()
+        .map_err(|e| ExecutionError::SpawnFailed(format!("Failed to spawn command: {}", e)))?;
+    let root = Pid::from_raw(child.id() as i32);
+
+    waitpid(root, Some(WaitPidFlag::__WALL)).map_err(trace_error)?;
+    ptrace::setoptions(
+        root,
+        Options::PTRACE_O_TRACESYSGOOD
+            | Options::PTRACE_O_TRACEFORK
+            | Options::PTRACE_O_TRACEVFORK
+            | Options::PTRACE_O_TRACECLONE
+            | Options::PTRACE_O_EXITKILL,
+    )
+    .map_err(trace_error)?;
+    // The execve that got us here is not reported as a syscall stop
+    let mut syscalls = BTreeSet::from([libc::SYS_execve]);
+    ptrace::syscall(root, None).map_err(trace_error)?;
+
+    let mut exit_code = -1;
+    loop {
+        let status = match waitpid(None, Some(WaitPidFlag::__WALL)) {
+            Ok(status) => status,
+            Err(Errno::ECHILD) => break,
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(trace_error(e)),
+        };
+
+        match status {
+            WaitStatus::PtraceSyscall(pid) => {
+                if let Some(nr) = syscall_entry(pid) {
+                    syscalls.insert(nr);
+                }
+                let _ = ptrace::syscall(pid, None);
+            }
+            // Fork/clone notifications and the initial stop of new tracees
+            WaitStatus::PtraceEvent(pid, _, _) | WaitStatus::Stopped(pid, Signal::SIGSTOP) => {
+                let _ = ptrace::syscall(pid, None);
+            }
+            WaitStatus::Stopped(pid, signal) => {
+                // Deliver real signals to the tracee
+                let _ = ptrace::syscall(pid, Some(signal));
+            }
+            WaitStatus::Exited(pid, code) if pid == root => exit_code = code,
+            WaitStatus::Signaled(pid, signal, _) if pid == root => exit_code = 128 + signal as i32,
+            _ => {}
+        }
+    }
+
+    Ok(SyscallRecording {
+        exit_code,
+        syscalls,
+    })
+}
+
+/// Syscall number when `pid` is stopped at a syscall entry (not exit).
+fn syscall_entry(pid: Pid) -> Option<i64> {
+    let mut info = SyscallInfo::default();
+    let result = unsafe {
+        libc::ptrace(
+            PTRACE_GET_SYSCALL_INFO as _,
+            pid.as_raw(),
+            std::mem::size_of::<SyscallInfo>(),
+            &mut info as *mut SyscallInfo,
+        )
+    };
+    (result > 0 && info.op == PTRACE_SYSCALL_INFO_ENTRY).then_some(info.nr as i64)
+}
+
+fn trace_error(e: Errno) -> crate::error::CapsuleError {
+    ExecutionError::MonitoringError(format!("Failed to trace command: {}", e)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syscall_info_layout() {
+        // Must match struct ptrace_syscall_info from linux/ptrace.h
+        assert_eq!(std::mem::size_of::<SyscallInfo>(), 88);
+    }
+}
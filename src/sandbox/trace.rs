@@ -0,0 +1,54 @@
+use std::fmt::Display;
+use std::time::Instant;
+use tracing::Level;
+
+/// Run `f` as setup step `name`, logging at debug level how long it took
+/// and whether it failed. `detail` is only formatted when that is logged.
+///
+/// The event has `step`, `took_ms` and, on failure, `error` fields; `detail`
+/// is logfmt, such as `target=/usr source=/usr`. The supervisor forks into
+/// the sandbox's pid namespace during setup and keeps logging from there.
+pub fn step<T, E: Display>(
+    name: &str,
+    detail: impl FnOnce() -> String,
+    f: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    if !tracing::enabled!(Level::DEBUG) {
+        return f();
+    }
+    let started = Instant::now();
+    let result = f();
+    let took_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let detail = Some(detail()).filter(|detail| !detail.is_empty());
+    let detail = detail.as_deref();
+    match &result {
+        Ok(_) => tracing::debug!(step = name, took_ms, detail, "Setup step done"),
+        Err(e) => {
+            let error = e.to_string();
+            tracing::debug!(step = name, took_ms, detail, error, "Setup step failed")
+        }
+    }
+    result
+}
+
+/// `value` as a logfmt value, quoted when it has spaces, quotes or `=`
+pub fn quote(value: impl Display) -> String {
+    let value = value.to_string();
+    if value.is_empty() || value.contains([' ', '"', '=', '\\']) {
+        format!("{:?}", value)
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("/usr"), "/usr");
+        assert_eq!(quote("size=5M,mode=755"), "\"size=5M,mode=755\"");
+        assert_eq!(quote(""), "\"\"");
+    }
+}
@@ -0,0 +1,20 @@
+// Kept in its own test binary: the recorder reaps with waitpid(-1) and would
+// steal children from tests running in parallel.
+#![cfg(all(target_os = "linux", feature = "seccomp"))]
+
+use capsule_run::sandbox::syscall_recorder::record_syscalls;
+use std::collections::HashMap;
+
+#[test]
+fn test_record_syscalls_of_shell() {
+    let command = vec!["sh".to_string(), "-c".to_string(), "exit 3".to_string()];
+
+    // ptrace may be forbidden in restricted containers
+    let Ok(recording) = record_syscalls(&command, &HashMap::new()) else {
+        return;
+    };
+
+    assert_eq!(recording.exit_code, 3);
+    assert!(recording.syscalls.contains(&libc::SYS_execve));
+    assert!(recording.syscalls.contains(&libc::SYS_exit_group));
+}